@@ -11,6 +11,7 @@ use rustyline::validate::{ValidationContext, ValidationResult, Validator};
 use rustyline::{Editor, Result};
 use rustyline_derive::{Completer, Helper, Hinter};
 use std::borrow::Cow::Owned;
+use std::path::{Path, PathBuf};
 use std::time::UNIX_EPOCH;
 
 #[derive(Completer, Helper, Hinter)]
@@ -73,6 +74,11 @@ impl SystemInterface for ReplSystemInterface {
 
 fn main() {
     pretty_env_logger::init();
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if args.first().map(String::as_str) == Some("compile") {
+        std::process::exit(compile(&args[1..]));
+    }
+
     let validator = InputValidator {
         highlighter: ReplHighlighter::new(),
     };
@@ -86,6 +92,19 @@ fn main() {
         None => (0, 0),
     };
     vm.set_system_interface(Box::new(ReplSystemInterface { term_dimensions }));
+
+    // Ctrl-C interrupts the running program rather than exiting. While
+    // reading a line, rustyline receives it as input instead.
+    let interrupt = vm.interrupt_handle();
+    if let Err(e) = ctrlc::set_handler(move || interrupt.interrupt()) {
+        eprintln!("error: unable to handle Ctrl-C: {}", e);
+    }
+    for path in &args {
+        if let Err(e) = load(&mut vm, path) {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+    }
     loop {
         let readline = rl.readline_with_initial("> ", (&remaining, ""));
         match readline {
@@ -102,6 +121,79 @@ fn main() {
     }
 }
 
+/// Compile a file to a bytecode image:
+///
+/// marwood-repl compile [--no-prelude] file.scm [-o file.mwc]
+///
+/// The image is compiled in a vm with the prelude loaded, unless
+/// --no-prelude is given (as when compiling the prelude itself).
+fn compile(args: &[String]) -> i32 {
+    let mut input = None;
+    let mut output = None;
+    let mut prelude = true;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = args.next(),
+            "--no-prelude" => prelude = false,
+            _ if input.is_none() => input = Some(arg),
+            _ => {
+                eprintln!("usage: marwood-repl compile [--no-prelude] file.scm [-o file.mwc]");
+                return 2;
+            }
+        }
+    }
+    let Some(input) = input else {
+        eprintln!("usage: marwood-repl compile [--no-prelude] file.scm [-o file.mwc]");
+        return 2;
+    };
+    let output = match output {
+        Some(output) => PathBuf::from(output),
+        None => Path::new(input).with_extension("mwc"),
+    };
+
+    let text = match std::fs::read_to_string(input) {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("error: {}: {}", input, e);
+            return 1;
+        }
+    };
+    let mut vm = match prelude {
+        true => Vm::new(),
+        false => Vm::new_without_prelude(),
+    };
+    let image = match vm.compile_bytecode(&text) {
+        Ok(image) => image,
+        Err(e) => {
+            eprintln!("error: {}: {}", input, e);
+            return 1;
+        }
+    };
+    if let Err(e) = std::fs::write(&output, image) {
+        eprintln!("error: {}: {}", output.display(), e);
+        return 1;
+    }
+    0
+}
+
+/// Load a bytecode image, or evaluate each expression of a source
+/// file, before starting the REPL.
+fn load(vm: &mut Vm, path: &str) -> std::result::Result<(), String> {
+    let error = |e: &dyn std::fmt::Display| format!("{}: {}", path, e);
+    let bytes = std::fs::read(path).map_err(|e| error(&e))?;
+    if Path::new(path).extension().is_some_and(|it| it == "mwc") {
+        return vm.load_bytecode(&bytes).map(|_| ()).map_err(|e| error(&e));
+    }
+    let text = String::from_utf8(bytes).map_err(|e| error(&e))?;
+    vm.eval_source(path, &text).map(|_| ()).map_err(|e| {
+        match vm.last_stacktrace().and_then(|it| it.span()) {
+            Some(span) => format!("{}: {}\n{}", span, e, span.excerpt()),
+            None => error(&e),
+        }
+    })
+}
+
 /// Evaluate one expression from the input text and return
 /// any text that was not evaluated.
 fn eval<'a>(vm: &mut Vm, text: &'a str) -> &'a str {
@@ -120,6 +212,9 @@ fn eval<'a>(vm: &mut Vm, text: &'a str) -> &'a str {
         Err(e) => {
             println!("error: {}", e);
             if let Some(trace) = vm.last_stacktrace() {
+                if let Some(span) = trace.span() {
+                    println!("{}", span.excerpt());
+                }
                 print_stacktrace(trace);
             }
             ""
@@ -140,12 +235,17 @@ fn print_stacktrace(trace: &StackTrace) {
             _ => Cell::Nil,
         };
 
+        let location = match &frame.span {
+            Some(span) => format!(" at {}", span),
+            None => "".to_owned(),
+        };
+
         match desc {
             Cell::Nil => {
-                println!("\t({})", name)
+                println!("\t({}){}", name, location)
             }
             _ => {
-                println!("\t({} {})", name, desc);
+                println!("\t({} {}){}", name, desc, location);
             }
         }
     }
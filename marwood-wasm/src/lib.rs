@@ -5,8 +5,10 @@ use marwood::cell::Cell;
 use marwood::lex;
 use marwood::parse;
 use marwood::syntax::ReplHighlighter;
+use marwood::vm::execution::Execution;
 use marwood::vm::{SystemInterface, Vm};
 use std::borrow::Cow;
+use std::task::Poll;
 use wasm_bindgen::prelude::*;
 
 #[wasm_bindgen(module = "/display.js")]
@@ -21,6 +23,7 @@ extern "C" {
 pub struct Marwood {
     vm: Vm,
     hl: ReplHighlighter,
+    exec: Option<Execution>,
 }
 
 #[derive(Debug)]
@@ -55,6 +58,7 @@ impl Marwood {
         Marwood {
             vm,
             hl: ReplHighlighter::new(),
+            exec: None,
         }
     }
 
@@ -72,8 +76,8 @@ impl Marwood {
         let mut cur = tokens.iter().peekable();
 
         match parse::parse(text, &mut cur) {
-            Ok(cell) => match self.vm.prepare_eval(&cell) {
-                Ok(()) => {}
+            Ok(cell) => match self.vm.start(&cell) {
+                Ok(exec) => self.exec = Some(exec),
                 Err(e) => return EvalResult::new_error(format!("error: {}", e)),
             },
             Err(parse::Error::Incomplete) => return EvalResult::new_eof(),
@@ -90,11 +94,14 @@ impl Marwood {
     }
 
     pub fn eval_continue(&mut self, count: usize) -> EvalResult {
-        match self.vm.run_count(count) {
-            Ok(Some(Cell::Void)) => EvalResult::new_ok(""),
-            Ok(Some(cell)) => EvalResult::new_ok(format!("{:#}", cell)),
-            Ok(None) => EvalResult::new_not_completed(),
-            Err(e) => EvalResult::new_error(format!(
+        let Some(exec) = &self.exec else {
+            return EvalResult::new_ok("");
+        };
+        match exec.resume(&mut self.vm, count) {
+            Poll::Ready(Ok(Cell::Void)) => EvalResult::new_ok(""),
+            Poll::Ready(Ok(cell)) => EvalResult::new_ok(format!("{:#}", cell)),
+            Poll::Pending => EvalResult::new_not_completed(),
+            Poll::Ready(Err(e)) => EvalResult::new_error(format!(
                 "error: {}\ntrace: \n{}",
                 e,
                 self.build_stacktrace()
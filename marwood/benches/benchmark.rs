@@ -62,6 +62,138 @@ fn heap_alloc(n: u64) -> Cell {
     result
 }
 
+fn string_ref_set(n: u64) -> Cell {
+    let mut vm = Vm::new();
+    vm.eval(&parse!(
+        r#"
+        (define (string-rotate! s)
+          (define len (string-length s))
+          (define first (string-ref s 0))
+          (let ~ ((i 1))
+            (cond ((< i len)
+              (string-set! s (- i 1) (string-ref s i))
+              (~ (+ i 1)))))
+          (string-set! s (- len 1) first))
+    "#
+    ))
+    .unwrap();
+    vm.eval(&parse!(&format!("(define s (make-string {} #\\🐶))", n)))
+        .unwrap();
+    vm.eval(&parse!("(string-set! s 0 #\\a)")).unwrap();
+    vm.eval(&parse!("(string-rotate! s)")).unwrap();
+    let result = vm
+        .eval(&parse!(&format!("(string-ref s {})", n - 1)))
+        .unwrap();
+    assert_eq!(result, Cell::Char('a'));
+    result
+}
+
+fn substring(n: u64) -> Cell {
+    let mut vm = Vm::new();
+    vm.eval(&parse!(
+        r#"
+        (define (substrings s)
+          (define len (string-length s))
+          (let ~ ((i 0) (acc 0))
+            (if (< i len)
+                (~ (+ i 1) (+ acc (string-length (string-copy s i len))))
+                acc)))
+    "#
+    ))
+    .unwrap();
+    let result = vm
+        .eval(&parse!(&format!("(substrings (make-string {} #\\λ))", n)))
+        .unwrap();
+    assert_eq!(result, Cell::from((n * (n + 1) / 2) as i64));
+    result
+}
+
+fn call_cc_deep(n: u64) -> Cell {
+    let mut vm = Vm::new();
+    vm.eval(&parse!(
+        r#"
+        (define (deep depth thunk)
+          (if (zero? depth)
+              (thunk)
+              (+ 0 (deep (- depth 1) thunk))))
+    "#
+    ))
+    .unwrap();
+    vm.eval(&parse!(
+        r#"
+        (define (capture n)
+          (let ~ ((i 0))
+            (if (< i n)
+                (begin (call/cc (lambda (k) k)) (~ (+ i 1)))
+                i)))
+    "#
+    ))
+    .unwrap();
+    let result = vm
+        .eval(&parse!(&format!("(deep 1000 (lambda () (capture {})))", n)))
+        .unwrap();
+    assert_eq!(result, Cell::from(n as i64));
+    result
+}
+
+fn call_cc_generator(n: u64) -> Cell {
+    let mut vm = Vm::new();
+    vm.eval(&parse!(
+        r#"
+        (define (deep depth thunk)
+          (if (zero? depth)
+              (thunk)
+              (+ 0 (deep (- depth 1) thunk))))
+    "#
+    ))
+    .unwrap();
+    vm.eval(&parse!(
+        r#"
+        (define (make-generator lst)
+          (define return #f)
+          (define (resume)
+            (call/cc
+              (lambda (r)
+                (set! return r)
+                (for-each
+                  (lambda (x)
+                    (call/cc
+                      (lambda (k)
+                        (set! resume (lambda () (call/cc (lambda (r) (set! return r) (k #f)))))
+                        (return x))))
+                  lst)
+                (return 'done))))
+          (lambda () (resume)))
+    "#
+    ))
+    .unwrap();
+    vm.eval(&parse!(
+        r#"
+        (define (range n)
+          (let ~ ((i n) (acc '()))
+            (if (zero? i) acc (~ (- i 1) (cons i acc)))))
+    "#
+    ))
+    .unwrap();
+    vm.eval(&parse!(
+        r#"
+        (define (sum-generator g)
+          (let ~ ((acc 0))
+            (let ((x (g)))
+              (if (eq? x 'done) acc (~ (+ acc x))))))
+    "#
+    ))
+    .unwrap();
+    let result = vm
+        .eval(&parse!(&format!(
+            "(deep 500 (lambda () (sum-generator (make-generator (range {})))))",
+            n
+        )))
+        .unwrap();
+    assert_eq!(result, Cell::from((n * (n + 1) / 2) as i64));
+    result
+}
+
 fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("sum-of-triangles 1000", |b| {
         b.iter(|| sum_of_triangles(black_box(1000)))
@@ -70,6 +202,16 @@ fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("heap-alloc 25000", |b| {
         b.iter(|| heap_alloc(black_box(25000)))
     });
+    c.bench_function("string-ref/set! 2000", |b| {
+        b.iter(|| string_ref_set(black_box(2000)))
+    });
+    c.bench_function("substring 1000", |b| b.iter(|| substring(black_box(1000))));
+    c.bench_function("call/cc deep 1000", |b| {
+        b.iter(|| call_cc_deep(black_box(1000)))
+    });
+    c.bench_function("call/cc generator 1000", |b| {
+        b.iter(|| call_cc_generator(black_box(1000)))
+    });
 }
 
 criterion_group!(benches, criterion_benchmark);
@@ -0,0 +1,49 @@
+// Build Script
+//
+// Compile prelude.scm into the bytecode image embedded by the prelude
+// module. The image is compiled by this crate's own compiler, so the
+// crate's modules are built into the build script as well. The prelude
+// image they embed is empty here, which is never loaded because the
+// prelude is compiled in a vm without one.
+//
+// The modules are private to the build script, so lints that only apply
+// to private items are allowed.
+#![allow(dead_code, unused_imports, unused_macros)]
+#![allow(clippy::enum_variant_names, clippy::wrong_self_convention)]
+
+#[path = "src/cell.rs"]
+mod cell;
+#[path = "src/char.rs"]
+mod char;
+#[path = "src/error.rs"]
+mod error;
+#[path = "src/lex.rs"]
+mod lex;
+#[path = "src/number.rs"]
+mod number;
+#[path = "src/parse.rs"]
+mod parse;
+#[path = "src/source.rs"]
+mod source;
+#[path = "src/syntax.rs"]
+mod syntax;
+#[path = "src/vm/mod.rs"]
+mod vm;
+
+mod prelude {
+    pub const PRELUDE_IMAGE: &[u8] = &[];
+}
+
+use std::path::Path;
+
+fn main() {
+    println!("cargo:rerun-if-changed=prelude.scm");
+    println!("cargo:rerun-if-changed=src");
+
+    let text = std::fs::read_to_string("prelude.scm").expect("prelude.scm");
+    let image = vm::Vm::new_without_prelude()
+        .compile_bytecode(&text)
+        .expect("invalid prelude");
+    let out_dir = std::env::var("OUT_DIR").expect("OUT_DIR");
+    std::fs::write(Path::new(&out_dir).join("prelude.mwc"), image).expect("write prelude.mwc");
+}
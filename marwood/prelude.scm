@@ -256,3 +256,1005 @@
                   (for-each-all (map1 cdr xss)) void)))))
 
     (for-each-all xss)))
+
+(define (list-merge less? xs ys)
+  (let loop ((xs xs) (ys ys) (acc '()))
+    (cond
+      ((null? xs) (append (reverse acc) ys))
+      ((null? ys) (append (reverse acc) xs))
+      ((less? (car ys) (car xs)) (loop xs (cdr ys) (cons (car ys) acc)))
+      (else (loop (cdr xs) ys (cons (car xs) acc))))))
+
+(define (list-sort less? lis)
+  (define (sort lis n)
+    (cond
+      ((= n 0) '())
+      ((= n 1) (list (car lis)))
+      (else
+        (let ((half (quotient n 2)))
+          (list-merge less?
+                      (sort lis half)
+                      (sort (list-tail lis half) (- n half)))))))
+  (sort lis (length lis)))
+
+(define list-stable-sort list-sort)
+
+(define (list-sorted? less? lis)
+  (or (null? lis)
+      (let loop ((prev (car lis)) (rest (cdr lis)))
+        (cond
+          ((null? rest) #t)
+          ((less? (car rest) prev) #f)
+          (else (loop (car rest) (cdr rest)))))))
+
+(define (vector-sort less? v . range)
+  (list->vector (list-sort less? (apply vector->list v range))))
+
+(define vector-stable-sort vector-sort)
+
+(define (vector-sort! v less? . range)
+  (let loop ((i (if (pair? range) (car range) 0))
+             (sorted (list-sort less? (apply vector->list v range))))
+    (unless (null? sorted)
+      (vector-set! v i (car sorted))
+      (loop (+ i 1) (cdr sorted)))))
+
+(define vector-stable-sort! vector-sort!)
+
+(define (vector-sorted? less? v . range)
+  (list-sorted? less? (apply vector->list v range)))
+
+(define (string-map f string . strings)
+  (list->string
+    (apply map f (string->list string) (map1 string->list strings))))
+
+(define (string-for-each f string . strings)
+  (apply for-each f (string->list string) (map1 string->list strings)))
+
+(define (vector-map f vector . vectors)
+  (list->vector
+    (apply map f (vector->list vector) (map1 vector->list vectors))))
+
+(define (vector-for-each f vector . vectors)
+  (apply for-each f (vector->list vector) (map1 vector->list vectors)))
+
+(define (%char-criterion criterion)
+  (cond
+    ((char? criterion) (lambda (c) (char=? c criterion)))
+    ((char-set? criterion) (lambda (c) (char-set-contains? criterion c)))
+    (else criterion)))
+
+(define (string-index string criterion . range)
+  (let ((pred? (%char-criterion criterion))
+        (start (if (pair? range) (car range) 0)))
+    (let loop ((chars (apply string->list string range)) (i start))
+      (cond
+        ((null? chars) #f)
+        ((pred? (car chars)) i)
+        (else (loop (cdr chars) (+ i 1)))))))
+
+(define (string-split string criterion)
+  (let ((pred? (%char-criterion criterion)))
+    (let loop ((chars (string->list string)) (field '()) (fields '()))
+      (cond
+        ((null? chars)
+         (reverse (cons (list->string (reverse field)) fields)))
+        ((pred? (car chars))
+         (loop (cdr chars) '() (cons (list->string (reverse field)) fields)))
+        (else (loop (cdr chars) (cons (car chars) field) fields))))))
+
+(define (%drop-chars-while pred? chars)
+  (if (and (pair? chars) (pred? (car chars)))
+      (%drop-chars-while pred? (cdr chars))
+      chars))
+
+(define (string-trim-left string . criterion)
+  (let ((pred? (if (pair? criterion)
+                   (%char-criterion (car criterion))
+                   char-whitespace?)))
+    (list->string (%drop-chars-while pred? (string->list string)))))
+
+(define (string-trim-right string . criterion)
+  (let ((pred? (if (pair? criterion)
+                   (%char-criterion (car criterion))
+                   char-whitespace?)))
+    (list->string
+      (reverse (%drop-chars-while pred? (reverse (string->list string)))))))
+
+(define (string-trim string . criterion)
+  (apply string-trim-left (apply string-trim-right string criterion) criterion))
+
+(define %handlers '())
+
+(define (with-exception-handler handler thunk)
+  (let ((saved %handlers))
+    (set! %handlers (cons handler saved))
+    (let ((result (thunk)))
+      (set! %handlers saved)
+      result)))
+
+(define (raise-continuable obj)
+  (if (null? %handlers)
+      (%raise obj)
+      (let ((saved %handlers))
+        (set! %handlers (cdr saved))
+        (let ((result ((car saved) obj)))
+          (set! %handlers saved)
+          result))))
+
+(define (raise obj)
+  (if (null? %handlers)
+      (if (error-object? obj)
+          (apply error (error-object-message obj) (error-object-irritants obj))
+          (%raise obj))
+      (let ((handler (car %handlers)))
+        (set! %handlers (cdr %handlers))
+        (handler obj)
+        (error "exception handler returned from non-continuable raise" obj))))
+
+(define %error-object-tag (list 'error-object))
+
+(define (%raise-error message irritants)
+  (raise (vector %error-object-tag message irritants)))
+
+(define (error-object? obj)
+  (and (vector? obj)
+       (= (vector-length obj) 3)
+       (eq? (vector-ref obj 0) %error-object-tag)))
+
+(define (error-object-message obj) (vector-ref obj 1))
+(define (error-object-irritants obj) (vector-ref obj 2))
+
+(define %error error)
+
+(define (error message . irritants)
+  (if (null? %handlers)
+      (apply %error message irritants)
+      (raise (vector %error-object-tag message irritants))))
+
+(define-syntax guard
+  (syntax-rules ()
+    ((guard (var clause ...) body ...)
+     ((call/cc
+       (lambda (%guard-k)
+         (let ((%guard-handlers %handlers))
+           (with-exception-handler
+            (lambda (condition)
+              (set! %handlers %guard-handlers)
+              (%guard-k
+               (lambda ()
+                 (let ((var condition))
+                   (%guard-clauses (raise condition) clause ...)))))
+            (lambda ()
+              (let ((%guard-result (let () body ...)))
+                (lambda () %guard-result)))))))))))
+
+(define-syntax %guard-clauses
+  (syntax-rules (else =>)
+    ((_ reraise (else result1 result2 ...))
+     (begin result1 result2 ...))
+    ((_ reraise (test => receiver))
+     (let ((%guard-test test))
+       (if %guard-test (receiver %guard-test) reraise)))
+    ((_ reraise (test => receiver) clause1 clause2 ...)
+     (let ((%guard-test test))
+       (if %guard-test
+           (receiver %guard-test)
+           (%guard-clauses reraise clause1 clause2 ...))))
+    ((_ reraise (test))
+     (or test reraise))
+    ((_ reraise (test) clause1 clause2 ...)
+     (or test (%guard-clauses reraise clause1 clause2 ...)))
+    ((_ reraise (test result1 result2 ...))
+     (if test (begin result1 result2 ...) reraise))
+    ((_ reraise (test result1 result2 ...) clause1 clause2 ...)
+     (if test
+         (begin result1 result2 ...)
+         (%guard-clauses reraise clause1 clause2 ...)))))
+
+(define %thread-tag (list 'thread))
+(define %uncaught-exception-tag (list 'uncaught-exception))
+(define %terminated-thread-exception-tag (list 'terminated-thread-exception))
+(define %abandoned-mutex-exception-tag (list 'abandoned-mutex-exception))
+
+(define (make-thread thunk . name)
+  (vector %thread-tag thunk (if (pair? name) (car name) #f) #f 'new #f))
+
+(define %primordial-thread (vector %thread-tag #f 'primordial #f 'runnable #f))
+
+(define (thread? obj)
+  (and (vector? obj)
+       (= (vector-length obj) 6)
+       (eq? (vector-ref obj 0) %thread-tag)))
+
+(define (current-thread) (or (%current-thread) %primordial-thread))
+(define (thread-name thread) (vector-ref thread 2))
+(define (thread-specific thread) (vector-ref thread 3))
+(define (thread-specific-set! thread obj) (vector-set! thread 3 obj))
+
+(define (%thread-done? thread)
+  (memq (vector-ref thread 4) '(terminated failed killed)))
+
+(define (%thread-main thread)
+  (guard (e (#t (vector-set! thread 5 e)
+                (vector-set! thread 4 'failed)))
+    (vector-set! thread 5 ((vector-ref thread 1)))
+    (vector-set! thread 4 'terminated))
+  (%thread-exit!))
+
+(define (thread-start! thread)
+  (if (%vector-cas! thread 4 'new 'runnable)
+      (begin (%thread-spawn! thread) thread)
+      (error "thread already started" thread)))
+
+(define (thread-yield!)
+  (%thread-yield!)
+  (if #f #f))
+
+(define (thread-terminate! thread)
+  (cond
+    ((eq? thread %primordial-thread)
+     (error "the primordial thread may not be terminated"))
+    ((not (%thread-done? thread))
+     (vector-set! thread 4 'killed)
+     (%thread-remove! thread)
+     (if (eq? thread (current-thread)) (%thread-exit!)))))
+
+(define %join-timeout-exception-tag (list 'join-timeout-exception))
+
+(define (%poll-timeout? timeout)
+  (cond
+    ((not timeout) #f)
+    ((and (real? timeout) (<= timeout 0)) #t)
+    (else (error "only timeouts of #f and 0 are supported" timeout))))
+
+(define (thread-join! thread . timeout)
+  (if (eq? thread (current-thread))
+      (error "thread may not join itself" thread))
+  (let ((poll? (and (pair? timeout) (%poll-timeout? (car timeout)))))
+    (let loop ()
+      (case (vector-ref thread 4)
+        ((terminated) (vector-ref thread 5))
+        ((failed) (raise (vector %uncaught-exception-tag (vector-ref thread 5))))
+        ((killed) (raise (vector %terminated-thread-exception-tag)))
+        (else
+         (cond
+           (poll?
+            (if (pair? (cdr timeout))
+                (cadr timeout)
+                (raise (vector %join-timeout-exception-tag))))
+           ((or (%thread-yield!) (%thread-done? thread)) (loop))
+           (else (error "deadlock: thread-join! on a thread that can never run" thread))))))))
+
+(define (uncaught-exception? obj)
+  (and (vector? obj)
+       (= (vector-length obj) 2)
+       (eq? (vector-ref obj 0) %uncaught-exception-tag)))
+
+(define (uncaught-exception-reason exc) (vector-ref exc 1))
+
+(define (terminated-thread-exception? obj)
+  (and (vector? obj)
+       (= (vector-length obj) 1)
+       (eq? (vector-ref obj 0) %terminated-thread-exception-tag)))
+
+(define (join-timeout-exception? obj)
+  (and (vector? obj)
+       (= (vector-length obj) 1)
+       (eq? (vector-ref obj 0) %join-timeout-exception-tag)))
+
+(define (abandoned-mutex-exception? obj)
+  (and (vector? obj)
+       (= (vector-length obj) 1)
+       (eq? (vector-ref obj 0) %abandoned-mutex-exception-tag)))
+
+(define %mutex-tag (list 'mutex))
+
+(define (make-mutex . name)
+  (vector %mutex-tag (if (pair? name) (car name) #f) #f #f))
+
+(define (mutex? obj)
+  (and (vector? obj)
+       (= (vector-length obj) 4)
+       (eq? (vector-ref obj 0) %mutex-tag)))
+
+(define (mutex-name mutex) (vector-ref mutex 1))
+(define (mutex-specific mutex) (vector-ref mutex 2))
+(define (mutex-specific-set! mutex obj) (vector-set! mutex 2 obj))
+
+(define (mutex-state mutex)
+  (let ((owner (vector-ref mutex 3)))
+    (cond
+      ((not owner) 'not-abandoned)
+      ((eq? owner 'not-owned) 'not-owned)
+      ((%thread-done? owner) 'abandoned)
+      (else owner))))
+
+(define (mutex-lock! mutex . args)
+  (let ((poll? (and (pair? args) (%poll-timeout? (car args))))
+        (owner (if (and (pair? args) (pair? (cdr args)))
+                   (or (cadr args) 'not-owned)
+                   (current-thread))))
+    (let loop ()
+      (let ((state (vector-ref mutex 3)))
+        (cond
+          ((not state)
+           (or (%vector-cas! mutex 3 #f owner) (loop)))
+          ((and (thread? state) (%thread-done? state))
+           (if (%vector-cas! mutex 3 state owner)
+               (raise (vector %abandoned-mutex-exception-tag))
+               (loop)))
+          (poll? #f)
+          ((or (%thread-yield!) (not (eq? state (vector-ref mutex 3)))) (loop))
+          (else (error "deadlock: mutex-lock! on a mutex that is never unlocked" mutex)))))))
+
+(define %condition-variable-tag (list 'condition-variable))
+
+(define (make-condition-variable . name)
+  (vector %condition-variable-tag (if (pair? name) (car name) #f) #f '()))
+
+(define (condition-variable? obj)
+  (and (vector? obj)
+       (= (vector-length obj) 4)
+       (eq? (vector-ref obj 0) %condition-variable-tag)))
+
+(define (condition-variable-name condvar) (vector-ref condvar 1))
+(define (condition-variable-specific condvar) (vector-ref condvar 2))
+(define (condition-variable-specific-set! condvar obj) (vector-set! condvar 2 obj))
+
+(define (%condition-variable-update! condvar update)
+  (let loop ()
+    (let ((waiters (vector-ref condvar 3)))
+      (if (%vector-cas! condvar 3 waiters (update waiters))
+          waiters
+          (loop)))))
+
+(define (mutex-unlock! mutex . args)
+  (let ((condvar (and (pair? args) (car args)))
+        (poll? (and (pair? args) (pair? (cdr args)) (%poll-timeout? (cadr args)))))
+    (if condvar
+        (let ((waiter (list #f)))
+          (%condition-variable-update! condvar (lambda (waiters) (cons waiter waiters)))
+          (vector-set! mutex 3 #f)
+          (let loop ()
+            (cond
+              ((car waiter) #t)
+              (poll?
+               (%condition-variable-update!
+                condvar
+                (lambda (waiters)
+                  (let remove ((waiters waiters))
+                    (cond
+                      ((null? waiters) '())
+                      ((eq? (car waiters) waiter) (cdr waiters))
+                      (else (cons (car waiters) (remove (cdr waiters))))))))
+               (car waiter))
+              ((or (%thread-yield!) (car waiter)) (loop))
+              (else (error "deadlock: condition variable is never signaled" condvar)))))
+        (begin
+          (vector-set! mutex 3 #f)
+          #t))))
+
+(define (condition-variable-signal! condvar)
+  (let ((waiters (%condition-variable-update!
+                  condvar
+                  (lambda (waiters)
+                    (if (pair? waiters) (reverse (cdr (reverse waiters))) waiters)))))
+    (if (pair? waiters)
+        (set-car! (car (reverse waiters)) #t))))
+
+(define (condition-variable-broadcast! condvar)
+  (for-each (lambda (waiter) (set-car! waiter #t))
+            (%condition-variable-update! condvar (lambda (waiters) '()))))
+
+(define (make-continuation-prompt-tag . name)
+  (list (if (pair? name) (car name) 'prompt)))
+
+(define %default-prompt-tag (make-continuation-prompt-tag 'default))
+
+(define (default-continuation-prompt-tag) %default-prompt-tag)
+
+(define (call-with-continuation-prompt thunk . args)
+  (let* ((tag (if (pair? args) (car args) %default-prompt-tag))
+         (handler (if (and (pair? args) (pair? (cdr args)))
+                      (cadr args)
+                      (lambda (thunk) (call-with-continuation-prompt thunk tag))))
+         (handlers %handlers))
+    (%call-with-prompt thunk tag (lambda args
+                                   (set! %handlers handlers)
+                                   (apply handler args)))))
+
+(define (call-with-composable-continuation proc . tag)
+  (%call-with-composable-continuation proc (if (pair? tag) (car tag) %default-prompt-tag)))
+
+(define (abort-current-continuation tag . args)
+  (apply %abort-current-continuation tag args))
+
+(define (call-with-escape-continuation proc)
+  (let ((tag (make-continuation-prompt-tag 'escape)))
+    (call-with-continuation-prompt
+     (lambda () (proc (lambda (value) (%escape tag value))))
+     tag
+     (lambda (value) value))))
+
+(define call/ec call-with-escape-continuation)
+
+(define-syntax reset
+  (syntax-rules ()
+    ((reset body ...)
+     (call-with-continuation-prompt (lambda () body ...)))))
+
+(define-syntax shift
+  (syntax-rules ()
+    ((shift k body ...)
+     (%shift (lambda (k) body ...)))))
+
+(define (%shift proc)
+  (call-with-composable-continuation
+   (lambda (k)
+     (abort-current-continuation
+      %default-prompt-tag
+      (lambda ()
+        (proc (lambda args (reset (apply k args)))))))))
+
+(define %eof-object (vector (list 'eof-object)))
+
+(define (eof-object) %eof-object)
+
+(define (eof-object? obj) (eq? obj %eof-object))
+
+(define (generator . args)
+  (lambda ()
+    (if (null? args)
+        (eof-object)
+        (let ((next (car args)))
+          (set! args (cdr args))
+          next))))
+
+(define (circular-generator . args)
+  (let ((rest args))
+    (lambda ()
+      (if (null? rest) (set! rest args))
+      (let ((next (car rest)))
+        (set! rest (cdr rest))
+        next))))
+
+(define (make-iota-generator count . args)
+  (let ((start (if (pair? args) (car args) 0))
+        (step (if (and (pair? args) (pair? (cdr args))) (cadr args) 1))
+        (i 0))
+    (lambda ()
+      (if (< i count)
+          (let ((next (+ start (* i step))))
+            (set! i (+ i 1))
+            next)
+          (eof-object)))))
+
+(define (make-range-generator start . args)
+  (let ((end (if (pair? args) (car args) #f))
+        (step (if (and (pair? args) (pair? (cdr args))) (cadr args) 1))
+        (i 0))
+    (lambda ()
+      (let ((next (+ start (* i step))))
+        (if (and end (>= next end))
+            (eof-object)
+            (begin (set! i (+ i 1)) next))))))
+
+(define (make-coroutine-generator proc)
+  (define return #f)
+  (define resume #f)
+  (define (yield value)
+    (call/cc (lambda (k) (set! resume k) (return value))))
+  (lambda ()
+    (call/cc
+     (lambda (k)
+       (set! return k)
+       (if resume
+           (resume #f)
+           (begin
+             (proc yield)
+             (set! resume (lambda (value) (return (eof-object))))
+             (return (eof-object))))))))
+
+(define (list->generator lst) (apply generator lst))
+
+(define (vector->generator vector . args)
+  (let ((i (if (pair? args) (car args) 0))
+        (end (if (and (pair? args) (pair? (cdr args))) (cadr args) (vector-length vector))))
+    (lambda ()
+      (if (< i end)
+          (let ((next (vector-ref vector i)))
+            (set! i (+ i 1))
+            next)
+          (eof-object)))))
+
+(define (reverse-vector->generator vector . args)
+  (let ((start (if (pair? args) (car args) 0))
+        (i (if (and (pair? args) (pair? (cdr args))) (cadr args) (vector-length vector))))
+    (lambda ()
+      (if (> i start)
+          (begin (set! i (- i 1)) (vector-ref vector i))
+          (eof-object)))))
+
+(define (string->generator string . args)
+  (let ((i (if (pair? args) (car args) 0))
+        (end (if (and (pair? args) (pair? (cdr args))) (cadr args) (string-length string))))
+    (lambda ()
+      (if (< i end)
+          (let ((next (string-ref string i)))
+            (set! i (+ i 1))
+            next)
+          (eof-object)))))
+
+(define (make-for-each-generator for-each obj)
+  (make-coroutine-generator (lambda (yield) (for-each yield obj))))
+
+(define (make-unfold-generator stop? mapper successor seed)
+  (lambda ()
+    (if (stop? seed)
+        (eof-object)
+        (let ((next (mapper seed)))
+          (set! seed (successor seed))
+          next))))
+
+(define (gcons* . args)
+  (lambda ()
+    (if (null? (cdr args))
+        ((car args))
+        (let ((next (car args)))
+          (set! args (cdr args))
+          next))))
+
+(define (gappend . gens)
+  (lambda ()
+    (let loop ()
+      (if (null? gens)
+          (eof-object)
+          (let ((next ((car gens))))
+            (if (eof-object? next)
+                (begin (set! gens (cdr gens)) (loop))
+                next))))))
+
+(define (gflatten gen)
+  (let ((items '()))
+    (lambda ()
+      (let loop ()
+        (if (pair? items)
+            (let ((next (car items)))
+              (set! items (cdr items))
+              next)
+            (let ((next (gen)))
+              (if (eof-object? next)
+                  next
+                  (begin (set! items next) (loop)))))))))
+
+(define (ggroup gen k . padding)
+  (lambda ()
+    (let ((group (generator->list gen k)))
+      (if (or (null? group) (null? padding))
+          (if (null? group) (eof-object) group)
+          (let pad ((group (reverse group)) (n (- k (length group))))
+            (if (> n 0)
+                (pad (cons (car padding) group) (- n 1))
+                (reverse group)))))))
+
+(define (gmerge less? . gens)
+  (let ((gens (list->vector gens))
+        (heads #f))
+    (lambda ()
+      (if (not heads)
+          (set! heads (vector-map (lambda (gen) (gen)) gens)))
+      (let loop ((i 0) (best #f))
+        (cond
+          ((< i (vector-length heads))
+           (let ((head (vector-ref heads i)))
+             (loop (+ i 1)
+                   (if (and (not (eof-object? head))
+                            (or (not best) (less? head (vector-ref heads best))))
+                       i
+                       best))))
+          (best
+           (let ((next (vector-ref heads best)))
+             (vector-set! heads best ((vector-ref gens best)))
+             next))
+          (else (eof-object)))))))
+
+(define (%generator-args gens)
+  (let ((args (map (lambda (gen) (gen)) gens)))
+    (if (any? eof-object? args) #f args)))
+
+(define (gmap proc . gens)
+  (lambda ()
+    (let ((args (%generator-args gens)))
+      (if args (apply proc args) (eof-object)))))
+
+(define (gfilter pred gen)
+  (lambda ()
+    (let loop ()
+      (let ((next (gen)))
+        (if (or (eof-object? next) (pred next))
+            next
+            (loop))))))
+
+(define (gremove pred gen)
+  (gfilter (lambda (obj) (not (pred obj))) gen))
+
+(define (gtake gen k . padding)
+  (lambda ()
+    (if (> k 0)
+        (let ((next (gen)))
+          (set! k (- k 1))
+          (if (and (eof-object? next) (pair? padding)) (car padding) next))
+        (eof-object))))
+
+(define (gdrop gen k)
+  (lambda ()
+    (let loop ()
+      (if (> k 0)
+          (begin (set! k (- k 1)) (gen) (loop))))
+    (gen)))
+
+(define (gtake-while pred gen)
+  (let ((done #f))
+    (lambda ()
+      (if done
+          (eof-object)
+          (let ((next (gen)))
+            (if (or (eof-object? next) (not (pred next)))
+                (begin (set! done #t) (eof-object))
+                next))))))
+
+(define (gdrop-while pred gen)
+  (let ((dropping #t))
+    (lambda ()
+      (if dropping
+          (let loop ()
+            (let ((next (gen)))
+              (if (and (not (eof-object? next)) (pred next))
+                  (loop)
+                  (begin (set! dropping #f) next))))
+          (gen)))))
+
+(define (gdelete item gen . args)
+  (let ((same? (if (pair? args) (car args) equal?)))
+    (gremove (lambda (obj) (same? item obj)) gen)))
+
+(define (gdelete-neighbor-dups gen . args)
+  (let ((same? (if (pair? args) (car args) equal?))
+        (first #t)
+        (prev #f))
+    (lambda ()
+      (let loop ()
+        (let ((next (gen)))
+          (cond
+            ((eof-object? next) next)
+            ((and (not first) (same? prev next)) (loop))
+            (else (set! first #f) (set! prev next) next)))))))
+
+(define (gindex value-gen index-gen)
+  (let ((i 0))
+    (lambda ()
+      (let ((index (index-gen)))
+        (if (eof-object? index)
+            index
+            (let loop ()
+              (let ((value (value-gen)))
+                (set! i (+ i 1))
+                (if (or (eof-object? value) (= (- i 1) index))
+                    value
+                    (loop)))))))))
+
+(define (gselect value-gen truth-gen)
+  (lambda ()
+    (let loop ()
+      (let ((value (value-gen))
+            (truth (truth-gen)))
+        (cond
+          ((or (eof-object? value) (eof-object? truth)) (eof-object))
+          (truth value)
+          (else (loop)))))))
+
+(define (generator->reverse-list gen . n)
+  (let loop ((acc '()) (k (if (pair? n) (car n) #f)))
+    (if (and k (<= k 0))
+        acc
+        (let ((next (gen)))
+          (if (eof-object? next)
+              acc
+              (loop (cons next acc) (and k (- k 1))))))))
+
+(define (generator->list gen . n)
+  (reverse (apply generator->reverse-list gen n)))
+
+(define (generator->vector gen . n)
+  (list->vector (apply generator->list gen n)))
+
+(define (generator->vector! vector at gen)
+  (let loop ((i at))
+    (if (< i (vector-length vector))
+        (let ((next (gen)))
+          (if (eof-object? next)
+              (- i at)
+              (begin (vector-set! vector i next) (loop (+ i 1)))))
+        (- i at))))
+
+(define (generator->string gen . n)
+  (list->string (apply generator->list gen n)))
+
+(define (generator-fold proc seed . gens)
+  (let loop ((seed seed))
+    (let ((args (%generator-args gens)))
+      (if args
+          (loop (apply proc (append args (list seed))))
+          seed))))
+
+(define (generator-for-each proc . gens)
+  (let loop ()
+    (let ((args (%generator-args gens)))
+      (if args
+          (begin (apply proc args) (loop))))))
+
+(define (generator-map->list proc . gens)
+  (generator->list (apply gmap proc gens)))
+
+(define (generator-find pred gen)
+  (let loop ()
+    (let ((next (gen)))
+      (cond
+        ((eof-object? next) #f)
+        ((pred next) next)
+        (else (loop))))))
+
+(define (generator-count pred gen)
+  (generator-fold (lambda (obj n) (if (pred obj) (+ n 1) n)) 0 gen))
+
+(define (generator-any pred gen)
+  (let loop ()
+    (let ((next (gen)))
+      (if (eof-object? next)
+          #f
+          (or (pred next) (loop))))))
+
+(define (generator-every pred gen)
+  (let loop ((last #t))
+    (let ((next (gen)))
+      (if (eof-object? next)
+          last
+          (let ((result (pred next)))
+            (if result (loop result) #f))))))
+
+(define (generator-unfold gen unfold . args)
+  (apply unfold eof-object? (lambda (obj) obj) (lambda (obj) (gen)) (gen) args))
+
+(define (make-accumulator kons knil finalizer)
+  (let ((state knil))
+    (lambda (obj)
+      (if (eof-object? obj)
+          (finalizer state)
+          (set! state (kons obj state))))))
+
+(define (count-accumulator)
+  (make-accumulator (lambda (obj n) (+ n 1)) 0 (lambda (n) n)))
+
+(define (list-accumulator)
+  (make-accumulator cons '() reverse))
+
+(define (reverse-list-accumulator)
+  (make-accumulator cons '() (lambda (lst) lst)))
+
+(define (vector-accumulator)
+  (make-accumulator cons '() (lambda (lst) (list->vector (reverse lst)))))
+
+(define (reverse-vector-accumulator)
+  (make-accumulator cons '() list->vector))
+
+(define (vector-accumulator! vector at)
+  (lambda (obj)
+    (if (eof-object? obj)
+        vector
+        (begin (vector-set! vector at obj) (set! at (+ at 1))))))
+
+(define (string-accumulator)
+  (make-accumulator cons '() (lambda (lst) (list->string (reverse lst)))))
+
+(define (sum-accumulator)
+  (make-accumulator + 0 (lambda (n) n)))
+
+(define (product-accumulator)
+  (make-accumulator * 1 (lambda (n) n)))
+
+(define %stream-tag (list 'stream))
+
+(define (%make-stream promise) (vector %stream-tag promise))
+
+(define (stream? obj)
+  (and (vector? obj)
+       (= (vector-length obj) 2)
+       (eq? (vector-ref obj 0) %stream-tag)))
+
+(define (%stream-force strm) (force (vector-ref strm 1)))
+
+(define-syntax %stream-lazy
+  (syntax-rules ()
+    ((%stream-lazy expr)
+     (%make-stream (delay-force (vector-ref expr 1))))))
+
+(define (%stream-eager obj) (%make-stream (make-promise #t obj)))
+
+(define-syntax %stream-delay
+  (syntax-rules ()
+    ((%stream-delay expr)
+     (%stream-lazy (%stream-eager expr)))))
+
+(define stream-null (%make-stream (delay '())))
+
+(define-syntax stream-cons
+  (syntax-rules ()
+    ((stream-cons obj strm)
+     (%stream-eager (cons (%stream-delay obj) (%stream-lazy strm))))))
+
+(define (stream-null? obj)
+  (and (stream? obj) (null? (%stream-force obj))))
+
+(define (stream-pair? obj)
+  (and (stream? obj) (pair? (%stream-force obj))))
+
+(define (stream-car strm)
+  (if (stream-pair? strm)
+      (%stream-force (car (%stream-force strm)))
+      (error "stream-car: expected stream pair" strm)))
+
+(define (stream-cdr strm)
+  (if (stream-pair? strm)
+      (cdr (%stream-force strm))
+      (error "stream-cdr: expected stream pair" strm)))
+
+(define-syntax stream-lambda
+  (syntax-rules ()
+    ((stream-lambda formals body ...)
+     (lambda formals (%stream-lazy (let () body ...))))))
+
+(define-syntax define-stream
+  (syntax-rules ()
+    ((define-stream (name . formals) body ...)
+     (define name (stream-lambda formals body ...)))))
+
+(define-syntax stream
+  (syntax-rules ()
+    ((stream) stream-null)
+    ((stream x y ...) (stream-cons x (stream y ...)))))
+
+(define-syntax stream-let
+  (syntax-rules ()
+    ((stream-let tag ((name val) ...) body ...)
+     ((letrec ((tag (stream-lambda (name ...) body ...))) tag) val ...))))
+
+(define-stream (list->stream objs)
+  (if (null? objs)
+      stream-null
+      (stream-cons (car objs) (list->stream (cdr objs)))))
+
+(define (stream->list . args)
+  (let loop ((n (if (pair? (cdr args)) (car args) #f))
+             (strm (if (pair? (cdr args)) (cadr args) (car args)))
+             (acc '()))
+    (if (or (and n (<= n 0)) (stream-null? strm))
+        (reverse acc)
+        (loop (and n (- n 1)) (stream-cdr strm) (cons (stream-car strm) acc)))))
+
+(define-stream (%stream-append strms)
+  (cond
+    ((null? strms) stream-null)
+    ((stream-null? (car strms)) (%stream-append (cdr strms)))
+    (else (stream-cons (stream-car (car strms))
+                       (%stream-append (cons (stream-cdr (car strms)) (cdr strms)))))))
+
+(define (stream-append . strms) (%stream-append strms))
+
+(define-stream (stream-concat strms)
+  (cond
+    ((stream-null? strms) stream-null)
+    ((stream-null? (stream-car strms)) (stream-concat (stream-cdr strms)))
+    (else (stream-cons (stream-car (stream-car strms))
+                       (stream-concat (stream-cons (stream-cdr (stream-car strms))
+                                                   (stream-cdr strms)))))))
+
+(define-stream (%stream-cycle objs rest)
+  (if (null? rest)
+      (%stream-cycle objs objs)
+      (stream-cons (car rest) (%stream-cycle objs (cdr rest)))))
+
+(define (stream-constant . objs)
+  (if (null? objs) stream-null (%stream-cycle objs objs)))
+
+(define-stream (stream-drop n strm)
+  (if (or (<= n 0) (stream-null? strm))
+      strm
+      (stream-drop (- n 1) (stream-cdr strm))))
+
+(define-stream (stream-drop-while pred strm)
+  (if (and (stream-pair? strm) (pred (stream-car strm)))
+      (stream-drop-while pred (stream-cdr strm))
+      strm))
+
+(define-stream (stream-filter pred strm)
+  (cond
+    ((stream-null? strm) stream-null)
+    ((pred (stream-car strm))
+     (stream-cons (stream-car strm) (stream-filter pred (stream-cdr strm))))
+    (else (stream-filter pred (stream-cdr strm)))))
+
+(define (stream-fold proc base strm)
+  (if (stream-null? strm)
+      base
+      (stream-fold proc (proc base (stream-car strm)) (stream-cdr strm))))
+
+(define (stream-for-each proc . strms)
+  (let loop ((strms strms))
+    (unless (any? stream-null? strms)
+      (apply proc (map stream-car strms))
+      (loop (map stream-cdr strms)))))
+
+(define-stream (%stream-from first step)
+  (stream-cons first (%stream-from (+ first step) step)))
+
+(define (stream-from first . step)
+  (%stream-from first (if (pair? step) (car step) 1)))
+
+(define-stream (stream-iterate proc base)
+  (stream-cons base (stream-iterate proc (proc base))))
+
+(define (stream-length strm)
+  (let loop ((strm strm) (n 0))
+    (if (stream-null? strm) n (loop (stream-cdr strm) (+ n 1)))))
+
+(define-stream (%stream-map proc strms)
+  (if (any? stream-null? strms)
+      stream-null
+      (stream-cons (apply proc (map stream-car strms))
+                   (%stream-map proc (map stream-cdr strms)))))
+
+(define (stream-map proc . strms) (%stream-map proc strms))
+
+(define-stream (%stream-range first past step less?)
+  (if (less? first past)
+      (stream-cons first (%stream-range (+ first step) past step less?))
+      stream-null))
+
+(define (stream-range first past . step)
+  (let ((step (if (pair? step) (car step) (if (< first past) 1 -1))))
+    (%stream-range first past step (if (< 0 step) < >))))
+
+(define (stream-ref strm n)
+  (if (zero? n)
+      (stream-car strm)
+      (stream-ref (stream-cdr strm) (- n 1))))
+
+(define-stream (%stream-reverse strm rev)
+  (if (stream-null? strm)
+      rev
+      (%stream-reverse (stream-cdr strm) (stream-cons (stream-car strm) rev))))
+
+(define (stream-reverse strm) (%stream-reverse strm stream-null))
+
+(define-stream (stream-scan proc base strm)
+  (if (stream-null? strm)
+      (stream base)
+      (stream-cons base (stream-scan proc (proc base (stream-car strm)) (stream-cdr strm)))))
+
+(define-stream (stream-take n strm)
+  (if (or (<= n 0) (stream-null? strm))
+      stream-null
+      (stream-cons (stream-car strm) (stream-take (- n 1) (stream-cdr strm)))))
+
+(define-stream (stream-take-while pred strm)
+  (if (and (stream-pair? strm) (pred (stream-car strm)))
+      (stream-cons (stream-car strm) (stream-take-while pred (stream-cdr strm)))
+      stream-null))
+
+(define-stream (stream-unfold mapper pred generator base)
+  (if (pred base)
+      (stream-cons (mapper base) (stream-unfold mapper pred generator (generator base)))
+      stream-null))
+
+(define (stream-zip . strms) (apply stream-map list strms))
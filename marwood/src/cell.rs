@@ -19,6 +19,7 @@ pub enum Cell {
 
     // Types that exist in VCell, but need Cell representation for
     // printing purposes. These are never created by the lexer/parser.
+    CharSet,
     Continuation,
     Macro,
     Procedure(Option<String>),
@@ -74,7 +75,7 @@ impl Cell {
         for cell in iter {
             match tail {
                 Cell::Pair(_, next) => {
-                    *next = Box::new(Cell::Pair(Box::new(cell), Box::new(Cell::Nil)));
+                    **next = Cell::Pair(Box::new(cell), Box::new(Cell::Nil));
                     tail = &mut (**next);
                 }
                 _ => {
@@ -85,7 +86,7 @@ impl Cell {
 
         if last_cdr.is_some() {
             if let Cell::Pair(_, ref mut cdr) = *tail.deref_mut() {
-                *cdr = Box::new(last_cdr.take().unwrap());
+                **cdr = last_cdr.take().unwrap();
             }
         }
 
@@ -96,11 +97,11 @@ impl Cell {
         Cell::Pair(Box::new(car), Box::new(cdr))
     }
 
-    pub fn iter(&self) -> IntoIter {
+    pub fn iter(&self) -> IntoIter<'_> {
         IntoIter { next: self }
     }
 
-    pub fn iter_improper(&self) -> IntoIter {
+    pub fn iter_improper(&self) -> IntoIter<'_> {
         IntoIter { next: self }
     }
 
@@ -479,6 +480,9 @@ impl Display for Cell {
                 }
                 write!(f, ")")
             }
+            Cell::CharSet => {
+                write!(f, "#<char-set>")
+            }
             Cell::Continuation => {
                 write!(f, "#<continuation>")
             }
@@ -512,8 +516,7 @@ macro_rules! cell {
         Cell::from($elt)
     };
     ($($elt:expr),+) => {{
-        let mut v = vec![];
-        $(v.push(Cell::from($elt));)+
+        let v = vec![$(Cell::from($elt)),+];
         Cell::from(v)
     }};
 }
@@ -629,7 +632,7 @@ mod tests {
 
     #[test]
     fn improper_list() {
-        let improper_list = Cell::new_improper_list(vec![cell![1], cell![2]].into_iter(), cell![3]);
+        let improper_list = Cell::new_improper_list(vec![cell![1], cell![2]], cell![3]);
         assert!(!improper_list.is_list());
         assert!(improper_list.is_improper_list());
         assert_eq!(improper_list, cons!(cell!(1), cons!(cell!(2), cell!(3))));
@@ -659,7 +662,7 @@ mod tests {
 
     #[test]
     fn iter_improper() {
-        let improper_list = Cell::new_improper_list(vec![cell![1], cell![2]].into_iter(), cell![3]);
+        let improper_list = Cell::new_improper_list(vec![cell![1], cell![2]], cell![3]);
         assert_eq!(
             improper_list.iter_improper().collect::<Vec<&Cell>>(),
             vec![&cell![1], &cell![2], &cell![3]]
@@ -40,3 +40,63 @@ pub fn write_escaped_char(c: char, f: &mut Formatter<'_>) -> std::fmt::Result {
         },
     }
 }
+
+/// Simple Upcase
+///
+/// Return the single character uppercase mapping of c. If Unicode only
+/// defines a multi-character mapping (e.g. ß => SS), c is returned as is.
+pub fn simple_upcase(c: char) -> char {
+    single_char(c.to_uppercase()).unwrap_or(c)
+}
+
+/// Simple Downcase
+///
+/// Return the single character lowercase mapping of c, or c if the
+/// mapping is multi-character.
+pub fn simple_downcase(c: char) -> char {
+    single_char(c.to_lowercase()).unwrap_or(c)
+}
+
+/// Simple Foldcase
+///
+/// Return the single character case folding of c, falling back to its
+/// simple lowercase mapping if the full folding is multi-character.
+pub fn simple_foldcase(c: char) -> char {
+    single_char(fold_char(c)).unwrap_or_else(|| simple_downcase(c))
+}
+
+/// Foldcase
+///
+/// Apply full Unicode case folding to s, e.g. "Straße" folds to "strasse".
+pub fn foldcase(s: &str) -> String {
+    foldcase_chars(s.chars()).collect()
+}
+
+/// Foldcase Chars
+///
+/// Apply full Unicode case folding to each character of chars, without
+/// collecting them into a string.
+pub fn foldcase_chars(chars: impl Iterator<Item = char>) -> impl Iterator<Item = char> {
+    chars.flat_map(fold_char)
+}
+
+/// Fold Char
+///
+/// Full case folding is the lowercase mapping of the uppercase mapping,
+/// which folds ß to ss and final sigma to σ. Dotless i is the exception,
+/// as its uppercase mapping would otherwise fold it onto i.
+fn fold_char(c: char) -> impl Iterator<Item = char> {
+    let simple = c.is_ascii() || c == '\u{131}';
+    let full = (!simple).then(|| c.to_uppercase().flat_map(char::to_lowercase));
+    simple
+        .then(|| c.to_ascii_lowercase())
+        .into_iter()
+        .chain(full.into_iter().flatten())
+}
+
+fn single_char(mut it: impl Iterator<Item = char>) -> Option<char> {
+    match (it.next(), it.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
@@ -21,6 +21,12 @@ pub enum Error {
     #[error("invalid bytecode")]
     InvalidBytecode,
 
+    #[error("invalid bytecode image: {0}")]
+    InvalidImage(String),
+
+    #[error("builtin {0} is not registered")]
+    UnregisteredBuiltin(String),
+
     #[error("call of non-procedure: {0:#}")]
     InvalidProcedure(Cell),
 
@@ -51,6 +57,24 @@ pub enum Error {
     #[error("string index {0} out of range of 0..{}", .1 - 1)]
     InvalidStringIndex(usize, usize),
 
+    #[error("{0} limit of {1} exceeded")]
+    LimitExceeded(&'static str, usize),
+
+    #[error("uncaught exception: {0:#}")]
+    UncaughtException(Cell),
+
+    #[error("interrupted")]
+    Interrupted,
+
+    #[error("execution has finished")]
+    ExecutionFinished,
+
+    #[error("no continuation prompt tagged {0:#}")]
+    NoContinuationPrompt(Cell),
+
+    #[error("escape continuation invoked after its extent has ended")]
+    EscapeContinuationExpired,
+
     #[error("{0}")]
     Other(String),
 
@@ -61,6 +85,24 @@ pub enum Error {
     LexError(#[from] lex::Error),
 }
 
+impl Error {
+    /// Is Catchable
+    ///
+    /// Return true if the error may be handled by the scheme exception
+    /// system. Errors indicating that the bytecode or the state of the
+    /// Vm is invalid may not be.
+    pub fn is_catchable(&self) -> bool {
+        !matches!(
+            self,
+            Error::InvalidBytecode
+                | Error::InvalidImage(_)
+                | Error::UnregisteredBuiltin(_)
+                | Error::InvalidStackIndex(_)
+                | Error::Interrupted
+        )
+    }
+}
+
 impl From<String> for Error {
     fn from(value: String) -> Self {
         Self::Other(value)
@@ -4,5 +4,7 @@ pub mod error;
 pub mod lex;
 pub mod number;
 pub mod parse;
+pub mod prelude;
+pub mod source;
 pub mod syntax;
 pub mod vm;
@@ -1248,7 +1248,7 @@ mod tests {
     #[test]
     fn partial_ord() {
         assert!(Number::from(200) > Number::from(100));
-        assert!(!(Number::from(100) > Number::from(100)));
+        assert!(Number::from(100) <= Number::from(100));
         assert!(Number::from(100) >= Number::from(100));
         assert!(Number::from(200) >= Number::from(100));
 
@@ -1424,7 +1424,7 @@ mod tests {
         verify![|x, y| x / y,
             BigInt::from(100), 50 => Rational32::from_integer(2),
             BigInt::from(i32_oflow), 2 => i32_oflow as f64 / 2_f64,
-            BigInt::from(100), i32_oflow => 100 as f64 / i32_oflow as f64,
+            BigInt::from(100), i32_oflow => 100_f64 / i32_oflow as f64,
             BigInt::from(100), BigInt::from(50) => Rational32::from_integer(2),
             BigInt::from(i32_oflow), BigInt::from(100) => i32_oflow as f64 / 100_f64,
             BigInt::from(100), BigInt::from(i32_oflow) => 100_f64 / i32_oflow as f64,
@@ -6,6 +6,7 @@ use crate::number::{Exactness, Number};
 use crate::parse::Error::{
     ExpectedListTerminator, ExpectedVectorTerminator, Incomplete, UnexpectedToken, UnknownChar,
 };
+use crate::source::SourceMap;
 use crate::{lex, list};
 use std::iter::Peekable;
 
@@ -57,18 +58,37 @@ pub fn parse_text(text: &str) -> Result<(Cell, Option<&str>), Error> {
 pub fn parse<'a, T: Iterator<Item = &'a Token>>(
     text: &str,
     cur: &mut Peekable<T>,
+) -> Result<Cell, Error> {
+    parse_spanned(text, cur, &mut SourceMap::default())
+}
+
+/// Parse Spanned
+///
+/// Parse one expression from the token stream as parse does, recording
+/// the span of each list within it in spans.
+///
+/// # Arguments
+/// *`cur` - an iterator over the token stream. The parser will only
+///          advance the iterator enough to satisfy one expression.
+/// *`text` - the text backed by the token spans, which must be the text
+///           of the source spans was created for.
+/// *`spans` - the source map to record spans in.
+pub fn parse_spanned<'a, T: Iterator<Item = &'a Token>>(
+    text: &str,
+    cur: &mut Peekable<T>,
+    spans: &mut SourceMap,
 ) -> Result<Cell, Error> {
     let token = match cur.next() {
         Some(token) => token,
         None => return Err(Error::Incomplete),
     };
     match token.token_type {
-        TokenType::SingleQuote => Ok(list!["quote", parse(text, cur)?]),
-        TokenType::Quasiquote => Ok(list!["quasiquote", parse(text, cur)?]),
-        TokenType::Unquote => Ok(list!["unquote", parse(text, cur)?]),
+        TokenType::SingleQuote => Ok(list!["quote", parse_spanned(text, cur, spans)?]),
+        TokenType::Quasiquote => Ok(list!["quasiquote", parse_spanned(text, cur, spans)?]),
+        TokenType::Unquote => Ok(list!["unquote", parse_spanned(text, cur, spans)?]),
         TokenType::RightParen => Err(Error::UnexpectedToken(")".into())),
-        TokenType::LeftParen => parse_list(text, cur, token),
-        TokenType::HashParen => parse_vector(text, cur),
+        TokenType::LeftParen => parse_list(text, cur, token, spans),
+        TokenType::HashParen => parse_vector(text, cur, spans),
         TokenType::True => Ok(Cell::Bool(true)),
         TokenType::False => Ok(Cell::Bool(false)),
         TokenType::Char => parse_char(text, token),
@@ -97,34 +117,39 @@ pub fn parse<'a, T: Iterator<Item = &'a Token>>(
 ///          advance the iterator enough to satisfy one expression.
 /// *`text` - the text backed by the token spans.
 /// * `start_token` - The start of list token, used to match the end of
-///     list token.
+///   list token.
+/// * `spans` - the source map to record the span of the list in.
 fn parse_list<'a, T: Iterator<Item = &'a Token>>(
     text: &str,
     cur: &mut Peekable<T>,
     start_token: &Token,
+    spans: &mut SourceMap,
 ) -> Result<Cell, Error> {
     let mut list = vec![];
     loop {
         match cur.peek().ok_or(Error::Incomplete)?.token_type {
             TokenType::RightParen => {
-                let start_token = start_token.span(text).chars().next().unwrap();
-                let end_token = cur.next().unwrap().span(text).chars().next().unwrap();
-                if !match start_token {
-                    '(' => end_token == ')',
-                    '[' => end_token == ']',
-                    '{' => end_token == '}',
+                let end = cur.next().unwrap();
+                let start_char = start_token.span(text).chars().next().unwrap();
+                let end_char = end.span(text).chars().next().unwrap();
+                if !match start_char {
+                    '(' => end_char == ')',
+                    '[' => end_char == ']',
+                    '{' => end_char == '}',
                     _ => false,
                 } {
-                    return Err(ExpectedListTerminator(start_token, end_token));
+                    return Err(ExpectedListTerminator(start_char, end_char));
                 }
-                return Ok(Cell::new_list(list));
+                let list = Cell::new_list(list);
+                spans.record(&list, start_token.span.0, end.span.1);
+                return Ok(list);
             }
             TokenType::Dot => {
                 cur.next();
-                return parse_improper_list_tail(list, text, cur);
+                return parse_improper_list_tail(list, text, cur, start_token, spans);
             }
             _ => {
-                list.push(parse(text, cur)?);
+                list.push(parse_spanned(text, cur, spans)?);
             }
         }
     }
@@ -140,10 +165,14 @@ fn parse_list<'a, T: Iterator<Item = &'a Token>>(
 /// `text` - the text backed by the token spans.
 /// `cur` - a cursor pointing to the position in the token stream
 ///         immediately after the encountered '.'
+/// `start_token` - the start of list token
+/// `spans` - the source map to record the span of the list in.
 fn parse_improper_list_tail<'a, T: Iterator<Item = &'a Token>>(
     list: Vec<Cell>,
     text: &str,
     cur: &mut Peekable<T>,
+    start_token: &Token,
+    spans: &mut SourceMap,
 ) -> Result<Cell, Error> {
     // At least one value must be read before the dot
     if list.is_empty() {
@@ -153,12 +182,17 @@ fn parse_improper_list_tail<'a, T: Iterator<Item = &'a Token>>(
     // Exactly one value must be parsed after the dot
     let last_cdr = match cur.peek().ok_or(Error::Incomplete)?.token_type {
         TokenType::Dot | TokenType::RightParen => Err(Error::ExpectedOneTokenAfterDot),
-        _ => Ok(parse(text, cur)?),
+        _ => Ok(parse_spanned(text, cur, spans)?),
     }?;
 
     // The next token must be a ')'
-    match cur.next().ok_or(Error::Incomplete)?.token_type {
-        TokenType::RightParen => Ok(Cell::new_improper_list(list, last_cdr)),
+    let end = cur.next().ok_or(Error::Incomplete)?;
+    match end.token_type {
+        TokenType::RightParen => {
+            let list = Cell::new_improper_list(list, last_cdr);
+            spans.record(&list, start_token.span.0, end.span.1);
+            Ok(list)
+        }
         _ => Err(Error::ExpectedOneTokenAfterDot),
     }
 }
@@ -176,10 +210,11 @@ fn parse_improper_list_tail<'a, T: Iterator<Item = &'a Token>>(
 ///          advance the iterator enough to satisfy one expression.
 /// *`text` - the text backed by the token spans.
 /// * `start_token` - The start of list token, used to match the end of
-///     list token.
+///   list token.
 fn parse_vector<'a, T: Iterator<Item = &'a Token>>(
     text: &str,
     cur: &mut Peekable<T>,
+    spans: &mut SourceMap,
 ) -> Result<Cell, Error> {
     let mut vector = vec![];
     loop {
@@ -195,7 +230,7 @@ fn parse_vector<'a, T: Iterator<Item = &'a Token>>(
                 return Err(UnexpectedToken(".".into()));
             }
             _ => {
-                vector.push(parse(text, cur)?);
+                vector.push(parse_spanned(text, cur, spans)?);
             }
         }
     }
@@ -370,7 +405,7 @@ mod tests {
     macro_rules! fails {
         ($($lhs:expr),+) => {{
              $(
-                assert!(matches!(parse($lhs, &mut lex::scan($lhs).unwrap().iter().peekable()), Err(_)));
+                assert!(parse($lhs, &mut lex::scan($lhs).unwrap().iter().peekable()).is_err());
              )+
         }};
     }
@@ -400,7 +435,7 @@ mod tests {
     fn consumes_one_expression_per_call() {
         let text = "foo bar baz";
         let tokens = lex::scan(text).unwrap();
-        let mut cur = (&tokens).iter().peekable();
+        let mut cur = tokens.iter().peekable();
         assert_eq!(parse(text, &mut cur), Ok(cell!["foo"]));
         assert_eq!(parse(text, &mut cur), Ok(cell!["bar"]));
         assert_eq!(parse(text, &mut cur), Ok(cell!["baz"]));
@@ -411,7 +446,7 @@ mod tests {
     fn lists_are_fully_consumed() {
         let text = "(foo bar)";
         let tokens = lex::scan(text).unwrap();
-        let mut cur = (&tokens).iter().peekable();
+        let mut cur = tokens.iter().peekable();
         assert_eq!(parse(text, &mut cur), Ok(list!["foo", "bar"]));
         assert_eq!(parse(text, &mut cur), Err(Error::Incomplete));
     }
@@ -0,0 +1,5 @@
+/// Prelude Image
+///
+/// The prelude precompiled to a bytecode image by the build script, which
+/// compiles prelude.scm with this crate's own compiler.
+pub const PRELUDE_IMAGE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/prelude.mwc"));
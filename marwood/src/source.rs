@@ -0,0 +1,311 @@
+use crate::cell::Cell;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+/// Source
+///
+/// The name and text of parsed source, such as a file, kept so that
+/// spans within it may be reported with a line, column and excerpt.
+#[derive(Debug, Eq, PartialEq)]
+pub struct Source {
+    pub name: String,
+    pub text: String,
+}
+
+impl Source {
+    pub fn new(name: &str, text: &str) -> Rc<Source> {
+        Rc::new(Source {
+            name: name.into(),
+            text: text.into(),
+        })
+    }
+}
+
+/// Span
+///
+/// The (start, end) byte offsets of an expression within its source.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Span {
+    pub source: Rc<Source>,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// Line and Column
+    ///
+    /// Return the 1-based line and column of the start of the span.
+    pub fn line_col(&self) -> (usize, usize) {
+        let prefix = &self.source.text[..self.start];
+        let line = prefix.matches('\n').count() + 1;
+        let line_start = prefix.rfind('\n').map(|it| it + 1).unwrap_or(0);
+        (line, prefix[line_start..].chars().count() + 1)
+    }
+
+    /// Excerpt
+    ///
+    /// Return the line the span starts on, followed by a line of carets
+    /// underlining the span up to the end of that line:
+    ///
+    /// ```text
+    /// 3 | (car '())
+    ///   | ^^^^^^^^^
+    /// ```
+    pub fn excerpt(&self) -> String {
+        let text = &self.source.text;
+        let line_start = text[..self.start].rfind('\n').map(|it| it + 1).unwrap_or(0);
+        let line_end = text[self.start..]
+            .find('\n')
+            .map(|it| self.start + it)
+            .unwrap_or(text.len());
+        let line = &text[line_start..line_end];
+        let indent = text[line_start..self.start].chars().count();
+        let len = text[self.start..self.end.clamp(self.start, line_end)]
+            .chars()
+            .count()
+            .max(1);
+        let number = self.line_col().0.to_string();
+        format!(
+            "{} | {}\n{} | {}{}",
+            number,
+            line,
+            " ".repeat(number.len()),
+            " ".repeat(indent),
+            "^".repeat(len)
+        )
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let (line, col) = self.line_col();
+        write!(f, "{}:{}:{}", self.source.name, line, col)
+    }
+}
+
+/// Source Map
+///
+/// A side table of the spans of the lists parsed from a source, carried
+/// through macro expansion to the compiler. A list is identified by the
+/// address of its first cons cell's car, which doesn't move for as long as
+/// the list is alive. Once a list is dropped its address may be reused by
+/// another, so the spans of lists that don't outlive the map, such as
+/// macro expansions and transformed expressions, are forgotten as soon as
+/// those lists are dropped.
+///
+/// A source map without a source records nothing, as when parsing text
+/// that isn't going to be reported on.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    source: Option<Rc<Source>>,
+    spans: HashMap<usize, Span>,
+
+    /// The spans of the macro uses currently being expanded, innermost
+    /// last, which expanded lists without a span of their own inherit
+    expansions: Vec<Span>,
+
+    /// The keys given spans by insert, oldest first, which forget_since
+    /// removes once the lists they belong to are dropped
+    inserted: Vec<usize>,
+}
+
+impl SourceMap {
+    pub fn new(source: Rc<Source>) -> SourceMap {
+        SourceMap {
+            source: Some(source),
+            ..Default::default()
+        }
+    }
+
+    /// Record
+    ///
+    /// Record that the list cell was parsed from the (start, end) byte
+    /// offsets of the source.
+    pub fn record(&mut self, cell: &Cell, start: usize, end: usize) {
+        if let (Some(source), Some(key)) = (&self.source, key(cell)) {
+            let span = Span {
+                source: source.clone(),
+                start,
+                end,
+            };
+            self.spans.insert(key, span);
+        }
+    }
+
+    /// Get
+    ///
+    /// Return the span of the list cell, or the span of the innermost macro
+    /// use being expanded if cell has none of its own.
+    pub fn get(&self, cell: &Cell) -> Option<&Span> {
+        key(cell)
+            .and_then(|it| self.spans.get(&it))
+            .or_else(|| self.expansions.last())
+    }
+
+    /// Insert
+    ///
+    /// Record span as the span of the list cell, such as a list rebuilt
+    /// from one with a span.
+    pub fn insert(&mut self, cell: &Cell, span: Option<Span>) {
+        if let (Some(span), Some(key)) = (span, key(cell)) {
+            self.spans.insert(key, span);
+            self.inserted.push(key);
+        }
+    }
+
+    /// Mark
+    ///
+    /// Return a mark that may be passed to forget_since to forget the spans
+    /// inserted after it.
+    pub fn mark(&self) -> usize {
+        self.inserted.len()
+    }
+
+    /// Forget Since
+    ///
+    /// Forget the spans inserted since mark was taken, which are no longer
+    /// valid once the lists they were inserted for are dropped.
+    pub fn forget_since(&mut self, mark: usize) {
+        for key in self.inserted.drain(mark..) {
+            self.spans.remove(&key);
+        }
+    }
+
+    /// Enter Expansion
+    ///
+    /// Called before transforming the expansion of the macro use in expr,
+    /// returning what must be passed to exit_expansion once the expansion is
+    /// no longer needed, or None if expr has no span.
+    ///
+    /// Lists in the expansion that are equal to a list in the macro use,
+    /// such as the body of a let, are given that list's span. Other lists
+    /// in the expansion inherit the span of the macro use itself.
+    pub fn enter_expansion(&mut self, expr: &Cell, expansion: &Cell) -> Option<Vec<usize>> {
+        let span = self.get(expr).cloned()?;
+        let mut spanned = vec![];
+        self.spanned_lists(expr, &mut spanned);
+
+        let mut keys = vec![];
+        let mut lists = vec![];
+        lists_of(expansion, &mut lists);
+        for list in lists {
+            if let Some((_, span)) = spanned.iter().find(|(it, _)| *it == list) {
+                if let Some(key) = key(list) {
+                    self.spans.insert(key, span.clone());
+                    keys.push(key);
+                }
+            }
+        }
+        self.expansions.push(span);
+        Some(keys)
+    }
+
+    /// Exit Expansion
+    ///
+    /// Forget the spans given to an expansion by enter_expansion, which are
+    /// no longer valid once the expansion is dropped.
+    pub fn exit_expansion(&mut self, keys: Option<Vec<usize>>) {
+        if let Some(keys) = keys {
+            for key in keys {
+                self.spans.remove(&key);
+            }
+            self.expansions.pop();
+        }
+    }
+
+    /// Spanned Lists
+    ///
+    /// Collect each list within cell that has a span of its own.
+    fn spanned_lists<'a>(&self, cell: &'a Cell, spanned: &mut Vec<(&'a Cell, Span)>) {
+        let mut lists = vec![];
+        lists_of(cell, &mut lists);
+        for list in lists {
+            if let Some(span) = key(list).and_then(|it| self.spans.get(&it)) {
+                spanned.push((list, span.clone()));
+            }
+        }
+    }
+}
+
+/// Key
+///
+/// Return the address that identifies the list cell, or None if cell
+/// isn't a pair.
+fn key(cell: &Cell) -> Option<usize> {
+    match cell {
+        Cell::Pair(car, _) => Some(&**car as *const Cell as usize),
+        _ => None,
+    }
+}
+
+/// Lists Of
+///
+/// Collect cell, if it's a list, and each list nested in its elements.
+fn lists_of<'a>(cell: &'a Cell, lists: &mut Vec<&'a Cell>) {
+    let mut rest = cell;
+    if rest.is_pair() {
+        lists.push(cell);
+    }
+    while let Cell::Pair(car, cdr) = rest {
+        lists_of(car, lists);
+        rest = cdr;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lex;
+    use crate::parse::parse_spanned;
+
+    #[test]
+    fn line_col_and_excerpt() {
+        let text = "(define x 1)\n  (car '())";
+        let span = Span {
+            source: Source::new("test.scm", text),
+            start: 15,
+            end: 24,
+        };
+        assert_eq!(span.line_col(), (2, 3));
+        assert_eq!(span.to_string(), "test.scm:2:3");
+        assert_eq!(span.excerpt(), "2 |   (car '())\n  |   ^^^^^^^^^");
+    }
+
+    #[test]
+    fn parsed_lists_are_spanned() {
+        let text = "(f (g x)\n   (h))";
+        let mut spans = SourceMap::new(Source::new("test.scm", text));
+        let tokens = lex::scan(text).unwrap();
+        let cell = parse_spanned(text, &mut tokens.iter().peekable(), &mut spans).unwrap();
+
+        let g = cell.cdr().unwrap().car().unwrap();
+        let h = cell.cdr().unwrap().cdr().unwrap().car().unwrap();
+        assert_eq!(spans.get(&cell).map(|it| (it.start, it.end)), Some((0, 16)));
+        assert_eq!(spans.get(g).map(|it| (it.start, it.end)), Some((3, 8)));
+        assert_eq!(
+            spans.get(h).map(|it| it.to_string()),
+            Some("test.scm:2:4".into())
+        );
+
+        // A list with the same contents isn't the parsed list
+        assert_eq!(spans.get(&g.clone()), None);
+    }
+
+    #[test]
+    fn inserted_spans_are_forgotten() {
+        let text = "(f x)";
+        let mut spans = SourceMap::new(Source::new("test.scm", text));
+        let tokens = lex::scan(text).unwrap();
+        let cell = parse_spanned(text, &mut tokens.iter().peekable(), &mut spans).unwrap();
+
+        let mark = spans.mark();
+        let transformed = cell.clone();
+        spans.insert(&transformed, spans.get(&cell).cloned());
+        assert_eq!(spans.get(&transformed).map(|it| it.start), Some(0));
+
+        spans.forget_since(mark);
+        assert_eq!(spans.get(&transformed), None);
+        assert_eq!(spans.get(&cell).map(|it| it.start), Some(0));
+    }
+}
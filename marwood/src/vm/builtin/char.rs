@@ -1,3 +1,4 @@
+use crate::char::{simple_downcase, simple_foldcase, simple_upcase};
 use crate::error::Error;
 use crate::error::Error::InvalidSyntax;
 use crate::number::Number;
@@ -81,37 +82,19 @@ pub fn char_to_integer(vm: &mut Vm) -> Result<VCell, Error> {
 pub fn char_upcase(vm: &mut Vm) -> Result<VCell, Error> {
     pop_argc(vm, 1, Some(1), "char-upcase")?;
     let c = pop_char(vm)?;
-    if c.is_ascii() {
-        Ok(c.to_ascii_uppercase().into())
-    } else if c.to_uppercase().into_iter().count() == 1 {
-        Ok(c.to_uppercase().into_iter().next().unwrap().into())
-    } else {
-        Ok(c.into())
-    }
+    Ok(simple_upcase(c).into())
 }
 
 pub fn char_downcase(vm: &mut Vm) -> Result<VCell, Error> {
     pop_argc(vm, 1, Some(1), "char-downcase")?;
     let c = pop_char(vm)?;
-    if c.is_ascii() {
-        Ok(c.to_ascii_lowercase().into())
-    } else if c.to_lowercase().into_iter().count() == 1 {
-        Ok(c.to_lowercase().into_iter().next().unwrap().into())
-    } else {
-        Ok(c.into())
-    }
+    Ok(simple_downcase(c).into())
 }
 
 pub fn char_foldcase(vm: &mut Vm) -> Result<VCell, Error> {
     pop_argc(vm, 1, Some(1), "char-foldcase")?;
     let c = pop_char(vm)?;
-    if c.is_ascii() {
-        Ok(c.to_ascii_lowercase().into())
-    } else if c.to_lowercase().into_iter().count() == 1 {
-        Ok(c.to_lowercase().into_iter().next().unwrap().into())
-    } else {
-        Ok(c.into())
-    }
+    Ok(simple_foldcase(c).into())
 }
 
 pub fn digit_value(vm: &mut Vm) -> Result<VCell, Error> {
@@ -145,30 +128,32 @@ pub fn char_gt_eq(vm: &mut Vm) -> Result<VCell, Error> {
 }
 
 pub fn char_ci_eq(vm: &mut Vm) -> Result<VCell, Error> {
-    char_comp(vm, "char-ci=?", |x, y| x.eq_ignore_ascii_case(y))
+    char_comp(vm, "char-ci=?", |x, y| {
+        simple_foldcase(*x) == simple_foldcase(*y)
+    })
 }
 
 pub fn char_ci_lt(vm: &mut Vm) -> Result<VCell, Error> {
     char_comp(vm, "char-ci<?", |x, y| {
-        x.to_ascii_lowercase() < y.to_ascii_lowercase()
+        simple_foldcase(*x) < simple_foldcase(*y)
     })
 }
 
 pub fn char_ci_lt_eq(vm: &mut Vm) -> Result<VCell, Error> {
     char_comp(vm, "char-ci<=?", |x, y| {
-        x.to_ascii_lowercase() <= y.to_ascii_lowercase()
+        simple_foldcase(*x) <= simple_foldcase(*y)
     })
 }
 
 pub fn char_ci_gt(vm: &mut Vm) -> Result<VCell, Error> {
     char_comp(vm, "char-ci>?", |x, y| {
-        x.to_ascii_lowercase() > y.to_ascii_lowercase()
+        simple_foldcase(*x) > simple_foldcase(*y)
     })
 }
 
 pub fn char_ci_gt_eq(vm: &mut Vm) -> Result<VCell, Error> {
     char_comp(vm, "char-ci>=?", |x, y| {
-        x.to_ascii_lowercase() >= y.to_ascii_lowercase()
+        simple_foldcase(*x) >= simple_foldcase(*y)
     })
 }
 
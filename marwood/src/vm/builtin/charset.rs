@@ -0,0 +1,138 @@
+use crate::error::Error;
+use crate::error::Error::InvalidSyntax;
+use crate::number::Number;
+use crate::vm::builtin::{pop_argc, pop_char, pop_char_set, pop_string};
+use crate::vm::charset;
+use crate::vm::charset::CharSet;
+use crate::vm::vcell::VCell;
+use crate::vm::Vm;
+
+pub fn load_builtins(vm: &mut Vm) {
+    vm.load_builtin("char-set", char_set);
+    vm.load_builtin("char-set?", is_char_set);
+    vm.load_builtin("char-set-complement", char_set_complement);
+    vm.load_builtin("char-set-contains?", char_set_contains);
+    vm.load_builtin("char-set-difference", char_set_difference);
+    vm.load_builtin("char-set-intersection", char_set_intersection);
+    vm.load_builtin("char-set-size", char_set_size);
+    vm.load_builtin("char-set-union", char_set_union);
+    vm.load_builtin("char-set->list", char_set_list);
+    vm.load_builtin("list->char-set", list_char_set);
+    vm.load_builtin("string->char-set", string_char_set);
+
+    vm.load_global("char-set:ascii", charset::ASCII.clone().into());
+    vm.load_global("char-set:digit", charset::DIGIT.clone().into());
+    vm.load_global("char-set:empty", charset::EMPTY.clone().into());
+    vm.load_global("char-set:full", charset::FULL.clone().into());
+    vm.load_global("char-set:letter", charset::LETTER.clone().into());
+    vm.load_global(
+        "char-set:letter+digit",
+        charset::LETTER_DIGIT.clone().into(),
+    );
+    vm.load_global("char-set:lower-case", charset::LOWER_CASE.clone().into());
+    vm.load_global("char-set:upper-case", charset::UPPER_CASE.clone().into());
+    vm.load_global("char-set:whitespace", charset::WHITESPACE.clone().into());
+}
+
+pub fn char_set(vm: &mut Vm) -> Result<VCell, Error> {
+    let argc = pop_argc(vm, 0, None, "char-set")?;
+    let mut chars = Vec::with_capacity(argc);
+    for _ in 0..argc {
+        chars.push(pop_char(vm)?);
+    }
+    Ok(chars.into_iter().collect::<CharSet>().into())
+}
+
+pub fn is_char_set(vm: &mut Vm) -> Result<VCell, Error> {
+    pop_argc(vm, 1, Some(1), "char-set?")?;
+    let result = vm.heap.get(vm.stack.pop()?);
+    Ok(matches!(result, VCell::CharSet(_)).into())
+}
+
+pub fn char_set_contains(vm: &mut Vm) -> Result<VCell, Error> {
+    pop_argc(vm, 2, Some(2), "char-set-contains?")?;
+    let c = pop_char(vm)?;
+    let cs = pop_char_set(vm, "char-set-contains?")?;
+    Ok(cs.contains(c).into())
+}
+
+pub fn char_set_size(vm: &mut Vm) -> Result<VCell, Error> {
+    pop_argc(vm, 1, Some(1), "char-set-size")?;
+    let cs = pop_char_set(vm, "char-set-size")?;
+    Ok(Number::from(cs.size() as u64).into())
+}
+
+pub fn char_set_complement(vm: &mut Vm) -> Result<VCell, Error> {
+    pop_argc(vm, 1, Some(1), "char-set-complement")?;
+    let cs = pop_char_set(vm, "char-set-complement")?;
+    Ok(cs.complement().into())
+}
+
+pub fn char_set_union(vm: &mut Vm) -> Result<VCell, Error> {
+    let argc = pop_argc(vm, 0, None, "char-set-union")?;
+    let mut result = CharSet::default();
+    for _ in 0..argc {
+        result = result.union(&*pop_char_set(vm, "char-set-union")?);
+    }
+    Ok(result.into())
+}
+
+pub fn char_set_intersection(vm: &mut Vm) -> Result<VCell, Error> {
+    let argc = pop_argc(vm, 1, None, "char-set-intersection")?;
+    let mut result = charset::FULL.clone();
+    for _ in 0..argc {
+        result = result.intersection(&*pop_char_set(vm, "char-set-intersection")?);
+    }
+    Ok(result.into())
+}
+
+pub fn char_set_difference(vm: &mut Vm) -> Result<VCell, Error> {
+    let argc = pop_argc(vm, 1, None, "char-set-difference")?;
+    let mut subtrahend = CharSet::default();
+    for _ in 0..argc - 1 {
+        subtrahend = subtrahend.union(&*pop_char_set(vm, "char-set-difference")?);
+    }
+    let cs = pop_char_set(vm, "char-set-difference")?;
+    Ok(cs.difference(&subtrahend).into())
+}
+
+pub fn char_set_list(vm: &mut Vm) -> Result<VCell, Error> {
+    pop_argc(vm, 1, Some(1), "char-set->list")?;
+    let cs = pop_char_set(vm, "char-set->list")?;
+    let chars = cs.chars().collect::<Vec<_>>();
+    vm.reserve_heap(2 * chars.len() + 1)?;
+    let mut list = vm.heap.put(VCell::nil());
+    for c in chars.into_iter().rev() {
+        let c = vm.heap.put(VCell::from(c));
+        list = vm.heap.put(VCell::pair(c.as_ptr()?, list.as_ptr()?));
+    }
+    Ok(list)
+}
+
+pub fn list_char_set(vm: &mut Vm) -> Result<VCell, Error> {
+    pop_argc(vm, 1, Some(1), "list->char-set")?;
+    let mut rest = vm.heap.get(vm.stack.pop()?);
+    if !rest.is_pair() && !rest.is_nil() {
+        return Err(Error::ExpectedPairButFound(vm.heap.get_as_cell(&rest)));
+    }
+    let mut chars = vec![];
+    while rest.is_pair() {
+        match vm.heap.get(&rest.as_car()?) {
+            VCell::Char(c) => chars.push(c),
+            vcell => {
+                return Err(InvalidSyntax(format!(
+                    "list->char-set expected char but found {:#}",
+                    vm.heap.get_as_cell(&vcell)
+                )))
+            }
+        }
+        rest = vm.heap.get(&rest.as_cdr()?);
+    }
+    Ok(chars.into_iter().collect::<CharSet>().into())
+}
+
+pub fn string_char_set(vm: &mut Vm) -> Result<VCell, Error> {
+    pop_argc(vm, 1, Some(1), "string->char-set")?;
+    let s = pop_string(vm, "string->char-set")?;
+    Ok(s.to_chars().into_iter().collect::<CharSet>().into())
+}
@@ -0,0 +1,48 @@
+use crate::cell::Cell;
+use crate::error::Error;
+use crate::number::Number;
+use crate::vm::builtin::pop_argc;
+use crate::vm::vcell::VCell;
+use crate::vm::Vm;
+use std::time::Duration;
+
+pub fn load_builtins(vm: &mut Vm) {
+    vm.load_builtin("gc", gc);
+    vm.load_builtin("gc-stats", gc_stats);
+}
+
+fn gc(vm: &mut Vm) -> Result<VCell, Error> {
+    pop_argc(vm, 0, Some(0), "gc")?;
+    vm.collect_garbage();
+    Ok(VCell::Void)
+}
+
+/// GC Stats
+///
+/// Return the Vm's GcStats as an association list from symbols to integers.
+/// Pause durations are in microseconds.
+fn gc_stats(vm: &mut Vm) -> Result<VCell, Error> {
+    pop_argc(vm, 0, Some(0), "gc-stats")?;
+    let stats = vm.gc_stats();
+    let micros = |it: Duration| it.as_micros() as u64;
+    let alist = Cell::new_list(
+        [
+            ("collections", stats.collections as u64),
+            ("increments", stats.increments as u64),
+            ("total-pause", micros(stats.total_pause)),
+            ("max-pause", micros(stats.max_pause)),
+            ("last-pause", micros(stats.last_pause)),
+            ("cells-reclaimed", stats.cells_reclaimed as u64),
+            ("bytes-reclaimed", stats.bytes_reclaimed as u64),
+            ("heap-growths", stats.heap_growths as u64),
+            ("heap-size", stats.heap_size as u64),
+            ("used", stats.used as u64),
+            ("peak-used", stats.peak_used as u64),
+        ]
+        .into_iter()
+        .map(|(key, value)| {
+            Cell::new_pair(Cell::new_symbol(key), Cell::Number(Number::from(value)))
+        }),
+    );
+    Ok(vm.heap.put_cell(&alist))
+}
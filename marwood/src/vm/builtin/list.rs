@@ -49,7 +49,7 @@ pub fn set_car(vm: &mut Vm) -> Result<VCell, Error> {
             return Err(InvalidSyntax("set-car! expected a pair".into()));
         }
     };
-    *vm.heap.get_at_index_mut(pair.as_ptr()?) = new_pair;
+    vm.heap.put_at_index(pair.as_ptr()?, new_pair);
     Ok(VCell::Void)
 }
 
@@ -63,7 +63,7 @@ pub fn set_cdr(vm: &mut Vm) -> Result<VCell, Error> {
             return Err(InvalidSyntax("set-cdr! expected a pair".into()));
         }
     };
-    *vm.heap.get_at_index_mut(pair.as_ptr()?) = new_pair;
+    vm.heap.put_at_index(pair.as_ptr()?, new_pair);
     Ok(VCell::Void)
 }
 
@@ -95,8 +95,10 @@ fn clone_list(vm: &mut Vm, list: VCell) -> Result<(VCell, VCell), Error> {
             tail = pair.clone();
         } else {
             let last_pair = vm.heap.get(&tail);
-            *vm.heap.get_at_index_mut(tail.as_ptr()?) =
-                VCell::Pair(last_pair.as_car()?.as_ptr()?, pair.as_ptr()?);
+            vm.heap.put_at_index(
+                tail.as_ptr()?,
+                VCell::Pair(last_pair.as_car()?.as_ptr()?, pair.as_ptr()?),
+            );
             tail = pair;
         }
         rest = vm.heap.get(&rest.as_cdr()?);
@@ -113,14 +115,33 @@ fn clone_list(vm: &mut Vm, list: VCell) -> Result<(VCell, VCell), Error> {
     }
 }
 
+/// Count Pairs
+///
+/// Return the number of pairs in the chain starting at list, which is the
+/// number of vcells needed to copy it.
+fn count_pairs(vm: &Vm, list: &VCell) -> usize {
+    let mut count = 0;
+    let mut rest = vm.heap.get(list);
+    while let VCell::Pair(_, cdr) = rest {
+        count += 1;
+        rest = vm.heap.get_at_index(cdr).clone();
+    }
+    count
+}
+
 pub fn append(vm: &mut Vm) -> Result<VCell, Error> {
     let argc = pop_argc(vm, 0, None, "append")?;
     if argc == 0 {
         return Ok(VCell::Nil);
     }
+    let mut cells = argc;
+    for offset in 1..argc {
+        cells += count_pairs(vm, vm.stack.get_offset(-(offset as i64))?);
+    }
+    vm.reserve_heap(cells)?;
     let mut tail = vm.heap.put(vm.stack.pop()?.clone());
     for _ in 0..(argc - 1) {
-        let list = vm.heap.get(&vm.stack.pop()?.clone());
+        let list = vm.heap.get(vm.stack.pop()?.clone());
         match list {
             VCell::Nil => {
                 continue;
@@ -132,8 +153,10 @@ pub fn append(vm: &mut Vm) -> Result<VCell, Error> {
         }
         let (head, sub_tail) = clone_list(vm, list)?;
         let sub_pair = vm.heap.get(&sub_tail);
-        *vm.heap.get_at_index_mut(sub_tail.as_ptr()?) =
-            VCell::Pair(sub_pair.as_car()?.as_ptr()?, tail.as_ptr()?);
+        vm.heap.put_at_index(
+            sub_tail.as_ptr()?,
+            VCell::Pair(sub_pair.as_car()?.as_ptr()?, tail.as_ptr()?),
+        );
         tail = head;
     }
 
@@ -151,6 +174,7 @@ pub fn reverse(vm: &mut Vm) -> Result<VCell, Error> {
             Err(ExpectedPairButFound(vm.heap.get_as_cell(&rest)))
         };
     }
+    vm.reserve_heap(count_pairs(vm, &list) + 1)?;
     let mut tail = vm.heap.put(VCell::Nil);
     loop {
         tail = vm
@@ -1,13 +1,16 @@
 use crate::error::Error;
 use crate::error::Error::{InvalidNumArgs, InvalidSyntax};
 use crate::number::Number;
+use crate::vm::charset::CharSet;
+use crate::vm::string::SchemeString;
 use crate::vm::vcell::VCell;
 use crate::vm::vector::Vector;
 use crate::vm::Vm;
-use std::cell::RefCell;
 use std::rc::Rc;
 
 mod char;
+mod charset;
+mod gc;
 mod list;
 mod number;
 mod ports;
@@ -16,6 +19,7 @@ mod procedure;
 mod rand;
 mod string;
 mod symbol;
+mod thread;
 mod vector;
 
 /// Built Ins
@@ -35,10 +39,11 @@ mod vector;
 /// is argc, and immediately preceding argc are the arguments being applied.
 /// All builtins must pop the argc value along with argc* arguments off
 /// the stack before returning.
-
 impl Vm {
     pub fn load_builtins(&mut self) {
         char::load_builtins(self);
+        charset::load_builtins(self);
+        gc::load_builtins(self);
         list::load_builtins(self);
         number::load_builtins(self);
         ports::load_builtins(self);
@@ -47,6 +52,7 @@ impl Vm {
         rand::load_builtins(self);
         string::load_builtins(self);
         symbol::load_builtins(self);
+        thread::load_builtins(self);
         vector::load_builtins(self);
     }
 
@@ -55,10 +61,18 @@ impl Vm {
         symbol: &'static str,
         func: fn(&mut Vm) -> Result<VCell, Error>,
     ) {
-        let syscall = self.heap.put(VCell::builtin(symbol, func));
+        self.load_global(symbol, VCell::builtin(symbol, func));
+    }
+
+    /// Load Global
+    ///
+    /// Bind symbol to value in the global environment. This is used for
+    /// library values that aren't procedures, such as char-set:letter.
+    pub fn load_global(&mut self, symbol: &'static str, value: VCell) {
+        let value = self.heap.put(value);
         let symbol = self.heap.put(VCell::symbol(symbol));
         let slot = self.globenv.get_binding(symbol.as_ptr().unwrap());
-        self.globenv.put_slot(slot, syscall);
+        self.globenv.put_slot(slot, value);
     }
 }
 
@@ -75,10 +89,7 @@ fn pop_argc(vm: &mut Vm, min: usize, max: Option<usize>, proc: &str) -> Result<u
     }
 }
 
-///
-/// Numerical Procedures
-///
-///
+// Numerical Procedures
 
 fn pop_number(vm: &mut Vm) -> Result<Number, Error> {
     match vm.heap.get(vm.stack.pop()?) {
@@ -118,7 +129,7 @@ fn pop_char(vm: &mut Vm) -> Result<char, Error> {
     }
 }
 
-fn pop_string(vm: &mut Vm, proc: &str) -> Result<Rc<RefCell<String>>, Error> {
+fn pop_string(vm: &mut Vm, proc: &str) -> Result<Rc<SchemeString>, Error> {
     match vm.heap.get(vm.stack.pop()?) {
         VCell::String(s) => Ok(s),
         vcell => Err(InvalidSyntax(format!(
@@ -129,6 +140,17 @@ fn pop_string(vm: &mut Vm, proc: &str) -> Result<Rc<RefCell<String>>, Error> {
     }
 }
 
+fn pop_char_set(vm: &mut Vm, proc: &str) -> Result<Rc<CharSet>, Error> {
+    match vm.heap.get(vm.stack.pop()?) {
+        VCell::CharSet(cs) => Ok(cs),
+        vcell => Err(InvalidSyntax(format!(
+            "bad argument to {}: {:#} is not a char-set",
+            proc,
+            vm.heap.get_as_cell(&vcell)
+        ))),
+    }
+}
+
 fn pop_symbol(vm: &mut Vm, proc: &str) -> Result<Rc<String>, Error> {
     match vm.heap.get(vm.stack.pop()?) {
         VCell::Symbol(s) => Ok(s),
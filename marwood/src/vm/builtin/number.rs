@@ -488,7 +488,7 @@ fn string_number(vm: &mut Vm) -> Result<VCell, Error> {
         _ => 10_u32,
     };
     let s = pop_string(vm, "string->number")?;
-    let s = s.borrow();
+    let s = s.to_string();
     let s = s.as_str();
     match Number::parse_with_exactness(s, Exactness::Unspecified, radix) {
         Some(num) => Ok(VCell::Number(num)),
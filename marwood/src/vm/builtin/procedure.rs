@@ -1,6 +1,8 @@
 use crate::cell::Cell;
 use crate::error::Error;
-use crate::error::Error::{ErrorSignal, InvalidSyntax};
+use crate::error::Error::{
+    ErrorSignal, EscapeContinuationExpired, InvalidSyntax, NoContinuationPrompt, UncaughtException,
+};
 use crate::vm::builtin::pop_argc;
 use crate::vm::lambda::Lambda;
 use crate::vm::opcode::OpCode;
@@ -14,7 +16,17 @@ pub fn load_builtins(vm: &mut Vm) {
     vm.load_builtin("apply", apply);
     vm.load_builtin("call/cc", call_cc);
     vm.load_builtin("call-with-current-continuation", call_cc);
+    let call_with_prompt = call_with_prompt(vm);
+    vm.load_global("%call-with-prompt", call_with_prompt);
+    vm.load_builtin(
+        "%call-with-composable-continuation",
+        call_with_composable_continuation,
+    );
+    vm.load_builtin("%abort-current-continuation", abort_current_continuation);
+    vm.load_builtin("%escape", escape);
+    vm.load_builtin("break", debug_break);
     vm.load_builtin("error", error);
+    vm.load_builtin("%raise", raise);
     vm.load_builtin("eval", eval);
 }
 
@@ -28,6 +40,16 @@ fn error(vm: &mut Vm) -> Result<VCell, Error> {
     Err(ErrorSignal(result))
 }
 
+/// Raise
+///
+/// Raise an object that no exception handler was installed to handle,
+/// which ends evaluation with an error.
+fn raise(vm: &mut Vm) -> Result<VCell, Error> {
+    pop_argc(vm, 1, Some(1), "raise")?;
+    let obj = vm.stack.pop()?.clone();
+    Err(UncaughtException(vm.heap.get_as_cell(&obj)))
+}
+
 /// Eval
 ///
 /// Eval pops the expr off the stack to eval, converts ot an AST
@@ -52,7 +74,7 @@ fn eval(vm: &mut Vm) -> Result<VCell, Error> {
     lambda.emit(OpCode::Ret);
     let lambda = vm.heap.put(lambda);
 
-    vm.stack.push(ArgumentCount(0));
+    vm.stack.push(ArgumentCount(0))?;
     vm.ip.1 -= 1;
     Ok(lambda)
 }
@@ -91,7 +113,7 @@ fn apply(vm: &mut Vm) -> Result<VCell, Error> {
     let mut argc = argc - 2;
     while rest.is_pair() {
         argc += 1;
-        vm.stack.push(rest.as_car()?);
+        vm.stack.push(rest.as_car()?)?;
         rest = vm.heap.get(&rest.as_cdr()?);
     }
     if !rest.is_nil() {
@@ -99,7 +121,7 @@ fn apply(vm: &mut Vm) -> Result<VCell, Error> {
             "the last argument to apply must be a proper list".into(),
         ));
     }
-    vm.stack.push(VCell::ArgumentCount(argc));
+    vm.stack.push(VCell::ArgumentCount(argc))?;
     vm.ip.1 -= 1;
     Ok(proc)
 }
@@ -127,8 +149,113 @@ fn call_cc(vm: &mut Vm) -> Result<VCell, Error> {
     let cont = Rc::new(vm.to_continuation());
     trace!("cont: {:?}", cont);
     let cont = vm.heap.put(VCell::Continuation(cont));
-    vm.stack.push(cont);
-    vm.stack.push(ArgumentCount(1));
+    vm.stack.push(cont)?;
+    vm.stack.push(ArgumentCount(1))?;
     vm.ip.1 -= 1;
     Ok(proc)
 }
+
+/// %call-with-prompt
+///
+/// Return the procedure that calls thunk with a prompt tagged tag
+/// installed, whose abort handler is handler. It's assembled rather than
+/// compiled, so that its prompt frame is pushed just below the call to
+/// thunk:
+///
+/// ```text
+/// ENTER
+/// PROMPT   abort
+/// PUSH     %argc[0]
+/// MOV      [%bp[-2]]    %acc
+/// abort:
+/// CALL     %acc
+/// RET
+/// ```
+///
+/// Aborting to the prompt resumes at abort with the handler in %acc.
+fn call_with_prompt(vm: &mut Vm) -> VCell {
+    let formals = ["thunk", "tag", "handler"];
+    let mut lambda = Lambda::new(
+        formals
+            .iter()
+            .map(|it| vm.heap.put(VCell::symbol(*it)))
+            .collect(),
+    );
+    lambda.set_desc(Cell::new_list(formals.map(Cell::new_symbol)));
+    lambda.emit(OpCode::Enter);
+    lambda.emit(OpCode::Prompt);
+    let abort = lambda.emit_jump_placeholder();
+    lambda.emit(OpCode::PushImmediate);
+    lambda.emit(ArgumentCount(0));
+    lambda.emit(OpCode::Mov);
+    lambda.emit(VCell::BasePointerOffset(-2));
+    lambda.emit(VCell::Acc);
+    lambda.patch_jump(abort, lambda.bc.len());
+    lambda.emit(OpCode::CallAcc);
+    lambda.emit(OpCode::Ret);
+    VCell::lambda(lambda)
+}
+
+/// %call-with-composable-continuation
+///
+/// Like call/cc, apply proc to the continuation up to the nearest prompt
+/// tagged tag, which is delimited by the prompt.
+fn call_with_composable_continuation(vm: &mut Vm) -> Result<VCell, Error> {
+    pop_argc(vm, 2, Some(2), "call-with-composable-continuation")?;
+    let tag = vm.stack.pop()?.clone();
+    let proc = match vm.stack.pop()?.clone() {
+        proc if vm.heap.get(&proc).is_procedure() => proc,
+        _ => {
+            return Err(InvalidSyntax(
+                "bad call-with-composable-continuation".into(),
+            ));
+        }
+    };
+    let cont = Rc::new(vm.to_delimited_continuation(&tag)?);
+    let cont = vm.heap.put(VCell::Continuation(cont));
+    vm.stack.push(cont)?;
+    vm.stack.push(ArgumentCount(1))?;
+    vm.ip.1 -= 1;
+    Ok(proc)
+}
+
+/// %abort-current-continuation
+///
+/// Discard the continuation up to the nearest prompt tagged tag, and apply
+/// the prompt's handler to the remaining arguments in its place.
+fn abort_current_continuation(vm: &mut Vm) -> Result<VCell, Error> {
+    let argc = pop_argc(vm, 1, None, "abort-current-continuation")?;
+    let mut values = vec![VCell::Undefined; argc - 1];
+    for it in values.iter_mut().rev() {
+        *it = vm.stack.pop()?.clone();
+    }
+    let tag = vm.stack.pop()?.clone();
+    vm.abort_to_prompt(&tag, &values)
+}
+
+/// %escape
+///
+/// Invoke the escape continuation whose prompt is tagged tag, unwinding
+/// the stack to the prompt's frame and returning value from it. The prompt
+/// is gone once the escape continuation's extent has ended.
+fn escape(vm: &mut Vm) -> Result<VCell, Error> {
+    pop_argc(vm, 2, Some(2), "escape continuation")?;
+    let value = vm.stack.pop()?.clone();
+    let tag = vm.stack.pop()?.clone();
+    match vm.abort_to_prompt(&tag, &[value]) {
+        Err(NoContinuationPrompt(_)) => Err(EscapeContinuationExpired),
+        result => result,
+    }
+}
+
+/// break
+///
+/// Pause the program once break returns, if it's running under the
+/// debugger. Otherwise break does nothing.
+fn debug_break(vm: &mut Vm) -> Result<VCell, Error> {
+    pop_argc(vm, 0, Some(0), "break")?;
+    if vm.debugger.attached {
+        vm.debugger.break_requested = true;
+    }
+    Ok(VCell::Void)
+}
@@ -1,10 +1,13 @@
+use crate::char::foldcase;
 use crate::error::Error;
 use crate::error::Error::{InvalidStringIndex, InvalidSyntax};
 use crate::number::Number;
 use crate::vm::builtin::{pop_argc, pop_char, pop_index, pop_string, pop_usize, pop_vector};
+use crate::vm::string::SchemeString;
 use crate::vm::vcell::VCell;
 use crate::vm::Vm;
-use std::ops::DerefMut;
+use std::rc::Rc;
+use unicode_normalization::UnicodeNormalization;
 
 pub fn load_builtins(vm: &mut Vm) {
     vm.load_builtin("make-string", make_string);
@@ -27,83 +30,112 @@ pub fn load_builtins(vm: &mut Vm) {
     vm.load_builtin("string-ref", string_ref);
     vm.load_builtin("string-set!", string_set);
     vm.load_builtin("string-upcase", string_upcase);
+    vm.load_builtin("string-normalize-nfc", string_normalize_nfc);
+    vm.load_builtin("string-normalize-nfd", string_normalize_nfd);
+    vm.load_builtin("string-normalize-nfkc", string_normalize_nfkc);
+    vm.load_builtin("string-normalize-nfkd", string_normalize_nfkd);
     vm.load_builtin("string->list", string_list);
     vm.load_builtin("string->vector", string_vector);
     vm.load_builtin("vector->string", vector_string);
     vm.load_builtin("list->string", list_string);
     vm.load_builtin("string-copy", string_copy);
+    vm.load_builtin("string-copy!", string_mut_copy);
+    vm.load_builtin("string-contains", string_contains);
+    vm.load_builtin("string-join", string_join);
+    vm.load_builtin("string-pad", string_pad);
+    vm.load_builtin("string-pad-right", string_pad_right);
+    vm.load_builtin("string-prefix?", string_prefix);
+    vm.load_builtin("string-reverse", string_reverse);
+    vm.load_builtin("string-search-forward", string_search_forward);
+    vm.load_builtin("string-suffix?", string_suffix);
 }
 
 pub fn string_append(vm: &mut Vm) -> Result<VCell, Error> {
     let argc = pop_argc(vm, 1, None, "string-append")?;
-    let mut output = String::new();
+    let mut strings = vec![];
     for _ in 0..argc {
-        let s = pop_string(vm, "string-append")?;
-        output.insert_str(0, s.borrow().as_str());
+        strings.push(pop_string(vm, "string-append")?);
     }
+    vm.check_string_length(strings.iter().map(|it| it.len()).sum())?;
+    let output = strings
+        .iter()
+        .rev()
+        .flat_map(|s| s.to_chars())
+        .collect::<Vec<_>>();
     Ok(VCell::string(output))
 }
 
 pub fn string_length(vm: &mut Vm) -> Result<VCell, Error> {
     pop_argc(vm, 1, Some(1), "string-length")?;
     let s = pop_string(vm, "string-length")?;
-    let s = s.borrow();
-    Ok(Number::from(s.chars().count() as u64).into())
+    Ok(Number::from(s.len() as u64).into())
 }
 
 pub fn string_downcase(vm: &mut Vm) -> Result<VCell, Error> {
     pop_argc(vm, 1, Some(1), "string-downcase")?;
     let s = pop_string(vm, "string-downcase")?;
-    let s = s.borrow().to_lowercase();
+    let s = s.to_string().to_lowercase();
     Ok(VCell::string(s))
 }
 
 pub fn string_upcase(vm: &mut Vm) -> Result<VCell, Error> {
     pop_argc(vm, 1, Some(1), "string-upcase")?;
     let s = pop_string(vm, "string-upcase")?;
-    let s = s.borrow().to_uppercase();
+    let s = s.to_string().to_uppercase();
     Ok(VCell::string(s))
 }
 
 pub fn string_foldcase(vm: &mut Vm) -> Result<VCell, Error> {
     pop_argc(vm, 1, Some(1), "string-foldcase")?;
     let s = pop_string(vm, "string-foldcase")?;
-    let s = s.borrow().to_lowercase();
+    let s = foldcase(&s.to_string());
     Ok(VCell::string(s))
 }
 
+pub fn string_normalize_nfc(vm: &mut Vm) -> Result<VCell, Error> {
+    pop_argc(vm, 1, Some(1), "string-normalize-nfc")?;
+    let s = pop_string(vm, "string-normalize-nfc")?;
+    Ok(VCell::string(s.to_string().nfc().collect::<String>()))
+}
+
+pub fn string_normalize_nfd(vm: &mut Vm) -> Result<VCell, Error> {
+    pop_argc(vm, 1, Some(1), "string-normalize-nfd")?;
+    let s = pop_string(vm, "string-normalize-nfd")?;
+    Ok(VCell::string(s.to_string().nfd().collect::<String>()))
+}
+
+pub fn string_normalize_nfkc(vm: &mut Vm) -> Result<VCell, Error> {
+    pop_argc(vm, 1, Some(1), "string-normalize-nfkc")?;
+    let s = pop_string(vm, "string-normalize-nfkc")?;
+    Ok(VCell::string(s.to_string().nfkc().collect::<String>()))
+}
+
+pub fn string_normalize_nfkd(vm: &mut Vm) -> Result<VCell, Error> {
+    pop_argc(vm, 1, Some(1), "string-normalize-nfkd")?;
+    let s = pop_string(vm, "string-normalize-nfkd")?;
+    Ok(VCell::string(s.to_string().nfkd().collect::<String>()))
+}
+
 pub fn string_ref(vm: &mut Vm) -> Result<VCell, Error> {
     pop_argc(vm, 2, Some(2), "string-ref")?;
     let idx = pop_index(vm, "string-ref")?;
     let s = pop_string(vm, "string-ref")?;
-    let s = s.borrow();
-    match s.chars().nth(idx) {
+    match s.get(idx) {
         Some(c) => Ok(c.into()),
-        None => Err(InvalidStringIndex(idx, s.chars().count() - 1)),
+        None => Err(InvalidStringIndex(idx, s.len().saturating_sub(1))),
     }
 }
 
-fn char_offset(s: &str, idx: usize) -> Result<usize, Error> {
-    s.char_indices()
-        .nth(idx)
-        .map(|it| it.0)
-        .ok_or_else(|| InvalidStringIndex(idx, s.chars().count() - 1))
-}
-
-fn char_offset_inclusive(s: &str, idx: usize) -> Result<usize, Error> {
-    s.char_indices()
-        .nth(idx)
-        .map(|it| it.0 + it.1.len_utf8())
-        .ok_or_else(|| InvalidStringIndex(idx, s.chars().count() - 1))
-}
-
-fn char_substring_offset(
-    s: &str,
+/// Substring Range
+///
+/// Validate the optional start and end arguments of a substring procedure
+/// against a string of len characters, returning the resulting character
+/// range.
+fn substring_range(
+    len: usize,
     start: Option<usize>,
     end: Option<usize>,
 ) -> Result<(usize, usize), Error> {
-    let len = s.chars().count();
-
     if let (Some(start), Some(end)) = (start, end) {
         if start == end {
             return Ok((0, 0));
@@ -120,13 +152,17 @@ fn char_substring_offset(
     }
 
     let start = match start {
-        Some(start) => char_offset(s, start)?,
+        Some(start) if start >= len => {
+            return Err(InvalidStringIndex(start, len.saturating_sub(1)))
+        }
+        Some(start) => start,
         None => 0,
     };
 
     let end = match end {
-        Some(end) => char_offset_inclusive(s, end - 1)?,
-        None => s.len(),
+        Some(end) if end > len => return Err(InvalidStringIndex(end - 1, len.saturating_sub(1))),
+        Some(end) => end,
+        None => len,
     };
 
     Ok((start, end))
@@ -146,14 +182,11 @@ pub fn string_list(vm: &mut Vm) -> Result<VCell, Error> {
     };
 
     let s = pop_string(vm, "string->list")?;
-    let s = s.borrow();
-    let s = s.as_str();
-
-    let (start, end) = char_substring_offset(s, start, end)?;
-    let substr = &s[start..end];
+    let (start, end) = substring_range(s.len(), start, end)?;
+    vm.reserve_heap(2 * (end - start) + 1)?;
 
     let mut list = vm.heap.put(VCell::nil());
-    for c in substr.chars().rev() {
+    for c in s.chars(start, end).into_iter().rev() {
         let c = vm.heap.put(VCell::from(c));
         list = vm.heap.put(VCell::pair(c.as_ptr()?, list.as_ptr()?));
     }
@@ -165,9 +198,12 @@ pub fn string_vector(vm: &mut Vm) -> Result<VCell, Error> {
     pop_argc(vm, 1, Some(1), "string->vector")?;
 
     let s = pop_string(vm, "string->vector")?;
-    let s = s.borrow();
-    let s = s.as_str();
-    let v = s.chars().map(VCell::Char).collect::<Vec<_>>();
+    vm.check_vector_length(s.len())?;
+    let v = s
+        .to_chars()
+        .into_iter()
+        .map(VCell::Char)
+        .collect::<Vec<_>>();
     Ok(VCell::vector(v))
 }
 
@@ -175,9 +211,10 @@ pub fn vector_string(vm: &mut Vm) -> Result<VCell, Error> {
     pop_argc(vm, 1, Some(1), "vector->string")?;
 
     let v = pop_vector(vm)?;
-    let mut s = String::with_capacity(v.len());
+    vm.check_string_length(v.len())?;
+    let mut s = Vec::with_capacity(v.len());
     for it in 0..v.len() {
-        let vcell = vm.heap.get(&v.get(it).unwrap());
+        let vcell = vm.heap.get(v.get(it).unwrap());
         s.push(vcell.as_char()?);
     }
     Ok(VCell::string(s))
@@ -189,7 +226,7 @@ pub fn list_string(vm: &mut Vm) -> Result<VCell, Error> {
     if !rest.is_pair() && !rest.is_nil() {
         return Err(Error::ExpectedPairButFound(vm.heap.get_as_cell(&rest)));
     }
-    let mut s = String::new();
+    let mut s = vec![];
     while rest.is_pair() {
         match vm.heap.get(&rest.as_car()?) {
             VCell::Char(c) => s.push(c),
@@ -219,12 +256,44 @@ pub fn string_copy(vm: &mut Vm) -> Result<VCell, Error> {
     };
 
     let s = pop_string(vm, "string-copy")?;
-    let s = s.borrow();
-    let s = s.as_str();
+    let (start, end) = substring_range(s.len(), start, end)?;
+    Ok(VCell::String(Rc::new(s.substring(start, end))))
+}
+
+// (string-copy! to at from start end)
+pub fn string_mut_copy(vm: &mut Vm) -> Result<VCell, Error> {
+    let argc = pop_argc(vm, 3, Some(5), "string-copy!")?;
+
+    let end = match argc {
+        5 => Some(pop_index(vm, "string-copy!")?),
+        _ => None,
+    };
 
-    let (start, end) = char_substring_offset(s, start, end)?;
-    let substr = &s[start..end];
-    Ok(VCell::string(substr))
+    let start = match argc {
+        4 | 5 => Some(pop_index(vm, "string-copy!")?),
+        _ => None,
+    };
+
+    let from = pop_string(vm, "string-copy!")?;
+    let at = pop_index(vm, "string-copy!")?;
+    let to = pop_string(vm, "string-copy!")?;
+
+    let start = start.unwrap_or(0);
+    let end = end.unwrap_or(from.len());
+    if end > from.len() {
+        return Err(InvalidStringIndex(end, from.len()));
+    }
+    if start > end {
+        return Err(InvalidSyntax("string-copy! requires start <= end".into()));
+    }
+    if at > to.len() || to.len() - at < end - start {
+        return Err(InvalidSyntax("string-copy!: to string is too small".into()));
+    }
+
+    // Copy the source characters out before writing the destination, which
+    // may be the same string.
+    to.copy_from(at, &from.chars(start, end));
+    Ok(VCell::void())
 }
 
 pub fn string_fill(vm: &mut Vm) -> Result<VCell, Error> {
@@ -243,19 +312,8 @@ pub fn string_fill(vm: &mut Vm) -> Result<VCell, Error> {
     let c = pop_char(vm)?;
 
     let s = pop_string(vm, "string-fill")?;
-    let mut s = s.borrow_mut();
-    let s = s.deref_mut();
-
-    let count = match (start, end) {
-        (Some(start), Some(end)) if end >= start => end - start,
-        (Some(start), None) => s.chars().count() - start,
-        _ => s.chars().count(),
-    };
-
-    let (start, end) = char_substring_offset(s, start, end)?;
-    let fill = std::iter::repeat(c).take(count).collect::<String>();
-
-    s.replace_range(start..end, &fill);
+    let (start, end) = substring_range(s.len(), start, end)?;
+    s.fill(start, end, c);
     Ok(VCell::void())
 }
 
@@ -264,13 +322,7 @@ pub fn string_set(vm: &mut Vm) -> Result<VCell, Error> {
     let c = pop_char(vm)?;
     let idx = pop_index(vm, "string-set!")?;
     let s = pop_string(vm, "string-set!")?;
-    let mut s = s.borrow_mut();
-    let range = s
-        .char_indices()
-        .nth(idx)
-        .ok_or_else(|| InvalidStringIndex(idx, s.chars().count() - 1))
-        .map(|it| (it.0, it.0 + it.1.len_utf8()))?;
-    s.replace_range(range.0..range.1, &c.to_string());
+    s.put(idx, c)?;
     Ok(VCell::void())
 }
 
@@ -281,18 +333,18 @@ pub fn make_string(vm: &mut Vm) -> Result<VCell, Error> {
         _ => pop_char(vm)?,
     };
     let size = pop_usize(vm)?;
-    Ok(VCell::string(
-        std::iter::repeat(c).take(size).collect::<String>(),
-    ))
+    vm.check_string_length(size)?;
+    Ok(VCell::string(vec![c; size]))
 }
 
 pub fn string(vm: &mut Vm) -> Result<VCell, Error> {
     let argc = pop_argc(vm, 1, None, "string")?;
+    vm.check_string_length(argc)?;
     let mut v = vec!['\0'; argc];
     for it in 0..argc {
         *v.get_mut(argc - it - 1).unwrap() = pop_char(vm)?;
     }
-    Ok(VCell::string(v.into_iter().collect::<String>()))
+    Ok(VCell::string(v))
 }
 
 pub fn string_eq(vm: &mut Vm) -> Result<VCell, Error> {
@@ -316,51 +368,179 @@ pub fn string_gt_eq(vm: &mut Vm) -> Result<VCell, Error> {
 }
 
 pub fn string_ci_eq(vm: &mut Vm) -> Result<VCell, Error> {
-    string_comp(vm, "string-ci=?", |x, y| {
-        x.to_lowercase() == y.to_lowercase()
-    })
+    string_comp(vm, "string-ci=?", |x, y| x.cmp_folded(y).is_eq())
 }
 
 pub fn string_ci_lt(vm: &mut Vm) -> Result<VCell, Error> {
-    string_comp(vm, "string-ci<?", |x, y| {
-        x.to_lowercase() < y.to_lowercase()
-    })
+    string_comp(vm, "string-ci<?", |x, y| x.cmp_folded(y).is_lt())
 }
 
 pub fn string_ci_gt(vm: &mut Vm) -> Result<VCell, Error> {
-    string_comp(vm, "string-ci>?", |x, y| {
-        x.to_lowercase() > y.to_lowercase()
-    })
+    string_comp(vm, "string-ci>?", |x, y| x.cmp_folded(y).is_gt())
 }
 
 pub fn string_ci_lt_eq(vm: &mut Vm) -> Result<VCell, Error> {
-    string_comp(vm, "string-ci<=?", |x, y| {
-        x.to_lowercase() <= y.to_lowercase()
-    })
+    string_comp(vm, "string-ci<=?", |x, y| x.cmp_folded(y).is_le())
 }
 
 pub fn string_ci_gt_eq(vm: &mut Vm) -> Result<VCell, Error> {
-    string_comp(vm, "string-ci>=?", |x, y| {
-        x.to_lowercase() >= y.to_lowercase()
-    })
+    string_comp(vm, "string-ci>=?", |x, y| x.cmp_folded(y).is_ge())
 }
 
-fn string_comp(vm: &mut Vm, name: &str, comp: impl Fn(&str, &str) -> bool) -> Result<VCell, Error> {
+fn string_comp(
+    vm: &mut Vm,
+    name: &str,
+    comp: impl Fn(&SchemeString, &SchemeString) -> bool,
+) -> Result<VCell, Error> {
     let argc = pop_argc(vm, 1, None, name)?;
     let mut result = true;
 
     let mut y = pop_string(vm, name)?;
     for _ in 0..argc - 1 {
         let x = pop_string(vm, name)?;
-        {
-            let y_s = y.borrow();
-            let x_s = x.borrow();
-            if !comp(x_s.as_str(), y_s.as_str()) {
-                result = false;
-            }
+        if !comp(&x, &y) {
+            result = false;
         }
         y = x;
     }
 
     Ok(result.into())
 }
+
+/// Search Chars
+///
+/// Return the character index of the first occurrence of needle in
+/// haystack at or after start, or None if there is no such occurrence.
+fn search_chars(haystack: &[char], needle: &[char], start: usize) -> Option<usize> {
+    if start > haystack.len() || needle.len() > haystack.len() - start {
+        return None;
+    }
+    (start..=haystack.len() - needle.len()).find(|&it| haystack[it..].starts_with(needle))
+}
+
+fn search_result(idx: Option<usize>) -> VCell {
+    match idx {
+        Some(idx) => Number::from(idx as u64).into(),
+        None => false.into(),
+    }
+}
+
+// (string-search-forward pattern string start)
+pub fn string_search_forward(vm: &mut Vm) -> Result<VCell, Error> {
+    let argc = pop_argc(vm, 2, Some(3), "string-search-forward")?;
+    let start = match argc {
+        3 => pop_index(vm, "string-search-forward")?,
+        _ => 0,
+    };
+    let s = pop_string(vm, "string-search-forward")?;
+    let pattern = pop_string(vm, "string-search-forward")?;
+    let s = s.to_chars();
+    let pattern = pattern.to_chars();
+    if start > s.len() {
+        return Err(InvalidStringIndex(start, s.len()));
+    }
+    Ok(search_result(search_chars(&s, &pattern, start)))
+}
+
+// (string-contains string pattern)
+pub fn string_contains(vm: &mut Vm) -> Result<VCell, Error> {
+    pop_argc(vm, 2, Some(2), "string-contains")?;
+    let pattern = pop_string(vm, "string-contains")?;
+    let s = pop_string(vm, "string-contains")?;
+    let s = s.to_chars();
+    let pattern = pattern.to_chars();
+    Ok(search_result(search_chars(&s, &pattern, 0)))
+}
+
+pub fn string_prefix(vm: &mut Vm) -> Result<VCell, Error> {
+    pop_argc(vm, 2, Some(2), "string-prefix?")?;
+    let s = pop_string(vm, "string-prefix?")?;
+    let prefix = pop_string(vm, "string-prefix?")?;
+    let result = s.to_chars().starts_with(&prefix.to_chars());
+    Ok(result.into())
+}
+
+pub fn string_suffix(vm: &mut Vm) -> Result<VCell, Error> {
+    pop_argc(vm, 2, Some(2), "string-suffix?")?;
+    let s = pop_string(vm, "string-suffix?")?;
+    let suffix = pop_string(vm, "string-suffix?")?;
+    let result = s.to_chars().ends_with(&suffix.to_chars());
+    Ok(result.into())
+}
+
+// (string-join list delimiter)
+pub fn string_join(vm: &mut Vm) -> Result<VCell, Error> {
+    let argc = pop_argc(vm, 1, Some(2), "string-join")?;
+    let delimiter = match argc {
+        2 => pop_string(vm, "string-join")?.to_string(),
+        _ => " ".to_string(),
+    };
+
+    let list = vm.heap.get(vm.stack.pop()?);
+    let mut rest = list.clone();
+    let mut strings = vec![];
+    while rest.is_pair() {
+        match vm.heap.get(&rest.as_car()?) {
+            VCell::String(s) => strings.push(s.to_string()),
+            vcell => {
+                return Err(InvalidSyntax(format!(
+                    "bad argument to string-join: {:#} is not a string",
+                    vm.heap.get_as_cell(&vcell)
+                )))
+            }
+        }
+        rest = vm.heap.get(&rest.as_cdr()?);
+    }
+    if !rest.is_nil() {
+        return Err(Error::ExpectedPairButFound(vm.heap.get_as_cell(&list)));
+    }
+    let delimiters = strings.len().saturating_sub(1) * delimiter.chars().count();
+    vm.check_string_length(
+        strings.iter().map(|it| it.chars().count()).sum::<usize>() + delimiters,
+    )?;
+    Ok(VCell::string(strings.join(&delimiter)))
+}
+
+pub fn string_pad(vm: &mut Vm) -> Result<VCell, Error> {
+    string_pad_impl(vm, "string-pad", true)
+}
+
+pub fn string_pad_right(vm: &mut Vm) -> Result<VCell, Error> {
+    string_pad_impl(vm, "string-pad-right", false)
+}
+
+/// String Pad
+///
+/// Pad or truncate a string to exactly len characters. string-pad pads
+/// and truncates on the left, keeping the rightmost characters, while
+/// string-pad-right pads and truncates on the right.
+fn string_pad_impl(vm: &mut Vm, name: &str, left: bool) -> Result<VCell, Error> {
+    let argc = pop_argc(vm, 2, Some(3), name)?;
+    let c = match argc {
+        3 => pop_char(vm)?,
+        _ => ' ',
+    };
+    let len = pop_usize(vm)?;
+    vm.check_string_length(len)?;
+    let s = pop_string(vm, name)?;
+    let chars = s.to_chars();
+    let fill = std::iter::repeat_n(c, len.saturating_sub(chars.len()));
+    let s = match left {
+        true => fill
+            .chain(chars[chars.len().saturating_sub(len)..].iter().copied())
+            .collect::<String>(),
+        false => chars[..len.min(chars.len())]
+            .iter()
+            .copied()
+            .chain(fill)
+            .collect::<String>(),
+    };
+    Ok(VCell::string(s))
+}
+
+pub fn string_reverse(vm: &mut Vm) -> Result<VCell, Error> {
+    pop_argc(vm, 1, Some(1), "string-reverse")?;
+    let s = pop_string(vm, "string-reverse")?;
+    let s = s.to_chars().into_iter().rev().collect::<Vec<_>>();
+    Ok(VCell::string(s))
+}
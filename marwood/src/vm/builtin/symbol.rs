@@ -13,7 +13,7 @@ pub fn load_builtins(vm: &mut Vm) {
 pub fn string_symbol(vm: &mut Vm) -> Result<VCell, Error> {
     pop_argc(vm, 1, Some(1), "string->symbol")?;
     let s = pop_string(vm, "string->append")?;
-    let s = s.borrow();
+    let s = s.to_string();
     let s = s.as_str();
     let sym = s
         .char_indices()
@@ -0,0 +1,66 @@
+use crate::error::Error;
+use crate::vm::builtin::pop_argc;
+use crate::vm::thread::Switch;
+use crate::vm::vcell::VCell;
+use crate::vm::Vm;
+
+/// Threads
+///
+/// The primitives the prelude builds SRFI 18 threads, mutexes and
+/// condition variables on. Thread objects are created by the prelude and
+/// are opaque to the scheduler, which only keeps them to identify threads.
+pub fn load_builtins(vm: &mut Vm) {
+    vm.load_builtin("%current-thread", current_thread);
+    vm.load_builtin("%thread-spawn!", thread_spawn);
+    vm.load_builtin("%thread-remove!", thread_remove);
+    vm.load_builtin("%thread-yield!", thread_yield);
+    vm.load_builtin("%thread-exit!", thread_exit);
+}
+
+/// %current-thread
+///
+/// Return the thread object of the running thread, or #f if the running
+/// thread is the primordial thread.
+fn current_thread(vm: &mut Vm) -> Result<VCell, Error> {
+    pop_argc(vm, 0, Some(0), "%current-thread")?;
+    Ok(vm.scheduler.current.clone().unwrap_or(VCell::Bool(false)))
+}
+
+fn thread_spawn(vm: &mut Vm) -> Result<VCell, Error> {
+    pop_argc(vm, 1, Some(1), "%thread-spawn!")?;
+    let thread = vm.stack.pop()?.clone();
+    vm.spawn_thread(thread)?;
+    Ok(VCell::Void)
+}
+
+fn thread_remove(vm: &mut Vm) -> Result<VCell, Error> {
+    pop_argc(vm, 1, Some(1), "%thread-remove!")?;
+    let thread = vm.stack.pop()?.clone();
+    Ok(vm.remove_thread(&thread).into())
+}
+
+/// %thread-yield!
+///
+/// Switch to the next ready thread once this builtin returns. Return #t if
+/// there's another thread to switch to, or #f if the running thread is the
+/// only one, in which case waiting on another thread would never end.
+fn thread_yield(vm: &mut Vm) -> Result<VCell, Error> {
+    pop_argc(vm, 0, Some(0), "%thread-yield!")?;
+    if vm.scheduler.ready.is_empty() {
+        return Ok(false.into());
+    }
+    vm.scheduler.switch = Some(Switch::Yield);
+    Ok(true.into())
+}
+
+/// %thread-exit!
+///
+/// Discard the running thread once this builtin returns. The primordial
+/// thread may not exit.
+fn thread_exit(vm: &mut Vm) -> Result<VCell, Error> {
+    pop_argc(vm, 0, Some(0), "%thread-exit!")?;
+    if vm.scheduler.current.is_some() {
+        vm.scheduler.switch = Some(Switch::Exit);
+    }
+    Ok(VCell::Void)
+}
@@ -16,10 +16,55 @@ pub fn load_builtins(vm: &mut Vm) {
     vm.load_builtin("vector-fill!", vector_fill);
     vm.load_builtin("vector-copy", vector_copy);
     vm.load_builtin("vector-copy!", vector_mut_copy);
+    vm.load_builtin("vector-append", vector_append);
+    vm.load_builtin("%vector-cas!", vector_cas);
+}
+
+/// Pop Range
+///
+/// Pop the optional start and end arguments of a procedure that accepts
+/// a trailing `[start [end]]` range. `optional` is the number of optional
+/// arguments that were applied.
+fn pop_range(
+    vm: &mut Vm,
+    optional: usize,
+    proc: &str,
+) -> Result<(Option<usize>, Option<usize>), Error> {
+    let end = match optional {
+        2 => Some(pop_index(vm, proc)?),
+        _ => None,
+    };
+    let start = match optional {
+        1 | 2 => Some(pop_index(vm, proc)?),
+        _ => None,
+    };
+    Ok((start, end))
+}
+
+/// Check Range
+///
+/// Resolve an optional start and end against a vector of length len,
+/// returning an error unless start <= end <= len.
+fn check_range(
+    start: Option<usize>,
+    end: Option<usize>,
+    len: usize,
+    proc: &str,
+) -> Result<(usize, usize), Error> {
+    let start = start.unwrap_or(0);
+    let end = end.unwrap_or(len);
+    if end > len {
+        return Err(InvalidVectorIndex(end, len));
+    }
+    if start > end {
+        return Err(InvalidSyntax(format!("{} requires start <= end", proc)));
+    }
+    Ok((start, end))
 }
 
 pub fn vector(vm: &mut Vm) -> Result<VCell, Error> {
     let len = pop_argc(vm, 0, None, "vector")?;
+    vm.check_vector_length(len)?;
     let mut outv = vec![VCell::Undefined; len];
     for idx in (0..len).rev() {
         *outv.get_mut(idx).unwrap() = vm.stack.pop()?.clone();
@@ -39,6 +84,7 @@ pub fn make_vector(vm: &mut Vm) -> Result<VCell, Error> {
         VCell::Number(num) => num.to_usize().ok_or_else(err)?,
         _ => return Err(err()),
     };
+    vm.check_vector_length(len)?;
 
     let outv = vec![fill; len];
     Ok(VCell::vector(outv))
@@ -68,25 +114,54 @@ pub fn vector_set(vm: &mut Vm) -> Result<VCell, Error> {
     if idx > vector.len() - 1 {
         return Err(InvalidVectorIndex(idx, vector.len()));
     }
+    vm.heap.shade(&value);
     vector.put(idx, value);
     Ok(VCell::Void)
 }
 
+/// %vector-cas!
+///
+/// Set the element at idx of vector to new if it's eqv? to old, returning
+/// #t if it was set. Threads are never preempted within a builtin, so the
+/// prelude uses this to update mutexes and condition variables atomically.
+pub fn vector_cas(vm: &mut Vm) -> Result<VCell, Error> {
+    pop_argc(vm, 4, Some(4), "%vector-cas!")?;
+    let new = vm.stack.pop()?.clone();
+    let old = vm.stack.pop()?.clone();
+    let idx = pop_index(vm, "%vector-cas!")?;
+    let vector = pop_vector(vm)?;
+    let Some(value) = vector.get(idx) else {
+        return Err(InvalidVectorIndex(idx, vector.len()));
+    };
+    if !vm.eqv(&value, &old)? {
+        return Ok(false.into());
+    }
+    vm.heap.shade(&new);
+    vector.put(idx, new);
+    Ok(true.into())
+}
+
 pub fn vector_fill(vm: &mut Vm) -> Result<VCell, Error> {
-    pop_argc(vm, 2, Some(2), "vector-fill!")?;
+    let argc = pop_argc(vm, 2, Some(4), "vector-fill!")?;
+    let (start, end) = pop_range(vm, argc - 2, "vector-fill!")?;
     let value = vm.heap.get(vm.stack.pop()?);
     let vector = pop_vector(vm)?;
-    for idx in 0..vector.len() {
+    let (start, end) = check_range(start, end, vector.len(), "vector-fill!")?;
+    vm.heap.shade(&value);
+    for idx in start..end {
         vector.put(idx, value.clone());
     }
     Ok(VCell::Void)
 }
 
 pub fn vector_to_list(vm: &mut Vm) -> Result<VCell, Error> {
-    pop_argc(vm, 1, Some(1), "vector->list")?;
+    let argc = pop_argc(vm, 1, Some(3), "vector->list")?;
+    let (start, end) = pop_range(vm, argc - 1, "vector->list")?;
     let vector = pop_vector(vm)?;
+    let (start, end) = check_range(start, end, vector.len(), "vector->list")?;
+    vm.reserve_heap(2 * (end - start) + 1)?;
     let mut tail = vm.heap.put(VCell::Nil);
-    for idx in (0..vector.len()).rev() {
+    for idx in (start..end).rev() {
         let car = vector.get(idx).unwrap();
         let car = vm.heap.put(car);
         tail = vm.heap.put(VCell::Pair(car.as_ptr()?, tail.as_ptr()?));
@@ -109,6 +184,7 @@ pub fn list_to_vector(vm: &mut Vm) -> Result<VCell, Error> {
         outv.push(list.as_car()?);
         list = vm.heap.get(&list.as_cdr()?);
     }
+    vm.check_vector_length(outv.len())?;
     Ok(VCell::vector(outv))
 }
 
@@ -192,8 +268,23 @@ pub fn vector_mut_copy(vm: &mut Vm) -> Result<VCell, Error> {
 
     for i in start..end {
         let val = from_vector.get(i).unwrap();
+        vm.heap.shade(&val);
         to_vector.put(i + at, val);
     }
 
     Ok(VCell::Void)
 }
+
+pub fn vector_append(vm: &mut Vm) -> Result<VCell, Error> {
+    let argc = pop_argc(vm, 0, None, "vector-append")?;
+    let mut vectors = Vec::with_capacity(argc);
+    for _ in 0..argc {
+        vectors.push(pop_vector(vm)?);
+    }
+    vm.check_vector_length(vectors.iter().map(|it| it.len()).sum())?;
+    let mut outv = vec![];
+    for vector in vectors.iter().rev() {
+        outv.extend(vector.clone_vector(None, None));
+    }
+    Ok(VCell::vector(outv))
+}
@@ -0,0 +1,217 @@
+use ::lazy_static::lazy_static;
+use std::cmp::{max, min};
+
+lazy_static! {
+    pub static ref EMPTY: CharSet = CharSet::default();
+    pub static ref FULL: CharSet = CharSet {
+        ranges: vec![(0, 0xd7ff), (0xe000, char::MAX as u32)],
+    };
+    pub static ref ASCII: CharSet = CharSet::from_predicate(|c| c.is_ascii());
+    pub static ref DIGIT: CharSet = CharSet::from_ranges(
+        DECIMAL_DIGIT_ZEROS
+            .iter()
+            .map(|zero| (*zero, zero + 9))
+            .collect()
+    );
+    pub static ref LETTER: CharSet = CharSet::from_predicate(char::is_alphabetic);
+    pub static ref LETTER_DIGIT: CharSet = LETTER.union(&DIGIT);
+    pub static ref LOWER_CASE: CharSet = CharSet::from_predicate(char::is_lowercase);
+    pub static ref UPPER_CASE: CharSet = CharSet::from_predicate(char::is_uppercase);
+    pub static ref WHITESPACE: CharSet = CharSet::from_predicate(char::is_whitespace);
+}
+
+/// Decimal Digit Zeros
+///
+/// The zero of each run of decimal digits, the characters of Unicode
+/// general category Nd, as of Unicode 15.0. Each run holds the digits zero
+/// through nine at consecutive code points. char::is_numeric isn't used for
+/// char-set:digit as it also holds other numeric characters, such as ½ and
+/// Roman numerals.
+const DECIMAL_DIGIT_ZEROS: &[u32] = &[
+    0x30, 0x660, 0x6f0, 0x7c0, 0x966, 0x9e6, 0xa66, 0xae6, 0xb66, 0xbe6, 0xc66, 0xce6, 0xd66,
+    0xde6, 0xe50, 0xed0, 0xf20, 0x1040, 0x1090, 0x17e0, 0x1810, 0x1946, 0x19d0, 0x1a80, 0x1a90,
+    0x1b50, 0x1bb0, 0x1c40, 0x1c50, 0xa620, 0xa8d0, 0xa900, 0xa9d0, 0xa9f0, 0xaa50, 0xabf0, 0xff10,
+    0x104a0, 0x10d30, 0x11066, 0x110f0, 0x11136, 0x111d0, 0x112f0, 0x11450, 0x114d0, 0x11650,
+    0x116c0, 0x11730, 0x118e0, 0x11950, 0x11c50, 0x11d50, 0x11da0, 0x11f50, 0x16a60, 0x16ac0,
+    0x16b50, 0x1d7ce, 0x1d7d8, 0x1d7e2, 0x1d7ec, 0x1d7f6, 0x1e140, 0x1e2f0, 0x1e4f0, 0x1e950,
+    0x1fbf0,
+];
+
+/// Char Set
+///
+/// An immutable set of characters (SRFI 14), represented as a sorted
+/// list of disjoint, non-adjacent inclusive ranges of code points. This
+/// keeps sets like char-set:letter small, and makes membership a binary
+/// search.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CharSet {
+    ranges: Vec<(u32, u32)>,
+}
+
+impl CharSet {
+    /// From Predicate
+    ///
+    /// Construct the char set of each character for which pred holds,
+    /// merging runs of adjacent code points into ranges as they're found.
+    pub fn from_predicate(pred: impl Fn(char) -> bool) -> CharSet {
+        let mut ranges: Vec<(u32, u32)> = vec![];
+        for c in FULL.chars().filter(|c| pred(*c)) {
+            let c = c as u32;
+            match ranges.last_mut() {
+                Some(last) if last.1 + 1 == c => last.1 = c,
+                _ => ranges.push((c, c)),
+            }
+        }
+        CharSet { ranges }
+    }
+
+    /// From Ranges
+    ///
+    /// Construct a char set from an arbitrary list of inclusive ranges,
+    /// sorting and merging overlapping or adjacent ranges.
+    pub fn from_ranges(mut ranges: Vec<(u32, u32)>) -> CharSet {
+        ranges.sort_unstable();
+        let mut merged: Vec<(u32, u32)> = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some(last) if start <= last.1.saturating_add(1) => last.1 = max(last.1, end),
+                _ => merged.push((start, end)),
+            }
+        }
+        CharSet { ranges: merged }
+    }
+
+    /// Ranges
+    ///
+    /// Return the sorted inclusive ranges of code points in the set.
+    pub fn ranges(&self) -> &[(u32, u32)] {
+        &self.ranges
+    }
+
+    pub fn contains(&self, c: char) -> bool {
+        let c = c as u32;
+        self.ranges
+            .binary_search_by(|(start, end)| {
+                if *end < c {
+                    std::cmp::Ordering::Less
+                } else if *start > c {
+                    std::cmp::Ordering::Greater
+                } else {
+                    std::cmp::Ordering::Equal
+                }
+            })
+            .is_ok()
+    }
+
+    pub fn size(&self) -> usize {
+        self.ranges
+            .iter()
+            .map(|(start, end)| (end - start + 1) as usize)
+            .sum()
+    }
+
+    pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+        self.ranges
+            .iter()
+            .flat_map(|(start, end)| *start..=*end)
+            .filter_map(char::from_u32)
+    }
+
+    pub fn union(&self, other: &CharSet) -> CharSet {
+        CharSet::from_ranges(
+            self.ranges
+                .iter()
+                .chain(other.ranges.iter())
+                .copied()
+                .collect(),
+        )
+    }
+
+    pub fn intersection(&self, other: &CharSet) -> CharSet {
+        let mut ranges = vec![];
+        let (mut i, mut j) = (0, 0);
+        while i < self.ranges.len() && j < other.ranges.len() {
+            let (a, b) = (self.ranges[i], other.ranges[j]);
+            let (start, end) = (max(a.0, b.0), min(a.1, b.1));
+            if start <= end {
+                ranges.push((start, end));
+            }
+            if a.1 < b.1 {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        CharSet { ranges }
+    }
+
+    pub fn complement(&self) -> CharSet {
+        let mut ranges = vec![];
+        let mut next = 0;
+        for (start, end) in &self.ranges {
+            if *start > next {
+                ranges.push((next, start - 1));
+            }
+            next = end + 1;
+        }
+        if next <= char::MAX as u32 {
+            ranges.push((next, char::MAX as u32));
+        }
+        CharSet { ranges }.intersection(&FULL)
+    }
+
+    pub fn difference(&self, other: &CharSet) -> CharSet {
+        self.intersection(&other.complement())
+    }
+}
+
+impl FromIterator<char> for CharSet {
+    fn from_iter<T: IntoIterator<Item = char>>(iter: T) -> Self {
+        CharSet::from_ranges(iter.into_iter().map(|c| (c as u32, c as u32)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_operations() {
+        let abc = "abc".chars().collect::<CharSet>();
+        let cde = "edc".chars().collect::<CharSet>();
+        assert_eq!(abc.ranges, vec![('a' as u32, 'c' as u32)]);
+        assert_eq!(abc.union(&cde), "abcde".chars().collect());
+        assert_eq!(abc.intersection(&cde), "c".chars().collect());
+        assert_eq!(abc.difference(&cde), "ab".chars().collect());
+        assert!(abc.complement().contains('d'));
+        assert!(!abc.complement().contains('b'));
+        assert_eq!(EMPTY.complement(), *FULL);
+        assert_eq!(FULL.complement(), *EMPTY);
+        assert_eq!(abc.complement().size() + 3, FULL.size());
+    }
+
+    #[test]
+    fn predefined() {
+        assert!(LETTER.contains('λ'));
+        assert!(!LETTER.contains('1'));
+        assert!(LETTER_DIGIT.contains('1'));
+        assert!(DIGIT.contains('٣'));
+        assert!(DIGIT.contains('𝟗'));
+        assert!(!DIGIT.contains('½'));
+        assert!(!DIGIT.contains('Ⅻ'));
+        assert!(!DIGIT.contains('²'));
+        assert!(WHITESPACE.contains('\u{3000}'));
+        assert!(UPPER_CASE.contains('Σ'));
+        assert!(LOWER_CASE.contains('ß'));
+    }
+
+    #[test]
+    fn from_predicate_merges_ranges() {
+        assert_eq!(ASCII.ranges(), &[(0, 0x7f)]);
+        assert_eq!(
+            CharSet::from_predicate(|c| c.is_ascii_digit() || c == 'a'),
+            "0123456789a".chars().collect()
+        );
+        assert_eq!(CharSet::from_predicate(|_| true), *FULL);
+    }
+}
@@ -78,7 +78,7 @@ impl Vm {
             return self.compare_vector(left, right);
         }
         if left.is_string() && right.is_string() {
-            return Ok(left.as_string()?.borrow().as_str() == right.as_string()?.borrow().as_str());
+            return Ok(left.as_string()? == right.as_string()?);
         }
         self.eqv(&left, &right)
     }
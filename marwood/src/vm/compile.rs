@@ -1,35 +1,19 @@
 use crate::cell::Cell;
 use crate::error::Error;
-use crate::error::Error::{
-    InvalidArgs, InvalidNumArgs, InvalidSyntax, InvalidUsePrimitive, LambdaMissingExpression,
-    UnquotedNil,
-};
-use crate::vm::environment::{free_symbols, internally_defined_symbols, BindingLocation};
+use crate::error::Error::InvalidSyntax;
+use crate::source::Span;
+use crate::vm::environment::BindingSource;
+use crate::vm::inline::inline;
+use crate::vm::ir::{resolve_spanned, Expr, LambdaExpr, Template, Var};
 use crate::vm::lambda::Lambda;
 use crate::vm::opcode::OpCode;
 use crate::vm::transform::Transform;
 use crate::vm::vcell::VCell;
-use crate::vm::vcell::VCell::{BasePointerOffset, LexicalEnvSlot};
 use crate::vm::Vm;
 use log::trace;
+use std::collections::HashMap;
 use std::rc::Rc;
 
-macro_rules! car {
-    ($cell:expr) => {{
-        $cell
-            .car()
-            .ok_or(Error::ExpectedPairButFound($cell.clone()))?
-    }};
-}
-
-macro_rules! cdr {
-    ($cell:expr) => {{
-        $cell
-            .cdr()
-            .ok_or(Error::ExpectedPairButFound($cell.clone()))?
-    }};
-}
-
 impl Vm {
     /// Compile Runnable
     ///
@@ -38,36 +22,57 @@ impl Vm {
     ///
     /// `expr` - The expression to compile.
     pub fn compile_runnable(&mut self, expr: &Cell) -> Result<Lambda, Error> {
-        let mut entry_lambda = Lambda::new(vec![]);
-        let mut lambda = Lambda::new_from_iof(vec![], vec![], &entry_lambda, &[], false);
+        let mut lambda = Lambda::new(vec![]);
         lambda.set_top_level();
         lambda.emit(OpCode::Enter);
         self.compile(&mut lambda, true, expr)?;
         lambda.emit(OpCode::Ret);
+        self.optimize_lambda(&mut lambda)?;
         trace!("main: \n{}", self.decompile_text(&lambda));
         let lambda = self.heap.put(lambda);
+        Ok(self.entry_lambda(lambda))
+    }
+
+    /// Entry Lambda
+    ///
+    /// Return a lambda that applies the top level lambda main and halts.
+    pub fn entry_lambda(&self, main: VCell) -> Lambda {
+        let mut entry_lambda = Lambda::new(vec![]);
         entry_lambda.emit(OpCode::PushImmediate);
         entry_lambda.emit(VCell::ArgumentCount(0));
         entry_lambda.emit(OpCode::MovImmediate);
-        entry_lambda.emit(lambda);
+        entry_lambda.emit(main);
         entry_lambda.emit(VCell::Acc);
         entry_lambda.emit(OpCode::CallAcc);
         entry_lambda.emit(OpCode::Halt);
-        Ok(entry_lambda)
+        entry_lambda
     }
 
     /// Compile
     ///
-    /// Apply any pre-compilation transforms to expr, compile it, and return
-    /// the resulting bytecode.
+    /// Apply any pre-compilation transforms to the top level expression
+    /// expr, resolve it into the core IR, and emit its bytecode to lambda.
     ///
     /// # Arguments
+    /// `lambda` - The top level lambda to emit byte code to
+    /// `tail` - Tail is true if this expression is in a tail position.
     /// `expr` - The expression to compile.
     pub fn compile(&mut self, lambda: &mut Lambda, tail: bool, expr: &Cell) -> Result<(), Error> {
         trace!("transforming {}", expr);
-        let expr = self.transform(expr)?;
+        let mark = self.source_map.mark();
+        let expr = self
+            .transform(expr)
+            .and_then(|it| resolve_spanned(&it, &self.source_map));
+        self.source_map.forget_since(mark);
+        let expr = expr?;
+        let expr = match self.optimize {
+            true => inline(expr),
+            false => expr,
+        };
         trace!("compiling {}", expr);
-        self.compile_expression(lambda, tail, &expr)?;
+        let mut frame = Frame::default();
+        self.compile_expression(lambda, &mut frame, tail, &expr)?;
+        frame.patch_jumps(lambda);
         Ok(())
     }
 
@@ -100,7 +105,10 @@ impl Vm {
             if let Some(VCell::Macro(transform)) = vcell {
                 let expansion = transform.transform(expr)?;
                 trace!("macro expansion: {} => {}", expr, expansion);
-                return self.transform(&expansion);
+                let spans = self.source_map.enter_expansion(expr, &expansion);
+                let result = self.transform(&expansion);
+                self.source_map.exit_expansion(spans);
+                return result;
             }
         }
 
@@ -109,240 +117,170 @@ impl Vm {
             v.push(self.transform(rest.car().unwrap())?);
             rest = rest.cdr().unwrap();
         }
-        if rest.is_nil() {
-            Ok(Cell::new_list(v))
+        let transformed = if rest.is_nil() {
+            Cell::new_list(v)
         } else {
             let rest = self.transform(rest)?;
-            Ok(Cell::new_improper_list(v, rest))
-        }
+            Cell::new_improper_list(v, rest)
+        };
+        let span = self.source_map.get(expr).cloned();
+        self.source_map.insert(&transformed, span);
+        Ok(transformed)
     }
 
     /// Compile Expression
     ///
-    /// Compile expression compiles a single expression, emitting its byte code to the currently
-    /// compiling procedure.
+    /// Compile expression compiles a single core IR expression, emitting its byte code
+    /// to the currently compiling procedure.
     ///
     /// # Arguments
     /// `lambda` - The lambda to emit byte code to
-    /// `expr` - The expression to compile.
+    /// `frame` - The layout of lambda's frame
     /// `tail` - Tail is true if this expression is in a tail position.
+    /// `expr` - The expression to compile.
     pub fn compile_expression(
         &mut self,
         lambda: &mut Lambda,
+        frame: &mut Frame,
         tail: bool,
-        expr: &Cell,
+        expr: &Expr,
     ) -> Result<(), Error> {
         match expr {
-            Cell::Pair(_, _) => self.compile_procedure_application(lambda, tail, expr),
-            Cell::Symbol(_) => self.compile_symbol_expression(lambda, expr),
-            Cell::Nil => Err(UnquotedNil),
-            Cell::Procedure(_)
-            | Cell::Void
-            | Cell::Undefined
-            | Cell::Macro
-            | Cell::Continuation => Err(InvalidSyntax(expr.to_string())),
-            Cell::Bool(_) | Cell::Char(_) | Cell::Number(_) | Cell::String(_) | Cell::Vector(_) => {
-                self.compile_quote(lambda, expr)
+            Expr::Constant(cell) => self.compile_quote(lambda, cell),
+            Expr::LocalRef(var) => {
+                lambda.emit(OpCode::Mov);
+                lambda.emit(frame.slot(var));
+                lambda.emit(VCell::Acc);
+                Ok(())
+            }
+            Expr::GlobalRef(sym) => {
+                let env_slot = VCell::env_slot(self.global_slot(sym));
+                lambda.emit(OpCode::Mov);
+                lambda.emit(env_slot);
+                lambda.emit(VCell::Acc);
+                Ok(())
+            }
+            Expr::LocalSet(_, value) | Expr::GlobalSet(_, value) => {
+                self.compile_set(lambda, frame, expr, value)
+            }
+            Expr::Lambda(lambda_expr) => self.compile_lambda(lambda, frame, lambda_expr),
+            Expr::If(test, consequent, alternate) => {
+                self.compile_if(lambda, frame, tail, test, consequent, alternate.as_deref())
+            }
+            Expr::Call(proc, args, span) => self.compile_runtime_procedure_application(
+                lambda,
+                frame,
+                tail,
+                proc,
+                args,
+                span.as_ref(),
+            ),
+            Expr::Seq(body) => self.compile_body(lambda, frame, tail, body),
+            Expr::Quasiquote(template) => self.compile_quasiquote(lambda, frame, template),
+            Expr::DefineSyntax(transform) => self.compile_define_syntax(lambda, transform),
+            Expr::Block(var, body) => self.compile_block(lambda, frame, var, body),
+            Expr::Jump(var) => {
+                lambda.emit(OpCode::Jmp);
+                let operand = lambda.emit_jump_placeholder();
+                frame.jumps.push((var.clone(), operand));
+                Ok(())
             }
         }
     }
 
-    /// Compile Procedure Application
+    /// Compile Block
     ///
-    /// Apply the given procedure by either:
-    /// * Expanding a macro if proc is a macro in the current
-    ///   environment
-    /// * Emitting inlined bytecode for built-in primitives, such
-    ///   as lambda and define
-    /// * Compiling a runtime procedure application
+    /// Compile the body of a known local procedure into the current frame.
+    /// The block is only entered by a jump from a tail position, so it
+    /// returns from the frame once done. Evaluating the block itself jumps
+    /// over it, and results in #<void>.
     ///
     /// # Arguments
-    /// `lambda` - The lambda to emit byte code to
-    /// `tail` - Tail is true if this expression is in a tail position.
-    /// `expr` - The expression to compile.
-    pub fn compile_procedure_application(
+    /// `lambda` - The lambda to emit bytecode to
+    /// `frame` - The layout of lambda's frame
+    /// `var` - The variable the local procedure was bound to
+    /// `body` - The local procedure's body
+    pub fn compile_block(
         &mut self,
         lambda: &mut Lambda,
-        tail: bool,
-        expr: &Cell,
+        frame: &mut Frame,
+        var: &Var,
+        body: &[Expr],
     ) -> Result<(), Error> {
-        let proc = expr.car().unwrap();
-        let rest = expr.cdr().unwrap();
-        match proc {
-            Cell::Symbol(proc) => match proc.as_str() {
-                "define" => self.compile_define(lambda, expr),
-                "define-syntax" => self.compile_define_syntax(lambda, expr),
-                "lambda" | "λ" => self.compile_lambda(lambda, expr, false),
-                "quasiquote" => self.compile_quasiquote(lambda, car!(rest), 0),
-                "quote" => self.compile_quote(lambda, car!(rest)),
-                "if" => self.compile_if(lambda, tail, expr),
-                "set!" => self.compile_set(lambda, tail, expr),
-                _ => self.compile_runtime_procedure_application(lambda, tail, expr),
-            },
-            _ => self.compile_runtime_procedure_application(lambda, tail, expr),
-        }
+        lambda.emit(OpCode::Jmp);
+        let jmp_operand = lambda.emit_jump_placeholder();
+        frame.blocks.insert(var.clone(), lambda.bc.len());
+        self.compile_body(lambda, frame, true, body)?;
+        lambda.emit(OpCode::Ret);
+        lambda.patch_jump(jmp_operand, lambda.bc.len());
+        lambda.emit(OpCode::MovImmediate);
+        lambda.emit(VCell::Void);
+        lambda.emit(VCell::Acc);
+        Ok(())
     }
 
-    /// Compile Symbol Expression
+    /// Compile Body
     ///
-    /// Given the symbol in sym, evaluate a load from the global environment
-    /// or lexical environment, given the binding location returned by lambda.
+    /// Compile a sequence of expressions, of which only the last may be in a
+    /// tail position. An empty sequence evaluates to #<void>.
     ///
+    /// # Arguments
     /// `lambda` - The lambda to emit bytecode to
-    /// `sym` - The symbol to evaluate
-    pub fn compile_symbol_expression(
+    /// `frame` - The layout of lambda's frame
+    /// `tail` - Tail is true if this sequence is in a tail position.
+    /// `body` - The expressions to compile.
+    pub fn compile_body(
         &mut self,
         lambda: &mut Lambda,
-        sym: &Cell,
+        frame: &mut Frame,
+        tail: bool,
+        body: &[Expr],
     ) -> Result<(), Error> {
-        if sym.is_primitive_symbol() {
-            return Err(InvalidUsePrimitive(sym.to_string()));
+        if body.is_empty() {
+            lambda.emit(OpCode::MovImmediate);
+            lambda.emit(VCell::Void);
+            lambda.emit(VCell::Acc);
         }
-        let sym_ref = self.heap.put_cell(sym);
-        match lambda.binding_location(&sym_ref) {
-            BindingLocation::Global => {
-                let sym_ref = sym_ref.as_ptr().expect("expected ptr");
-                let env_slot = VCell::env_slot(self.globenv.get_binding(sym_ref));
-                lambda.emit(OpCode::Mov);
-                lambda.emit(env_slot);
-                lambda.emit(VCell::Acc);
-            }
-            BindingLocation::Argument(n) => {
-                let arg_offset = 0_i64 - lambda.argc() as i64 + n as i64 + 1;
-                lambda.emit(OpCode::Mov);
-                lambda.emit(BasePointerOffset(arg_offset));
-                lambda.emit(VCell::Acc);
-            }
-            BindingLocation::Environment(n) => {
-                lambda.emit(OpCode::Mov);
-                lambda.emit(LexicalEnvSlot(n));
-                lambda.emit(VCell::Acc);
-            }
+        for (i, expr) in body.iter().enumerate() {
+            self.compile_expression(lambda, frame, tail && i == body.len() - 1, expr)?;
         }
         Ok(())
     }
 
-    /// Compile Define
+    /// Global Slot
     ///
-    /// A definition should be in one of the following forms:
-    ///
-    /// * (define ⟨variable⟩ ⟨expression⟩)
-    /// * (define (⟨variable⟩ ⟨formals⟩) ⟨body⟩)
-    /// * (define (⟨variable⟩ . ⟨formal⟩) ⟨body⟩)
-    ///
-    /// `lambda` - The lambda to emit bytecode to
-    /// `expr` - (define variable expression)    
-    pub fn compile_define(&mut self, lambda: &mut Lambda, expr: &Cell) -> Result<(), Error> {
-        let rest = cdr!(expr);
-
-        // A define must have at least 2 arguments
-        if rest.is_nil() || cdr!(rest).is_nil() {
-            return Err(InvalidNumArgs("define".into()));
-        }
-
-        // Extract the symbol given the form, and at the same time compile the
-        // expression or lambda so that its result will be in %acc for the define.
-        let symbol = match car!(rest) {
-            Cell::Symbol(_) => {
-                if !cdr!(cdr!(rest)).is_nil() {
-                    return Err(InvalidNumArgs("define".into()));
-                }
-                self.compile_expression(lambda, false, car!(cdr!(rest)))?;
-                car!(rest)
-            }
-            Cell::Pair(_, _) => {
-                self.compile_lambda(lambda, expr, true)?;
-                car!(car!(rest))
-            }
-            _ => {
-                return Err(InvalidArgs(
-                    "define".into(),
-                    "symbol or (variable formals)".into(),
-                    car!(rest).to_string(),
-                ));
-            }
-        };
-
-        if symbol.is_primitive_symbol() {
-            return Err(InvalidUsePrimitive(symbol.to_string()));
-        }
-
-        let sym_ref = self.heap.put_cell(symbol);
-        lambda.emit(OpCode::Mov);
-        lambda.emit(VCell::Acc);
-        match lambda.binding_location(&sym_ref) {
-            BindingLocation::Global => {
-                let sym_ref = sym_ref.as_ptr().expect("expected ptr");
-                let env_slot = VCell::env_slot(self.globenv.get_binding(sym_ref));
-                lambda.emit(env_slot);
-            }
-            BindingLocation::Argument(n) => {
-                let arg_offset = 0_i64 - lambda.argc() as i64 + n as i64 + 1;
-                lambda.emit(BasePointerOffset(arg_offset));
-            }
-            BindingLocation::Environment(n) => {
-                lambda.emit(LexicalEnvSlot(n));
-            }
-        }
-
-        lambda.emit(OpCode::MovImmediate);
-        lambda.emit(VCell::Void);
-        lambda.emit(VCell::Acc);
-        Ok(())
+    /// Return the global environment slot bound to sym, creating the binding
+    /// if it does not already exist.
+    pub fn global_slot(&mut self, sym: &Cell) -> usize {
+        let sym_ref = self.heap.put_cell(sym).as_ptr().expect("expected ptr");
+        self.globenv.get_binding(sym_ref)
     }
 
-    /// Set
+    /// Compile Set
     ///
-    /// Set is a primitive that provides support for the set! procedure.
-    ///
-    /// A set! must be in the following form:
-    ///
-    /// * (set ⟨variable⟩ ⟨expression⟩)
+    /// Compile a define or set! of a lexical or global variable, both of
+    /// which evaluate to #<void>.
     ///
     /// `lambda` - The lambda to emit bytecode to
-    /// `expr` - (set! variable expression)
-    /// `tail` - whether or not this set is in a tail context
+    /// `frame` - The layout of lambda's frame
+    /// `expr` - The LocalSet or GlobalSet expression
+    /// `value` - The expression to assign
     pub fn compile_set(
         &mut self,
         lambda: &mut Lambda,
-        _tail: bool,
-        expr: &Cell,
+        frame: &mut Frame,
+        expr: &Expr,
+        value: &Expr,
     ) -> Result<(), Error> {
-        let rest = cdr!(expr);
-        let (variable, expression) = match rest.collect_vec().as_slice() {
-            [variable, expression] => (*variable, *expression),
-            _ => {
-                return Err(InvalidNumArgs("set!".into()));
-            }
+        self.compile_expression(lambda, frame, false, value)?;
+        let dest = match expr {
+            Expr::LocalSet(var, _) => frame.slot(var),
+            Expr::GlobalSet(sym, _) => VCell::env_slot(self.global_slot(sym)),
+            _ => return Err(InvalidSyntax(expr.to_string())),
         };
-
-        if !variable.is_symbol() || variable.is_primitive_symbol() {
-            return Err(InvalidSyntax(format!(
-                "expected variable, but got {:#}",
-                variable
-            )));
-        }
-
-        self.compile_expression(lambda, false, expression)?;
-
-        let sym_ref = self.heap.put_cell(variable);
         lambda.emit(OpCode::Mov);
         lambda.emit(VCell::Acc);
-        match lambda.binding_location(&sym_ref) {
-            BindingLocation::Global => {
-                let sym_ref = sym_ref.as_ptr().expect("expected ptr");
-                let env_slot = VCell::env_slot(self.globenv.get_binding(sym_ref));
-                lambda.emit(env_slot);
-            }
-            BindingLocation::Argument(n) => {
-                let arg_offset = 0_i64 - lambda.argc() as i64 + n as i64 + 1;
-                lambda.emit(BasePointerOffset(arg_offset));
-            }
-            BindingLocation::Environment(n) => {
-                lambda.emit(LexicalEnvSlot(n));
-            }
-        }
-
+        lambda.emit(dest);
         lambda.emit(OpCode::MovImmediate);
         lambda.emit(VCell::Void);
         lambda.emit(VCell::Acc);
@@ -351,18 +289,18 @@ impl Vm {
 
     /// Compile Define Syntax
     ///
-    /// Compile a top-level macro to a VCell::Transform, storing it in the
-    /// heap.
+    /// Store a top-level macro as a VCell::Macro in the heap, and bind it
+    /// to its keyword in the global environment.
     ///
     /// `lambda` - The lambda to emit bytecode to
-    /// `expr` - (define variable expression)    
-    pub fn compile_define_syntax(&mut self, lambda: &mut Lambda, expr: &Cell) -> Result<(), Error> {
-        let transform = Transform::try_new(expr)?;
-        let symbol = transform.keyword().clone();
-        let transform = self.heap.put(VCell::Macro(Rc::new(transform)));
-
-        let sym_ref = self.heap.put_cell(&symbol).as_ptr()?;
-        let env_slot = VCell::env_slot(self.globenv.get_binding(sym_ref));
+    /// `transform` - The macro's syntax-rules
+    pub fn compile_define_syntax(
+        &mut self,
+        lambda: &mut Lambda,
+        transform: &Rc<Transform>,
+    ) -> Result<(), Error> {
+        let env_slot = VCell::env_slot(self.global_slot(transform.keyword()));
+        let transform = self.heap.put(VCell::Macro(transform.clone()));
 
         lambda.emit(OpCode::MovImmediate);
         lambda.emit(transform);
@@ -375,81 +313,67 @@ impl Vm {
 
     /// Compile Lambda
     ///
-    /// A lambda should be the following form:
-    ///     
-    /// (lambda ⟨formals⟩ ⟨body⟩)
-    ///   or
-    /// (define ⟨variable formals⟩ ⟨body⟩)
+    /// Compile the lambda into a new Lambda on the heap, and emit bytecode
+    /// to the IOF that creates a closure of it.
     ///
-    /// Where formals is one of the following:
-    ///
-    /// * (var1 var2 ...): A fixed number of arguments
-    /// * var: A variable number of arguments, allocated in a new list bound to var.
-    /// * (var1 var2 . rest): A fixed number of arguments, any additional arguments bound
-    ///   to a new list bound to rest.
+    /// The lambda's lexical environment holds its formal arguments, followed by
+    /// its internal definitions and finally any free variables it references,
+    /// which are captured from the IOF's environment when the closure is created.
     ///
     /// # Arguments
-    /// `iof_lambda` - The immediate outer function in which to inherit an
-    ///                environment from
-    /// `expr` - The full lambda expression, i.e. (lambda ...). This function takes the
-    /// full expression so that free_symbols() considers this lambda when building
-    /// an "environment".
-    /// `is_define_special` - Is this a define special form?
+    /// `iof` - The immediate outer function in which to inherit an
+    ///         environment from
+    /// `iof_frame` - The layout of the IOF's frame
+    /// `expr` - The lambda expression
     pub fn compile_lambda(
         &mut self,
         iof: &mut Lambda,
-        expr: &Cell,
-        is_define_special: bool,
+        iof_frame: &Frame,
+        expr: &LambdaExpr,
     ) -> Result<(), Error> {
-        let rest = cdr!(expr);
-        if rest.is_nil() {
-            return Err(InvalidNumArgs("procedure".into()));
-        }
-
-        // The position of formal args and body differ slightly
-        // on whether this was a define special form or a lambda
-        let (formal_args_ast, body) = match is_define_special {
-            true => (cdr!(car!(rest)), cdr!(rest)),
-            false => (car!(rest), cdr!(rest)),
-        };
-
-        // Compile formal args into a list of symbols
-        let (formal_args, is_vararg) = self.compile_formal_arguments(formal_args_ast)?;
-        let free_symbols = free_symbols(expr)?
-            .iter()
-            .inspect(|it| trace!("free: {}", it))
-            .map(|sym| self.heap.put_cell(sym))
-            .collect::<Vec<VCell>>();
-        let internally_defined = internally_defined_symbols(body)?
+        let args = expr
+            .params
             .iter()
-            .inspect(|it| trace!("internal: {}", it))
-            .map(|sym| self.heap.put_cell(sym))
+            .map(|var| self.heap.put_cell(&var.name))
             .collect::<Vec<VCell>>();
+        let mut lambda = Lambda::new(args);
+        lambda.is_vararg = expr.is_vararg;
+        lambda.set_desc(expr.desc_args.clone());
+
+        let mut frame = Frame::default();
+        for (n, var) in expr.params.iter().enumerate() {
+            let sym = self.heap.put_cell(&var.name);
+            lambda.envmap.bind(sym, BindingSource::Argument(n));
+            frame.env.push(var.clone());
+        }
+        for var in &expr.defines {
+            let sym = self.heap.put_cell(&var.name);
+            lambda.envmap.bind(sym, BindingSource::InternalDefinition);
+            frame.env.push(var.clone());
+        }
+        for var in expr.free_vars() {
+            trace!("free: {}", var);
+            if let Some(slot) = iof_frame.env.iter().position(|it| *it == var) {
+                let sym = self.heap.put_cell(&var.name);
+                lambda.envmap.bind(sym, BindingSource::IofEnvironment(slot));
+                frame.env.push(var);
+            }
+        }
 
-        let mut lambda = Lambda::new_from_iof(
-            formal_args,
-            internally_defined,
-            iof,
-            &free_symbols,
-            is_vararg,
-        );
-        lambda.set_desc(formal_args_ast.clone());
         if lambda.is_vararg {
             lambda.emit(OpCode::VarArg);
         }
         lambda.emit(OpCode::Enter);
-
-        // Compile each body expression in sequence
-        let mut body = body;
-        if body.is_nil() {
-            return Err(LambdaMissingExpression);
+        for var in &expr.locals {
+            lambda.emit(OpCode::PushImmediate);
+            lambda.emit(VCell::Void);
+            lambda.locals.push(var.name.clone());
+            frame.locals.push(var.clone());
         }
-        while body.is_pair() {
-            self.compile_expression(&mut lambda, cdr!(body).is_nil(), car!(body))?;
-            body = cdr!(body);
-        }
-
+        self.compile_body(&mut lambda, &mut frame, true, &expr.body)?;
         lambda.emit(OpCode::Ret);
+        frame.patch_jumps(&mut lambda);
+        self.optimize_lambda(&mut lambda)?;
         trace!("lambda: \n{}", self.decompile_text(&lambda));
         let lambda = self.heap.put(lambda);
         iof.emit(OpCode::MovImmediate);
@@ -459,111 +383,118 @@ impl Vm {
         Ok(())
     }
 
-    /// Compile Formal Arguments
-    ///
-    /// Given a lambda call, extract the formal arguments, place the symbols
-    /// on the heap, and return a vector of symbol pointers for each argument.
-    ///
-    /// # Arguments
-    /// `formal_args` - the formal arguments of the lambda or define
-    ///
-    /// # Returns
-    /// (symbols, vararg) where vec is a vector of symbols, and vararg is true
-    /// if a vararg form was encountered.
-    pub fn compile_formal_arguments(
-        &mut self,
-        formal_args: &Cell,
-    ) -> Result<(Vec<VCell>, bool), Error> {
-        if formal_args.is_nil() {
-            return Ok((vec![], false));
-        }
-        let mut symbols = vec![];
-        let mut rest = formal_args;
-        while rest.is_pair() {
-            let symbol = car!(rest);
-            if !symbol.is_symbol() {
-                return Err(InvalidArgs(
-                    "procedure".into(),
-                    "symbol".into(),
-                    symbol.to_string(),
-                ));
-            }
-            if symbol.is_primitive_symbol() {
-                return Err(InvalidUsePrimitive(symbol.to_string()));
-            }
-            symbols.push(self.heap.put_cell(symbol));
-            rest = cdr!(rest);
-        }
-
-        if rest.is_symbol() {
-            if rest.is_primitive_symbol() {
-                return Err(InvalidUsePrimitive(rest.to_string()));
-            }
-            symbols.push(self.heap.put_cell(rest));
-            Ok((symbols, true))
-        } else {
-            Ok((symbols, false))
-        }
-    }
-
     /// Compile Runtime Procedure Application
     ///
-    /// Evaluate the argument expressions in expr, and then apply their
-    /// results to proc.
+    /// Evaluate the argument expressions, and then apply their results
+    /// to proc.
     ///
-    /// Procedure calls are in the form:
-    ///```example
-    ///     (proc arg1 arg2 arg3 ...)
-    ///```
     /// Proecure application is as follows:
     ///
     /// 1. Evaluate and push the arguments left-to-right, resulting in
-    /// the last argument.
+    ///    the last argument.
     /// 2. Push the number of arguments on the stack.
     /// 3. Evaluate proc, the result of which will be in ACC.
     /// 4. Emit a CALL instruction that will execute the procedure in ACC.
     ///
     /// # Arguments
     /// `lambda` - The lambda to emit bytecode to
-    /// `proc` - The procedure to apply
-    /// `expr` - The procedure to apply
+    /// `frame` - The layout of lambda's frame
     /// `tail` - Tail is true if this procedure application is in a tail position.
+    /// `proc` - The procedure to apply
+    /// `args` - The arguments to apply proc to
+    /// `span` - The span of the application in the source, if known
     pub fn compile_runtime_procedure_application(
         &mut self,
         lambda: &mut Lambda,
+        frame: &mut Frame,
         tail: bool,
-        expr: &Cell,
+        proc: &Expr,
+        args: &[Expr],
+        span: Option<&Span>,
     ) -> Result<(), Error> {
-        let proc = car!(expr);
-        let mut rest = cdr!(expr);
+        // The application's instructions are attributed to its span, and
+        // those that follow to the enclosing application's
+        let outer_span = lambda.span().cloned();
+        if span.is_some() {
+            lambda.set_span(span.cloned());
+        }
+
         // Evaluate and push each argument left-to-right
-        let mut n = 0;
-        while rest.is_pair() {
-            self.compile_expression(lambda, false, rest.car().unwrap())?;
+        for arg in args {
+            self.compile_expression(lambda, frame, false, arg)?;
             lambda.emit(OpCode::PushAcc);
-            n += 1;
-            rest = rest.cdr().unwrap();
         }
 
         // Push the argument count
         lambda.emit(OpCode::PushImmediate);
-        lambda.emit(VCell::ArgumentCount(n));
+        lambda.emit(VCell::ArgumentCount(args.len()));
+
+        // If proc is a global bound to a builtin with an inline primitive,
+        // emit the primitive ahead of the regular call. The primitive jumps
+        // past the call if it was applied.
+        let primitive_jmp = self.compile_primitive(lambda, proc, args.len())?;
 
         // Evaluate the procedure to call, and emit a CALL instruction
-        self.compile_expression(lambda, false, proc)?;
+        self.compile_expression(lambda, frame, false, proc)?;
         lambda.emit(match tail {
             true => OpCode::TCallAcc,
             false => OpCode::CallAcc,
         });
+        if let Some(jmp_operand) = primitive_jmp {
+            lambda.patch_jump(jmp_operand, lambda.bc.len());
+        }
+        lambda.set_span(outer_span);
         Ok(())
     }
 
-    /// Compile If
+    /// Compile Primitive
+    ///
+    /// Emit an inline primitive instruction for applying proc to argc
+    /// arguments if proc is a global variable currently bound to a builtin
+    /// that has a primitive opcode (e.g. car or +).
+    ///
+    /// The global may be redefined after this procedure is compiled, so the
+    /// primitive is guarded: at runtime it is only applied if the global is
+    /// still bound to the same builtin, and otherwise falls through to the
+    /// runtime procedure application that follows it. The primitive also
+    /// falls through for arguments it does not handle (e.g. (car 1)), which
+    /// leaves error reporting to the builtin.
     ///
-    /// Compile an if conditional, which is either one of the following forms:
+    /// The fallback isn't a copy of the application. The primitive reads
+    /// the arguments already pushed for the CALL that follows it, so it
+    /// adds only its own opcode, global slot, builtin and jump operand to
+    /// the application's bytecode.
+    ///
+    /// Returns the offset of the primitive's jump operand, which the caller
+    /// must patch to point after the runtime procedure application.
+    fn compile_primitive(
+        &mut self,
+        lambda: &mut Lambda,
+        proc: &Expr,
+        argc: usize,
+    ) -> Result<Option<usize>, Error> {
+        let (sym, op) = match proc {
+            Expr::GlobalRef(sym @ Cell::Symbol(name)) => match OpCode::primitive(name, argc) {
+                Some(op) => (sym, op),
+                None => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        let slot = self.global_slot(sym);
+        let builtin = self.globenv.get_slot(slot);
+        if !matches!(self.heap.get(&builtin), VCell::BuiltInProc(_)) {
+            return Ok(None);
+        }
+
+        lambda.emit(op);
+        lambda.emit(VCell::env_slot(slot));
+        lambda.emit(builtin);
+        Ok(Some(lambda.emit_jump_placeholder()))
+    }
+
+    /// Compile If
     ///
-    /// * (if ⟨test⟩ ⟨consequent⟩ ⟨alternate⟩)
-    /// * (if ⟨test⟩ ⟨consequent⟩)
+    /// Compile an if conditional.
     ///
     /// If has a few special rules:
     /// * The consequent and alternate expressions may only be evaluated if their
@@ -573,47 +504,37 @@ impl Vm {
     ///
     /// # Arguments
     /// `lambda` - The lambda to emit bytecode to
-    /// `expr` - The if expression
+    /// `frame` - The layout of lambda's frame
     /// `tail` - Tail is true if this expression is in a tail position
+    /// `test`, `consequent`, `alternate` - The if expression
     pub fn compile_if(
         &mut self,
         lambda: &mut Lambda,
+        frame: &mut Frame,
         tail: bool,
-        expr: &Cell,
+        test: &Expr,
+        consequent: &Expr,
+        alternate: Option<&Expr>,
     ) -> Result<(), Error> {
-        let rest = cdr!(expr);
-        if rest.is_nil() || !rest.is_list() {
-            return Err(InvalidArgs("if".into(), "test".into(), rest.to_string()));
-        }
-        let (test, consequent, alternate) = match rest.collect_vec().as_slice() {
-            [test, consequent] => (*test, *consequent, None),
-            [test, consequent, alternate] => (*test, *consequent, Some(*alternate)),
-            _ => {
-                return Err(InvalidNumArgs("if".into()));
-            }
-        };
-
         // Evaluate test
-        self.compile_expression(lambda, false, test)?;
+        self.compile_expression(lambda, frame, false, test)?;
 
         // JMP if %acc is #f
         lambda.emit(OpCode::Jnt);
-        let jnt_operand = lambda.bc.len();
-        lambda.emit(VCell::Ptr(0xCAFEBEEF));
+        let jnt_operand = lambda.emit_jump_placeholder();
 
         // Compile the consequent and update the JMP offset to be
         // the bytecode directly after the consequent. The consequent's
         // final instruction is a JMP to the end of the alternate.
-        self.compile_expression(lambda, tail, consequent)?;
+        self.compile_expression(lambda, frame, tail, consequent)?;
         lambda.emit(OpCode::Jmp);
-        let jmp_operand = lambda.bc.len();
-        lambda.emit(VCell::Ptr(0xCAFEBEEF));
-        *lambda.bc.get_mut(jnt_operand).unwrap() = VCell::ptr(lambda.bc.len());
+        let jmp_operand = lambda.emit_jump_placeholder();
+        lambda.patch_jump(jnt_operand, lambda.bc.len());
 
         // Compile the alternate, or if there is no alternate then evaluate to #<void>
         match alternate {
             Some(alternate) => {
-                self.compile_expression(lambda, tail, alternate)?;
+                self.compile_expression(lambda, frame, tail, alternate)?;
             }
             None => {
                 lambda.emit(OpCode::MovImmediate);
@@ -621,7 +542,7 @@ impl Vm {
                 lambda.emit(VCell::Acc);
             }
         }
-        *lambda.bc.get_mut(jmp_operand).unwrap() = VCell::ptr(lambda.bc.len());
+        lambda.patch_jump(jmp_operand, lambda.bc.len());
         Ok(())
     }
 
@@ -649,75 +570,111 @@ impl Vm {
     ///
     /// # Arguments
     /// `lambda` - The lambda to emit bytecode to
-    /// `expr` - The expression to quote.
-    /// `depth` - The quasiquote depth
+    /// `frame` - The layout of lambda's frame
+    /// `template` - The quasiquote template.
     pub fn compile_quasiquote(
         &mut self,
         lambda: &mut Lambda,
-        expr: &Cell,
-        mut depth: usize,
+        frame: &mut Frame,
+        template: &Template,
     ) -> Result<(), Error> {
-        //
-        // Vector
-        //
-        if expr.is_vector() {
-            let vector = expr.as_vector().unwrap();
-
-            let new_vector = self.heap.put(VCell::vector(vec![]));
-            lambda.emit(OpCode::MovImmediate);
-            lambda.emit(new_vector);
-            lambda.emit(VCell::Acc);
+        match template {
+            Template::Vector(templates) => {
+                let new_vector = self.heap.put(VCell::vector(vec![]));
+                lambda.emit(OpCode::MovImmediate);
+                lambda.emit(new_vector);
+                lambda.emit(VCell::Acc);
 
-            for it in vector {
-                lambda.emit(OpCode::PushAcc);
-                self.compile_quasiquote(lambda, it, depth)?;
-                lambda.emit(OpCode::VPushAcc);
+                for it in templates {
+                    lambda.emit(OpCode::PushAcc);
+                    self.compile_quasiquote(lambda, frame, it)?;
+                    lambda.emit(OpCode::VPushAcc);
+                }
+            }
+            Template::Constant(cell) => {
+                self.compile_quote(lambda, cell)?;
+            }
+            Template::Unquote(expr) => {
+                self.compile_expression(lambda, frame, false, expr)?;
+            }
+            Template::List(templates, tail) => {
+                for it in templates {
+                    self.compile_quasiquote(lambda, frame, it)?;
+                    lambda.emit(OpCode::PushAcc);
+                }
+                lambda.emit(OpCode::PushImmediate);
+                lambda.emit(self.heap.maybe_put_cell(tail));
+
+                for i in 0..templates.len() {
+                    lambda.emit(OpCode::Cons);
+                    if i < templates.len() - 1 {
+                        lambda.emit(OpCode::PushAcc);
+                    }
+                }
             }
-
-            return Ok(());
         }
+        Ok(())
+    }
+}
 
-        //
-        // Atom
-        //
-        if !expr.is_pair() {
-            return self.compile_quote(lambda, expr);
-        }
+/// Frame
+///
+/// The layout of the frame of the lambda being compiled: the variable
+/// held in each slot of its lexical environment, and the offset of each
+/// block compiled into it.
+#[derive(Debug, Default)]
+pub struct Frame {
+    pub env: Vec<Var>,
+    pub locals: Vec<Var>,
+    blocks: HashMap<Var, usize>,
+    jumps: Vec<(Var, usize)>,
+}
 
-        //
-        // Pair / List
-        //
-        if expr.car().unwrap().is_unquote() {
-            if depth == 0 {
-                return self.compile_expression(lambda, false, car!(cdr!(expr)));
-            } else {
-                depth -= 1;
-            }
+impl Frame {
+    /// Slot
+    ///
+    /// Return the lexical environment slot holding var, or for a local
+    /// its offset on the stack past the frame's saved %bp.
+    fn slot(&self, var: &Var) -> VCell {
+        if let Some(slot) = self.env.iter().position(|it| it == var) {
+            return VCell::LexicalEnvSlot(slot);
         }
-
-        if expr.car().unwrap().is_quasiquote() {
-            depth += 1;
+        match self.locals.iter().position(|it| it == var) {
+            Some(local) => VCell::BasePointerOffset(5 + local as i64),
+            None => panic!("unbound lexical variable {}", var),
         }
+    }
 
-        let mut count = 0;
-        let mut rest = expr;
-        while rest.is_pair() {
-            let car = rest.car().unwrap();
-            self.compile_quasiquote(lambda, car, depth)?;
-            lambda.emit(OpCode::PushAcc);
-            rest = rest.cdr().unwrap();
-            count += 1;
+    /// Patch Jumps
+    ///
+    /// Blocks may be jumped to before they're compiled, so each jump's
+    /// operand is patched once the lambda has been compiled.
+    fn patch_jumps(&mut self, lambda: &mut Lambda) {
+        for (var, operand) in self.jumps.drain(..) {
+            let offset = *self.blocks.get(&var).expect("unknown block");
+            lambda.patch_jump(operand, offset);
         }
-        lambda.emit(OpCode::PushImmediate);
-        lambda.emit(self.heap.maybe_put_cell(rest));
+    }
+}
 
-        for i in 0..count {
-            lambda.emit(OpCode::Cons);
-            if i < count - 1 {
-                lambda.emit(OpCode::PushAcc);
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lex, parse};
 
-        Ok(())
+    fn compile(vm: &mut Vm, text: &str) -> Lambda {
+        let lambda = vm.compile_runnable(&parse!(text)).unwrap();
+        let main = lambda.bc[3].as_ptr().unwrap();
+        vm.heap.get_at_index(main).as_lambda().unwrap().clone()
+    }
+
+    #[test]
+    fn primitive_code_size() {
+        let mut vm = Vm::new();
+        vm.set_optimize(false);
+        vm.eval(&parse!("(define kar car)")).unwrap();
+        let call = compile(&mut vm, "(kar '(1))").bc.len();
+        let primitive = compile(&mut vm, "(car '(1))").bc.len();
+        assert_eq!(primitive - call, 4);
     }
 }
@@ -1,3 +1,5 @@
+use crate::error::Error;
+use crate::error::Error::{InvalidBytecode, InvalidSyntax, NoContinuationPrompt};
 use crate::vm::stack::Stack;
 use crate::vm::vcell::VCell;
 use crate::vm::Vm;
@@ -8,9 +10,61 @@ pub struct Continuation {
     ep: usize,
     ip: (usize, usize),
     bp: usize,
+
+    /// The stack index of the first vcell of a delimited continuation's
+    /// stack segment, or None if the continuation contains the whole stack.
+    base: Option<usize>,
+
+    /// The base pointer of the frame that installed a delimited
+    /// continuation's prompt, which the segment's bottom frame returns to.
+    frame: usize,
 }
 
 impl Continuation {
+    pub fn new(stack: Stack, ep: usize, ip: (usize, usize), bp: usize) -> Continuation {
+        Continuation {
+            stack,
+            ep,
+            ip,
+            bp,
+            base: None,
+            frame: 0,
+        }
+    }
+
+    /// Delimited
+    ///
+    /// Return a delimited continuation, whose stack is the segment of the
+    /// stack starting at index base, above the prompt installed by the
+    /// frame at frame.
+    pub fn delimited(
+        stack: Stack,
+        ep: usize,
+        ip: (usize, usize),
+        bp: usize,
+        base: usize,
+        frame: usize,
+    ) -> Continuation {
+        Continuation {
+            stack,
+            ep,
+            ip,
+            bp,
+            base: Some(base),
+            frame,
+        }
+    }
+
+    /// With Stack
+    ///
+    /// Return a copy of this continuation with a different stack.
+    pub fn with_stack(&self, stack: Stack) -> Continuation {
+        Continuation {
+            stack,
+            ..self.clone()
+        }
+    }
+
     pub fn stack(&self) -> &Stack {
         &self.stack
     }
@@ -26,16 +80,31 @@ impl Continuation {
     pub fn bp(&self) -> usize {
         self.bp
     }
+
+    pub fn base(&self) -> Option<usize> {
+        self.base
+    }
+
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
 }
 
+/// Delimited Continuations
+///
+/// A prompt is installed by %call-with-prompt, which is applied to a thunk,
+/// a tag and an abort handler. Its PROMPT instruction pushes a prompt frame
+/// just below the call to the thunk: the address to resume at when aborted
+/// to, and a prompt marker holding %call-with-prompt's %bp, whose frame
+/// holds the tag and handler.
+///
+/// A delimited continuation contains only the stack segment above the
+/// prompt marker, starting with the thunk's call frame. Applying it copies
+/// the segment onto the top of the stack, relocating the saved base
+/// pointers of its frames, with the bottom frame returning to the caller.
 impl Vm {
-    pub fn to_continuation(&self) -> Continuation {
-        Continuation {
-            stack: self.stack.to_continuation(),
-            ep: self.ep,
-            ip: self.ip,
-            bp: self.bp,
-        }
+    pub fn to_continuation(&mut self) -> Continuation {
+        Continuation::new(self.stack.to_continuation(), self.ep, self.ip, self.bp)
     }
 
     pub fn restore_continuation(&mut self, cont: &Continuation) {
@@ -45,4 +114,170 @@ impl Vm {
         self.bp = cont.bp();
         self.acc = VCell::Undefined;
     }
+
+    /// Apply Continuation
+    ///
+    /// Apply cont to the arguments on the stack, which must include at least
+    /// one value.
+    pub fn apply_continuation(&mut self, cont: &Continuation) -> Result<(), Error> {
+        if self.stack.pop()?.as_argc()? == 0 {
+            return Err(InvalidSyntax("expected value".into()));
+        }
+        let result = self.stack.pop()?.clone();
+        match cont.base() {
+            Some(base) => self.compose_continuation(cont, base)?,
+            None => self.restore_continuation(cont),
+        }
+        self.acc = result;
+        Ok(())
+    }
+
+    /// To Delimited Continuation
+    ///
+    /// Capture the continuation up to the nearest prompt tagged tag.
+    pub fn to_delimited_continuation(&mut self, tag: &VCell) -> Result<Continuation, Error> {
+        let (marker, frame) = self.find_prompt(tag)?;
+        let base = marker + 1;
+        let segment = (base..=self.stack.get_sp())
+            .map(|idx| self.stack.get(idx).cloned())
+            .collect::<Result<Vec<_>, _>>()?;
+        let sp = segment.len() - 1;
+        Ok(Continuation::delimited(
+            Stack::from_vec(segment, sp),
+            self.ep,
+            self.ip,
+            self.bp,
+            base,
+            frame,
+        ))
+    }
+
+    /// Abort To Prompt
+    ///
+    /// Discard the continuation up to the nearest prompt tagged tag, along
+    /// with the prompt itself, and return its abort handler. The stack and
+    /// registers are left as they were when the prompt was installed, with
+    /// values pushed as the arguments of the call to the handler that
+    /// resuming at the prompt's abort address makes, such that the handler's
+    /// result is returned from the prompt.
+    pub fn abort_to_prompt(&mut self, tag: &VCell, values: &[VCell]) -> Result<VCell, Error> {
+        let (marker, frame) = self.find_prompt(tag)?;
+        let handler = self.stack.get(frame)?.clone();
+        let ep = self.stack.get(frame + 2)?.as_ep()?;
+        let ip = self.stack.get(marker - 1)?.as_ip()?;
+
+        *self.stack.get_sp_mut() = marker - 2;
+        for it in values {
+            self.stack.push(it.clone())?;
+        }
+        self.stack.push(VCell::ArgumentCount(values.len()))?;
+        self.ep = ep;
+        self.ip = ip;
+        self.bp = frame;
+        Ok(handler)
+    }
+
+    /// Compose Continuation
+    ///
+    /// Copy the stack segment of the delimited continuation cont, captured
+    /// from index base, onto the top of the stack, and resume it. Once its
+    /// bottom frame returns, it returns to the current %ip.
+    fn compose_continuation(&mut self, cont: &Continuation, base: usize) -> Result<(), Error> {
+        let new_base = self.stack.get_sp() + 1;
+        let mut bottom = None;
+        for it in cont.stack().iter_to_sp() {
+            // Every frame in the segment was called from within it, except
+            // for the bottom frame, which was called by the prompt's frame.
+            let it = match it {
+                VCell::BasePointer(bp) if *bp == cont.frame() => {
+                    bottom = Some(self.stack.get_sp() + 1);
+                    VCell::BasePointer(self.bp)
+                }
+                VCell::BasePointer(bp) => VCell::BasePointer(bp + new_base - base),
+                VCell::Prompt(bp) => VCell::Prompt(bp + new_base - base),
+                it => it.clone(),
+            };
+            self.stack.push(it)?;
+        }
+
+        // The bottom frame's saved %ep and %ip precede its saved %bp.
+        let bottom = bottom.ok_or(InvalidBytecode)?;
+        *self.stack.get_mut(bottom - 2)? = VCell::EnvironmentPointer(self.ep);
+        *self.stack.get_mut(bottom - 1)? = VCell::InstructionPointer(self.ip.0, self.ip.1);
+
+        self.ep = cont.ep();
+        self.ip = *cont.ip();
+        self.bp = cont.bp() + new_base - base;
+        Ok(())
+    }
+
+    /// Find Prompt
+    ///
+    /// Return the stack index of the nearest prompt marker whose prompt is
+    /// tagged tag, along with the base pointer of the frame that installed
+    /// it.
+    fn find_prompt(&mut self, tag: &VCell) -> Result<(usize, usize), Error> {
+        for idx in (0..=self.stack.get_sp()).rev() {
+            if let VCell::Prompt(frame) = *self.stack.get(idx)? {
+                if self.eqv(self.stack.get(frame - 1)?, tag)? {
+                    return Ok((idx, frame));
+                }
+            }
+        }
+        Err(NoContinuationPrompt(self.heap.get_as_cell(tag)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell::Cell;
+    use crate::vm::gc::GcConfig;
+    use crate::{cell, lex, parse};
+
+    #[test]
+    fn delimited_continuation_copies_only_segment() {
+        let mut vm = Vm::new();
+        vm.eval(&parse!(
+            r#"
+            (define (deep n)
+              (if (= n 0)
+                  (reset (+ 1 (call-with-composable-continuation
+                               (lambda (k) (abort-current-continuation
+                                            (default-continuation-prompt-tag)
+                                            (lambda () k))))))
+                  (car (list (deep (- n 1))))))
+            "#
+        ))
+        .unwrap();
+        vm.eval(&parse!("(define k (deep 1000))")).unwrap();
+
+        let k = vm.heap.get_sym_ref(&Cell::new_symbol("k")).unwrap();
+        let k = vm.globenv.get(k.as_ptr().unwrap()).unwrap();
+        let VCell::Continuation(cont) = vm.heap.get(&k) else {
+            panic!("expected continuation");
+        };
+        assert!(cont.base().unwrap() > 1000);
+        assert!(cont.stack().len() < 32);
+        assert_eq!(vm.eval(&parse!("(k 10)")), Ok(cell![11]));
+    }
+
+    #[test]
+    fn composed_frames_survive_gc() {
+        let mut vm = Vm::new();
+        vm.set_gc_config(GcConfig {
+            interval: 1,
+            threshold: 0.0,
+            grow_threshold: 0.75,
+            budget: Some(16),
+        });
+        vm.eval(&parse!(
+            "(define (walk l) (if (null? l) '() (cons (shift k (k (list (car l)))) (walk (cdr l)))))"
+        ))
+        .unwrap();
+        assert_eq!(
+            vm.eval(&parse!("(reset (walk '(1 2 3 4 5)))")),
+            Ok(parse!("((1) (2) (3) (4) (5))"))
+        );
+    }
 }
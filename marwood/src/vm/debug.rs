@@ -0,0 +1,445 @@
+use crate::cell::Cell;
+use crate::error::Error;
+use crate::error::Error::{
+    ExecutionFinished, InvalidProcedure, InvalidStackIndex, VariableNotBound,
+};
+use crate::vm::heap::HeapRef;
+use crate::vm::lambda::Lambda;
+use crate::vm::opcode::OpCode;
+use crate::vm::trace::StackTrace;
+use crate::vm::vcell::VCell;
+use crate::vm::Vm;
+use std::collections::HashSet;
+
+/// Step
+///
+/// The granularity of a single step of the debugger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// Execute one bytecode instruction
+    Instruction,
+    /// Execute until the next procedure application or return
+    Expression,
+}
+
+/// Pause
+///
+/// The reason a program running under the debugger paused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pause {
+    /// A procedure with a breakpoint was entered. The procedure's frame
+    /// has been built, so its arguments may be inspected.
+    Breakpoint(HeapRef),
+    /// A step requested with debug_step completed
+    Step,
+    /// The program called (break)
+    Break,
+}
+
+/// Debug Event
+///
+/// The result of running a program under the debugger.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DebugEvent {
+    /// The program paused, and may be inspected and then continued
+    Paused(Pause),
+    /// The program finished with the given result
+    Finished(Cell),
+}
+
+/// Debugger
+///
+/// The state of the debugger. Breakpoints and (break) only pause programs
+/// run with debug_eval, debug_continue and debug_step. Eval and other ways
+/// of running the Vm ignore them.
+#[derive(Debug, Clone, Default)]
+pub struct Debugger {
+    /// The lambdas that pause the program once entered
+    pub breakpoints: HashSet<HeapRef>,
+
+    /// Whether the program is running under the debugger
+    pub attached: bool,
+
+    /// Whether a paused program may be continued
+    pub paused: bool,
+
+    /// The step being executed, if any
+    pub step: Option<Step>,
+
+    /// Set by (break) to pause once it returns
+    pub break_requested: bool,
+
+    /// The reason the program paused
+    pub pause: Option<Pause>,
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger::default()
+    }
+}
+
+impl Vm {
+    /// Set Breakpoint
+    ///
+    /// Pause the program whenever the procedure bound to the global name
+    /// is entered. The breakpoint is set on the procedure's lambda, so it
+    /// applies to every closure of the lambda, and not to a procedure later
+    /// bound to name.
+    pub fn set_breakpoint(&mut self, name: &str) -> Result<(), Error> {
+        let lambda = self.breakpoint_lambda(name)?;
+        self.set_lambda_breakpoint(lambda);
+        Ok(())
+    }
+
+    /// Set Lambda Breakpoint
+    ///
+    /// Pause the program whenever the lambda at the heap reference lambda
+    /// is entered.
+    pub fn set_lambda_breakpoint(&mut self, lambda: HeapRef) {
+        self.debugger.breakpoints.insert(lambda);
+    }
+
+    /// Clear Breakpoint
+    ///
+    /// Clear the breakpoint set on the procedure bound to the global name.
+    pub fn clear_breakpoint(&mut self, name: &str) -> Result<(), Error> {
+        let lambda = self.breakpoint_lambda(name)?;
+        self.clear_lambda_breakpoint(lambda);
+        Ok(())
+    }
+
+    /// Clear Lambda Breakpoint
+    ///
+    /// Clear the breakpoint set on the lambda at the heap reference lambda.
+    pub fn clear_lambda_breakpoint(&mut self, lambda: HeapRef) {
+        self.debugger.breakpoints.remove(&lambda);
+    }
+
+    /// Breakpoints
+    ///
+    /// Return the lambdas that breakpoints are set on.
+    pub fn breakpoints(&self) -> impl Iterator<Item = HeapRef> + '_ {
+        self.debugger.breakpoints.iter().copied()
+    }
+
+    fn breakpoint_lambda(&mut self, name: &str) -> Result<HeapRef, Error> {
+        let procedure = self
+            .global(name)
+            .ok_or_else(|| VariableNotBound(name.into()))?;
+        match self.heap.get(&procedure) {
+            VCell::Closure(lambda, _) => Ok(lambda),
+            VCell::Lambda(_) => procedure.as_ptr(),
+            _ => Err(InvalidProcedure(self.heap.get_as_cell(&procedure))),
+        }
+    }
+
+    /// Debug Eval
+    ///
+    /// Compile and run expr under the debugger, until it either finishes
+    /// or pauses. A program that was already paused is abandoned.
+    pub fn debug_eval(&mut self, expr: &Cell) -> Result<DebugEvent, Error> {
+        self.debug_abort();
+        self.prepare_eval(expr)?;
+        self.debug_run(None)
+    }
+
+    /// Debug Continue
+    ///
+    /// Continue a paused program until it either finishes or pauses again.
+    pub fn debug_continue(&mut self) -> Result<DebugEvent, Error> {
+        if !self.debugger.paused {
+            return Err(ExecutionFinished);
+        }
+        self.debug_run(None)
+    }
+
+    /// Debug Step
+    ///
+    /// Continue a paused program for a single step, or until it finishes or
+    /// pauses for another reason.
+    pub fn debug_step(&mut self, step: Step) -> Result<DebugEvent, Error> {
+        if !self.debugger.paused {
+            return Err(ExecutionFinished);
+        }
+        self.debug_run(Some(step))
+    }
+
+    /// Debug Abort
+    ///
+    /// Abandon a paused program, so that the Vm may evaluate again.
+    pub fn debug_abort(&mut self) {
+        if std::mem::take(&mut self.debugger.paused) {
+            self.resume_primordial_thread();
+            self.reset_after_error();
+        }
+    }
+
+    /// Is Paused
+    ///
+    /// Return true if a program run under the debugger is paused.
+    pub fn is_paused(&self) -> bool {
+        self.debugger.paused
+    }
+
+    fn debug_run(&mut self, step: Option<Step>) -> Result<DebugEvent, Error> {
+        self.debugger.attached = true;
+        self.debugger.paused = false;
+        self.debugger.step = step;
+        let result = self.run_count(usize::MAX);
+        self.debugger.attached = false;
+        self.debugger.step = None;
+        self.debugger.break_requested = false;
+        match (result?, self.debugger.pause.take()) {
+            (Some(cell), _) => Ok(DebugEvent::Finished(cell)),
+            (None, Some(pause)) => {
+                self.debugger.paused = true;
+                Ok(DebugEvent::Paused(pause))
+            }
+            (None, None) => Err(ExecutionFinished),
+        }
+    }
+
+    /// Debug Pause
+    ///
+    /// Called by the run loop while the debugger is attached, after the
+    /// instruction op executed. Return true if the program should pause.
+    pub fn debug_pause(&mut self, op: OpCode) -> bool {
+        let pause = if std::mem::take(&mut self.debugger.break_requested) {
+            Pause::Break
+        } else if op == OpCode::Enter && self.debugger.breakpoints.contains(&self.ip.0) {
+            Pause::Breakpoint(self.ip.0)
+        } else {
+            match self.debugger.step {
+                Some(Step::Instruction) => Pause::Step,
+                Some(Step::Expression)
+                    if matches!(op, OpCode::CallAcc | OpCode::TCallAcc | OpCode::Ret) =>
+                {
+                    Pause::Step
+                }
+                _ => return false,
+            }
+        };
+        self.debugger.pause = Some(pause);
+        true
+    }
+
+    /// Debug Acc
+    ///
+    /// Return the value of the %acc register.
+    pub fn debug_acc(&self) -> Cell {
+        self.heap.get_as_cell(&self.acc)
+    }
+
+    /// Debug Bp Offset
+    ///
+    /// Return the value on the stack at offset from the %bp register. The
+    /// arguments of the current frame are at offsets 0 and below, the last
+    /// argument at offset 0, and its local variables start at offset 5.
+    pub fn debug_bp_offset(&self, offset: i64) -> Result<Cell, Error> {
+        let index = self.bp as i64 + offset;
+        if index < 0 {
+            return Err(InvalidStackIndex(0));
+        }
+        let index = index as usize;
+        match self.stack.get(index)? {
+            VCell::ArgumentCount(_)
+            | VCell::BasePointer(_)
+            | VCell::EnvironmentPointer(_)
+            | VCell::InstructionPointer(_, _) => Err(InvalidStackIndex(index)),
+            vcell => Ok(self.heap.get_as_cell(vcell)),
+        }
+    }
+
+    /// Debug Arguments
+    ///
+    /// Return the arguments of the current frame, in order.
+    pub fn debug_arguments(&self) -> Result<Vec<Cell>, Error> {
+        let argc = self.stack.get(self.bp + 1)?.as_argc()? as i64;
+        (1 - argc..=0).map(|it| self.debug_bp_offset(it)).collect()
+    }
+
+    /// Debug Environment
+    ///
+    /// Return the name and value of each slot of the current procedure's
+    /// lexical environment, i.e. the variables it closes over and its
+    /// internal definitions, followed by each of its locals, the variables
+    /// of lets inlined into it that are kept on the stack.
+    pub fn debug_environment(&self) -> Vec<(Cell, Cell)> {
+        let lambda = match self.heap.get_at_index(self.ip.0).as_lambda() {
+            Ok(lambda) => lambda,
+            Err(_) => return vec![],
+        };
+        let mut vars = self.debug_lexical_environment(lambda);
+        for (offset, name) in lambda.locals.iter().enumerate() {
+            let offset = 5 + offset;
+            if self.bp + offset > self.stack.get_sp() {
+                break;
+            }
+            if let Ok(value) = self.debug_bp_offset(offset as i64) {
+                vars.push((name.clone(), value));
+            }
+        }
+        vars
+    }
+
+    fn debug_lexical_environment(&self, lambda: &Lambda) -> Vec<(Cell, Cell)> {
+        let env = match self.heap.get_at_index(self.ep) {
+            VCell::LexicalEnv(env) if env.slot_len() == lambda.envmap.slots_len() => env,
+            _ => return vec![],
+        };
+        lambda
+            .envmap
+            .get_map()
+            .iter()
+            .enumerate()
+            .map(|(slot, (sym, _))| {
+                let value = match env.get(slot) {
+                    VCell::LexicalEnvPtr(env, slot) => {
+                        match self.heap.get_at_index(env).as_lexical_env() {
+                            Ok(env) => env.get(slot),
+                            Err(_) => VCell::Undefined,
+                        }
+                    }
+                    value => value,
+                };
+                (self.heap.get_as_cell(sym), self.heap.get_as_cell(&value))
+            })
+            .collect()
+    }
+
+    /// Debug Formals
+    ///
+    /// Return the formal arguments of the procedure being executed, if
+    /// they're known.
+    pub fn debug_formals(&self) -> Option<Cell> {
+        self.heap
+            .get_at_index(self.ip.0)
+            .as_lambda()
+            .ok()
+            .and_then(|lambda| lambda.desc_args.clone())
+    }
+
+    /// Debug Backtrace
+    ///
+    /// Return the stack trace of the paused program.
+    pub fn debug_backtrace(&self) -> StackTrace {
+        StackTrace::new(&self.stack, &self.heap, self.ip, self.acc.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cell, lex, parse};
+
+    #[test]
+    fn breakpoints_pause_on_entry() {
+        let mut vm = Vm::new();
+        vm.eval(&parse!(
+            "(define (fact n) (if (< n 2) 1 (* n (fact (- n 1)))))"
+        ))
+        .unwrap();
+        vm.set_breakpoint("fact").unwrap();
+        let fact = vm.breakpoints().next().unwrap();
+
+        assert_eq!(
+            vm.debug_eval(&parse!("(fact 3)")),
+            Ok(DebugEvent::Paused(Pause::Breakpoint(fact)))
+        );
+        assert_eq!(vm.debug_formals(), Some(parse!("(n)")));
+        assert_eq!(vm.debug_arguments(), Ok(vec![cell![3]]));
+        assert_eq!(
+            vm.debug_continue(),
+            Ok(DebugEvent::Paused(Pause::Breakpoint(fact)))
+        );
+        assert_eq!(vm.debug_arguments(), Ok(vec![cell![2]]));
+        assert_eq!(vm.debug_backtrace().frames.len(), 3);
+
+        vm.clear_breakpoint("fact").unwrap();
+        assert_eq!(vm.debug_continue(), Ok(DebugEvent::Finished(cell![6])));
+        assert!(!vm.is_paused());
+        assert_eq!(vm.debug_continue(), Err(ExecutionFinished));
+        assert_eq!(vm.eval(&parse!("(fact 4)")), Ok(cell![24]));
+    }
+
+    #[test]
+    fn frame_and_environment() {
+        let mut vm = Vm::new();
+        vm.eval(&parse!(
+            "(define (make-adder x) (lambda (a b) (define sum (+ a b)) (break) (+ x sum)))"
+        ))
+        .unwrap();
+        vm.eval(&parse!("(define add10 (make-adder 10))")).unwrap();
+
+        assert_eq!(
+            vm.debug_eval(&parse!("(add10 1 2)")),
+            Ok(DebugEvent::Paused(Pause::Break))
+        );
+        assert_eq!(vm.debug_arguments(), Ok(vec![cell![1], cell![2]]));
+        assert_eq!(vm.debug_bp_offset(0), Ok(cell![2]));
+        assert_eq!(vm.debug_bp_offset(-1), Ok(cell![1]));
+        assert_eq!(vm.debug_bp_offset(1), Err(InvalidStackIndex(vm.bp + 1)));
+        let env = vm.debug_environment();
+        assert!(env.contains(&(cell!["x"], cell![10])));
+        assert!(env.contains(&(cell!["sum"], cell![3])));
+        assert_eq!(vm.debug_continue(), Ok(DebugEvent::Finished(cell![13])));
+
+        vm.eval(&parse!(
+            "(define (f a b) (let ((sum (+ a b))) (break) (* 2 sum)))"
+        ))
+        .unwrap();
+        assert_eq!(
+            vm.debug_eval(&parse!("(f 1 2)")),
+            Ok(DebugEvent::Paused(Pause::Break))
+        );
+        let env = vm.debug_environment();
+        assert!(env.contains(&(cell!["a"], cell![1])));
+        assert!(env.contains(&(cell!["sum"], cell![3])));
+        assert_eq!(vm.debug_bp_offset(5), Ok(cell![3]));
+        assert_eq!(vm.debug_continue(), Ok(DebugEvent::Finished(cell![6])));
+    }
+
+    #[test]
+    fn stepping() {
+        let mut vm = Vm::new();
+        vm.eval(&parse!("(define (double x) (* x 2))")).unwrap();
+
+        let mut steps = |step| {
+            let mut count = 0;
+            let mut event = vm.debug_eval(&parse!("(begin (break) (double (double 1)))"));
+            assert_eq!(event, Ok(DebugEvent::Paused(Pause::Break)));
+            while event == Ok(DebugEvent::Paused(Pause::Break))
+                || event == Ok(DebugEvent::Paused(Pause::Step))
+            {
+                event = vm.debug_step(step);
+                count += 1;
+            }
+            assert_eq!(event, Ok(DebugEvent::Finished(cell![4])));
+            count
+        };
+        let instructions = steps(Step::Instruction);
+        let expressions = steps(Step::Expression);
+        assert!(expressions > 2);
+        assert!(instructions > expressions);
+    }
+
+    #[test]
+    fn debugger_is_only_attached_by_debug_eval() {
+        let mut vm = Vm::new();
+        vm.eval(&parse!("(define (f) (break) 1)")).unwrap();
+        vm.set_breakpoint("f").unwrap();
+        assert_eq!(vm.eval(&parse!("(f)")), Ok(cell![1]));
+
+        assert_eq!(
+            vm.debug_eval(&parse!("(+ 1 (f))")),
+            Ok(DebugEvent::Paused(Pause::Breakpoint(
+                vm.breakpoints().next().unwrap()
+            )))
+        );
+        vm.debug_abort();
+        assert!(!vm.is_paused());
+        assert_eq!(vm.eval(&parse!("(+ 2 3)")), Ok(cell![5]));
+        assert!(matches!(vm.set_breakpoint("car"), Err(InvalidProcedure(_))));
+    }
+}
@@ -1,26 +1,22 @@
-use crate::cell::Cell;
-use crate::error::Error;
-use crate::error::Error::InvalidSyntax;
-use crate::vm::lambda::Lambda;
 use crate::vm::vcell::VCell;
 use std::cell::RefCell;
-use std::collections::{HashMap, HashSet};
-
-/// Environment
-///
-/// Marwood's environment is composed of two different types of environments:
-///
-/// * The global environment, represented by a single instance of GlobalEnvironment
-///   on the VM.
-/// * Lexical environments, represented by the LexicalEnvironment structure. Each
-///   procedure in Marwood that is a closure contains a lexical environment represented
-///   by LexicalEnvironment. This environment is created by the CLOSURE instruction, which
-///   represents the point in time the (lambda ...) expression is evaluated.
-///
-/// These data structures differ slightly in that the global environment containns
-/// the notion of a "deep binding" represented by a HashMap along with shallow bindings,
-/// and LexicalEnvironment only has the notion of shallow bindings because lexical bindings
-/// are known at compile time.
+use std::collections::HashMap;
+
+// Environment
+//
+// Marwood's environment is composed of two different types of environments:
+//
+// * The global environment, represented by a single instance of GlobalEnvironment
+//   on the VM.
+// * Lexical environments, represented by the LexicalEnvironment structure. Each
+//   procedure in Marwood that is a closure contains a lexical environment represented
+//   by LexicalEnvironment. This environment is created by the CLOSURE instruction, which
+//   represents the point in time the (lambda ...) expression is evaluated.
+//
+// These data structures differ slightly in that the global environment containns
+// the notion of a "deep binding" represented by a HashMap along with shallow bindings,
+// and LexicalEnvironment only has the notion of shallow bindings because lexical bindings
+// are known at compile time.
 
 /// Binding Source
 ///
@@ -48,19 +44,6 @@ pub enum BindingSource {
     InternalDefinition,
 }
 
-/// Binding Location
-///
-/// Binding is used to specify the location of a binding in a lexical environment.
-/// It is used by the compiler during symbol lookup to determine whether or not a
-/// symbol is in the lexical environment, global, or should be fetched directly from
-/// the stack.
-#[derive(Debug, Clone, Eq, PartialEq)]
-pub enum BindingLocation {
-    Argument(usize),
-    Global,
-    Environment(usize),
-}
-
 /// Environment Map
 ///
 /// An environment map is a set of instructions on how to build a
@@ -88,41 +71,13 @@ impl EnvironmentMap {
         EnvironmentMap { map: vec![] }
     }
 
-    /// New From IOF
+    /// Bind
     ///
-    /// Create a new lambda, and populate its environment map
-    /// given the IOF and set of free symbols.
-    pub fn new_from_iof(
-        args: &[VCell],
-        internally_defined: &[VCell],
-        iof: &Lambda,
-        free_symbols: &[VCell],
-    ) -> EnvironmentMap {
-        let mut map = args
-            .iter()
-            .enumerate()
-            .map(|it| (it.1.clone(), BindingSource::Argument(it.0)))
-            .collect::<Vec<(VCell, BindingSource)>>();
-
-        internally_defined
-            .iter()
-            .map(|it| (it.clone(), BindingSource::InternalDefinition))
-            .for_each(|binding| map.push(binding));
-
-        free_symbols
-            .iter()
-            .filter_map(|sym| {
-                if let Some(slot) = iof.envmap.get_slot(sym) {
-                    Some((sym.clone(), BindingSource::IofEnvironment(slot)))
-                } else if let Some((n, _)) = iof.args.iter().enumerate().find(|(_, it)| *it == sym)
-                {
-                    Some((sym.clone(), BindingSource::IofArgument(n)))
-                } else {
-                    None
-                }
-            })
-            .for_each(|binding| map.push(binding));
-        EnvironmentMap { map }
+    /// Append a slot for sym to the map, populated from the given
+    /// source, and return the new slot.
+    pub fn bind(&mut self, sym: VCell, source: BindingSource) -> usize {
+        self.map.push((sym, source));
+        self.map.len() - 1
     }
 
     /// Get Slot
@@ -201,7 +156,7 @@ impl LexicalEnvironment {
 /// GlobalEnvironment represents a binding of a symbol to a value in the heap.
 /// The environment tracks both deep bindings (sym -> slot), and also a
 /// vector of shallow bindings (slot -> vcell).
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct GlobalEnvironment {
     /// Deep bindings is a map of symbol ptr -> slot, and
     /// is used by the compiler to aassociate a symbol at compilation
@@ -221,14 +176,25 @@ impl GlobalEnvironment {
         }
     }
 
-    pub fn iter_bindings(&self) -> std::collections::hash_map::Keys<usize, usize> {
+    pub fn iter_bindings(&self) -> std::collections::hash_map::Keys<'_, usize, usize> {
         self.bindings.keys()
     }
 
-    pub fn iter_slots(&self) -> std::slice::Iter<VCell> {
+    pub fn iter_slots(&self) -> std::slice::Iter<'_, VCell> {
         self.slots.iter()
     }
 
+    /// Slot Symbols
+    ///
+    /// Return a map of each slot to the symbol bound to it, the reverse
+    /// of every binding created with get_binding().
+    pub fn slot_symbols(&self) -> HashMap<usize, usize> {
+        self.bindings
+            .iter()
+            .map(|(sym, slot)| (*slot, *sym))
+            .collect()
+    }
+
     /// Get binding
     ///
     /// Get binding provides a deep binding lookup of sym -> slot. If the
@@ -321,178 +287,11 @@ impl Default for GlobalEnvironment {
     }
 }
 
-/// Free Symbols
-///
-/// Given a cell, recursively collect a list of free (unbound) symbols.
-///
-/// A symbol is 'free' if at the time it is encountered it meets the
-/// following conditions:
-///
-/// * It is a symbol
-/// * It does not yet exist in 'env'
-/// * It is not a primitive procedure (+, if, cons, etc.)
-/// * It is not currently being defined (e.g. first argument of a define procedure)
-///
-/// While recursing, any formal arguments to lambda procedures will be added
-/// to 'environment' and no longer represent free symbols.
-///
-/// # Arguments
-/// `cell` - The cell to recurse
-pub fn free_symbols(cell: &Cell) -> Result<HashSet<&Cell>, Error> {
-    let mut env = HashSet::new();
-    let mut free_set = HashSet::new();
-    find_free_symbols(cell, &mut env, &mut free_set)?;
-    Ok(free_set)
-}
-
-/// Find Free Symbols
-///
-/// This is a recursive call from free_symbols.
-fn find_free_symbols<'a>(
-    cell: &'a Cell,
-    env: &mut HashSet<&'a Cell>,
-    free: &mut HashSet<&'a Cell>,
-) -> Result<(), Error> {
-    match cell {
-        Cell::Symbol(_) => match env.contains(&cell) {
-            true => Ok(()),
-            false => {
-                free.insert(cell);
-                Ok(())
-            }
-        },
-        Cell::Pair(car, cdr) => {
-            let car = car.as_ref();
-            let cdr = cdr.as_ref();
-            let mut env = env.clone();
-            find_free_symbols_in_proc((car, cdr), &mut env, free)?;
-            Ok(())
-        }
-        _ => Ok(()),
-    }
-}
-
-/// Find Free Symbols In Proc
-///
-/// This is a recursive call from free_syumbols.
-fn find_free_symbols_in_proc<'a>(
-    (car, cdr): (&'a Cell, &'a Cell),
-    env: &mut HashSet<&'a Cell>,
-    free: &mut HashSet<&'a Cell>,
-) -> Result<(), Error> {
-    if car.is_quote() || car.is_quasiquote() {
-        return Ok(());
-    }
-
-    if car.is_symbol() && !car.is_primitive_symbol() && !env.contains(car) {
-        free.insert(car);
-    }
-
-    // This is a special case where the procedure being applied is a procedure
-    // e.g. ((proc) 1 2)
-    if car.is_pair() {
-        find_free_symbols(car, env, free)?;
-    }
-
-    let mut rest = match car {
-        Cell::Symbol(sym) => match sym.as_str() {
-            "define" => {
-                let sym_or_args = cdr
-                    .car()
-                    .ok_or_else(|| Error::InvalidNumArgs("define".into()))?;
-
-                if sym_or_args.is_pair() {
-                    for sym in sym_or_args.cdr().unwrap() {
-                        if sym.is_symbol() {
-                            env.insert(sym);
-                        }
-                    }
-                }
-
-                cdr.cdr()
-                    .ok_or_else(|| Error::InvalidNumArgs("define".into()))?
-            }
-            "lambda" => {
-                let mut args = cdr
-                    .car()
-                    .ok_or_else(|| Error::InvalidNumArgs("lambda".into()))?;
-                while args.is_pair() {
-                    let sym = args.car().unwrap();
-                    if !sym.is_symbol() {
-                        return Err(Error::InvalidArgs(
-                            "lambda".into(),
-                            "argument".into(),
-                            sym.to_string(),
-                        ));
-                    }
-                    env.insert(sym);
-                    args = args.cdr().unwrap();
-                }
-                cdr.cdr()
-                    .ok_or_else(|| Error::InvalidNumArgs("lambda".into()))?
-            }
-            _ => cdr,
-        },
-        _ => cdr,
-    };
-
-    while rest.is_pair() {
-        find_free_symbols(rest.car().unwrap(), env, free)?;
-        rest = rest.cdr().unwrap();
-    }
-
-    // improper list
-    if !rest.is_nil() {
-        find_free_symbols(rest, env, free)?;
-    }
-
-    Ok(())
-}
-
-/// Interally defined symbols
-///
-/// Given the body of a lambda, return the list of internally defined
-/// symbols as a result of calls to define at the beginning of the block.
-///
-/// Internal define expressions are only allowed at the beginning of the
-/// block. Any additional defines will be skipped in anticipation of the
-/// compiler compiling this lambda expression forming an error.
-///
-/// This function also acts as a syntax check to ensure define is only
-/// present at the beginning of a body.
-///
-/// # Arguments
-/// `body` - The body of the lambda
-pub fn internally_defined_symbols(body: &Cell) -> Result<HashSet<&Cell>, Error> {
-    let mut symbols = HashSet::new();
-    let mut beginning_of_body = true;
-
-    for expr in body {
-        if expr.is_pair() && expr.car().unwrap().is_define() {
-            if !beginning_of_body {
-                return Err(InvalidSyntax(format!("out of context define: {:#}", expr)));
-            }
-            let expr = expr.cdr().unwrap();
-            if expr.is_pair() {
-                let expr = expr.car().unwrap();
-                if expr.is_symbol() {
-                    symbols.insert(expr);
-                } else if expr.is_pair() && expr.car().unwrap().is_symbol() {
-                    symbols.insert(expr.car().unwrap());
-                }
-            }
-            continue;
-        } else {
-            beginning_of_body = false;
-        }
-    }
-    Ok(symbols)
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{cell, lex, parse};
+    use crate::cell;
+    use crate::cell::Cell;
 
     #[test]
     fn same_binding_same_slot() {
@@ -514,105 +313,4 @@ mod tests {
         assert!(!cell!["foo"].is_primitive_symbol());
         assert!(!cell![100].is_primitive_symbol());
     }
-
-    #[test]
-    fn free_syms() {
-        // Single symbol not in the environment
-        assert_eq!(free_symbols(&parse!["a"]), Ok(HashSet::from([&cell!["a"]])));
-
-        // Atom
-        assert_eq!(free_symbols(&parse!["42"]), Ok(HashSet::new()));
-        assert_eq!(free_symbols(&parse!["#t"]), Ok(HashSet::new()));
-
-        // Any quoted symbols are completely ignored
-        assert_eq!(free_symbols(&parse!["(quote (a b c))"]), Ok(HashSet::new()));
-
-        // procedure
-        assert_eq!(
-            free_symbols(&parse!["(a b c)"]),
-            Ok(HashSet::from([&cell!["a"], &cell!["b"], &cell!["c"]]))
-        );
-
-        // Nested primitive procedures
-        assert_eq!(
-            free_symbols(&parse!["(+ (* a b) (* c d) e)"]),
-            Ok(HashSet::from([
-                &cell!["+"],
-                &cell!["*"],
-                &cell!["a"],
-                &cell!["b"],
-                &cell!["c"],
-                &cell!["d"],
-                &cell!["e"]
-            ]))
-        );
-
-        // Improper list
-        assert_eq!(
-            free_symbols(&parse!["(a b . c)"]),
-            Ok(HashSet::from([&cell!["a"], &cell!["b"], &cell!["c"]]))
-        );
-
-        // Define
-        assert_eq!(
-            free_symbols(&parse!["(define a b)"]),
-            Ok(HashSet::from([&cell!["b"]]))
-        );
-        assert_eq!(
-            free_symbols(&parse!["(define (a x) (+ x y))"]),
-            Ok(HashSet::from([&cell!["+"], &cell!["y"]]))
-        );
-
-        // inner define procedure
-        assert_eq!(
-            free_symbols(&parse![
-                r#"
-                (define (factorial n)
-                    (define (factorial n acc)
-                       (if (= n 0) 
-                          acc
-                          (factorial (- n 1) (* n acc))))
-                    (factorial n 1))                    
-            "#
-            ]),
-            Ok(HashSet::from([
-                &cell!["="],
-                &cell!["-"],
-                &cell!["*"],
-                &cell!["factorial"]
-            ]))
-        );
-
-        // lambdas
-        assert_eq!(
-            free_symbols(&parse!["(lambda (x) (+ x y) z)"]),
-            Ok(HashSet::from([&cell!["y"], &cell!["z"], &cell!["+"]]))
-        );
-
-        assert_eq!(
-            free_symbols(&parse!("(lambda (n) (+ ((adder num) n)))")),
-            Ok(HashSet::from([&cell!["adder"], &cell!["num"], &cell!["+"]]))
-        );
-
-        assert!(free_symbols(&parse!["(lambda)"]).is_err());
-        assert!(free_symbols(&parse!["(lambda (10) (+ x y))"]).is_err());
-        assert!(free_symbols(&parse!["(lambda (a 10) (+ x y))"]).is_err());
-    }
-
-    #[test]
-    fn free_syms_returns_errors() {
-        assert!(free_symbols(&parse!["(define)"]).is_err());
-    }
-
-    #[test]
-    fn internally_defined_symbols_returns_vec() {
-        assert_eq!(
-            internally_defined_symbols(&parse!["((define foo 10)(define (bar baz) 10))"]),
-            Ok(HashSet::from([&cell!["foo"], &cell!["bar"]]))
-        );
-        assert!(internally_defined_symbols(&parse![
-            "((define foo 10)(set! foo 5)(define (bar baz) 10))"
-        ])
-        .is_err());
-    }
 }
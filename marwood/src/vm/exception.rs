@@ -0,0 +1,103 @@
+use crate::cell::Cell;
+use crate::error::Error;
+use crate::error::Error::ErrorSignal;
+use crate::vm::stack::Stack;
+use crate::vm::vcell::VCell;
+use crate::vm::Vm;
+
+/// Exceptions
+///
+/// The scheme exception system (with-exception-handler, raise, guard, etc)
+/// is implemented by the prelude, which keeps the stack of installed
+/// exception handlers in the global %handlers.
+///
+/// Runtime errors raised by the Vm itself, such as applying car to a
+/// non-pair or exceeding a memory limit, are raised to the handlers as
+/// error objects if any handlers are installed. Otherwise the error is
+/// returned from the evaluation that caused it.
+impl Vm {
+    /// Raise Error
+    ///
+    /// If any exception handlers are installed, raise error to them as an
+    /// error object by calling %raise-error with its message and irritants,
+    /// and return true. %raise-error never returns, so the call is made
+    /// on top of the stack as it was when the error occurred, unless that
+    /// would exceed the stack depth limit.
+    pub fn raise_error(&mut self, error: &Error) -> bool {
+        if !error.is_catchable() || !self.has_exception_handlers() {
+            return false;
+        }
+        let Some(raise) = self.global("%raise-error") else {
+            return false;
+        };
+        let lambda = match self.heap.get(&raise) {
+            VCell::Closure(lambda, _) => lambda,
+            VCell::Lambda(_) => match raise.as_ptr() {
+                Ok(lambda) => lambda,
+                Err(_) => return false,
+            },
+            _ => return false,
+        };
+
+        let (message, irritants) = match error {
+            ErrorSignal(cells) => match cells.split_first() {
+                Some((Cell::String(message), irritants)) => (message.clone(), irritants.to_vec()),
+                _ => (String::new(), cells.clone()),
+            },
+            error => (error.to_string(), vec![]),
+        };
+        let message = self.heap.put_cell(&Cell::String(message));
+        let irritants = self.heap.put_cell(&Cell::new_list(irritants));
+
+        let frame = [
+            message,
+            irritants,
+            VCell::ArgumentCount(2),
+            VCell::EnvironmentPointer(self.ep),
+            VCell::InstructionPointer(self.ip.0, self.ip.1),
+        ];
+        if frame
+            .into_iter()
+            .try_for_each(|it| self.stack.push(it))
+            .is_err()
+        {
+            return false;
+        }
+        self.acc = raise;
+        self.ip = (lambda, 0);
+        true
+    }
+
+    /// Reset After Error
+    ///
+    /// Discard the stack and any exception handlers of an evaluation that
+    /// failed with an error, so that the Vm may evaluate again.
+    pub fn reset_after_error(&mut self) {
+        self.stack = Stack::new();
+        self.bp = 0;
+        self.reset_stack_limit();
+        if self.has_exception_handlers() {
+            let slot = self.global_slot(&Cell::new_symbol("%handlers"));
+            let nil = self.heap.put(VCell::Nil);
+            self.globenv.put_slot(slot, nil);
+        }
+    }
+
+    fn has_exception_handlers(&mut self) -> bool {
+        match self.global("%handlers") {
+            Some(handlers) => self.heap.get(&handlers).is_pair(),
+            None => false,
+        }
+    }
+
+    /// Global
+    ///
+    /// Return the value bound to the global named name, if any.
+    pub fn global(&mut self, name: &str) -> Option<VCell> {
+        let sym = self.heap.get_sym_ref(&Cell::new_symbol(name))?;
+        match self.globenv.get(sym.as_ptr().ok()?)? {
+            VCell::Undefined => None,
+            value => Some(value),
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use crate::cell::Cell;
+use crate::error::Error;
+use crate::error::Error::ExecutionFinished;
+use crate::vm::heap::HeapRef;
+use crate::vm::stack::Stack;
+use crate::vm::vcell::VCell;
+use crate::vm::Vm;
+use std::task::Poll;
+
+/// Execution
+///
+/// An Execution is a handle to a computation started with Vm::start, which
+/// runs only when resumed, for a budget of instructions at a time. Many
+/// executions may be suspended in one Vm at once and resumed in any order,
+/// such as to cooperatively schedule scripts on one thread, and the Vm may
+/// evaluate other expressions while they're suspended.
+///
+/// The state of a suspended execution is kept by the Vm it was started in
+/// and is a root of garbage collection, so an execution that is abandoned
+/// before finishing should be cancelled to release it.
+#[derive(Debug, Eq, PartialEq, Hash)]
+pub struct Execution {
+    id: usize,
+}
+
+/// Execution State
+///
+/// The registers and stack of a suspended execution, along with the
+/// exception handlers it has installed, if any.
+#[derive(Debug, Clone)]
+pub struct ExecutionState {
+    pub stack: Stack,
+    pub acc: VCell,
+    pub ep: HeapRef,
+    pub ip: (HeapRef, usize),
+    pub bp: usize,
+    pub handlers: Option<VCell>,
+}
+
+impl ExecutionState {
+    /// Idle
+    ///
+    /// Return the state of a Vm that isn't running.
+    pub fn idle() -> ExecutionState {
+        ExecutionState {
+            stack: Stack::new(),
+            acc: VCell::Undefined,
+            ep: usize::MAX,
+            ip: (usize::MAX, 0),
+            bp: 0,
+            handlers: None,
+        }
+    }
+
+    /// Iter Roots
+    ///
+    /// Return an iterator to the vcells of the suspended execution that are
+    /// roots of garbage collection.
+    pub fn iter_roots(&self) -> impl Iterator<Item = VCell> + '_ {
+        self.stack
+            .iter_to_sp()
+            .cloned()
+            .chain([self.acc.clone(), VCell::Ptr(self.ip.0), VCell::Ptr(self.ep)])
+            .chain(self.handlers.clone())
+    }
+}
+
+impl Vm {
+    /// Start
+    ///
+    /// Compile expr and return an Execution that evaluates it once resumed.
+    ///
+    /// # Arguments
+    /// `expr` - The expression to evaluate
+    pub fn start(&mut self, expr: &Cell) -> Result<Execution, Error> {
+        let lambda = self.compile_runnable(expr)?;
+        let lambda = self.heap.put(lambda).as_ptr()?;
+        let id = self.next_execution;
+        self.next_execution += 1;
+        self.executions.insert(
+            id,
+            ExecutionState {
+                ip: (lambda, 0),
+                ..ExecutionState::idle()
+            },
+        );
+        Ok(Execution { id })
+    }
+
+    /// Swap Execution State
+    ///
+    /// Exchange the Vm's registers, stack and exception handlers with
+    /// state. A state without handlers has none installed. The stack
+    /// swapped in takes on the stack depth limit.
+    pub fn swap_execution_state(&mut self, state: &mut ExecutionState) {
+        std::mem::swap(&mut self.stack, &mut state.stack);
+        std::mem::swap(&mut self.acc, &mut state.acc);
+        std::mem::swap(&mut self.ep, &mut state.ep);
+        std::mem::swap(&mut self.ip, &mut state.ip);
+        std::mem::swap(&mut self.bp, &mut state.bp);
+
+        let slot = self.global_slot(&Cell::new_symbol("%handlers"));
+        let handlers = match state.handlers.take() {
+            Some(handlers) => handlers,
+            None => self.heap.put(VCell::Nil),
+        };
+        state.handlers = Some(self.globenv.get_slot(slot));
+        self.globenv.put_slot(slot, handlers);
+        self.reset_stack_limit();
+    }
+}
+
+impl Execution {
+    /// Resume
+    ///
+    /// Run the execution for up to budget instructions, returning Pending
+    /// if it's still running once the budget is exhausted, or else its
+    /// result. Resuming a finished or cancelled execution is an error.
+    ///
+    /// # Arguments
+    /// `vm` - The Vm the execution was started in
+    /// `budget` - The maximum number of instructions to execute
+    pub fn resume(&self, vm: &mut Vm, budget: usize) -> Poll<Result<Cell, Error>> {
+        let Some(mut state) = vm.executions.remove(&self.id) else {
+            return Poll::Ready(Err(ExecutionFinished));
+        };
+        vm.swap_execution_state(&mut state);
+        vm.saved_states.push(state);
+
+        let result = match vm.run_count_vcell(budget.saturating_add(1)) {
+            Ok(Some(acc)) => {
+                let acc = acc.clone();
+                Poll::Ready(Ok(vm.heap.get_as_cell(&acc)))
+            }
+            Ok(None) => Poll::Pending,
+            Err(e) => Poll::Ready(Err(e)),
+        };
+
+        // Restore the state the execution was resumed over, such as that of
+        // an evaluation suspended by run_count.
+        let mut state = vm.saved_states.pop().unwrap_or_else(ExecutionState::idle);
+        vm.swap_execution_state(&mut state);
+        if result.is_pending() {
+            vm.executions.insert(self.id, state);
+        }
+        result
+    }
+
+    /// Cancel
+    ///
+    /// Abandon the execution, releasing its state.
+    pub fn cancel(self, vm: &mut Vm) {
+        vm.executions.remove(&self.id);
+    }
+
+    /// Is Finished
+    ///
+    /// Return true if the execution has finished or was cancelled.
+    pub fn is_finished(&self, vm: &Vm) -> bool {
+        !vm.executions.contains_key(&self.id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error::VariableNotBound;
+    use crate::vm::gc::GcConfig;
+    use crate::{cell, lex, parse};
+
+    /// Resume each execution in turn for budget instructions until all
+    /// have finished, returning their results.
+    fn run_all(vm: &mut Vm, executions: &[Execution], budget: usize) -> Vec<Result<Cell, Error>> {
+        let mut results = executions.iter().map(|_| None).collect::<Vec<_>>();
+        while results.iter().any(Option::is_none) {
+            for (exec, result) in executions.iter().zip(results.iter_mut()) {
+                if result.is_none() {
+                    if let Poll::Ready(it) = exec.resume(vm, budget) {
+                        *result = Some(it);
+                    }
+                }
+            }
+        }
+        results.into_iter().map(Option::unwrap).collect()
+    }
+
+    #[test]
+    fn resume_until_ready() {
+        let mut vm = Vm::new();
+        let exec = vm
+            .start(&parse!("(let loop ((i 0) (acc '())) (if (= i 100) (length acc) (loop (+ i 1) (cons i acc))))"))
+            .unwrap();
+        let mut pending = 0;
+        let result = loop {
+            match exec.resume(&mut vm, 10) {
+                Poll::Pending => pending += 1,
+                Poll::Ready(result) => break result,
+            }
+            assert_eq!(vm.eval(&parse!("(+ 1 2)")), Ok(cell![3]));
+        };
+        assert_eq!(result, Ok(cell![100]));
+        assert!(pending > 10);
+        assert!(exec.is_finished(&vm));
+        assert_eq!(
+            exec.resume(&mut vm, 10),
+            Poll::Ready(Err(ExecutionFinished))
+        );
+    }
+
+    #[test]
+    fn interleaved_executions_survive_gc() {
+        let mut vm = Vm::new();
+        vm.set_gc_config(GcConfig {
+            interval: 1,
+            threshold: 0.0,
+            grow_threshold: 0.75,
+            budget: Some(16),
+        });
+        vm.eval(&parse!(
+            "(define (build n) (let loop ((i 0) (acc '())) (if (= i n) acc (loop (+ i 1) (cons (list i) acc)))))"
+        ))
+        .unwrap();
+        let executions = (1..=4)
+            .map(|n| {
+                vm.start(&parse!(&format!("(apply + (map car (build {})))", n * 100)))
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            run_all(&mut vm, &executions, 7),
+            vec![
+                Ok(cell![4950]),
+                Ok(cell![19900]),
+                Ok(cell![44850]),
+                Ok(cell![79800])
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_and_handlers() {
+        let mut vm = Vm::new();
+        let executions = [
+            "(with-exception-handler (lambda (e) 'a) (lambda () (let loop ((i 0)) (if (< i 500) (loop (+ i 1)) (raise-continuable 'x)))))",
+            "(guard (e (#t (list 'b e))) (let loop ((i 0)) (if (< i 500) (loop (+ i 1)) (raise 'y))))",
+            "(let loop ((i 0)) (if (< i 500) (loop (+ i 1)) undefined-variable))",
+        ]
+        .iter()
+        .map(|expr| vm.start(&parse!(expr)).unwrap())
+        .collect::<Vec<_>>();
+        assert_eq!(
+            run_all(&mut vm, &executions, 13),
+            vec![
+                Ok(cell!["a"]),
+                Ok(parse!("(b y)")),
+                Err(VariableNotBound("undefined-variable".into()))
+            ]
+        );
+        assert_eq!(vm.eval(&parse!("%handlers")), Ok(cell![]));
+    }
+
+    #[test]
+    fn resume_during_evaluation() {
+        let mut vm = Vm::new();
+        let exec = vm
+            .start(&parse!(
+                "(let loop ((i 0)) (if (= i 100) 'done (loop (+ i 1))))"
+            ))
+            .unwrap();
+        vm.prepare_eval(&parse!(
+            "(let loop ((i 0) (acc '())) (if (= i 100) (length acc) (loop (+ i 1) (cons i acc))))"
+        ))
+        .unwrap();
+        assert_eq!(vm.run_count(10), Ok(None));
+        assert_eq!(exec.resume(&mut vm, 10_000), Poll::Ready(Ok(cell!["done"])));
+        assert_eq!(vm.run(), Ok(cell![100]));
+    }
+
+    #[test]
+    fn cancel() {
+        let mut vm = Vm::new();
+        let exec = vm.start(&parse!("(let loop () (loop))")).unwrap();
+        assert_eq!(exec.resume(&mut vm, 100), Poll::Pending);
+        assert!(!exec.is_finished(&vm));
+        let id = exec.id;
+        exec.cancel(&mut vm);
+        assert!(Execution { id }.is_finished(&vm));
+    }
+}
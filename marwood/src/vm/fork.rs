@@ -0,0 +1,203 @@
+use crate::vm::environment::LexicalEnvironment;
+use crate::vm::execution::ExecutionState;
+use crate::vm::interrupt::InterruptHandle;
+use crate::vm::stack::Stack;
+use crate::vm::thread::{GreenThread, Scheduler};
+use crate::vm::vcell::VCell;
+use crate::vm::vector::Vector;
+use crate::vm::Vm;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+impl Vm {
+    /// Fork
+    ///
+    /// Return a deep copy of the Vm, duplicating its heap, global environment,
+    /// stack and registers. Mutable objects shared by reference counting, such
+    /// as strings, vectors, lexical environments and continuations, are copied
+    /// once each so that sharing and cycles among them are preserved, and no
+    /// mutation of the fork is visible to this Vm or vice versa. Immutable
+    /// objects such as lambdas and symbols remain shared.
+    ///
+    /// A warmed up Vm may be forked to cheaply obtain fresh interpreters
+    /// without loading the prelude again.
+    pub fn fork(&self) -> Vm {
+        let mut copier = Copier::default();
+
+        let heap = self.heap.map_cells(|it| copier.copy(it));
+
+        let mut globenv = self.globenv.clone();
+        for (slot, vcell) in self.globenv.iter_slots().enumerate() {
+            globenv.put_slot(slot, copier.copy(vcell));
+        }
+
+        let mut stack = Stack::from_vec(
+            self.stack.iter().map(|it| copier.copy(it)).collect(),
+            self.stack.get_sp(),
+        );
+        stack.set_limit(self.limits.stack_depth);
+
+        let mut copy_state = |state: &ExecutionState| ExecutionState {
+            stack: Stack::from_vec(
+                state.stack.iter().map(|it| copier.copy(it)).collect(),
+                state.stack.get_sp(),
+            ),
+            acc: copier.copy(&state.acc),
+            ..state.clone()
+        };
+
+        let executions = self
+            .executions
+            .iter()
+            .map(|(id, state)| (*id, copy_state(state)))
+            .collect();
+
+        let saved_states = self.saved_states.iter().map(&mut copy_state).collect();
+
+        let ready = self
+            .scheduler
+            .ready
+            .iter()
+            .map(|it| GreenThread {
+                thread: it.thread.clone(),
+                state: copy_state(&it.state),
+            })
+            .collect();
+        let scheduler = Scheduler {
+            ready,
+            ..self.scheduler.clone()
+        };
+
+        Vm {
+            heap,
+            globenv,
+            stack,
+            acc: copier.copy(&self.acc),
+            ep: self.ep,
+            ip: self.ip,
+            bp: self.bp,
+            sys: self.sys.clone(),
+            last_stacktrace: None,
+            optimize: self.optimize,
+            gc_config: self.gc_config.clone(),
+            limits: self.limits.clone(),
+            builtin_sp: 0,
+            interrupt: InterruptHandle::new(),
+            executions,
+            saved_states,
+            next_execution: self.next_execution,
+            scheduler,
+            debugger: self.debugger.clone(),
+            source_map: self.source_map.clone(),
+        }
+    }
+}
+
+impl Clone for Vm {
+    fn clone(&self) -> Self {
+        self.fork()
+    }
+}
+
+/// Copier
+///
+/// Copier deep copies vcells, mapping each shared object of the original
+/// to its single copy.
+#[derive(Default)]
+struct Copier {
+    copies: HashMap<*const (), VCell>,
+}
+
+impl Copier {
+    fn copy(&mut self, vcell: &VCell) -> VCell {
+        let key = match vcell {
+            VCell::String(s) => Rc::as_ptr(s) as *const (),
+            VCell::Vector(vector) => Rc::as_ptr(vector) as *const (),
+            VCell::LexicalEnv(env) => Rc::as_ptr(env) as *const (),
+            VCell::Continuation(cont) => Rc::as_ptr(cont) as *const (),
+            vcell => return vcell.clone(),
+        };
+        if let Some(copy) = self.copies.get(&key) {
+            return copy.clone();
+        }
+
+        // Aggregates are recorded before their elements are copied, so that
+        // an aggregate containing itself refers to its own copy.
+        match vcell {
+            VCell::String(s) => {
+                let copy = VCell::String(Rc::new(s.as_ref().clone()));
+                self.copies.insert(key, copy.clone());
+                copy
+            }
+            VCell::Vector(vector) => {
+                let copy = Rc::new(Vector::new(vec![]));
+                self.copies.insert(key, VCell::Vector(copy.clone()));
+                for it in vector.clone_vector(None, None) {
+                    copy.push(self.copy(&it));
+                }
+                VCell::Vector(copy)
+            }
+            VCell::LexicalEnv(env) => {
+                let copy = Rc::new(LexicalEnvironment::new(env.slot_len()));
+                self.copies.insert(key, VCell::LexicalEnv(copy.clone()));
+                for slot in 0..env.slot_len() {
+                    copy.put(slot, self.copy(&env.get(slot)));
+                }
+                VCell::LexicalEnv(copy)
+            }
+            VCell::Continuation(cont) => {
+                let stack = cont.stack().iter().map(|it| self.copy(it)).collect();
+                let copy = VCell::Continuation(Rc::new(
+                    cont.with_stack(Stack::from_vec(stack, cont.stack().get_sp())),
+                ));
+                self.copies.insert(key, copy.clone());
+                copy
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell::Cell;
+    use crate::{cell, lex, parse};
+
+    #[test]
+    fn fork_is_isolated() {
+        let mut vm = Vm::new();
+        for expr in [
+            "(define counter (let ((n 0)) (lambda () (set! n (+ n 1)) n)))",
+            "(counter)",
+            "(define v (vector 1 2))",
+            "(vector-set! v 0 v)",
+            "(define s (make-string 2 #\\a))",
+            "(define p (cons s s))",
+            "(define k #f)",
+            "(define l (list 1 (call/cc (lambda (c) (set! k c) 2))))",
+        ] {
+            vm.eval(&parse!(expr)).unwrap();
+        }
+
+        let mut fork = vm.clone();
+        assert_eq!(fork.eval(&parse!("(counter)")), Ok(cell![2]));
+        assert_eq!(fork.eval(&parse!("(counter)")), Ok(cell![3]));
+        assert_eq!(vm.eval(&parse!("(counter)")), Ok(cell![2]));
+
+        assert_eq!(
+            fork.eval(&parse!("(eq? (vector-ref v 0) v)")),
+            Ok(cell![true])
+        );
+        fork.eval(&parse!("(vector-set! v 1 'forked)")).unwrap();
+        assert_eq!(vm.eval(&parse!("(vector-ref v 1)")), Ok(cell![2]));
+
+        fork.eval(&parse!("(string-set! (car p) 0 #\\b)")).unwrap();
+        assert_eq!(fork.eval(&parse!("(cdr p)")), Ok(parse!(r#""ba""#)));
+        assert_eq!(vm.eval(&parse!("s")), Ok(parse!(r#""aa""#)));
+
+        fork.eval(&parse!("(k 3)")).unwrap();
+        assert_eq!(fork.eval(&parse!("l")), Ok(parse!("(1 3)")));
+        assert_eq!(vm.eval(&parse!("l")), Ok(parse!("(1 2)")));
+    }
+}
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 /// GcMap
 ///
 /// GcMap is a map used to track the disposition of objects on a
@@ -10,7 +12,7 @@
 ///
 /// The initial state for a vcell is free (0x0, and then when allocated
 /// the state is transitioned to Allocated.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Map {
     size: usize,
     map: Vec<u8>,
@@ -27,7 +29,8 @@ pub struct Map {
 /// Allocated: The object is allocated and believed to be in use. It is
 ///       subject to garbage collection checks.
 ///
-/// Used: The object has been marked during garbage collection. This is an
+/// Used: The object has been marked during garbage collection, or was
+///       allocated while a collection is in progress. This is an
 ///       ephemeral state used during GC before the object is either marked
 ///       allocated again, or free.
 #[derive(Debug, Eq, PartialEq, Clone)]
@@ -132,6 +135,137 @@ impl Map {
     }
 }
 
+/// Phase
+///
+/// Phase is the progress of an incremental garbage collection cycle.
+///
+/// Idle: No collection is in progress.
+///
+/// Mark: Objects reachable from the grey set are being marked. Objects
+///       allocated in this phase are marked as they're allocated.
+///
+/// Sweep: Unmarked objects are being freed, and every object before the
+///       cursor has been swept.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Phase {
+    Idle,
+    Mark,
+    Sweep(usize),
+}
+
+/// GC Config
+///
+/// GcConfig tunes when a Vm collects garbage, and how long it may pause
+/// for each increment of a collection.
+#[derive(Debug, PartialEq, Clone)]
+pub struct GcConfig {
+    /// The number of instructions executed between increments.
+    pub interval: usize,
+
+    /// The heap utilization at or above which a collection begins.
+    pub threshold: f64,
+
+    /// The heap utilization after a collection above which the heap grows.
+    pub grow_threshold: f64,
+
+    /// The number of objects each increment may mark or sweep, or None to
+    /// complete a collection in a single pause.
+    pub budget: Option<usize>,
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        GcConfig {
+            interval: 8192,
+            threshold: 0.75,
+            grow_threshold: 0.75,
+            budget: Some(32768),
+        }
+    }
+}
+
+/// GC Stats
+///
+/// GcStats records how the heap and its collector have behaved over the
+/// lifetime of a Vm.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct GcStats {
+    /// The number of completed collections.
+    pub collections: usize,
+
+    /// The number of pauses taken to collect, each of which performs one
+    /// increment of a collection.
+    pub increments: usize,
+
+    /// The total time spent paused for collection.
+    pub total_pause: Duration,
+
+    /// The longest single pause.
+    pub max_pause: Duration,
+
+    /// The most recent pause.
+    pub last_pause: Duration,
+
+    /// The total number of vcells freed by collection.
+    pub cells_reclaimed: usize,
+
+    /// The total number of bytes freed by collection.
+    pub bytes_reclaimed: usize,
+
+    /// The number of times the heap has grown.
+    pub heap_growths: usize,
+
+    /// The capacity of the heap in vcells.
+    pub heap_size: usize,
+
+    /// The number of vcells in use.
+    pub used: usize,
+
+    /// The largest number of vcells that have been in use at once.
+    pub peak_used: usize,
+}
+
+impl GcStats {
+    /// Record Pause
+    ///
+    /// Record a pause of the given duration, and whether it completed a
+    /// collection.
+    pub fn record_pause(&mut self, pause: Duration, completed: bool) {
+        self.increments += 1;
+        self.total_pause += pause;
+        self.max_pause = self.max_pause.max(pause);
+        self.last_pause = pause;
+        if completed {
+            self.collections += 1;
+        }
+    }
+}
+
+/// Stopwatch
+///
+/// Stopwatch measures collection pauses. There's no clock available to
+/// wasm32 targets without a host interface, so pauses there measure zero.
+pub struct Stopwatch {
+    #[cfg(not(target_arch = "wasm32"))]
+    start: std::time::Instant,
+}
+
+impl Stopwatch {
+    pub fn start() -> Stopwatch {
+        Stopwatch {
+            #[cfg(not(target_arch = "wasm32"))]
+            start: std::time::Instant::now(),
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        #[cfg(not(target_arch = "wasm32"))]
+        return self.start.elapsed();
+        #[cfg(target_arch = "wasm32")]
+        return Duration::ZERO;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,25 +1,40 @@
 use crate::cell;
 use crate::cell::Cell;
-use crate::vm::continuation::Continuation;
 use crate::vm::gc;
-use crate::vm::gc::State;
-use crate::vm::lambda::Lambda;
+use crate::vm::gc::{GcStats, Phase, State};
 use crate::vm::vcell::VCell;
 use log::trace;
 use num::ToPrimitive;
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::ops::Deref;
+use std::time::Duration;
 
 pub type HeapRef = usize;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Heap {
     chunk_size: usize,
     free_list: Vec<usize>,
     heap: Vec<VCell>,
     heap_map: gc::Map,
     symbol_table: HashMap<String, usize>,
+
+    /// The phase of the incremental collection in progress
+    phase: Phase,
+
+    /// Values whose children remain to be marked by the collection in
+    /// progress
+    grey: Vec<VCell>,
+
+    /// Statistics about the heap and its collections
+    stats: GcStats,
+
+    /// The number of vcells the heap may grow to hold, if limited
+    limit: Option<usize>,
+
+    /// Whether the heap has grown past its limit since it was last checked
+    exhausted: bool,
 }
 
 impl Heap {
@@ -34,36 +49,115 @@ impl Heap {
             free_list: (0..chunk_size).rev().collect(),
             heap_map: gc::Map::new(chunk_size),
             symbol_table: HashMap::new(),
+            phase: Phase::Idle,
+            grey: vec![],
+            stats: GcStats::default(),
+            limit: None,
+            exhausted: false,
+        }
+    }
+
+    /// From Cells
+    ///
+    /// Construct a heap of the given chunk size from the value of each of
+    /// its vcells, or None for a free vcell, such as when restoring a
+    /// snapshot. The number of cells must be a multiple of the chunk size.
+    pub fn from_cells(chunk_size: usize, cells: Vec<Option<VCell>>) -> Heap {
+        let mut heap = Heap {
+            chunk_size,
+            free_list: vec![],
+            heap: Vec::with_capacity(cells.len()),
+            heap_map: gc::Map::new(cells.len()),
+            symbol_table: HashMap::new(),
+            phase: Phase::Idle,
+            grey: vec![],
+            stats: GcStats::default(),
+            limit: None,
+            exhausted: false,
+        };
+        for (ptr, vcell) in cells.into_iter().enumerate() {
+            match vcell {
+                Some(vcell) => {
+                    if let VCell::Symbol(sym) = &vcell {
+                        heap.symbol_table.insert(sym.deref().into(), ptr);
+                    }
+                    heap.heap_map.set(ptr, State::Allocated);
+                    heap.heap.push(vcell);
+                }
+                None => heap.heap.push(VCell::undefined()),
+            }
         }
+        heap.free_list = (0..heap.heap.len())
+            .rev()
+            .filter(|it| heap.is_free(*it))
+            .collect();
+        heap.stats.peak_used = heap.used_size();
+        heap
     }
 
     /// Grow
     ///
     /// Grow the heap by one chunk, adding the newly created nodes
-    /// too the free list.
+    /// too the free list. A limited heap grows no further than its limit,
+    /// unless it's already full.
     pub fn grow(&mut self) {
         let current_size = self.heap.len();
-        let new_size = ((current_size / self.chunk_size) as f64 * 1.5)
+        let mut new_size = ((current_size / self.chunk_size) as f64 * 1.5)
             .ceil()
             .to_usize()
             .unwrap()
             * self.chunk_size;
+        if let Some(limit) = self.limit {
+            let limit = limit.next_multiple_of(self.chunk_size);
+            new_size = new_size.min(limit.max(current_size + self.chunk_size));
+        }
         self.heap.resize(new_size, VCell::undefined());
         self.heap_map.resize(new_size);
         (current_size..new_size).for_each(|it| self.free_list.push(it));
+        self.stats.heap_growths += 1;
+    }
+
+    /// Map Cells
+    ///
+    /// Return a copy of the heap with f applied to each of its vcells,
+    /// including those waiting to be marked by a collection in progress.
+    pub fn map_cells(&self, mut f: impl FnMut(&VCell) -> VCell) -> Heap {
+        Heap {
+            chunk_size: self.chunk_size,
+            free_list: self.free_list.clone(),
+            heap: self.heap.iter().map(&mut f).collect(),
+            heap_map: self.heap_map.clone(),
+            symbol_table: self.symbol_table.clone(),
+            phase: self.phase,
+            grey: self.grey.iter().map(&mut f).collect(),
+            stats: self.stats.clone(),
+            limit: self.limit,
+            exhausted: self.exhausted,
+        }
     }
 
     /// Alloc
     ///
-    /// Return the next free slot from the free list.
+    /// Return the next free slot from the free list. While a collection is
+    /// in progress, a slot that won't be swept again before the collection
+    /// ends is allocated already marked.
     pub fn alloc(&mut self) -> usize {
         match self.free_list.pop() {
             None => {
+                if self.is_full() {
+                    self.exhausted = true;
+                }
                 self.grow();
                 self.alloc()
             }
             Some(ptr) => {
-                self.heap_map.set(ptr, State::Allocated);
+                let state = match self.phase {
+                    Phase::Mark => State::Used,
+                    Phase::Sweep(cursor) if ptr >= cursor => State::Used,
+                    _ => State::Allocated,
+                };
+                self.heap_map.set(ptr, state);
+                self.stats.peak_used = self.stats.peak_used.max(self.used_size());
                 ptr
             }
         }
@@ -90,8 +184,11 @@ impl Heap {
         let vcell = vcell.into();
         match &vcell {
             VCell::Ptr(_) => vcell,
-            VCell::Symbol(sym) => match self.symbol_table.get(sym.deref()) {
-                Some(ptr) => VCell::ptr(*ptr),
+            VCell::Symbol(sym) => match self.symbol_table.get(sym.deref()).copied() {
+                Some(ptr) => {
+                    self.revive(ptr);
+                    VCell::ptr(ptr)
+                }
                 None => {
                     let ptr = self.alloc();
                     *self.heap.get_mut(ptr).expect("heap index is out of bounds") = vcell.clone();
@@ -101,7 +198,7 @@ impl Heap {
             },
             vcell => {
                 let ptr = self.alloc();
-                *self.heap.get_mut(ptr).expect("heap index is out of bounds") = vcell.clone();
+                self.put_at_index(ptr, vcell.clone());
                 VCell::Ptr(ptr)
             }
         }
@@ -125,8 +222,11 @@ impl Heap {
             | VCell::Void
             | VCell::Undefined => vcell,
             VCell::Ptr(_) => vcell,
-            VCell::Symbol(sym) => match self.symbol_table.get(sym.deref()) {
-                Some(ptr) => VCell::ptr(*ptr),
+            VCell::Symbol(sym) => match self.symbol_table.get(sym.deref()).copied() {
+                Some(ptr) => {
+                    self.revive(ptr);
+                    VCell::ptr(ptr)
+                }
                 None => {
                     let ptr = self.alloc();
                     *self.heap.get_mut(ptr).expect("heap index is out of bounds") = vcell.clone();
@@ -136,7 +236,7 @@ impl Heap {
             },
             vcell => {
                 let ptr = self.alloc();
-                *self.heap.get_mut(ptr).expect("heap index is out of bounds") = vcell.clone();
+                self.put_at_index(ptr, vcell.clone());
                 VCell::Ptr(ptr)
             }
         }
@@ -184,8 +284,9 @@ impl Heap {
                     _ => panic!("expected ptr, got {:?}", ast),
                 }
             }
-            cell::Cell::String(ref s) => self.put(VCell::string(s.clone())),
+            cell::Cell::String(ref s) => self.put(VCell::string(s.as_str())),
             cell::Cell::Symbol(ref sym) => self.put(VCell::symbol(sym.clone())),
+            cell::Cell::CharSet => panic!("unexpected char-set"),
             cell::Cell::Continuation => panic!("unexpected continuation"),
             cell::Cell::Macro => panic!("unexpected macro"),
             cell::Cell::Procedure(_) => panic!("unexpected lambda"),
@@ -221,6 +322,20 @@ impl Heap {
         self.heap.get_mut(ptr).expect("heap index out of bounds")
     }
 
+    /// Put at Index
+    ///
+    /// Replace the vcell at ptr, shading the new value while marking is in
+    /// progress. Any mutation of a heap object must go through put_at_index,
+    /// or shade the stored value itself.
+    ///
+    /// # Arguments
+    /// `ptr` - The index of the vcell to replace.
+    /// `vcell` - The new value.
+    pub fn put_at_index(&mut self, ptr: usize, vcell: VCell) {
+        self.shade(&vcell);
+        *self.heap.get_mut(ptr).expect("heap index out of bounds") = vcell;
+    }
+
     /// Get
     ///
     /// Return a vcell from the heap at the given ptr. If the vcell
@@ -290,10 +405,11 @@ impl Heap {
                 }
             }
             VCell::Ptr(ptr) => self.get_as_cell(self.get_at_index(*ptr)),
-            VCell::String(s) => Cell::String(s.borrow().deref().into()),
+            VCell::String(s) => Cell::String(s.to_string()),
             VCell::Symbol(s) => Cell::Symbol(s.deref().into()),
             VCell::Undefined => Cell::Undefined,
             VCell::Void => Cell::Void,
+            VCell::CharSet(_) => Cell::CharSet,
             VCell::Continuation(_) => Cell::Continuation,
             VCell::Closure(ptr, _) => match self.get_at_index(*ptr).as_lambda() {
                 Ok(lambda) => Cell::Procedure(Some(lambda.to_string())),
@@ -314,6 +430,7 @@ impl Heap {
             VCell::Acc
             | VCell::ArgumentCount(_)
             | VCell::BasePointer(_)
+            | VCell::Prompt(_)
             | VCell::BasePointerOffset(_)
             | VCell::EnvironmentPointer(_)
             | VCell::GlobalEnvSlot(_)
@@ -329,165 +446,202 @@ impl Heap {
 
     /// Mark
     ///
-    /// Mark the given root vcell in the gc map, and recursively mark any of
-    /// its children.
+    /// Mark the given root vcell in the gc map, and any of its children.
     ///
     /// # Arguments
     /// `root` - The root vcell to mark
     pub fn mark(&mut self, root: usize, force: bool) {
-        let mut ptr = root;
-        loop {
-            let vcell = match self.heap.get(ptr) {
-                Some(vcell) => vcell.clone(),
-                None => {
-                    return;
-                }
-            };
-
-            // Avoid cyclic graphs by following already marked paths
-            if self
-                .heap_map
-                .is_marked_as(ptr, if force { State::ForceUsed } else { State::Used })
-            {
-                return;
-            } else {
-                self.heap_map.mark(ptr, force);
-            }
+        self.mark_vcell(&VCell::Ptr(root), force);
+    }
 
-            // trace!("mark {} => {}", ptr, vcell);
-            match vcell {
-                VCell::Pair(car, cdr) => {
-                    self.mark(car, force);
-                    ptr = cdr;
-                }
-                VCell::Ptr(cdr) => {
-                    ptr = cdr;
-                }
-                VCell::Continuation(cont) => {
-                    self.mark_continuation(&cont, force);
-                }
-                VCell::Lambda(ptr) => {
-                    self.mark_lambda(&ptr, force);
-                }
-                VCell::Closure(lambda, env) => {
-                    self.mark(lambda, force);
-                    self.mark(env, force);
-                }
-                VCell::LexicalEnv(env) => {
-                    let env = env.as_ref();
-                    for it in 0..env.slot_len() {
-                        self.mark_vcell(&env.get(it), force);
-                    }
-                }
-                VCell::Vector(vector) => {
-                    for idx in 0..vector.len() {
-                        let vcell = vector.get(idx).unwrap();
-                        self.mark_vcell(&vcell, force);
-                    }
-                }
-                VCell::EnvironmentPointer(ptr) => self.mark(ptr, force),
-                VCell::Acc
-                | VCell::ArgumentCount(_)
-                | VCell::BasePointer(_)
-                | VCell::BasePointerOffset(_)
-                | VCell::Bool(_)
-                | VCell::Char(_)
-                | VCell::BuiltInProc(_)
-                | VCell::GlobalEnvSlot(_)
-                | VCell::LexicalEnvSlot(_)
-                | VCell::LexicalEnvPtr(_, _)
-                | VCell::InstructionPointer(_, _)
-                | VCell::Nil
-                | VCell::Number(_)
-                | VCell::OpCode(_)
-                | VCell::String(_)
-                | VCell::Symbol(_)
-                | VCell::Macro(_)
-                | VCell::Undefined
-                | VCell::Void => {}
-            }
+    /// Mark VCell
+    ///
+    /// Mark every object the given vcell refers to, and any of their
+    /// children.
+    pub fn mark_vcell(&mut self, vcell: &VCell, force: bool) {
+        let mut grey = vec![vcell.clone()];
+        while let Some(vcell) = grey.pop() {
+            self.scan(&vcell, force, &mut grey);
         }
     }
 
-    pub fn mark_vcell(&mut self, vcell: &VCell, force: bool) {
+    /// Scan
+    ///
+    /// Mark each unmarked object that vcell refers to, adding their values
+    /// to grey so that their own children are scanned. The elements of an
+    /// aggregate such as a vector or lambda are added to grey as is.
+    fn scan(&mut self, vcell: &VCell, force: bool, grey: &mut Vec<VCell>) {
+        let mut visit = |ptr: usize| self.visit(ptr, force, grey);
         match vcell {
-            VCell::InstructionPointer(lambda, _) => {
-                self.mark(*lambda, force);
-            }
-            VCell::Continuation(cont) => {
-                self.mark_continuation(cont, force);
+            VCell::Ptr(ptr)
+            | VCell::EnvironmentPointer(ptr)
+            | VCell::LexicalEnvPtr(ptr, _)
+            | VCell::InstructionPointer(ptr, _) => visit(*ptr),
+            VCell::Pair(car, cdr) => {
+                visit(*car);
+                visit(*cdr);
             }
-            VCell::Lambda(lambda) => self.mark_lambda(lambda.as_ref(), force),
             VCell::Closure(lambda, env) => {
-                self.mark(*lambda, force);
-                self.mark(*env, force)
+                visit(*lambda);
+                visit(*env);
             }
-            VCell::Pair(car, cdr) => {
-                self.mark(*car, force);
-                self.mark(*cdr, force);
+            VCell::Continuation(cont) => {
+                visit(cont.ip().0);
+                visit(cont.ep());
+                grey.extend(cont.stack().iter().cloned());
             }
-            VCell::Ptr(ptr) => {
-                self.mark(*ptr, force);
+            VCell::Lambda(lambda) => {
+                grey.extend(lambda.bc.iter().cloned());
+                grey.extend(lambda.args.iter().cloned());
+                grey.extend(lambda.envmap.get_map().iter().map(|it| it.0.clone()));
             }
-            VCell::LexicalEnvPtr(ptr, _) => {
-                self.mark(*ptr, force);
+            VCell::LexicalEnv(env) => {
+                grey.extend((0..env.slot_len()).map(|it| env.get(it)));
             }
             VCell::Vector(vector) => {
-                for idx in 0..vector.len() {
-                    let vcell = vector.get(idx).unwrap();
-                    self.mark_vcell(&vcell, force);
-                }
+                grey.extend(vector.clone_vector(None, None));
             }
-            VCell::EnvironmentPointer(ep) => self.mark(*ep, force),
             VCell::Acc
             | VCell::ArgumentCount(_)
             | VCell::BasePointer(_)
+            | VCell::Prompt(_)
             | VCell::BasePointerOffset(_)
             | VCell::Bool(_)
             | VCell::Char(_)
+            | VCell::CharSet(_)
+            | VCell::BuiltInProc(_)
             | VCell::GlobalEnvSlot(_)
-            | VCell::LexicalEnv(_)
             | VCell::LexicalEnvSlot(_)
             | VCell::Nil
             | VCell::Number(_)
             | VCell::OpCode(_)
             | VCell::String(_)
             | VCell::Symbol(_)
-            | VCell::BuiltInProc(_)
             | VCell::Macro(_)
             | VCell::Undefined
             | VCell::Void => {}
         }
     }
 
-    /// Mark Continuation
+    /// Visit
+    ///
+    /// Mark the object at ptr if it isn't already, adding its value to grey.
+    fn visit(&mut self, ptr: usize, force: bool, grey: &mut Vec<VCell>) {
+        let state = if force { State::ForceUsed } else { State::Used };
+        if let Some(vcell) = self.heap.get(ptr) {
+            if !self.heap_map.is_marked_as(ptr, state) {
+                self.heap_map.mark(ptr, force);
+                grey.push(vcell.clone());
+            }
+        }
+    }
+
+    /// GC Phase
+    ///
+    /// Return the phase of the incremental collection in progress.
+    pub fn gc_phase(&self) -> Phase {
+        self.phase
+    }
+
+    /// Begin Mark
+    ///
+    /// Begin an incremental collection. Roots must then be shaded before
+    /// marking proceeds with mark_step.
+    pub fn begin_mark(&mut self) {
+        self.phase = Phase::Mark;
+    }
+
+    /// Shade
+    ///
+    /// While marking is in progress, add vcell to the grey set so that the
+    /// objects it refers to are marked. This is both how roots are marked,
+    /// and the write barrier for any reference stored into a heap object
+    /// while marking, which keeps an object that was already marked from
+    /// hiding an unmarked one from the collector.
+    pub fn shade(&mut self, vcell: &VCell) {
+        if self.phase == Phase::Mark {
+            self.grey.push(vcell.clone());
+        }
+    }
+
+    /// Mark Step
     ///
-    /// Iterate the saved VM state in the continuation
-    pub fn mark_continuation(&mut self, cont: &Continuation, force: bool) {
-        for it in cont.stack().iter() {
-            self.mark_vcell(it, force);
+    /// Trace up to budget vcells of the grey set, returning true once the
+    /// grey set is empty.
+    pub fn mark_step(&mut self, budget: usize) -> bool {
+        let mut grey = std::mem::take(&mut self.grey);
+        for _ in 0..budget {
+            match grey.pop() {
+                Some(vcell) => self.scan(&vcell, false, &mut grey),
+                None => break,
+            }
         }
-        self.mark(cont.ip().0, force);
-        self.mark(cont.ep(), force);
+        self.grey = grey;
+        self.grey.is_empty()
     }
 
-    /// Mark Lambda
+    /// Begin Sweep
     ///
-    /// Iterate the lambda byte code and mark any value that contains a reference type
-    pub fn mark_lambda(&mut self, lambda: &Lambda, force: bool) {
-        // Mark every bytecode cell
-        for it in &lambda.bc {
-            self.mark_vcell(it, force)
+    /// End the mark phase of an incremental collection and begin sweeping.
+    /// The grey set must be empty.
+    pub fn begin_sweep(&mut self) {
+        debug_assert!(self.grey.is_empty());
+        self.phase = Phase::Sweep(0);
+    }
+
+    /// Sweep Step
+    ///
+    /// Sweep up to budget vcells, returning true and ending the collection
+    /// once the whole heap has been swept.
+    pub fn sweep_step(&mut self, budget: usize) -> bool {
+        let Phase::Sweep(cursor) = self.phase else {
+            return true;
+        };
+        let end = cursor.saturating_add(budget).min(self.heap.len());
+        let before = self.free_list.len();
+        for it in cursor..end {
+            self.sweep_one(it);
         }
+        self.record_reclaimed(self.free_list.len() - before);
+        if end == self.heap.len() {
+            self.phase = Phase::Idle;
+            true
+        } else {
+            self.phase = Phase::Sweep(end);
+            false
+        }
+    }
+
+    /// Record Reclaimed
+    ///
+    /// Record the number of vcells freed by a sweep.
+    fn record_reclaimed(&mut self, cells: usize) {
+        trace!("freed {} vcell(s)", cells);
+        self.stats.cells_reclaimed += cells;
+        self.stats.bytes_reclaimed += cells * std::mem::size_of::<VCell>();
+    }
 
-        // Mark every argument (symbol)
-        for it in &lambda.args {
-            self.mark_vcell(it, force);
+    fn sweep_one(&mut self, ptr: usize) {
+        match self.heap_map.get(ptr) {
+            Some(State::Allocated) => {
+                self.free(ptr);
+            }
+            Some(State::Used) => {
+                self.heap_map.set(ptr, State::Allocated);
+            }
+            _ => {}
         }
+    }
 
-        // Mark every symbol the envmap refers to
-        for it in lambda.envmap.get_map().iter() {
-            self.mark_vcell(&it.0, force);
+    /// Revive
+    ///
+    /// A symbol found in the symbol table during a sweep may be garbage
+    /// that hasn't been swept yet, so mark it to survive the sweep.
+    fn revive(&mut self, ptr: usize) {
+        if let Phase::Sweep(cursor) = self.phase {
+            if ptr >= cursor && self.heap_map.is_marked_as(ptr, State::Allocated) {
+                self.heap_map.set(ptr, State::Used);
+            }
         }
     }
 
@@ -497,22 +651,61 @@ impl Heap {
     ///
     /// * State::Free - no op
     /// * State::Allocated - Mark the vcell as State::Free and append it to the
-    ///        free list.
+    ///   free list.
     /// * State::Used - Mark the vcell as allocated.
     pub fn sweep(&mut self) {
         let before = self.free_list.len();
         for it in 0..self.heap.len() {
-            match self.heap_map.get(it) {
-                Some(State::Allocated) => {
-                    self.free(it);
-                }
-                Some(State::Used) => {
-                    self.heap_map.set(it, State::Allocated);
-                }
-                _ => {}
-            }
+            self.sweep_one(it);
+        }
+        self.record_reclaimed(self.free_list.len() - before);
+    }
+
+    /// GC Stats
+    ///
+    /// Return statistics about the heap and its collections.
+    pub fn gc_stats(&self) -> GcStats {
+        GcStats {
+            heap_size: self.capacity(),
+            used: self.used_size(),
+            ..self.stats.clone()
         }
-        trace!("freed {} vcell(s)", self.free_list.len() - before);
+    }
+
+    /// Record Pause
+    ///
+    /// Record a pause taken to collect, and whether it completed a
+    /// collection.
+    pub fn record_pause(&mut self, pause: Duration, completed: bool) {
+        self.stats.record_pause(pause, completed);
+    }
+
+    /// Set Limit
+    ///
+    /// Limit the number of vcells the heap may grow to hold. Once full, the
+    /// heap still grows to satisfy allocations, but is marked exhausted so
+    /// that the Vm may collect garbage and check the limit.
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+    }
+
+    /// Is Full
+    ///
+    /// Return true if the heap may not grow without exceeding its limit.
+    pub fn is_full(&self) -> bool {
+        self.limit.is_some_and(|limit| self.capacity() >= limit)
+    }
+
+    /// Is Exhausted
+    ///
+    /// Return true if the heap has grown past its limit since the
+    /// exhaustion was last cleared.
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted
+    }
+
+    pub fn clear_exhausted(&mut self) {
+        self.exhausted = false;
     }
 
     /// Size
@@ -522,6 +715,13 @@ impl Heap {
         self.heap.len()
     }
 
+    /// Is Free
+    ///
+    /// Return true if the vcell at ptr is on the free list.
+    pub fn is_free(&self, ptr: usize) -> bool {
+        self.heap_map.is_marked_as(ptr, State::Free)
+    }
+
     /// Free Size
     ///
     /// The number of nodes in the free list.
@@ -662,6 +862,34 @@ mod tests {
         assert_eq!(heap.free_list.len(), CHUNK_SIZE - 2);
     }
 
+    #[test]
+    fn incremental_mark_and_sweep() {
+        let mut heap = Heap::new(CHUNK_SIZE);
+        let garbage = heap.put_cell(&cell![10]).as_ptr().unwrap();
+        let root = heap.put_cell(&cons![100, 200]);
+        heap.begin_mark();
+        heap.shade(&root);
+        assert!(!heap.mark_step(1));
+        assert_eq!(heap.heap_map.get(3), Some(State::Used));
+        assert_eq!(heap.heap_map.get(1), Some(State::Allocated));
+
+        // Objects allocated while marking are already marked, and references
+        // stored into the heap are shaded.
+        let new = heap.put(VCell::Pair(garbage, garbage)).as_ptr().unwrap();
+        assert_eq!(heap.heap_map.get(new), Some(State::Used));
+        while !heap.mark_step(1) {}
+        assert_eq!(heap.heap_map.get(garbage), Some(State::Used));
+
+        heap.begin_sweep();
+        assert!(!heap.sweep_step(2));
+        assert_eq!(heap.gc_phase(), Phase::Sweep(2));
+        let late = heap.alloc();
+        assert_eq!(heap.heap_map.get(late), Some(State::Used));
+        assert!(heap.sweep_step(usize::MAX));
+        assert_eq!(heap.gc_phase(), Phase::Idle);
+        assert_eq!(heap.free_list.len(), CHUNK_SIZE - 6);
+    }
+
     #[test]
     fn heap_auto_grows() {
         let mut heap = Heap::new(8);
@@ -0,0 +1,1476 @@
+use crate::cell::Cell;
+use crate::error::Error;
+use crate::error::Error::{InvalidImage, UnregisteredBuiltin};
+use crate::lex;
+use crate::number::Number;
+use crate::parse;
+use crate::source::{Source, Span};
+use crate::vm::charset::CharSet;
+use crate::vm::continuation::Continuation;
+use crate::vm::debug::Debugger;
+use crate::vm::environment::{
+    BindingSource, EnvironmentMap, GlobalEnvironment, LexicalEnvironment,
+};
+use crate::vm::heap::{Heap, HeapRef};
+use crate::vm::lambda::Lambda;
+use crate::vm::opcode::OpCode;
+use crate::vm::stack::Stack;
+use crate::vm::thread::Scheduler;
+use crate::vm::transform::Transform;
+use crate::vm::vcell::VCell;
+use crate::vm::vector::Vector;
+use crate::vm::Vm;
+use log::trace;
+use num::bigint::BigInt;
+use num::Rational32;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::rc::Rc;
+
+// Bytecode Images
+//
+// A bytecode image is the compiled form of a program, which can be loaded
+// into a Vm without lexing, parsing or compiling its source. An image is a
+// header followed by a unit for each top level expression of the program:
+//
+//   image  := "MWC\0" version:u32 schema:u64 source-hash:u64 unit-count:u32 unit*
+//   unit   := object-count:u32 object* entry:u32
+//
+// The schema is a hash of the opcodes an image's bytecode is made of, see
+// schema_hash. Each unit holds the heap objects reachable from the top level lambda
+// compiled for one expression, and entry is the index of that lambda.
+// References between objects are indices into the unit's objects, while
+// jump operands remain bytecode offsets. References to the global
+// environment are by symbol, and builtin procedures are referenced by name
+// and linked to the builtins of the loading Vm.
+//
+// A snapshot is the entire state of a Vm at rest, its heap and global
+// environment, using the same encoding of VCells:
+//
+//   snapshot := "MWS\0" version:u32 schema:u64 heap-size:u32 vcell* slot-count:u32 slot*
+//   slot     := symbol:u32 vcell
+//
+// Each vcell of the heap is written in place, so references between them
+// are heap references and need no relocation. Objects shared by reference
+// counting, such as vectors and lexical environments, are written once and
+// referred to by id afterwards so that restoring them preserves identity.
+// Builtin procedures are again linked by name.
+//
+// Each lambda carries its span table, attributing its bytecode to the source
+// it was compiled from. A source is written in full, as its name and text,
+// the first time a span refers to it and by id afterwards:
+//
+//   spans  := span-count:u32 (offset:u32 0:u8 | offset:u32 1:u8 source start:u32 end:u32)*
+//   source := id:u32 [name:str text:str]
+//
+// Integers are little endian, and strings are a u32 length followed by
+// UTF-8 bytes.
+
+/// Image Version
+///
+/// The version of the image format, which must be incremented whenever
+/// the format or the meaning of any bytecode changes.
+pub const IMAGE_VERSION: u32 = 6;
+
+const IMAGE_MAGIC: &[u8; 4] = b"MWC\0";
+const SNAPSHOT_MAGIC: &[u8; 4] = b"MWS\0";
+
+// VCell tags
+const TAG_BOOL: u8 = 0;
+const TAG_CHAR: u8 = 1;
+const TAG_NIL: u8 = 2;
+const TAG_FIXNUM: u8 = 3;
+const TAG_FLOAT: u8 = 4;
+const TAG_BIGINT: u8 = 5;
+const TAG_RATIONAL: u8 = 6;
+const TAG_PAIR: u8 = 7;
+const TAG_SYMBOL: u8 = 8;
+const TAG_STRING: u8 = 9;
+const TAG_VECTOR: u8 = 10;
+const TAG_UNDEFINED: u8 = 11;
+const TAG_VOID: u8 = 12;
+const TAG_LAMBDA: u8 = 13;
+const TAG_MACRO: u8 = 14;
+const TAG_BUILTIN: u8 = 15;
+const TAG_ACC: u8 = 16;
+const TAG_ARGUMENT_COUNT: u8 = 17;
+const TAG_BASE_POINTER_OFFSET: u8 = 18;
+const TAG_GLOBAL_ENV_SLOT: u8 = 19;
+const TAG_LEXICAL_ENV_SLOT: u8 = 20;
+const TAG_OPCODE: u8 = 21;
+const TAG_PTR: u8 = 22;
+const TAG_OFFSET: u8 = 23;
+
+// VCell tags only found in snapshots
+const TAG_FREE: u8 = 24;
+const TAG_SHARED: u8 = 25;
+const TAG_CHAR_SET: u8 = 26;
+const TAG_CONTINUATION: u8 = 27;
+const TAG_CLOSURE: u8 = 28;
+const TAG_LEXICAL_ENV: u8 = 29;
+const TAG_LEXICAL_ENV_PTR: u8 = 30;
+const TAG_BASE_POINTER: u8 = 31;
+const TAG_ENVIRONMENT_POINTER: u8 = 32;
+const TAG_GLOBAL_ENV_INDEX: u8 = 33;
+const TAG_INSTRUCTION_POINTER: u8 = 34;
+const TAG_DELIMITED_CONTINUATION: u8 = 35;
+const TAG_PROMPT: u8 = 36;
+
+// BindingSource tags
+const BINDING_GLOBAL: u8 = 0;
+const BINDING_ARGUMENT: u8 = 1;
+const BINDING_IOF_ARGUMENT: u8 = 2;
+const BINDING_IOF_ENVIRONMENT: u8 = 3;
+const BINDING_INTERNAL_DEFINITION: u8 = 4;
+
+impl Vm {
+    /// Compile Bytecode
+    ///
+    /// Compile each expression in text, returning a bytecode image that
+    /// may be loaded with load_bytecode. The expressions are not evaluated,
+    /// but any top level define-syntax takes effect so that the remaining
+    /// expressions may use it.
+    ///
+    /// # Arguments
+    /// `text` - The program to compile
+    pub fn compile_bytecode(&mut self, text: &str) -> Result<Vec<u8>, Error> {
+        let tokens = lex::scan(text)?;
+        let mut it = tokens.iter().peekable();
+        let mut entries = vec![];
+        while it.peek().is_some() {
+            let ast = parse::parse(text, &mut it)?;
+            let lambda = self.compile_runnable(&ast)?;
+            let main = match lambda.bc.as_slice() {
+                [_, _, VCell::OpCode(OpCode::MovImmediate), VCell::Ptr(main), ..] => *main,
+                _ => return Err(Error::InvalidBytecode),
+            };
+            self.define_syntax(main)?;
+            entries.push(main);
+        }
+
+        let mut writer = ImageWriter::new(self);
+        writer.buf.extend_from_slice(IMAGE_MAGIC);
+        writer.write_u32(IMAGE_VERSION);
+        writer.write_u64(schema_hash());
+        writer.write_u64(source_hash(text));
+        writer.write_u32(entries.len() as u32);
+        for entry in entries {
+            writer.write_unit(entry)?;
+        }
+        Ok(writer.buf)
+    }
+
+    /// Load Bytecode
+    ///
+    /// Load and evaluate each unit of a bytecode image produced by
+    /// compile_bytecode, returning the result of the last.
+    ///
+    /// # Arguments
+    /// `image` - The bytecode image
+    pub fn load_bytecode(&mut self, image: &[u8]) -> Result<Cell, Error> {
+        let mut reader = ImageReader::new(image);
+        reader.read_header()?;
+        reader.read_u64()?;
+        let mut result = Cell::Void;
+        for _ in 0..reader.read_u32()? {
+            let main = reader.read_unit(self)?;
+            let lambda = self.entry_lambda(VCell::Ptr(main));
+            let lambda = self.heap.put(lambda);
+            self.ip = (lambda.as_ptr()?, 0);
+            result = self.run()?;
+        }
+        if !reader.is_empty() {
+            return Err(InvalidImage("unexpected data after last unit".into()));
+        }
+        Ok(result)
+    }
+
+    /// Snapshot
+    ///
+    /// Return a snapshot of the entire state of the Vm, including its heap,
+    /// global environment, symbol table and every lambda compiled so far,
+    /// which may be restored into this or another Vm with restore. The stack
+    /// and registers are not saved, so a snapshot is taken between
+    /// evaluations.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut writer = ImageWriter::snapshot(self);
+        writer.buf.extend_from_slice(SNAPSHOT_MAGIC);
+        writer.write_u32(IMAGE_VERSION);
+        writer.write_u64(schema_hash());
+        writer.write_u32(self.heap.capacity() as u32);
+        for ptr in 0..self.heap.capacity() {
+            if self.heap.is_free(ptr) {
+                writer.write_u8(TAG_FREE);
+            } else {
+                writer
+                    .write_vcell(self.heap.get_at_index(ptr))
+                    .expect("every vcell may be written to a snapshot");
+            }
+        }
+        writer.write_u32(self.globenv.iter_slots().len() as u32);
+        for (slot, vcell) in self.globenv.iter_slots().enumerate() {
+            let sym = writer.globals[&slot];
+            writer.write_u32(sym as u32);
+            writer
+                .write_vcell(vcell)
+                .expect("every vcell may be written to a snapshot");
+        }
+        writer.buf
+    }
+
+    /// Restore
+    ///
+    /// Replace the state of the Vm with a snapshot produced by snapshot,
+    /// linking its builtin procedures to those registered with this Vm. The
+    /// Vm is left unchanged if the snapshot is invalid or refers to a
+    /// builtin that isn't registered.
+    ///
+    /// # Arguments
+    /// `snapshot` - The snapshot to restore
+    pub fn restore(&mut self, snapshot: &[u8]) -> Result<(), Error> {
+        let builtins = self
+            .globenv
+            .iter_slots()
+            .filter_map(|it| match self.heap.get(it) {
+                VCell::BuiltInProc(proc) => Some((proc.desc(), VCell::BuiltInProc(proc))),
+                _ => None,
+            })
+            .collect::<HashMap<_, _>>();
+
+        let mut reader = ImageReader::snapshot(snapshot);
+        reader.read_header()?;
+        let capacity = reader.read_u32()? as usize;
+        if capacity == 0 || !capacity.is_multiple_of(self.heap.chunk_size()) {
+            return Err(InvalidImage("invalid heap size".into()));
+        }
+        let mut cells = Vec::with_capacity(capacity);
+        for _ in 0..capacity {
+            cells.push(match reader.read_u8()? {
+                TAG_FREE => None,
+                TAG_SYMBOL => Some(VCell::symbol(reader.read_str()?)),
+                TAG_BUILTIN => {
+                    let name = reader.read_str()?;
+                    match builtins.get(name.as_str()) {
+                        Some(builtin) => Some(builtin.clone()),
+                        None => return Err(UnregisteredBuiltin(name)),
+                    }
+                }
+                tag => Some(reader.read_tagged_vcell(self, tag, capacity)?),
+            });
+        }
+
+        let mut globenv = GlobalEnvironment::new();
+        for slot in 0..reader.read_u32()? as usize {
+            let sym = reader.read_u32()? as usize;
+            if !matches!(cells.get(sym), Some(Some(VCell::Symbol(_))))
+                || globenv.get_binding(sym) != slot
+            {
+                return Err(InvalidImage("invalid global binding".into()));
+            }
+            let vcell = reader.read_vcell(self, capacity)?;
+            globenv.put_slot(slot, vcell);
+        }
+        if !reader.is_empty() {
+            return Err(InvalidImage("unexpected data after last slot".into()));
+        }
+
+        self.heap = Heap::from_cells(self.heap.chunk_size(), cells);
+        self.heap.set_limit(self.limits.heap_cells);
+        self.globenv = globenv;
+        self.stack = Stack::new();
+        self.acc = VCell::Undefined;
+        self.ep = usize::MAX;
+        self.ip = (usize::MAX, 0);
+        self.bp = 0;
+        self.executions.clear();
+        self.saved_states.clear();
+        self.scheduler = Scheduler::new();
+        self.debugger = Debugger::new();
+        self.reset_stack_limit();
+        self.last_stacktrace = None;
+        trace!("restored snapshot of {} vcells", capacity);
+        Ok(())
+    }
+
+    /// Define Syntax
+    ///
+    /// Apply the define-syntax expressions compiled into the top level of
+    /// the lambda at the given heap reference.
+    fn define_syntax(&mut self, lambda: HeapRef) -> Result<(), Error> {
+        let lambda = self.heap.get_at_index(lambda).as_lambda()?.clone();
+        let mut it = lambda.bc.iter();
+        while let Some(vcell) = it.next() {
+            let op = vcell.as_opcode()?;
+            let operands = (&mut it).take(op.operand_count()).collect::<Vec<_>>();
+            if let (OpCode::MovImmediate, [transform @ VCell::Ptr(_), VCell::GlobalEnvSlot(slot)]) =
+                (op, operands.as_slice())
+            {
+                if let VCell::Macro(_) = self.heap.get(*transform) {
+                    self.globenv.put_slot(*slot, (*transform).clone());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Source Hash
+///
+/// Return the FNV-1a hash of the source text an image was compiled from,
+/// used to determine whether an image is current with its source.
+pub fn source_hash(text: &str) -> u64 {
+    text.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Schema Hash
+///
+/// Return a hash of the bytecode schema: the image version along with the
+/// name, operand count and jump operand of each opcode, in the order
+/// they're serialized. An image or snapshot is only loaded by a Vm with
+/// the same schema, so that changing the opcodes invalidates images
+/// compiled before the change even if IMAGE_VERSION wasn't incremented.
+pub fn schema_hash() -> u64 {
+    let mut schema = IMAGE_VERSION.to_string();
+    for op in (0..=u8::MAX).map_while(OpCode::from_u8) {
+        write!(
+            schema,
+            " {:?}/{}/{:?}",
+            op,
+            op.operand_count(),
+            op.jump_operand()
+        )
+        .unwrap();
+    }
+    source_hash(&schema)
+}
+
+/// Image Header
+///
+/// Return the source hash recorded in the header of image, or an error
+/// if image is not a bytecode image of the current version and schema.
+pub fn image_source_hash(image: &[u8]) -> Result<u64, Error> {
+    let mut reader = ImageReader::new(image);
+    reader.read_header()?;
+    reader.read_u64()
+}
+
+/// Image Writer
+///
+/// ImageWriter serializes units of compiled code, or an entire snapshot,
+/// from a Vm's heap.
+struct ImageWriter<'a> {
+    vm: &'a Vm,
+    buf: Vec<u8>,
+
+    /// The symbol bound to each global environment slot
+    globals: HashMap<usize, usize>,
+
+    /// The index of each object in the unit being written, or None when
+    /// writing a snapshot, where objects are referred to by heap reference
+    index: Option<HashMap<HeapRef, u32>>,
+
+    /// The id of each shared object written to a snapshot
+    shared: HashMap<*const (), u32>,
+
+    /// The id of each source written by a span
+    sources: HashMap<*const Source, u32>,
+}
+
+impl<'a> ImageWriter<'a> {
+    fn new(vm: &'a Vm) -> ImageWriter<'a> {
+        ImageWriter {
+            vm,
+            buf: vec![],
+            globals: vm.globenv.slot_symbols(),
+            index: Some(HashMap::new()),
+            shared: HashMap::new(),
+            sources: HashMap::new(),
+        }
+    }
+
+    fn snapshot(vm: &'a Vm) -> ImageWriter<'a> {
+        ImageWriter {
+            index: None,
+            ..ImageWriter::new(vm)
+        }
+    }
+
+    fn is_snapshot(&self) -> bool {
+        self.index.is_none()
+    }
+
+    /// Write Unit
+    ///
+    /// Write each object reachable from entry, followed by entry's index.
+    fn write_unit(&mut self, entry: HeapRef) -> Result<(), Error> {
+        let mut objects = vec![];
+        let mut index = HashMap::new();
+        self.collect(entry, &mut objects, &mut index);
+        self.index = Some(index);
+        self.write_u32(objects.len() as u32);
+        for ptr in objects {
+            let vcell = self.vm.heap.get_at_index(ptr);
+            self.write_vcell(vcell)?;
+        }
+        self.write_u32(self.object(entry));
+        Ok(())
+    }
+
+    /// Object
+    ///
+    /// Return the index of the object at ptr within the unit being
+    /// written, or ptr itself within a snapshot.
+    fn object(&self, ptr: HeapRef) -> u32 {
+        match &self.index {
+            Some(index) => index[&ptr],
+            None => ptr as u32,
+        }
+    }
+
+    /// Write Shared
+    ///
+    /// Within a snapshot, write a reference to the shared object at ptr
+    /// and return true if it has already been written. Otherwise assign it
+    /// the next id and return false, so that the object itself is written.
+    fn write_shared<T>(&mut self, ptr: *const T) -> bool {
+        if !self.is_snapshot() {
+            return false;
+        }
+        match self.shared.get(&(ptr as *const ())) {
+            Some(id) => {
+                let id = *id;
+                self.write_u8(TAG_SHARED);
+                self.write_u32(id);
+                true
+            }
+            None => {
+                self.shared
+                    .insert(ptr as *const (), self.shared.len() as u32);
+                false
+            }
+        }
+    }
+
+    /// Collect
+    ///
+    /// Assign an index to each heap object reachable from ptr.
+    fn collect(&self, ptr: HeapRef, objects: &mut Vec<HeapRef>, index: &mut HashMap<HeapRef, u32>) {
+        if index.contains_key(&ptr) {
+            return;
+        }
+        index.insert(ptr, objects.len() as u32);
+        objects.push(ptr);
+        let mut refs = vec![];
+        match self.vm.heap.get_at_index(ptr) {
+            VCell::Pair(car, cdr) => refs.extend([*car, *cdr]),
+            VCell::Vector(vector) => refs.extend(
+                vector
+                    .clone_vector(None, None)
+                    .iter()
+                    .filter_map(|it| it.as_ptr().ok()),
+            ),
+            VCell::Lambda(lambda) => {
+                let jumps = jump_operands(&lambda.bc);
+                refs.extend(
+                    lambda
+                        .args
+                        .iter()
+                        .chain(lambda.envmap.get_map().iter().map(|it| &it.0))
+                        .chain(
+                            lambda
+                                .bc
+                                .iter()
+                                .zip(&jumps)
+                                .filter(|it| !it.1)
+                                .map(|it| it.0),
+                        )
+                        .filter_map(|it| it.as_ptr().ok()),
+                )
+            }
+            _ => {}
+        }
+        for it in refs {
+            self.collect(it, objects, index);
+        }
+    }
+
+    fn write_vcell(&mut self, vcell: &VCell) -> Result<(), Error> {
+        match vcell {
+            VCell::Bool(val) => {
+                self.write_u8(TAG_BOOL);
+                self.write_u8(*val as u8);
+            }
+            VCell::Char(c) => {
+                self.write_u8(TAG_CHAR);
+                self.write_u32(*c as u32);
+            }
+            VCell::Nil => self.write_u8(TAG_NIL),
+            VCell::Number(num) => self.write_number(num),
+            VCell::Pair(car, cdr) => {
+                self.write_u8(TAG_PAIR);
+                self.write_u32(self.object(*car));
+                self.write_u32(self.object(*cdr));
+            }
+            VCell::Symbol(sym) => {
+                self.write_u8(TAG_SYMBOL);
+                self.write_str(sym);
+            }
+            VCell::String(s) => {
+                if !self.write_shared(Rc::as_ptr(s)) {
+                    self.write_u8(TAG_STRING);
+                    self.write_str(&s.to_string());
+                }
+            }
+            VCell::Vector(vector) => {
+                if !self.write_shared(Rc::as_ptr(vector)) {
+                    self.write_u8(TAG_VECTOR);
+                    let vector = vector.clone_vector(None, None);
+                    self.write_u32(vector.len() as u32);
+                    for it in &vector {
+                        self.write_vcell(it)?;
+                    }
+                }
+            }
+            VCell::Undefined => self.write_u8(TAG_UNDEFINED),
+            VCell::Void => self.write_u8(TAG_VOID),
+            VCell::Lambda(lambda) => {
+                self.write_u8(TAG_LAMBDA);
+                self.write_lambda(lambda)?;
+            }
+            VCell::Macro(transform) => {
+                self.write_u8(TAG_MACRO);
+                self.write_cell(transform.source());
+            }
+            VCell::BuiltInProc(proc) => {
+                self.write_u8(TAG_BUILTIN);
+                self.write_str(proc.desc());
+            }
+            VCell::Acc => self.write_u8(TAG_ACC),
+            VCell::ArgumentCount(argc) => {
+                self.write_u8(TAG_ARGUMENT_COUNT);
+                self.write_u64(*argc as u64);
+            }
+            VCell::BasePointerOffset(offset) => {
+                self.write_u8(TAG_BASE_POINTER_OFFSET);
+                self.write_u64(*offset as u64);
+            }
+            VCell::GlobalEnvSlot(slot) if self.is_snapshot() => {
+                self.write_u8(TAG_GLOBAL_ENV_INDEX);
+                self.write_u32(*slot as u32);
+            }
+            VCell::GlobalEnvSlot(slot) => {
+                self.write_u8(TAG_GLOBAL_ENV_SLOT);
+                let sym = self.vm.heap.get_at_index(self.globals[slot]);
+                self.write_str(sym.as_symbol()?);
+            }
+            VCell::LexicalEnvSlot(slot) => {
+                self.write_u8(TAG_LEXICAL_ENV_SLOT);
+                self.write_u32(*slot as u32);
+            }
+            VCell::OpCode(op) => {
+                self.write_u8(TAG_OPCODE);
+                self.write_u8(op.clone() as u8);
+            }
+            VCell::Ptr(ptr) => {
+                self.write_u8(TAG_PTR);
+                self.write_u32(self.object(*ptr));
+            }
+            VCell::CharSet(cs) if self.is_snapshot() => {
+                self.write_u8(TAG_CHAR_SET);
+                self.write_u32(cs.ranges().len() as u32);
+                for (start, end) in cs.ranges() {
+                    self.write_u32(*start);
+                    self.write_u32(*end);
+                }
+            }
+            VCell::Continuation(cont) if self.is_snapshot() => {
+                if !self.write_shared(Rc::as_ptr(cont)) {
+                    match cont.base() {
+                        Some(base) => {
+                            self.write_u8(TAG_DELIMITED_CONTINUATION);
+                            self.write_u64(base as u64);
+                            self.write_u64(cont.frame() as u64);
+                        }
+                        None => self.write_u8(TAG_CONTINUATION),
+                    }
+                    self.write_u32(cont.stack().len() as u32);
+                    for it in cont.stack().iter() {
+                        self.write_vcell(it)?;
+                    }
+                    self.write_u64(cont.stack().get_sp() as u64);
+                    self.write_u64(cont.ep() as u64);
+                    self.write_u64(cont.ip().0 as u64);
+                    self.write_u64(cont.ip().1 as u64);
+                    self.write_u64(cont.bp() as u64);
+                }
+            }
+            VCell::Closure(lambda, env) if self.is_snapshot() => {
+                self.write_u8(TAG_CLOSURE);
+                self.write_u64(*lambda as u64);
+                self.write_u64(*env as u64);
+            }
+            VCell::LexicalEnv(env) if self.is_snapshot() => {
+                if !self.write_shared(Rc::as_ptr(env)) {
+                    self.write_u8(TAG_LEXICAL_ENV);
+                    self.write_u32(env.slot_len() as u32);
+                    for slot in 0..env.slot_len() {
+                        self.write_vcell(&env.get(slot))?;
+                    }
+                }
+            }
+            VCell::LexicalEnvPtr(env, slot) if self.is_snapshot() => {
+                self.write_u8(TAG_LEXICAL_ENV_PTR);
+                self.write_u64(*env as u64);
+                self.write_u64(*slot as u64);
+            }
+            VCell::BasePointer(bp) if self.is_snapshot() => {
+                self.write_u8(TAG_BASE_POINTER);
+                self.write_u64(*bp as u64);
+            }
+            VCell::Prompt(bp) if self.is_snapshot() => {
+                self.write_u8(TAG_PROMPT);
+                self.write_u64(*bp as u64);
+            }
+            VCell::EnvironmentPointer(ep) if self.is_snapshot() => {
+                self.write_u8(TAG_ENVIRONMENT_POINTER);
+                self.write_u64(*ep as u64);
+            }
+            VCell::InstructionPointer(ip, offset) if self.is_snapshot() => {
+                self.write_u8(TAG_INSTRUCTION_POINTER);
+                self.write_u64(*ip as u64);
+                self.write_u64(*offset as u64);
+            }
+            vcell => {
+                return Err(InvalidImage(format!(
+                    "cannot serialize {}",
+                    vcell.type_text()
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    fn write_lambda(&mut self, lambda: &Lambda) -> Result<(), Error> {
+        self.write_u8(lambda.top_level as u8);
+        self.write_u8(lambda.is_vararg as u8);
+        self.write_u32(lambda.args.len() as u32);
+        for it in &lambda.args {
+            self.write_vcell(it)?;
+        }
+        self.write_u32(lambda.envmap.get_map().len() as u32);
+        for (sym, source) in lambda.envmap.get_map() {
+            self.write_vcell(sym)?;
+            let (tag, n) = match source {
+                BindingSource::Global => (BINDING_GLOBAL, 0),
+                BindingSource::Argument(n) => (BINDING_ARGUMENT, *n),
+                BindingSource::IofArgument(n) => (BINDING_IOF_ARGUMENT, *n),
+                BindingSource::IofEnvironment(n) => (BINDING_IOF_ENVIRONMENT, *n),
+                BindingSource::InternalDefinition => (BINDING_INTERNAL_DEFINITION, 0),
+            };
+            self.write_u8(tag);
+            self.write_u32(n as u32);
+        }
+        self.write_u32(lambda.bc.len() as u32);
+        for (it, is_jump) in lambda.bc.iter().zip(jump_operands(&lambda.bc)) {
+            match it {
+                VCell::Ptr(offset) if is_jump => {
+                    self.write_u8(TAG_OFFSET);
+                    self.write_u32(*offset as u32);
+                }
+                it => self.write_vcell(it)?,
+            }
+        }
+        match &lambda.desc_args {
+            Some(desc) => {
+                self.write_u8(1);
+                self.write_cell(desc);
+            }
+            None => self.write_u8(0),
+        }
+        self.write_u32(lambda.locals.len() as u32);
+        for it in &lambda.locals {
+            self.write_cell(it);
+        }
+        self.write_u32(lambda.spans.len() as u32);
+        for (offset, span) in &lambda.spans {
+            self.write_u32(*offset as u32);
+            match span {
+                Some(span) => {
+                    self.write_u8(1);
+                    self.write_span(span);
+                }
+                None => self.write_u8(0),
+            }
+        }
+        Ok(())
+    }
+
+    /// Write Span
+    ///
+    /// Write span, along with its source if it hasn't been written yet.
+    fn write_span(&mut self, span: &Span) {
+        let ptr = Rc::as_ptr(&span.source);
+        match self.sources.get(&ptr) {
+            Some(id) => self.write_u32(*id),
+            None => {
+                let id = self.sources.len() as u32;
+                self.sources.insert(ptr, id);
+                self.write_u32(id);
+                self.write_str(&span.source.name);
+                self.write_str(&span.source.text);
+            }
+        }
+        self.write_u32(span.start as u32);
+        self.write_u32(span.end as u32);
+    }
+
+    fn write_number(&mut self, num: &Number) {
+        match num {
+            Number::Fixnum(num) => {
+                self.write_u8(TAG_FIXNUM);
+                self.write_u64(*num as u64);
+            }
+            Number::Float(num) => {
+                self.write_u8(TAG_FLOAT);
+                self.write_u64(num.to_bits());
+            }
+            Number::BigInt(num) => {
+                self.write_u8(TAG_BIGINT);
+                let bytes = num.to_signed_bytes_le();
+                self.write_u32(bytes.len() as u32);
+                self.buf.extend_from_slice(&bytes);
+            }
+            Number::Rational(num) => {
+                self.write_u8(TAG_RATIONAL);
+                self.write_u32(*num.numer() as u32);
+                self.write_u32(*num.denom() as u32);
+            }
+        }
+    }
+
+    /// Write Cell
+    ///
+    /// Write a cell, such as the source of a macro, using the same tags as
+    /// the equivalent VCell.
+    fn write_cell(&mut self, cell: &Cell) {
+        match cell {
+            Cell::Bool(val) => {
+                self.write_u8(TAG_BOOL);
+                self.write_u8(*val as u8);
+            }
+            Cell::Char(c) => {
+                self.write_u8(TAG_CHAR);
+                self.write_u32(*c as u32);
+            }
+            Cell::Nil => self.write_u8(TAG_NIL),
+            Cell::Number(num) => self.write_number(num),
+            Cell::Pair(car, cdr) => {
+                self.write_u8(TAG_PAIR);
+                self.write_cell(car);
+                self.write_cell(cdr);
+            }
+            Cell::String(s) => {
+                self.write_u8(TAG_STRING);
+                self.write_str(s);
+            }
+            Cell::Symbol(sym) => {
+                self.write_u8(TAG_SYMBOL);
+                self.write_str(sym);
+            }
+            Cell::Vector(vector) => {
+                self.write_u8(TAG_VECTOR);
+                self.write_u32(vector.len() as u32);
+                vector.iter().for_each(|it| self.write_cell(it));
+            }
+            Cell::Void => self.write_u8(TAG_VOID),
+            _ => self.write_u8(TAG_UNDEFINED),
+        }
+    }
+
+    fn write_u8(&mut self, val: u8) {
+        self.buf.push(val);
+    }
+
+    fn write_u32(&mut self, val: u32) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, val: u64) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    fn write_str(&mut self, s: &str) {
+        self.write_u32(s.len() as u32);
+        self.buf.extend_from_slice(s.as_bytes());
+    }
+}
+
+/// Object
+///
+/// An object read from a unit, before it's placed on the heap. Symbols
+/// and builtins refer to existing heap objects rather than being copied.
+enum Object {
+    Symbol(String),
+    Builtin(String),
+    Value(VCell),
+}
+
+/// Image Reader
+///
+/// ImageReader deserializes units of compiled code into a Vm's heap, or
+/// the vcells of a snapshot.
+struct ImageReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+
+    /// Whether a snapshot is being read
+    snapshot: bool,
+
+    /// Each shared object read from a snapshot, by id
+    shared: Vec<VCell>,
+
+    /// Each source read by a span, by id
+    sources: Vec<Rc<Source>>,
+}
+
+impl<'a> ImageReader<'a> {
+    fn new(buf: &'a [u8]) -> ImageReader<'a> {
+        ImageReader {
+            buf,
+            pos: 0,
+            snapshot: false,
+            shared: vec![],
+            sources: vec![],
+        }
+    }
+
+    fn snapshot(buf: &'a [u8]) -> ImageReader<'a> {
+        ImageReader {
+            snapshot: true,
+            ..ImageReader::new(buf)
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos == self.buf.len()
+    }
+
+    /// Read Header
+    ///
+    /// Check the magic number, version and schema of the image or snapshot.
+    fn read_header(&mut self) -> Result<(), Error> {
+        let (magic, kind) = match self.snapshot {
+            true => (SNAPSHOT_MAGIC, "snapshot"),
+            false => (IMAGE_MAGIC, "bytecode image"),
+        };
+        if self.read_bytes(magic.len()).ok() != Some(magic.as_slice()) {
+            return Err(InvalidImage(format!("not a {}", kind)));
+        }
+        match self.read_u32()? {
+            IMAGE_VERSION => {}
+            version => {
+                return Err(InvalidImage(format!(
+                    "unsupported version {} (expected {})",
+                    version, IMAGE_VERSION
+                )))
+            }
+        }
+        match self.read_u64()? == schema_hash() {
+            true => Ok(()),
+            false => Err(InvalidImage(
+                "compiled for a different bytecode schema".into(),
+            )),
+        }
+    }
+
+    /// Read Unit
+    ///
+    /// Read a unit's objects onto the heap, returning the heap reference
+    /// of its entry lambda.
+    fn read_unit(&mut self, vm: &mut Vm) -> Result<HeapRef, Error> {
+        let len = self.read_u32()? as usize;
+        let mut objects = Vec::with_capacity(len);
+        for _ in 0..len {
+            objects.push(match self.read_u8()? {
+                TAG_SYMBOL => Object::Symbol(self.read_str()?),
+                TAG_BUILTIN => Object::Builtin(self.read_str()?),
+                tag => Object::Value(self.read_tagged_vcell(vm, tag, len)?),
+            });
+        }
+        let entry = self.read_u32()? as usize;
+        if entry >= len {
+            return Err(InvalidImage("invalid entry".into()));
+        }
+
+        // Symbols and builtins are linked first, so the remaining objects
+        // may be relocated to refer to them.
+        let mut ptrs = Vec::with_capacity(len);
+        for it in &objects {
+            ptrs.push(match it {
+                Object::Symbol(sym) => vm.heap.put(VCell::symbol(sym.as_str())).as_ptr()?,
+                Object::Builtin(name) => link_builtin(vm, name)?,
+                Object::Value(_) => vm.heap.alloc(),
+            });
+        }
+        for (it, ptr) in objects.into_iter().zip(ptrs.iter()) {
+            if let Object::Value(vcell) = it {
+                vm.heap.put_at_index(*ptr, relocate(vcell, &ptrs));
+            }
+        }
+        trace!("loaded unit of {} objects", len);
+        Ok(ptrs[entry])
+    }
+
+    fn read_vcell(&mut self, vm: &mut Vm, len: usize) -> Result<VCell, Error> {
+        let tag = self.read_u8()?;
+        self.read_tagged_vcell(vm, tag, len)
+    }
+
+    /// Read Tagged VCell
+    ///
+    /// Read a VCell with the given tag. References to objects are read as
+    /// indices into the unit's objects, to be relocated once every object
+    /// is on the heap, or as heap references within a snapshot.
+    fn read_tagged_vcell(&mut self, vm: &mut Vm, tag: u8, len: usize) -> Result<VCell, Error> {
+        let object = |reader: &mut Self| match reader.read_u32()? as usize {
+            idx if idx < len => Ok(idx),
+            _ => Err(InvalidImage("invalid object reference".into())),
+        };
+        // Registers saved within a snapshot may also hold usize::MAX, such
+        // as the environment pointer of a top level procedure.
+        let heap_ref = |reader: &mut Self| match reader.read_u64()? as usize {
+            ptr if ptr < len || ptr == usize::MAX => Ok(ptr),
+            _ => Err(InvalidImage("invalid object reference".into())),
+        };
+        Ok(match tag {
+            TAG_PAIR => VCell::Pair(object(self)?, object(self)?),
+            TAG_PTR => VCell::Ptr(object(self)?),
+            TAG_OFFSET => VCell::Ptr(self.read_u32()? as usize),
+            TAG_STRING => {
+                let s = VCell::string(self.read_str()?);
+                self.shared.push(s.clone());
+                s
+            }
+            TAG_VECTOR => {
+                let vector = Rc::new(Vector::new(vec![]));
+                self.shared.push(VCell::Vector(vector.clone()));
+                for _ in 0..self.read_u32()? {
+                    vector.push(self.read_vcell(vm, len)?);
+                }
+                VCell::Vector(vector)
+            }
+            TAG_SHARED if self.snapshot => {
+                let id = self.read_u32()? as usize;
+                self.shared
+                    .get(id)
+                    .cloned()
+                    .ok_or_else(|| InvalidImage("invalid shared object reference".into()))?
+            }
+            TAG_CHAR_SET if self.snapshot => {
+                let mut ranges = vec![];
+                for _ in 0..self.read_u32()? {
+                    ranges.push((self.read_u32()?, self.read_u32()?));
+                }
+                VCell::CharSet(Rc::new(CharSet::from_ranges(ranges)))
+            }
+            TAG_CONTINUATION | TAG_DELIMITED_CONTINUATION if self.snapshot => {
+                let base = match tag {
+                    TAG_DELIMITED_CONTINUATION => {
+                        Some((self.read_u64()? as usize, self.read_u64()? as usize))
+                    }
+                    _ => None,
+                };
+                // A continuation can't contain itself, so its id is
+                // reserved until it's constructed.
+                let id = self.shared.len();
+                self.shared.push(VCell::Undefined);
+                let mut stack = vec![];
+                for _ in 0..self.read_u32()? {
+                    stack.push(self.read_vcell(vm, len)?);
+                }
+                let sp = self.read_u64()? as usize;
+                if sp >= stack.len() {
+                    return Err(InvalidImage("invalid stack pointer".into()));
+                }
+                let ep = heap_ref(self)?;
+                let ip = (heap_ref(self)?, self.read_u64()? as usize);
+                let bp = self.read_u64()? as usize;
+                let stack = Stack::from_vec(stack, sp);
+                let cont = match base {
+                    Some((base, frame)) => Continuation::delimited(stack, ep, ip, bp, base, frame),
+                    None => Continuation::new(stack, ep, ip, bp),
+                };
+                self.shared[id] = VCell::Continuation(Rc::new(cont));
+                self.shared[id].clone()
+            }
+            TAG_CLOSURE if self.snapshot => VCell::Closure(heap_ref(self)?, heap_ref(self)?),
+            TAG_LEXICAL_ENV if self.snapshot => {
+                let env = Rc::new(LexicalEnvironment::new(self.read_u32()? as usize));
+                self.shared.push(VCell::LexicalEnv(env.clone()));
+                for slot in 0..env.slot_len() {
+                    env.put(slot, self.read_vcell(vm, len)?);
+                }
+                VCell::LexicalEnv(env)
+            }
+            TAG_LEXICAL_ENV_PTR if self.snapshot => {
+                VCell::LexicalEnvPtr(heap_ref(self)?, self.read_u64()? as usize)
+            }
+            TAG_BASE_POINTER if self.snapshot => VCell::BasePointer(self.read_u64()? as usize),
+            TAG_PROMPT if self.snapshot => VCell::Prompt(self.read_u64()? as usize),
+            TAG_ENVIRONMENT_POINTER if self.snapshot => VCell::EnvironmentPointer(heap_ref(self)?),
+            TAG_GLOBAL_ENV_INDEX if self.snapshot => {
+                VCell::GlobalEnvSlot(self.read_u32()? as usize)
+            }
+            TAG_INSTRUCTION_POINTER if self.snapshot => {
+                VCell::InstructionPointer(heap_ref(self)?, self.read_u64()? as usize)
+            }
+            TAG_LAMBDA => VCell::lambda(self.read_lambda(vm, len)?),
+            TAG_MACRO => {
+                let source = self.read_cell()?;
+                VCell::Macro(Rc::new(Transform::try_new(&source)?))
+            }
+            TAG_ACC => VCell::Acc,
+            TAG_ARGUMENT_COUNT => VCell::ArgumentCount(self.read_u64()? as usize),
+            TAG_BASE_POINTER_OFFSET => VCell::BasePointerOffset(self.read_u64()? as i64),
+            TAG_GLOBAL_ENV_SLOT if !self.snapshot => {
+                let sym = vm.heap.put(VCell::symbol(self.read_str()?)).as_ptr()?;
+                VCell::GlobalEnvSlot(vm.globenv.get_binding(sym))
+            }
+            TAG_LEXICAL_ENV_SLOT => VCell::LexicalEnvSlot(self.read_u32()? as usize),
+            TAG_OPCODE => VCell::OpCode(
+                OpCode::from_u8(self.read_u8()?)
+                    .ok_or_else(|| InvalidImage("invalid opcode".into()))?,
+            ),
+            tag => match self.read_tagged_cell(tag)? {
+                Cell::Bool(val) => VCell::Bool(val),
+                Cell::Char(c) => VCell::Char(c),
+                Cell::Nil => VCell::Nil,
+                Cell::Number(num) => VCell::Number(num),
+                Cell::Void => VCell::Void,
+                Cell::Undefined => VCell::Undefined,
+                _ => return Err(InvalidImage(format!("unexpected tag {}", tag))),
+            },
+        })
+    }
+
+    fn read_lambda(&mut self, vm: &mut Vm, len: usize) -> Result<Lambda, Error> {
+        let top_level = self.read_u8()? != 0;
+        let is_vararg = self.read_u8()? != 0;
+        let mut args = vec![];
+        for _ in 0..self.read_u32()? {
+            args.push(self.read_vcell(vm, len)?);
+        }
+        let mut envmap = EnvironmentMap::new();
+        for _ in 0..self.read_u32()? {
+            let sym = self.read_vcell(vm, len)?;
+            let tag = self.read_u8()?;
+            let n = self.read_u32()? as usize;
+            envmap.bind(
+                sym,
+                match tag {
+                    BINDING_GLOBAL => BindingSource::Global,
+                    BINDING_ARGUMENT => BindingSource::Argument(n),
+                    BINDING_IOF_ARGUMENT => BindingSource::IofArgument(n),
+                    BINDING_IOF_ENVIRONMENT => BindingSource::IofEnvironment(n),
+                    BINDING_INTERNAL_DEFINITION => BindingSource::InternalDefinition,
+                    _ => return Err(InvalidImage("invalid binding source".into())),
+                },
+            );
+        }
+        let mut bc = vec![];
+        for _ in 0..self.read_u32()? {
+            bc.push(self.read_vcell(vm, len)?);
+        }
+        let desc_args = match self.read_u8()? {
+            0 => None,
+            _ => Some(self.read_cell()?),
+        };
+        let mut locals = vec![];
+        for _ in 0..self.read_u32()? {
+            locals.push(self.read_cell()?);
+        }
+        let mut spans = vec![];
+        for _ in 0..self.read_u32()? {
+            let offset = self.read_u32()? as usize;
+            let span = match self.read_u8()? {
+                0 => None,
+                _ => Some(self.read_span()?),
+            };
+            spans.push((offset, span));
+        }
+        Ok(Lambda {
+            top_level,
+            is_vararg,
+            envmap,
+            args,
+            bc,
+            desc_args,
+            locals,
+            spans,
+        })
+    }
+
+    /// Read Span
+    ///
+    /// Read a span written by write_span, reading its source if this is
+    /// the first span to refer to it.
+    fn read_span(&mut self) -> Result<Span, Error> {
+        let id = self.read_u32()? as usize;
+        if id == self.sources.len() {
+            let name = self.read_str()?;
+            let text = self.read_str()?;
+            self.sources.push(Source::new(&name, &text));
+        }
+        let source = match self.sources.get(id) {
+            Some(source) => source.clone(),
+            None => return Err(InvalidImage("invalid source".into())),
+        };
+        let start = self.read_u32()? as usize;
+        let end = self.read_u32()? as usize;
+        if start > end || !source.text.is_char_boundary(start) || !source.text.is_char_boundary(end)
+        {
+            return Err(InvalidImage("invalid span".into()));
+        }
+        Ok(Span { source, start, end })
+    }
+
+    fn read_cell(&mut self) -> Result<Cell, Error> {
+        let tag = self.read_u8()?;
+        self.read_tagged_cell(tag)
+    }
+
+    fn read_tagged_cell(&mut self, tag: u8) -> Result<Cell, Error> {
+        Ok(match tag {
+            TAG_BOOL => Cell::Bool(self.read_u8()? != 0),
+            TAG_CHAR => Cell::Char(
+                char::from_u32(self.read_u32()?)
+                    .ok_or_else(|| InvalidImage("invalid char".into()))?,
+            ),
+            TAG_NIL => Cell::Nil,
+            TAG_FIXNUM => Cell::Number(Number::Fixnum(self.read_u64()? as i64)),
+            TAG_FLOAT => Cell::Number(Number::Float(f64::from_bits(self.read_u64()?))),
+            TAG_BIGINT => {
+                let len = self.read_u32()? as usize;
+                let num = BigInt::from_signed_bytes_le(self.read_bytes(len)?);
+                Cell::Number(Number::BigInt(Rc::new(num)))
+            }
+            TAG_RATIONAL => {
+                let numer = self.read_u32()? as i32;
+                let denom = self.read_u32()? as i32;
+                if denom == 0 {
+                    return Err(InvalidImage("invalid rational".into()));
+                }
+                Cell::Number(Number::Rational(Rational32::new(numer, denom)))
+            }
+            TAG_PAIR => Cell::new_pair(self.read_cell()?, self.read_cell()?),
+            TAG_STRING => Cell::String(self.read_str()?),
+            TAG_SYMBOL => Cell::Symbol(self.read_str()?),
+            TAG_VECTOR => {
+                let mut vector = vec![];
+                for _ in 0..self.read_u32()? {
+                    vector.push(self.read_cell()?);
+                }
+                Cell::Vector(vector)
+            }
+            TAG_UNDEFINED => Cell::Undefined,
+            TAG_VOID => Cell::Void,
+            tag => return Err(InvalidImage(format!("unexpected tag {}", tag))),
+        })
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        match self.buf.get(self.pos..self.pos + len) {
+            Some(bytes) => {
+                self.pos += len;
+                Ok(bytes)
+            }
+            None => Err(InvalidImage("unexpected end of image".into())),
+        }
+    }
+
+    fn read_u8(&mut self) -> Result<u8, Error> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.read_bytes(4)?.try_into().unwrap()))
+    }
+
+    fn read_u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(self.read_bytes(8)?.try_into().unwrap()))
+    }
+
+    fn read_str(&mut self) -> Result<String, Error> {
+        let len = self.read_u32()? as usize;
+        String::from_utf8(self.read_bytes(len)?.to_vec())
+            .map_err(|_| InvalidImage("invalid string".into()))
+    }
+}
+
+/// Link Builtin
+///
+/// Return the heap reference of the builtin procedure bound to name in
+/// the global environment of vm.
+fn link_builtin(vm: &mut Vm, name: &str) -> Result<HeapRef, Error> {
+    let sym = vm.heap.put(VCell::symbol(name)).as_ptr()?;
+    let slot = vm.globenv.get_binding(sym);
+    let builtin = vm.globenv.get_slot(slot);
+    match vm.heap.get(&builtin) {
+        VCell::BuiltInProc(proc) if proc.desc() == name => builtin.as_ptr(),
+        _ => Err(UnregisteredBuiltin(name.into())),
+    }
+}
+
+/// Jump Operands
+///
+/// Return whether each cell of bc is a jump operand, which holds an offset
+/// into bc rather than a heap reference.
+fn jump_operands(bc: &[VCell]) -> Vec<bool> {
+    let mut jumps = vec![false; bc.len()];
+    let mut idx = 0;
+    while let Some(Ok(op)) = bc.get(idx).map(|it| it.as_opcode()) {
+        if let Some(operand) = op.jump_operand() {
+            if let Some(it) = jumps.get_mut(idx + 1 + operand) {
+                *it = true;
+            }
+        }
+        idx += 1 + op.operand_count();
+    }
+    jumps
+}
+
+/// Relocate
+///
+/// Replace the object indices within vcell with their heap references.
+fn relocate(vcell: VCell, ptrs: &[HeapRef]) -> VCell {
+    match vcell {
+        VCell::Ptr(idx) => VCell::Ptr(ptrs[idx]),
+        VCell::Pair(car, cdr) => VCell::Pair(ptrs[car], ptrs[cdr]),
+        VCell::Vector(vector) => VCell::vector(
+            vector
+                .clone_vector(None, None)
+                .into_iter()
+                .map(|it| relocate(it, ptrs))
+                .collect::<Vec<_>>(),
+        ),
+        VCell::Lambda(lambda) => {
+            let mut lambda = Rc::unwrap_or_clone(lambda);
+            lambda.args = lambda
+                .args
+                .into_iter()
+                .map(|it| relocate(it, ptrs))
+                .collect();
+            let jumps = jump_operands(&lambda.bc);
+            lambda.bc = lambda
+                .bc
+                .into_iter()
+                .zip(jumps)
+                .map(|(it, is_jump)| if is_jump { it } else { relocate(it, ptrs) })
+                .collect();
+            let mut envmap = EnvironmentMap::new();
+            for (sym, source) in lambda.envmap.get_map() {
+                envmap.bind(relocate(sym.clone(), ptrs), source.clone());
+            }
+            lambda.envmap = envmap;
+            VCell::Lambda(Rc::new(lambda))
+        }
+        vcell => vcell,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cell, lex, parse};
+
+    #[test]
+    fn round_trip() {
+        let mut vm = Vm::new();
+        let image = vm
+            .compile_bytecode(
+                r#"
+                (define-syntax swap!
+                  (syntax-rules ()
+                    ((_ a b) (let ((tmp a)) (set! a b) (set! b tmp)))))
+                (define (f x) (let ((y (list x "two" #\3 4.5 1/2 #(6) 'sym)))
+                  (swap! x y)
+                  (list x y)))
+                (f 100000000000000000000)
+                "#,
+            )
+            .unwrap();
+
+        let mut vm = Vm::new();
+        assert_eq!(
+            vm.load_bytecode(&image),
+            Ok(parse!(
+                r#"((100000000000000000000 "two" #\3 4.5 1/2 #(6) sym) 100000000000000000000)"#
+            ))
+        );
+        assert_eq!(
+            vm.eval(&parse!("(f 1)")),
+            Ok(parse!(r#"((1 "two" #\3 4.5 1/2 #(6) sym) 1)"#))
+        );
+        assert_eq!(
+            vm.eval(&parse!("(let ((a 1) (b 2)) (swap! a b) (list a b))")),
+            Ok(parse!("(2 1)"))
+        );
+    }
+
+    #[test]
+    fn compile_does_not_evaluate() {
+        let mut vm = Vm::new();
+        let image = vm.compile_bytecode("(define x 10) x").unwrap();
+        assert!(vm.eval(&parse!("x")).is_err());
+        assert_eq!(Vm::new().load_bytecode(&image), Ok(cell![10]));
+    }
+
+    #[test]
+    fn prelude_image_is_current() {
+        let image = Vm::new_without_prelude()
+            .compile_bytecode(crate::vm::PRELUDE_SOURCE)
+            .unwrap();
+        assert!(image == crate::prelude::PRELUDE_IMAGE);
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let mut vm = Vm::new();
+        for expr in [
+            "(define-syntax swap! (syntax-rules () ((_ a b) (let ((tmp a)) (set! a b) (set! b tmp)))))",
+            "(define counter (let ((n 0)) (lambda () (set! n (+ n 1)) n)))",
+            "(counter)",
+            "(define v (vector 1 2))",
+            "(vector-set! v 0 v)",
+            "(define s (make-string 2 #\\a))",
+            "(define p (cons s s))",
+            "(define k #f)",
+            "(+ 1 (call/cc (lambda (c) (set! k c) 1)))",
+            "(define dk #f)",
+            "(reset (* 2 (shift c (set! dk c) 1)))",
+        ] {
+            vm.eval(&parse!(expr)).unwrap();
+        }
+        let snapshot = vm.snapshot();
+
+        let mut restored = Vm::new();
+        restored.eval(&parse!("(define counter 0)")).unwrap();
+        assert_eq!(restored.restore(&snapshot), Ok(()));
+        assert_eq!(restored.eval(&parse!("(counter)")), Ok(cell![2]));
+        assert_eq!(restored.eval(&parse!("(counter)")), Ok(cell![3]));
+        assert_eq!(vm.eval(&parse!("(counter)")), Ok(cell![2]));
+        assert_eq!(
+            restored.eval(&parse!("(let ((a 1) (b 2)) (swap! a b) (list a b))")),
+            Ok(parse!("(2 1)"))
+        );
+        assert_eq!(
+            restored.eval(&parse!("(eq? (vector-ref v 0) v)")),
+            Ok(cell![true])
+        );
+        restored
+            .eval(&parse!("(string-set! (car p) 0 #\\b)"))
+            .unwrap();
+        assert_eq!(restored.eval(&parse!("(cdr p)")), Ok(parse!(r#""ba""#)));
+        assert_eq!(vm.eval(&parse!("s")), Ok(parse!(r#""aa""#)));
+        assert_eq!(restored.eval(&parse!("(k 10)")), Ok(cell![11]));
+        assert_eq!(restored.eval(&parse!("(+ 1 (dk 10))")), Ok(cell![21]));
+        assert_eq!(restored.snapshot(), restored.snapshot());
+    }
+
+    #[test]
+    fn snapshots_keep_spans() {
+        let text = "(define (f x)\n  (car x))\n(define (g x) (+ 1 (f x)))";
+        let mut vm = Vm::new();
+        vm.eval_source("test.scm", text).unwrap();
+        let snapshot = vm.snapshot();
+        let shared = snapshot
+            .windows(text.len())
+            .filter(|it| *it == text.as_bytes())
+            .count();
+        assert_eq!(shared, 1);
+
+        let mut restored = Vm::new();
+        assert_eq!(restored.restore(&snapshot), Ok(()));
+        assert!(restored.eval(&parse!("(g 1)")).is_err());
+        let span = restored.last_stacktrace().unwrap().span().unwrap();
+        assert_eq!(span.to_string(), "test.scm:2:3");
+        assert_eq!(span.excerpt(), "2 |   (car x))\n  |   ^^^^^^^");
+    }
+
+    #[test]
+    fn invalid_snapshots() {
+        let mut vm = Vm::new();
+        vm.eval(&parse!("(define x 10)")).unwrap();
+        let snapshot = vm.snapshot();
+
+        let mut vm = Vm::new();
+        let image = vm.compile_bytecode("x").unwrap();
+        assert_eq!(
+            vm.restore(&image),
+            Err(InvalidImage("not a snapshot".into()))
+        );
+        assert_eq!(
+            vm.restore(&snapshot[..snapshot.len() - 1]),
+            Err(InvalidImage("unexpected end of image".into()))
+        );
+
+        let name = b"vector-ref";
+        let mut unregistered = snapshot.clone();
+        let pos = unregistered
+            .windows(name.len())
+            .position(|it| it == name)
+            .unwrap();
+        unregistered[pos + name.len() - 1] = b'x';
+        assert_eq!(
+            vm.restore(&unregistered),
+            Err(UnregisteredBuiltin("vector-rex".into()))
+        );
+        assert!(vm.eval(&parse!("x")).is_err());
+        assert_eq!(vm.restore(&snapshot), Ok(()));
+        assert_eq!(vm.eval(&parse!("x")), Ok(cell![10]));
+    }
+
+    #[test]
+    fn invalid_images() {
+        let mut vm = Vm::new();
+        let image = vm.compile_bytecode("(+ 1 2)").unwrap();
+        assert_eq!(vm.load_bytecode(&image), Ok(cell![3]));
+        assert_eq!(
+            vm.load_bytecode(b"#!/bin/sh"),
+            Err(InvalidImage("not a bytecode image".into()))
+        );
+
+        let mut newer = image.clone();
+        newer[4..8].copy_from_slice(&(IMAGE_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            vm.load_bytecode(&newer),
+            Err(InvalidImage(format!(
+                "unsupported version {} (expected {})",
+                IMAGE_VERSION + 1,
+                IMAGE_VERSION
+            )))
+        );
+
+        let mut other_schema = image.clone();
+        other_schema[8] ^= 1;
+        assert_eq!(
+            vm.load_bytecode(&other_schema),
+            Err(InvalidImage(
+                "compiled for a different bytecode schema".into()
+            ))
+        );
+        assert_eq!(
+            image_source_hash(&other_schema),
+            Err(InvalidImage(
+                "compiled for a different bytecode schema".into()
+            ))
+        );
+
+        assert_eq!(
+            vm.load_bytecode(&image[..image.len() - 1]),
+            Err(InvalidImage("unexpected end of image".into()))
+        );
+    }
+}
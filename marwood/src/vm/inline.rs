@@ -0,0 +1,574 @@
+use crate::source::Span;
+use crate::vm::ir::{Expr, LambdaExpr, Template, Var};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+// Inlining
+//
+// Procedure calls in Marwood are relatively expensive: applying a lambda
+// allocates a closure and a lexical environment, and sets up a new frame
+// with CALL and ENTER. The inliner removes calls that are known at compile
+// time to apply a particular lambda:
+//
+// * An immediately applied lambda, which is how let is expanded, is
+//   inlined into the frame of the enclosing lambda. Its variables become
+//   locals of the enclosing lambda, which live on the stack rather than in
+//   a lexical environment, and its arguments are evaluated directly into
+//   them.
+//
+// * A local procedure that is bound once, and only ever applied, is a
+//   known procedure. If each of its applications is a tail call from the
+//   frame of the lambda binding it (including from its own body), the
+//   procedure is compiled into that frame as a block, and each application
+//   is a jump to the block. Otherwise, if it's applied exactly once from
+//   that frame, its body is inlined at the application.
+//
+// Locals are not recreated each time that code is reached, so only lambdas
+// whose variables aren't captured by any closure are inlined. A continuation
+// copies the stack, and with it the values of any locals, so neither are
+// lambdas whose variables are assigned after they're bound: re-entering the
+// continuation would restore the value the variable had when it was captured
+// rather than share one location, as a lexical environment does.
+
+/// Inline
+///
+/// Inline immediately applied lambdas and known local procedures within
+/// the top level expression expr.
+pub fn inline(expr: Expr) -> Expr {
+    let mut next_id = 0;
+    expr.walk(&mut |expr| match expr {
+        Expr::LocalRef(var) | Expr::LocalSet(var, _) => next_id = next_id.max(var.id + 1),
+        Expr::Lambda(lambda) => {
+            for var in lambda.bound_vars() {
+                next_id = next_id.max(var.id + 1);
+            }
+        }
+        _ => {}
+    });
+    let mut inliner = Inliner { next_id };
+    inliner.inline_lambdas(push_calls(expr))
+}
+
+/// Inliner
+///
+/// Inliner tracks the next unused variable id within the top level
+/// expression, for the temporary variables used by jumps.
+struct Inliner {
+    next_id: usize,
+}
+
+impl Inliner {
+    /// Inline Lambdas
+    ///
+    /// Inline within every lambda in expr, innermost first.
+    fn inline_lambdas(&mut self, expr: Expr) -> Expr {
+        match map_children(expr, &mut |it| self.inline_lambdas(it)) {
+            Expr::Lambda(lambda) => {
+                let mut lambda = Rc::unwrap_or_clone(lambda);
+                self.inline_lambda(&mut lambda);
+                Expr::Lambda(Rc::new(lambda))
+            }
+            expr => expr,
+        }
+    }
+
+    fn inline_lambda(&mut self, lambda: &mut LambdaExpr) {
+        self.inline_lets(lambda);
+        loop {
+            let vars = lambda.bound_vars().cloned().collect::<Vec<_>>();
+            let mut changed = false;
+            for var in vars {
+                changed |= self.compile_known_procedure(lambda, &var);
+            }
+            if !changed {
+                break;
+            }
+            self.inline_lets(lambda);
+        }
+    }
+
+    /// Inline Lets
+    ///
+    /// Inline each immediately applied lambda in the frame of lambda, if
+    /// the lambda's variables aren't captured. A lambda with blocks can only
+    /// be inlined at a tail position, as the blocks return from the frame.
+    fn inline_lets(&mut self, lambda: &mut LambdaExpr) {
+        let mut locals = std::mem::take(&mut lambda.locals);
+        let body = std::mem::take(&mut lambda.body);
+        let len = body.len();
+        lambda.body = body
+            .into_iter()
+            .enumerate()
+            .map(|(i, it)| inline_let(it, i + 1 == len, &mut locals))
+            .collect();
+        lambda.locals = locals;
+    }
+
+    /// Compile Known Procedure
+    ///
+    /// If var is a known procedure bound by lambda, either compile it into
+    /// a block or inline it at its only application. Returns true if var
+    /// was a known procedure.
+    fn compile_known_procedure(&mut self, lambda: &mut LambdaExpr, var: &Var) -> bool {
+        // The procedure must be assigned once by a statement in the body of
+        // lambda, and only referenced by applications with the right number
+        // of arguments.
+        let (mut sets, mut refs, mut calls) = (0, 0, 0);
+        let mut definition = None;
+        for (i, expr) in lambda.body.iter().enumerate() {
+            if let Expr::LocalSet(it, value) = expr {
+                if let Expr::Lambda(proc) = &**value {
+                    if it == var {
+                        definition = Some((i, proc.clone()));
+                    }
+                }
+            }
+        }
+        let Some((idx, proc)) = definition else {
+            return false;
+        };
+        for expr in &lambda.body {
+            expr.walk(&mut |expr| match expr {
+                Expr::LocalSet(it, _) if it == var => sets += 1,
+                Expr::LocalRef(it) if it == var => refs += 1,
+                Expr::Call(proc_expr, args, _) => match &**proc_expr {
+                    Expr::LocalRef(it) if it == var && proc.accepts(args.len()) => calls += 1,
+                    _ => {}
+                },
+                _ => {}
+            });
+        }
+        if sets != 1 || refs != calls || calls == 0 || is_captured(&proc) || is_assigned(&proc) {
+            return false;
+        }
+
+        // Count the applications that are tail calls from the frame of lambda,
+        // considering the procedure's own body as part of the frame.
+        let (mut frame_calls, mut frame_tail_calls) = (0, 0);
+        for (i, expr) in lambda.body.iter().enumerate() {
+            let mut count = |expr: &Expr, tail: bool| {
+                if is_call_to(expr, var) {
+                    frame_calls += 1;
+                    frame_tail_calls += tail as usize;
+                }
+            };
+            match i == idx {
+                true => proc.body.iter().enumerate().for_each(|(i, it)| {
+                    for_each_in_frame(it, i + 1 == proc.body.len(), &mut count)
+                }),
+                false => for_each_in_frame(expr, i + 1 == lambda.body.len(), &mut count),
+            }
+        }
+
+        let proc = Rc::unwrap_or_clone(proc);
+        if frame_tail_calls == calls {
+            let temps = (1..proc.params.len())
+                .map(|i| self.new_var(&proc.params[i - 1]))
+                .collect::<Vec<_>>();
+            lambda.locals.extend(proc.bound_vars().cloned());
+            lambda.locals.extend(temps.iter().cloned());
+            lambda.body[idx] = Expr::Block(var.clone(), proc.body.clone());
+            let body = std::mem::take(&mut lambda.body);
+            let len = body.len();
+            lambda.body = body
+                .into_iter()
+                .enumerate()
+                .map(|(i, it)| {
+                    map_in_frame(it, i + 1 == len, &mut |expr, _| match expr {
+                        Expr::Call(proc_expr, args, _) if is_local_ref(&proc_expr, var) => {
+                            jump(var, &proc.params, &temps, args)
+                        }
+                        expr => expr,
+                    })
+                })
+                .collect();
+            true
+        } else if calls == 1 && frame_calls == 1 && !proc.free_vars().contains(var) {
+            if has_blocks(&proc) {
+                return false;
+            }
+            lambda.body[idx] = Expr::Seq(vec![]);
+            let body = std::mem::take(&mut lambda.body);
+            let mut proc = Some(proc);
+            lambda.body = body
+                .into_iter()
+                .map(|it| {
+                    map_in_frame(it, false, &mut |expr, _| match expr {
+                        Expr::Call(proc_expr, args, span) if is_local_ref(&proc_expr, var) => {
+                            let proc = proc.take().expect("known procedure applied twice");
+                            Expr::Call(Box::new(Expr::Lambda(Rc::new(proc))), args, span)
+                        }
+                        expr => expr,
+                    })
+                })
+                .collect();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn new_var(&mut self, like: &Var) -> Var {
+        self.next_id += 1;
+        Var {
+            id: self.next_id - 1,
+            name: like.name.clone(),
+        }
+    }
+}
+
+impl LambdaExpr {
+    /// Accepts
+    ///
+    /// Return true if this lambda may be applied to argc arguments without
+    /// building a list of optional arguments.
+    pub fn accepts(&self, argc: usize) -> bool {
+        !self.is_vararg && self.params.len() == argc
+    }
+}
+
+/// Jump
+///
+/// Return a jump to the block for var, binding args to params. Each
+/// argument is evaluated before any of params are assigned, using temps
+/// to hold the results of all but the last argument with side effects.
+/// Constants and references to other variables are assigned last, and
+/// arguments that pass a parameter unchanged are skipped.
+fn jump(var: &Var, params: &[Var], temps: &[Var], args: Vec<Expr>) -> Expr {
+    let is_simple = |arg: &Expr| match arg {
+        Expr::Constant(_) | Expr::GlobalRef(_) => true,
+        Expr::LocalRef(var) => !params.contains(var),
+        _ => false,
+    };
+    let last = args.iter().rposition(|it| !is_simple(it));
+    let mut evals = vec![];
+    let mut moves = vec![];
+    for (i, arg) in args.into_iter().enumerate() {
+        if is_local_ref(&arg, &params[i]) {
+            continue;
+        }
+        if Some(i) == last {
+            moves.insert(0, Expr::LocalSet(params[i].clone(), Box::new(arg)));
+        } else if is_simple(&arg) {
+            moves.push(Expr::LocalSet(params[i].clone(), Box::new(arg)));
+        } else {
+            evals.push(Expr::LocalSet(temps[i].clone(), Box::new(arg)));
+            moves.push(Expr::LocalSet(
+                params[i].clone(),
+                Box::new(Expr::LocalRef(temps[i].clone())),
+            ));
+        }
+    }
+    evals.extend(moves);
+    evals.push(Expr::Jump(var.clone()));
+    Expr::Seq(evals)
+}
+
+/// Inline Let
+///
+/// Inline any immediately applied lambdas within expr, adding their
+/// variables to locals.
+fn inline_let(expr: Expr, tail: bool, locals: &mut Vec<Var>) -> Expr {
+    match map_frame_children(expr, tail, &mut |it, tail| inline_let(it, tail, locals)) {
+        Expr::Call(proc, args, span) => match *proc {
+            Expr::Lambda(lambda)
+                if lambda.accepts(args.len())
+                    && !is_captured(&lambda)
+                    && !is_assigned(&lambda)
+                    && (tail || !has_blocks(&lambda)) =>
+            {
+                let lambda = Rc::unwrap_or_clone(lambda);
+                locals.extend(lambda.bound_vars().cloned());
+                let mut body = lambda
+                    .params
+                    .into_iter()
+                    .zip(args)
+                    .map(|(param, arg)| Expr::LocalSet(param, Box::new(arg)))
+                    .collect::<Vec<_>>();
+                body.extend(lambda.body);
+                Expr::Seq(body)
+            }
+            proc => Expr::Call(Box::new(proc), args, span),
+        },
+        expr => expr,
+    }
+}
+
+/// Push Calls
+///
+/// Rewrite the application of a let expression, which commonly results
+/// from expanding a named let, into an application within the let's
+/// body:
+///
+/// ```text
+/// (((lambda (loop) (set! loop ...) loop) #f) 0)
+///   => ((lambda (loop) (set! loop ...) (loop 0)) #f)
+/// ```
+///
+/// The order in which the procedure and arguments of an application are
+/// evaluated is unspecified, so this only exposes the application of loop
+/// to the inliner.
+fn push_calls(expr: Expr) -> Expr {
+    match map_children(expr, &mut push_calls) {
+        Expr::Call(proc, args, span) if is_let(&proc) => push_call(*proc, args, span),
+        expr => expr,
+    }
+}
+
+fn push_call(expr: Expr, args: Vec<Expr>, span: Option<Span>) -> Expr {
+    match expr {
+        Expr::Call(proc, let_args, let_span) if is_let_application(&proc, &let_args) => {
+            let Expr::Lambda(lambda) = *proc else {
+                unreachable!()
+            };
+            let mut lambda = Rc::unwrap_or_clone(lambda);
+            let last = lambda.body.pop().expect("empty lambda body");
+            lambda.body.push(push_call(last, args, span));
+            Expr::Call(Box::new(Expr::Lambda(Rc::new(lambda))), let_args, let_span)
+        }
+        Expr::Seq(mut body) if !body.is_empty() => {
+            let last = body.pop().unwrap();
+            body.push(push_call(last, args, span));
+            Expr::Seq(body)
+        }
+        expr => Expr::Call(Box::new(expr), args, span),
+    }
+}
+
+fn is_let(expr: &Expr) -> bool {
+    matches!(expr, Expr::Call(proc, args, _) if is_let_application(proc, args))
+}
+
+fn is_let_application(proc: &Expr, args: &[Expr]) -> bool {
+    matches!(proc, Expr::Lambda(lambda) if lambda.accepts(args.len()) && !lambda.body.is_empty())
+}
+
+fn is_local_ref(expr: &Expr, var: &Var) -> bool {
+    matches!(expr, Expr::LocalRef(it) if it == var)
+}
+
+fn is_call_to(expr: &Expr, var: &Var) -> bool {
+    matches!(expr, Expr::Call(proc, _, _) if is_local_ref(proc, var))
+}
+
+/// Is Captured
+///
+/// Return true if any of the variables bound by lambda are referenced
+/// by a lambda nested within it.
+fn is_captured(lambda: &LambdaExpr) -> bool {
+    let bound = lambda.bound_vars().collect::<HashSet<_>>();
+    let mut captured = false;
+    for expr in &lambda.body {
+        expr.walk(&mut |expr| {
+            if let Expr::Lambda(nested) = expr {
+                captured |= nested.free_vars().iter().any(|it| bound.contains(it));
+            }
+        });
+    }
+    captured
+}
+
+/// Is Assigned
+///
+/// Return true if any of the arguments of lambda are assigned within it,
+/// or any of its internal definitions are assigned more than once.
+fn is_assigned(lambda: &LambdaExpr) -> bool {
+    let mut sets = HashMap::new();
+    for expr in &lambda.body {
+        expr.walk(&mut |expr| {
+            if let Expr::LocalSet(var, _) = expr {
+                *sets.entry(var.clone()).or_insert(0) += 1;
+            }
+        });
+    }
+    let count = |var: &Var| sets.get(var).copied().unwrap_or(0);
+    lambda.params.iter().any(|it| count(it) > 0) || lambda.defines.iter().any(|it| count(it) > 1)
+}
+
+/// Has Blocks
+///
+/// Return true if any blocks have been compiled into the frame of lambda.
+fn has_blocks(lambda: &LambdaExpr) -> bool {
+    let mut blocks = false;
+    for expr in &lambda.body {
+        for_each_in_frame(expr, false, &mut |expr, _| {
+            blocks |= matches!(expr, Expr::Block(_, _))
+        });
+    }
+    blocks
+}
+
+/// Map Children
+///
+/// Apply f to each immediate subexpression of expr, including the body
+/// of a lambda.
+fn map_children(expr: Expr, f: &mut impl FnMut(Expr) -> Expr) -> Expr {
+    match expr {
+        Expr::Lambda(lambda) => {
+            let mut lambda = Rc::unwrap_or_clone(lambda);
+            lambda.body = lambda.body.into_iter().map(&mut *f).collect();
+            Expr::Lambda(Rc::new(lambda))
+        }
+        expr => map_frame_children(expr, false, &mut |it, _| f(it)),
+    }
+}
+
+/// Map Frame Children
+///
+/// Apply f to each immediate subexpression of expr that is evaluated in
+/// the same frame as expr, along with whether the subexpression is in a
+/// tail position, given expr's tail position. The body of a lambda is not
+/// in the same frame.
+fn map_frame_children(expr: Expr, tail: bool, f: &mut impl FnMut(Expr, bool) -> Expr) -> Expr {
+    match expr {
+        Expr::Constant(_)
+        | Expr::LocalRef(_)
+        | Expr::GlobalRef(_)
+        | Expr::Lambda(_)
+        | Expr::DefineSyntax(_)
+        | Expr::Jump(_) => expr,
+        Expr::LocalSet(var, value) => Expr::LocalSet(var, Box::new(f(*value, false))),
+        Expr::GlobalSet(sym, value) => Expr::GlobalSet(sym, Box::new(f(*value, false))),
+        Expr::If(test, consequent, alternate) => {
+            let test = f(*test, false);
+            let consequent = f(*consequent, tail);
+            let alternate = alternate.map(|it| Box::new(f(*it, tail)));
+            Expr::If(Box::new(test), Box::new(consequent), alternate)
+        }
+        Expr::Call(proc, args, span) => {
+            let args = args.into_iter().map(|it| f(it, false)).collect();
+            Expr::Call(Box::new(f(*proc, false)), args, span)
+        }
+        Expr::Seq(body) => Expr::Seq(map_body(body, tail, f)),
+        Expr::Block(var, body) => Expr::Block(var, map_body(body, true, f)),
+        Expr::Quasiquote(template) => Expr::Quasiquote(map_template(template, f)),
+    }
+}
+
+fn map_body(body: Vec<Expr>, tail: bool, f: &mut impl FnMut(Expr, bool) -> Expr) -> Vec<Expr> {
+    let len = body.len();
+    body.into_iter()
+        .enumerate()
+        .map(|(i, it)| f(it, tail && i + 1 == len))
+        .collect()
+}
+
+fn map_template(template: Template, f: &mut impl FnMut(Expr, bool) -> Expr) -> Template {
+    match template {
+        Template::Constant(_) => template,
+        Template::Unquote(expr) => Template::Unquote(Box::new(f(*expr, false))),
+        Template::List(templates, tail) => Template::List(
+            templates
+                .into_iter()
+                .map(|it| map_template(it, f))
+                .collect(),
+            tail,
+        ),
+        Template::Vector(templates) => Template::Vector(
+            templates
+                .into_iter()
+                .map(|it| map_template(it, f))
+                .collect(),
+        ),
+    }
+}
+
+/// Map In Frame
+///
+/// Apply f to expr and each subexpression evaluated in the same frame,
+/// innermost first.
+fn map_in_frame(expr: Expr, tail: bool, f: &mut impl FnMut(Expr, bool) -> Expr) -> Expr {
+    let expr = map_frame_children(expr, tail, &mut |it, tail| map_in_frame(it, tail, f));
+    f(expr, tail)
+}
+
+/// For Each In Frame
+///
+/// Call f for expr and each subexpression evaluated in the same frame,
+/// along with whether it's in a tail position.
+fn for_each_in_frame(expr: &Expr, tail: bool, f: &mut impl FnMut(&Expr, bool)) {
+    f(expr, tail);
+    match expr {
+        Expr::Constant(_)
+        | Expr::LocalRef(_)
+        | Expr::GlobalRef(_)
+        | Expr::Lambda(_)
+        | Expr::DefineSyntax(_)
+        | Expr::Jump(_) => {}
+        Expr::LocalSet(_, value) | Expr::GlobalSet(_, value) => for_each_in_frame(value, false, f),
+        Expr::If(test, consequent, alternate) => {
+            for_each_in_frame(test, false, f);
+            for_each_in_frame(consequent, tail, f);
+            if let Some(alternate) = alternate {
+                for_each_in_frame(alternate, tail, f);
+            }
+        }
+        Expr::Call(proc, args, _) => {
+            args.iter().for_each(|it| for_each_in_frame(it, false, f));
+            for_each_in_frame(proc, false, f);
+        }
+        Expr::Seq(body) | Expr::Block(_, body) => {
+            let tail = tail || matches!(expr, Expr::Block(_, _));
+            for (i, it) in body.iter().enumerate() {
+                for_each_in_frame(it, tail && i + 1 == body.len(), f);
+            }
+        }
+        Expr::Quasiquote(template) => for_each_template(template, f),
+    }
+}
+
+fn for_each_template(template: &Template, f: &mut impl FnMut(&Expr, bool)) {
+    match template {
+        Template::Constant(_) => {}
+        Template::Unquote(expr) => for_each_in_frame(expr, false, f),
+        Template::List(templates, _) | Template::Vector(templates) => {
+            templates.iter().for_each(|it| for_each_template(it, f))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::ir::resolve;
+    use crate::{lex, parse};
+
+    macro_rules! inlines {
+        ($($lhs:expr => $rhs:expr),+) => {{
+            $(
+                assert_eq!(inline(resolve(&parse!($lhs)).unwrap()).to_string(), $rhs);
+            )+
+        }};
+    }
+
+    #[test]
+    fn lets() {
+        inlines![
+            "(lambda (x) ((lambda (y) (+ x y)) 1))" =>
+                "(lambda (x.0) (begin (set! y.1 1) (+ x.0 y.1)))",
+            "(lambda (x) ((lambda (y) (lambda () y)) 1))" =>
+                "(lambda (x.0) ((lambda (y.1) (lambda () y.1)) 1))",
+            "((lambda (x) x) 1)" => "((lambda (x.0) x.0) 1)"
+        ];
+    }
+
+    #[test]
+    fn known_procedures() {
+        inlines![
+            "(lambda (n) (define (loop i acc) (if (= i n) acc (loop (+ i 1) (+ acc i)))) (loop 0 0))" =>
+                "(lambda (n.0) \
+                    (block loop.1 (if (= i.2 n.0) acc.3 (begin \
+                        (set! i.4 (+ i.2 1)) (set! acc.3 (+ acc.3 i.2)) (set! i.2 i.4) (jump loop.1)))) \
+                    (begin (set! i.2 0) (set! acc.3 0) (jump loop.1)))",
+            "(lambda (a b) (define (swap x y) (if (< x y) (swap y x) (- x y))) (swap a b))" =>
+                "(lambda (a.0 b.1) \
+                    (block swap.2 (if (< x.3 y.4) (begin \
+                        (set! x.5 y.4) (set! y.4 x.3) (set! x.3 x.5) (jump swap.2)) (- x.3 y.4))) \
+                    (begin (set! x.3 a.0) (set! y.4 b.1) (jump swap.2)))",
+            "(lambda (n) (define (sq x) (* x x)) (+ (sq n) 1))" =>
+                "(lambda (n.0) (begin) (+ (begin (set! x.2 n.0) (* x.2 x.2)) 1))",
+            "(lambda (n) (define (f n) (if (= n 0) 1 (* n (f (- n 1))))) (f n))" =>
+                "(lambda (n.0) (set! f.1 (lambda (n.2) (if (= n.2 0) 1 (* n.2 (f.1 (- n.2 1)))))) (f.1 n.0))"
+        ];
+    }
+}
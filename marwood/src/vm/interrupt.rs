@@ -0,0 +1,86 @@
+use crate::vm::Vm;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Interrupt Handle
+///
+/// An InterruptHandle may be sent to another thread to stop a Vm that is
+/// running a script, such as one that never terminates. The run loop
+/// checks for an interrupt periodically, and once interrupted returns
+/// Error::Interrupted from the evaluation in progress. The Vm remains
+/// usable afterwards.
+///
+/// An interrupt made while the Vm isn't running interrupts its next
+/// evaluation.
+#[derive(Debug, Clone)]
+pub struct InterruptHandle {
+    interrupted: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    pub fn new() -> InterruptHandle {
+        InterruptHandle {
+            interrupted: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Interrupt
+    ///
+    /// Request that the Vm stop running.
+    pub fn interrupt(&self) {
+        self.interrupted.store(true, Ordering::Relaxed);
+    }
+
+    /// Take
+    ///
+    /// Return true and clear the interrupt if one was requested.
+    pub fn take(&self) -> bool {
+        self.interrupted.load(Ordering::Relaxed) && self.interrupted.swap(false, Ordering::Relaxed)
+    }
+}
+
+impl Default for InterruptHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vm {
+    /// Interrupt Handle
+    ///
+    /// Return a handle that may be used to interrupt this Vm from another
+    /// thread.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell::Cell;
+    use crate::error::Error::Interrupted;
+    use crate::{cell, lex, parse};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn interrupt_running_vm() {
+        let mut vm = Vm::new();
+        let interrupt = vm.interrupt_handle();
+        let interrupter = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            interrupt.interrupt();
+        });
+        assert_eq!(
+            vm.eval(&parse!("(guard (e (#t 'caught)) (let loop () (loop)))")),
+            Err(Interrupted)
+        );
+        interrupter.join().unwrap();
+        assert_eq!(vm.eval(&parse!("(+ 1 2)")), Ok(cell![3]));
+
+        vm.interrupt_handle().interrupt();
+        assert_eq!(vm.eval(&parse!("(+ 1 2)")), Err(Interrupted));
+        assert_eq!(vm.eval(&parse!("(+ 1 2)")), Ok(cell![3]));
+    }
+}
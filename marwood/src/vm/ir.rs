@@ -0,0 +1,894 @@
+use crate::cell::Cell;
+use crate::error::Error;
+use crate::error::Error::{
+    InvalidArgs, InvalidNumArgs, InvalidSyntax, InvalidUsePrimitive, LambdaMissingExpression,
+    UnquotedNil,
+};
+use crate::source::{SourceMap, Span};
+use crate::vm::transform::Transform;
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+// Core IR
+//
+// The compiler lowers a macro-expanded expression into a small core
+// language before emitting bytecode. Derived forms have already been
+// expanded by Vm::transform, and each symbol in the core language has
+// been resolved to either a lexical variable or a global:
+//
+// * Constant: a literal or quoted datum
+// * LocalRef, LocalSet: a reference to or assignment of a lexical variable
+// * GlobalRef, GlobalSet: a reference to or assignment of a global variable
+// * Lambda: a procedure, with its formal arguments and internal definitions
+// * If, Call, Seq: conditionals, procedure application and sequencing. A
+//   Call carries the span of the application in the source, if known
+// * Quasiquote, DefineSyntax: templates and macro definitions, which don't
+//   reduce to the other forms without changing their meaning
+// * Block, Jump: known local procedures compiled into the frame of the
+//   lambda that binds them, produced by the inliner
+//
+// Lexical variables are identified by a Var that is unique within the
+// top level expression being compiled, so analyses over the IR need not
+// concern themselves with shadowing.
+
+macro_rules! car {
+    ($cell:expr) => {{
+        let cell = $cell;
+        cell.car()
+            .ok_or_else(|| Error::ExpectedPairButFound(cell.clone()))?
+    }};
+}
+
+macro_rules! cdr {
+    ($cell:expr) => {{
+        let cell = $cell;
+        cell.cdr()
+            .ok_or_else(|| Error::ExpectedPairButFound(cell.clone()))?
+    }};
+}
+
+/// Var
+///
+/// A lexical variable, bound by a lambda either as a formal argument or
+/// an internal definition.
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+pub struct Var {
+    pub id: usize,
+    pub name: Cell,
+}
+
+/// Expr
+///
+/// An expression in the core IR.
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Constant(Cell),
+    LocalRef(Var),
+    LocalSet(Var, Box<Expr>),
+    GlobalRef(Cell),
+    GlobalSet(Cell, Box<Expr>),
+    Lambda(Rc<LambdaExpr>),
+    If(Box<Expr>, Box<Expr>, Option<Box<Expr>>),
+    Call(Box<Expr>, Vec<Expr>, Option<Span>),
+    Seq(Vec<Expr>),
+    Quasiquote(Template),
+    DefineSyntax(Rc<Transform>),
+
+    /// A local procedure's body compiled into the frame of the lambda that
+    /// binds it, and skipped over when evaluated. Its formal arguments are
+    /// internal definitions of that lambda, and it is only entered by a
+    /// Jump in a tail position, returning from the frame when done.
+    Block(Var, Vec<Expr>),
+    Jump(Var),
+}
+
+/// Lambda Expr
+///
+/// A lambda in the core IR. If the lambda is vararg, the last of params
+/// is bound to the list of optional arguments.
+#[derive(Debug, Clone)]
+pub struct LambdaExpr {
+    pub params: Vec<Var>,
+    pub is_vararg: bool,
+    pub defines: Vec<Var>,
+    pub body: Vec<Expr>,
+
+    /// Variables of inlined procedures, kept on the stack rather than
+    /// in the lexical environment
+    pub locals: Vec<Var>,
+
+    /// The formal arguments as written, for display purposes
+    pub desc_args: Cell,
+}
+
+/// Template
+///
+/// The template of a quasiquote expression, where only unquoted
+/// expressions at the outermost depth are evaluated.
+#[derive(Debug, Clone)]
+pub enum Template {
+    Constant(Cell),
+    Unquote(Box<Expr>),
+    List(Vec<Template>, Cell),
+    Vector(Vec<Template>),
+}
+
+impl Expr {
+    /// Walk
+    ///
+    /// Call f for this expression and each of its subexpressions in
+    /// evaluation order, including the bodies of any lambdas.
+    pub fn walk(&self, f: &mut impl FnMut(&Expr)) {
+        f(self);
+        match self {
+            Expr::Constant(_)
+            | Expr::LocalRef(_)
+            | Expr::GlobalRef(_)
+            | Expr::DefineSyntax(_)
+            | Expr::Jump(_) => {}
+            Expr::LocalSet(_, value) | Expr::GlobalSet(_, value) => value.walk(f),
+            Expr::Lambda(lambda) => lambda.body.iter().for_each(|it| it.walk(f)),
+            Expr::If(test, consequent, alternate) => {
+                test.walk(f);
+                consequent.walk(f);
+                if let Some(alternate) = alternate {
+                    alternate.walk(f);
+                }
+            }
+            Expr::Call(proc, args, _) => {
+                args.iter().for_each(|it| it.walk(f));
+                proc.walk(f);
+            }
+            Expr::Seq(body) | Expr::Block(_, body) => body.iter().for_each(|it| it.walk(f)),
+            Expr::Quasiquote(template) => template.walk(f),
+        }
+    }
+}
+
+impl Template {
+    fn walk(&self, f: &mut impl FnMut(&Expr)) {
+        match self {
+            Template::Constant(_) => {}
+            Template::Unquote(expr) => expr.walk(f),
+            Template::List(templates, _) | Template::Vector(templates) => {
+                templates.iter().for_each(|it| it.walk(f))
+            }
+        }
+    }
+}
+
+impl LambdaExpr {
+    /// Bound Vars
+    ///
+    /// Return the variables bound by this lambda, which are the
+    /// formal arguments followed by internal definitions and locals.
+    pub fn bound_vars(&self) -> impl Iterator<Item = &Var> {
+        self.params
+            .iter()
+            .chain(self.defines.iter())
+            .chain(self.locals.iter())
+    }
+
+    /// Free Vars
+    ///
+    /// Return the lexical variables referenced within this lambda or
+    /// any lambda nested in it that are bound outside of this lambda,
+    /// in the order they're first referenced.
+    pub fn free_vars(&self) -> Vec<Var> {
+        let mut bound = self.bound_vars().cloned().collect::<HashSet<_>>();
+        let mut referenced = vec![];
+        for expr in &self.body {
+            expr.walk(&mut |expr| match expr {
+                Expr::LocalRef(var) | Expr::LocalSet(var, _) => referenced.push(var.clone()),
+                Expr::Lambda(lambda) => bound.extend(lambda.bound_vars().cloned()),
+                _ => {}
+            });
+        }
+        let mut seen = HashSet::new();
+        referenced
+            .into_iter()
+            .filter(|var| !bound.contains(var) && seen.insert(var.clone()))
+            .collect()
+    }
+}
+
+/// Resolve
+///
+/// Lower the macro-expanded expression in expr into the core IR,
+/// resolving each symbol to either a lexical variable or a global.
+///
+/// # Arguments
+/// `expr` - The expression to resolve
+pub fn resolve(expr: &Cell) -> Result<Expr, Error> {
+    Resolver::default().resolve(expr)
+}
+
+/// Resolve Spanned
+///
+/// Lower expr into the core IR as resolve does, taking the span of each
+/// procedure application from spans.
+///
+/// # Arguments
+/// `expr` - The expression to resolve
+/// `spans` - The spans of the lists in expr
+pub fn resolve_spanned(expr: &Cell, spans: &SourceMap) -> Result<Expr, Error> {
+    Resolver {
+        spans: Some(spans),
+        ..Default::default()
+    }
+    .resolve(expr)
+}
+
+/// Resolver
+///
+/// Resolver tracks the lexical scope of each lambda enclosing the
+/// expression currently being resolved, innermost last.
+#[derive(Debug, Default)]
+struct Resolver<'a> {
+    scopes: Vec<Vec<Var>>,
+    next_id: usize,
+    spans: Option<&'a SourceMap>,
+}
+
+impl Resolver<'_> {
+    fn resolve(&mut self, expr: &Cell) -> Result<Expr, Error> {
+        match expr {
+            Cell::Pair(_, _) => self.resolve_procedure_application(expr),
+            Cell::Symbol(_) => self.resolve_symbol(expr),
+            Cell::Nil => Err(UnquotedNil),
+            Cell::Procedure(_)
+            | Cell::Void
+            | Cell::Undefined
+            | Cell::Macro
+            | Cell::CharSet
+            | Cell::Continuation => Err(InvalidSyntax(expr.to_string())),
+            Cell::Bool(_) | Cell::Char(_) | Cell::Number(_) | Cell::String(_) | Cell::Vector(_) => {
+                Ok(Expr::Constant(expr.clone()))
+            }
+        }
+    }
+
+    fn new_var(&mut self, name: &Cell) -> Var {
+        self.next_id += 1;
+        Var {
+            id: self.next_id - 1,
+            name: name.clone(),
+        }
+    }
+
+    /// Lookup
+    ///
+    /// Return the innermost lexical variable bound to sym, or None if
+    /// sym refers to a global.
+    fn lookup(&self, sym: &Cell) -> Option<Var> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.iter().find(|var| var.name == *sym))
+            .cloned()
+    }
+
+    fn resolve_symbol(&mut self, sym: &Cell) -> Result<Expr, Error> {
+        if sym.is_primitive_symbol() {
+            return Err(InvalidUsePrimitive(sym.to_string()));
+        }
+        Ok(match self.lookup(sym) {
+            Some(var) => Expr::LocalRef(var),
+            None => Expr::GlobalRef(sym.clone()),
+        })
+    }
+
+    /// Resolve Assignment
+    ///
+    /// Return a set of the variable sym to value, for both define and set!.
+    fn resolve_assignment(&mut self, sym: &Cell, value: Expr) -> Expr {
+        match self.lookup(sym) {
+            Some(var) => Expr::LocalSet(var, Box::new(value)),
+            None => Expr::GlobalSet(sym.clone(), Box::new(value)),
+        }
+    }
+
+    fn resolve_procedure_application(&mut self, expr: &Cell) -> Result<Expr, Error> {
+        let proc = expr.car().unwrap();
+        let rest = expr.cdr().unwrap();
+        match proc {
+            Cell::Symbol(proc) => match proc.as_str() {
+                // A lambda body's definitions are resolved by resolve_lambda,
+                // so this is a definition in an expression context.
+                "define" if !self.scopes.is_empty() => {
+                    Err(InvalidSyntax(format!("out of context define: {:#}", expr)))
+                }
+                "define" => self.resolve_define(expr),
+                "define-syntax" => Ok(Expr::DefineSyntax(Rc::new(Transform::try_new(expr)?))),
+                "lambda" | "λ" => {
+                    if rest.is_nil() {
+                        return Err(InvalidNumArgs("procedure".into()));
+                    }
+                    let lambda = self.resolve_lambda(car!(rest), cdr!(rest))?;
+                    Ok(Expr::Lambda(Rc::new(lambda)))
+                }
+                "quasiquote" => Ok(Expr::Quasiquote(self.resolve_template(car!(rest), 0)?)),
+                "quote" => Ok(Expr::Constant(car!(rest).clone())),
+                "if" => self.resolve_if(expr),
+                "set!" => self.resolve_set(expr),
+                _ => self.resolve_runtime_procedure_application(expr),
+            },
+            _ => self.resolve_runtime_procedure_application(expr),
+        }
+    }
+
+    /// Resolve Define
+    ///
+    /// A definition should be in one of the following forms:
+    ///
+    /// * (define ⟨variable⟩ ⟨expression⟩)
+    /// * (define (⟨variable⟩ ⟨formals⟩) ⟨body⟩)
+    /// * (define (⟨variable⟩ . ⟨formal⟩) ⟨body⟩)
+    ///
+    /// A definition at the beginning of a lambda body binds an internal
+    /// definition of that lambda. A definition outside of any lambda
+    /// assigns the global variable as set! would. Definitions anywhere else
+    /// are an error.
+    fn resolve_define(&mut self, expr: &Cell) -> Result<Expr, Error> {
+        let rest = cdr!(expr);
+
+        // A define must have at least 2 arguments
+        if rest.is_nil() || cdr!(rest).is_nil() {
+            return Err(InvalidNumArgs("define".into()));
+        }
+
+        let (symbol, value) = match car!(rest) {
+            Cell::Symbol(_) => {
+                if !cdr!(cdr!(rest)).is_nil() {
+                    return Err(InvalidNumArgs("define".into()));
+                }
+                (car!(rest), self.resolve(car!(cdr!(rest)))?)
+            }
+            Cell::Pair(_, _) if car!(car!(rest)).is_symbol() => {
+                let lambda = self.resolve_lambda(cdr!(car!(rest)), cdr!(rest))?;
+                (car!(car!(rest)), Expr::Lambda(Rc::new(lambda)))
+            }
+            _ => {
+                return Err(InvalidArgs(
+                    "define".into(),
+                    "symbol or (variable formals)".into(),
+                    car!(rest).to_string(),
+                ));
+            }
+        };
+
+        if symbol.is_primitive_symbol() {
+            return Err(InvalidUsePrimitive(symbol.to_string()));
+        }
+        Ok(self.resolve_assignment(symbol, value))
+    }
+
+    /// Resolve Set
+    ///
+    /// A set! must be in the following form:
+    ///
+    /// * (set ⟨variable⟩ ⟨expression⟩)
+    fn resolve_set(&mut self, expr: &Cell) -> Result<Expr, Error> {
+        let rest = cdr!(expr);
+        let (variable, expression) = match rest.collect_vec().as_slice() {
+            [variable, expression] => (*variable, *expression),
+            _ => {
+                return Err(InvalidNumArgs("set!".into()));
+            }
+        };
+
+        if !variable.is_symbol() || variable.is_primitive_symbol() {
+            return Err(InvalidSyntax(format!(
+                "expected variable, but got {:#}",
+                variable
+            )));
+        }
+
+        let value = self.resolve(expression)?;
+        Ok(self.resolve_assignment(variable, value))
+    }
+
+    /// Resolve Lambda
+    ///
+    /// Resolve a lambda given its formals and body, where formals is one
+    /// of the following:
+    ///
+    /// * (var1 var2 ...): A fixed number of arguments
+    /// * var: A variable number of arguments, allocated in a new list bound to var.
+    /// * (var1 var2 . rest): A fixed number of arguments, any additional arguments bound
+    ///   to a new list bound to rest.
+    fn resolve_lambda(&mut self, formals: &Cell, body: &Cell) -> Result<LambdaExpr, Error> {
+        let (formal_args, is_vararg) = formal_arguments(formals)?;
+        let internally_defined = internally_defined_symbols(body)?;
+        if body.is_nil() {
+            return Err(LambdaMissingExpression);
+        }
+
+        let params = formal_args
+            .into_iter()
+            .map(|sym| self.new_var(sym))
+            .collect::<Vec<_>>();
+        let mut scope = params.clone();
+        let mut defines = vec![];
+        for sym in internally_defined {
+            if !scope.iter().any(|var| var.name == *sym) {
+                let var = self.new_var(sym);
+                scope.push(var.clone());
+                defines.push(var);
+            }
+        }
+
+        self.scopes.push(scope);
+        let mut exprs = vec![];
+        let mut rest = body;
+        while rest.is_pair() {
+            let expr = car!(rest);
+            let expr = match expr.is_pair() && car!(expr).is_define() {
+                true => self.resolve_define(expr),
+                false => self.resolve(expr),
+            };
+            match expr {
+                Ok(expr) => exprs.push(expr),
+                Err(e) => {
+                    self.scopes.pop();
+                    return Err(e);
+                }
+            }
+            rest = cdr!(rest);
+        }
+        self.scopes.pop();
+
+        Ok(LambdaExpr {
+            params,
+            is_vararg,
+            defines,
+            body: exprs,
+            locals: vec![],
+            desc_args: formals.clone(),
+        })
+    }
+
+    /// Resolve Runtime Procedure Application
+    ///
+    /// Resolve the arguments in the order they're evaluated, followed by
+    /// the procedure.
+    fn resolve_runtime_procedure_application(&mut self, expr: &Cell) -> Result<Expr, Error> {
+        let proc = car!(expr);
+        let mut rest = cdr!(expr);
+        let mut args = vec![];
+        while rest.is_pair() {
+            args.push(self.resolve(rest.car().unwrap())?);
+            rest = rest.cdr().unwrap();
+        }
+        let proc = self.resolve(proc)?;
+        let span = self.spans.and_then(|it| it.get(expr)).cloned();
+        Ok(Expr::Call(Box::new(proc), args, span))
+    }
+
+    /// Resolve If
+    ///
+    /// * (if ⟨test⟩ ⟨consequent⟩ ⟨alternate⟩)
+    /// * (if ⟨test⟩ ⟨consequent⟩)
+    fn resolve_if(&mut self, expr: &Cell) -> Result<Expr, Error> {
+        let rest = cdr!(expr);
+        if rest.is_nil() || !rest.is_list() {
+            return Err(InvalidArgs("if".into(), "test".into(), rest.to_string()));
+        }
+        let (test, consequent, alternate) = match rest.collect_vec().as_slice() {
+            [test, consequent] => (*test, *consequent, None),
+            [test, consequent, alternate] => (*test, *consequent, Some(*alternate)),
+            _ => {
+                return Err(InvalidNumArgs("if".into()));
+            }
+        };
+        Ok(Expr::If(
+            Box::new(self.resolve(test)?),
+            Box::new(self.resolve(consequent)?),
+            match alternate {
+                Some(alternate) => Some(Box::new(self.resolve(alternate)?)),
+                None => None,
+            },
+        ))
+    }
+
+    /// Resolve Template
+    ///
+    /// Resolve the template of a quasiquote expression at the given
+    /// quasiquote depth. Only unquoted expressions at depth 0 are
+    /// evaluated.
+    fn resolve_template(&mut self, expr: &Cell, mut depth: usize) -> Result<Template, Error> {
+        if let Some(vector) = expr.as_vector() {
+            return Ok(Template::Vector(
+                vector
+                    .iter()
+                    .map(|it| self.resolve_template(it, depth))
+                    .collect::<Result<_, _>>()?,
+            ));
+        }
+
+        if !expr.is_pair() {
+            return Ok(Template::Constant(expr.clone()));
+        }
+
+        if expr.car().unwrap().is_unquote() {
+            if depth == 0 {
+                return Ok(Template::Unquote(Box::new(self.resolve(car!(cdr!(expr)))?)));
+            } else {
+                depth -= 1;
+            }
+        }
+
+        if expr.car().unwrap().is_quasiquote() {
+            depth += 1;
+        }
+
+        let mut templates = vec![];
+        let mut rest = expr;
+        while rest.is_pair() {
+            templates.push(self.resolve_template(rest.car().unwrap(), depth)?);
+            rest = rest.cdr().unwrap();
+        }
+        Ok(Template::List(templates, rest.clone()))
+    }
+}
+
+/// Formal Arguments
+///
+/// Given the formal arguments of a lambda or define, return the symbols
+/// for each argument and whether a vararg form was encountered.
+fn formal_arguments(formal_args: &Cell) -> Result<(Vec<&Cell>, bool), Error> {
+    let mut symbols = vec![];
+    let mut rest = formal_args;
+    while rest.is_pair() {
+        let symbol = car!(rest);
+        if !symbol.is_symbol() {
+            return Err(InvalidArgs(
+                "procedure".into(),
+                "symbol".into(),
+                symbol.to_string(),
+            ));
+        }
+        if symbol.is_primitive_symbol() {
+            return Err(InvalidUsePrimitive(symbol.to_string()));
+        }
+        symbols.push(symbol);
+        rest = cdr!(rest);
+    }
+
+    if rest.is_symbol() {
+        if rest.is_primitive_symbol() {
+            return Err(InvalidUsePrimitive(rest.to_string()));
+        }
+        symbols.push(rest);
+        Ok((symbols, true))
+    } else {
+        Ok((symbols, false))
+    }
+}
+
+/// Interally defined symbols
+///
+/// Given the body of a lambda, return the list of internally defined
+/// symbols as a result of calls to define at the beginning of the block.
+///
+/// Internal define expressions are only allowed at the beginning of the
+/// block, any define following another expression is an error.
+///
+/// # Arguments
+/// `body` - The body of the lambda
+fn internally_defined_symbols(body: &Cell) -> Result<Vec<&Cell>, Error> {
+    let mut symbols = vec![];
+    let mut beginning_of_body = true;
+
+    for expr in body {
+        if expr.is_pair() && expr.car().unwrap().is_define() {
+            if !beginning_of_body {
+                return Err(InvalidSyntax(format!("out of context define: {:#}", expr)));
+            }
+            let expr = expr.cdr().unwrap();
+            if expr.is_pair() {
+                let expr = expr.car().unwrap();
+                if expr.is_symbol() {
+                    symbols.push(expr);
+                } else if expr.is_pair() && expr.car().unwrap().is_symbol() {
+                    symbols.push(expr.car().unwrap());
+                }
+            }
+        } else {
+            beginning_of_body = false;
+        }
+    }
+    Ok(symbols)
+}
+
+impl Display for Var {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.name, self.id)
+    }
+}
+
+fn write_seq<T: Display>(f: &mut Formatter<'_>, items: &[T]) -> std::fmt::Result {
+    for it in items {
+        write!(f, " {}", it)?;
+    }
+    Ok(())
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expr::Constant(cell) => match cell {
+                Cell::Pair(_, _) | Cell::Symbol(_) | Cell::Nil => write!(f, "'{:#}", cell),
+                _ => write!(f, "{:#}", cell),
+            },
+            Expr::LocalRef(var) => write!(f, "{}", var),
+            Expr::GlobalRef(sym) => write!(f, "{}", sym),
+            Expr::LocalSet(var, value) => write!(f, "(set! {} {})", var, value),
+            Expr::GlobalSet(sym, value) => write!(f, "(set! {} {})", sym, value),
+            Expr::Lambda(lambda) => write!(f, "{}", lambda),
+            Expr::If(test, consequent, Some(alternate)) => {
+                write!(f, "(if {} {} {})", test, consequent, alternate)
+            }
+            Expr::If(test, consequent, None) => write!(f, "(if {} {})", test, consequent),
+            Expr::Call(proc, args, _) => {
+                write!(f, "({}", proc)?;
+                write_seq(f, args)?;
+                write!(f, ")")
+            }
+            Expr::Seq(body) => {
+                write!(f, "(begin")?;
+                write_seq(f, body)?;
+                write!(f, ")")
+            }
+            Expr::Quasiquote(template) => write!(f, "`{}", template),
+            Expr::DefineSyntax(transform) => write!(f, "(define-syntax {})", transform.keyword()),
+            Expr::Block(var, body) => {
+                write!(f, "(block {}", var)?;
+                write_seq(f, body)?;
+                write!(f, ")")
+            }
+            Expr::Jump(var) => write!(f, "(jump {})", var),
+        }
+    }
+}
+
+impl Display for LambdaExpr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let (required, rest) = match self.is_vararg {
+            true => self.params.split_at(self.params.len() - 1),
+            false => (self.params.as_slice(), &[][..]),
+        };
+        write!(f, "(lambda ")?;
+        match (required, rest) {
+            ([], [rest]) => write!(f, "{}", rest)?,
+            (required, rest) => {
+                write!(f, "(")?;
+                for (i, it) in required.iter().enumerate() {
+                    write!(f, "{}{}", if i == 0 { "" } else { " " }, it)?;
+                }
+                if let [rest] = rest {
+                    write!(f, " . {}", rest)?;
+                }
+                write!(f, ")")?;
+            }
+        }
+        write_seq(f, &self.body)?;
+        write!(f, ")")
+    }
+}
+
+impl Display for Template {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Template::Constant(cell) => write!(f, "{:#}", cell),
+            Template::Unquote(expr) => write!(f, ",{}", expr),
+            Template::List(templates, tail) => {
+                write!(f, "(")?;
+                for (i, it) in templates.iter().enumerate() {
+                    write!(f, "{}{}", if i == 0 { "" } else { " " }, it)?;
+                }
+                if !tail.is_nil() {
+                    write!(f, " . {:#}", tail)?;
+                }
+                write!(f, ")")
+            }
+            Template::Vector(templates) => {
+                write!(f, "#(")?;
+                for (i, it) in templates.iter().enumerate() {
+                    write!(f, "{}{}", if i == 0 { "" } else { " " }, it)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cell, lex, parse};
+
+    macro_rules! resolves {
+        ($($lhs:expr => $rhs:expr),+) => {{
+            $(
+                assert_eq!(resolve(&parse!($lhs)).unwrap().to_string(), $rhs);
+            )+
+        }};
+    }
+
+    #[test]
+    fn lexical_scope() {
+        resolves![
+            "(+ x 1)" => "(+ x 1)",
+            "(lambda (x) (lambda (y) (+ x y)))" => "(lambda (x.0) (lambda (y.1) (+ x.0 y.1)))",
+            "(lambda (x) (lambda (x) x))" => "(lambda (x.0) (lambda (x.1) x.1))",
+            "(lambda (x . rest) (set! x rest))" => "(lambda (x.0 . rest.1) (set! x.0 rest.1))",
+            "(lambda args args)" => "(lambda args.0 args.0)",
+            "(lambda (x) `(x ,x))" => "(lambda (x.0) `(x ,x.0))",
+            "(define (f x) (g 'x))" => "(set! f (lambda (x.0) (g 'x)))"
+        ];
+    }
+
+    #[test]
+    fn internal_definitions() {
+        resolves![
+            "(lambda () (define y 1) (define (f) y) (f))" =>
+                "(lambda () (set! y.0 1) (set! f.1 (lambda () y.0)) (f.1))",
+            "(lambda (x) (define x 1) x)" => "(lambda (x.0) (set! x.0 1) x.0)",
+            "(if #t (define y 1))" => "(if #t (set! y 1))"
+        ];
+        assert_eq!(
+            resolve(&parse!("(lambda () 1 (define y 1))")).unwrap_err(),
+            InvalidSyntax("out of context define: (define y 1)".into())
+        );
+        assert_eq!(
+            resolve(&parse!("(lambda () (if #t (define y 1)) y)")).unwrap_err(),
+            InvalidSyntax("out of context define: (define y 1)".into())
+        );
+        assert_eq!(
+            resolve(&parse!("(lambda () (f (define y 1)))")).unwrap_err(),
+            InvalidSyntax("out of context define: (define y 1)".into())
+        );
+    }
+
+    #[test]
+    fn free_vars() {
+        let expr = resolve(&parse!(
+            "(lambda (x y) (lambda (z) (define w 1) (lambda () (+ x z w y x))))"
+        ))
+        .unwrap();
+        let Expr::Lambda(outer) = expr else {
+            panic!("expected lambda");
+        };
+        let Expr::Lambda(inner) = &outer.body[0] else {
+            panic!("expected lambda");
+        };
+        let names = |vars: Vec<Var>| vars.iter().map(|it| it.to_string()).collect::<Vec<_>>();
+        assert!(outer.free_vars().is_empty());
+        assert_eq!(names(inner.free_vars()), vec!["x.0", "y.1"]);
+    }
+
+    /// Return the names of the globals referenced by text, in order.
+    fn free_globals(text: &str) -> Result<Vec<String>, Error> {
+        let mut globals = vec![];
+        resolve(&parse!(text))?.walk(&mut |expr| {
+            if let Expr::GlobalRef(sym) = expr {
+                if !globals.contains(&sym.to_string()) {
+                    globals.push(sym.to_string());
+                }
+            }
+        });
+        Ok(globals)
+    }
+
+    #[test]
+    fn free_syms() {
+        // Single symbol not in the environment
+        assert_eq!(free_globals("a"), Ok(vec!["a".into()]));
+
+        // Atom
+        assert_eq!(free_globals("42"), Ok(vec![]));
+        assert_eq!(free_globals("#t"), Ok(vec![]));
+
+        // Any quoted symbols are completely ignored
+        assert_eq!(free_globals("(quote (a b c))"), Ok(vec![]));
+
+        // procedure, with arguments evaluated before the procedure
+        assert_eq!(
+            free_globals("(a b c)"),
+            Ok(vec!["b".into(), "c".into(), "a".into()])
+        );
+
+        // Nested primitive procedures
+        assert_eq!(
+            free_globals("(+ (* a b) (* c d) e)"),
+            Ok(["a", "b", "*", "c", "d", "e", "+"]
+                .map(String::from)
+                .to_vec())
+        );
+
+        // Define
+        assert_eq!(free_globals("(define a b)"), Ok(vec!["b".into()]));
+        assert_eq!(
+            free_globals("(define (a x) (+ x y))"),
+            Ok(vec!["y".into(), "+".into()])
+        );
+
+        // inner define procedure
+        assert_eq!(
+            free_globals(
+                r#"
+                (define (factorial n)
+                    (define (factorial n acc)
+                       (if (= n 0)
+                          acc
+                          (factorial (- n 1) (* n acc))))
+                    (factorial n 1))
+            "#
+            ),
+            Ok(vec!["=".into(), "-".into(), "*".into()])
+        );
+
+        // lambdas
+        assert_eq!(
+            free_globals("(lambda (x) (+ x y) z)"),
+            Ok(vec!["y".into(), "+".into(), "z".into()])
+        );
+        assert_eq!(
+            free_globals("(lambda (n) (+ ((adder num) n)))"),
+            Ok(vec!["num".into(), "adder".into(), "+".into()])
+        );
+
+        assert!(free_globals("(lambda)").is_err());
+        assert!(free_globals("(lambda (10) (+ x y))").is_err());
+        assert!(free_globals("(lambda (a 10) (+ x y))").is_err());
+        assert!(free_globals("(define)").is_err());
+    }
+
+    #[test]
+    fn internally_defined_symbols_returns_vec() {
+        assert_eq!(
+            internally_defined_symbols(&parse!["((define foo 10)(define (bar baz) 10))"]),
+            Ok(vec![&cell!["foo"], &cell!["bar"]])
+        );
+        assert!(internally_defined_symbols(&parse![
+            "((define foo 10)(set! foo 5)(define (bar baz) 10))"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn lambda_binding() {
+        let Ok(Expr::Lambda(lambda)) = resolve(&parse!("(lambda (a b) (f a b c))")) else {
+            panic!("expected lambda");
+        };
+        let Expr::Call(proc, args, _) = &lambda.body[0] else {
+            panic!("expected call");
+        };
+        assert!(matches!(&args[0], Expr::LocalRef(var) if *var == lambda.params[0]));
+        assert!(matches!(&args[1], Expr::LocalRef(var) if *var == lambda.params[1]));
+        assert!(matches!(&args[2], Expr::GlobalRef(sym) if *sym == cell!["c"]));
+        assert!(matches!(&**proc, Expr::GlobalRef(sym) if *sym == cell!["f"]));
+    }
+
+    #[test]
+    fn resolve_errors() {
+        assert_eq!(
+            resolve(&parse!("(lambda (x) if)")).unwrap_err(),
+            InvalidUsePrimitive("if".into())
+        );
+        assert_eq!(
+            resolve(&parse!("(lambda (x))")).unwrap_err(),
+            LambdaMissingExpression
+        );
+        assert_eq!(
+            resolve(&parse!("(lambda (1) x)")).unwrap_err(),
+            InvalidArgs("procedure".into(), "symbol".into(), "1".into())
+        );
+        assert_eq!(
+            resolve(&parse!("(set! 1 2)")).unwrap_err(),
+            InvalidSyntax("expected variable, but got 1".into())
+        );
+    }
+}
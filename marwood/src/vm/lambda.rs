@@ -1,8 +1,14 @@
 use crate::cell::Cell;
-use crate::vm::environment::{BindingLocation, EnvironmentMap};
+use crate::source::Span;
+use crate::vm::environment::EnvironmentMap;
 use crate::vm::vcell::VCell;
 use std::fmt::{Display, Formatter};
 
+/// The operand emitted for a jump until its target is patched in. It's
+/// never a valid offset, so a jump left unpatched fails to run rather than
+/// jumping somewhere arbitrary.
+const UNPATCHED_JUMP: VCell = VCell::Ptr(usize::MAX);
+
 /// Lambda
 ///
 /// Lambda represents a unit of executable bytecode constructed
@@ -15,6 +21,15 @@ pub struct Lambda {
     pub args: Vec<VCell>,
     pub bc: Vec<VCell>,
     pub desc_args: Option<Cell>,
+
+    /// The name of each local kept on the stack past the frame's saved
+    /// %bp, in order of offset, for the debugger
+    pub locals: Vec<Cell>,
+
+    /// The span of the expression each run of instructions was compiled
+    /// from, as (offset, span) in order of offset. A span applies to the
+    /// instructions from its offset up to the next entry's.
+    pub spans: Vec<(usize, Option<Span>)>,
 }
 
 impl Lambda {
@@ -34,28 +49,8 @@ impl Lambda {
             args,
             bc: vec![],
             desc_args: None,
-        }
-    }
-
-    /// New From IOF
-    ///
-    /// Create a new lambda, and populate its environment map
-    /// given the IOF and set of free symbols.
-    pub fn new_from_iof(
-        args: Vec<VCell>,
-        internally_defined: Vec<VCell>,
-        iof: &Lambda,
-        free_symbols: &[VCell],
-        is_vararg: bool,
-    ) -> Lambda {
-        let envmap = EnvironmentMap::new_from_iof(&args, &internally_defined, iof, free_symbols);
-        Lambda {
-            top_level: false,
-            args,
-            is_vararg,
-            envmap,
-            bc: vec![],
-            desc_args: None,
+            locals: vec![],
+            spans: vec![],
         }
     }
 
@@ -86,27 +81,56 @@ impl Lambda {
         self.bc.push(vcell.into());
     }
 
-    /// Binding Location
+    /// Emit Jump Placeholder
     ///
-    /// Return the binding for the given symbol. First check the lexical environment.
-    /// It's possible the arguments were copied into the lexical environment in case they're
-    /// needed by inner procedures, or for other reasons such as set!.
+    /// Emit a jump operand whose target isn't known yet, returning its
+    /// offset so that it may be patched with patch_jump once it is.
+    pub fn emit_jump_placeholder(&mut self) -> usize {
+        let operand = self.bc.len();
+        self.emit(UNPATCHED_JUMP);
+        operand
+    }
+
+    /// Patch Jump
     ///
-    /// If the symbol is neither in the lexical environmenr or a known argument then it's
-    /// globally bound.
+    /// Set the jump operand at offset operand, emitted by
+    /// emit_jump_placeholder, to target.
+    pub fn patch_jump(&mut self, operand: usize, target: usize) {
+        debug_assert_eq!(self.bc[operand], UNPATCHED_JUMP);
+        self.bc[operand] = VCell::ptr(target);
+    }
+
+    /// Set Span
     ///
-    /// # Arguments
-    /// `sym` - The symbol to lookup the binding location of.
-    pub fn binding_location(&self, sym: &VCell) -> BindingLocation {
-        if let Some(slot) = self.envmap.get_slot(sym) {
-            BindingLocation::Environment(slot)
-        } else if let Some((arg, _)) = self.args.iter().enumerate().find(|it| it.1 == sym) {
-            BindingLocation::Argument(arg)
+    /// Attribute the instructions emitted from here on to span, until the
+    /// span is set again.
+    pub fn set_span(&mut self, span: Option<Span>) {
+        if self.span() == span.as_ref() {
+            return;
+        }
+        if let Some(last) = self.spans.last_mut().filter(|it| it.0 == self.bc.len()) {
+            last.1 = span;
         } else {
-            BindingLocation::Global
+            self.spans.push((self.bc.len(), span));
         }
     }
 
+    /// Span
+    ///
+    /// Return the span instructions are currently being emitted for.
+    pub fn span(&self) -> Option<&Span> {
+        self.spans.last().and_then(|it| it.1.as_ref())
+    }
+
+    /// Span At
+    ///
+    /// Return the span of the expression the instruction at offset was
+    /// compiled from, if known.
+    pub fn span_at(&self, offset: usize) -> Option<&Span> {
+        let idx = self.spans.partition_point(|it| it.0 <= offset);
+        idx.checked_sub(1).and_then(|it| self.spans[it].1.as_ref())
+    }
+
     /// Argument Count
     ///
     /// Return the number of arguments
@@ -124,6 +148,8 @@ impl From<Vec<VCell>> for Lambda {
             is_vararg: false,
             bc,
             desc_args: None,
+            locals: vec![],
+            spans: vec![],
         }
     }
 }
@@ -136,25 +162,3 @@ impl Display for Lambda {
         }
     }
 }
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn lambda_binding() {
-        let lambda = Lambda::new(vec![VCell::ptr(100), VCell::ptr(200)]);
-        assert_eq!(
-            lambda.binding_location(&VCell::ptr(100)),
-            BindingLocation::Argument(0)
-        );
-        assert_eq!(
-            lambda.binding_location(&VCell::ptr(200)),
-            BindingLocation::Argument(1)
-        );
-        assert_eq!(
-            lambda.binding_location(&VCell::ptr(300)),
-            BindingLocation::Global
-        );
-    }
-}
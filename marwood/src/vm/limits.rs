@@ -0,0 +1,128 @@
+use crate::error::Error;
+use crate::error::Error::LimitExceeded;
+use crate::number::Number;
+use crate::vm::vcell::VCell;
+use crate::vm::Vm;
+
+/// Limits
+///
+/// Limits bound the memory a Vm may consume, such as when running untrusted
+/// scripts. A limit of None is unbounded, which is the default.
+///
+/// Exceeding a limit is an Error::LimitExceeded, which may be caught by the
+/// scheme exception system. If it isn't, it's returned from the evaluation
+/// that exceeded it, and the Vm remains usable.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Limits {
+    /// The number of vcells the heap may hold.
+    pub heap_cells: Option<usize>,
+
+    /// The number of vcells the stack may hold.
+    pub stack_depth: Option<usize>,
+
+    /// The number of chars a string may hold.
+    pub string_length: Option<usize>,
+
+    /// The number of elements a vector may hold.
+    pub vector_length: Option<usize>,
+}
+
+impl Vm {
+    /// Set Limits
+    ///
+    /// Bound the memory this Vm may consume.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.heap.set_limit(limits.heap_cells);
+        self.stack.set_limit(limits.stack_depth);
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Check String Length
+    ///
+    /// Return an error if a string of len chars would exceed the string
+    /// length limit, or if the heap has no room for them. A string's chars
+    /// are counted as heap cells when it's allocated.
+    pub fn check_string_length(&mut self, len: usize) -> Result<(), Error> {
+        match self.limits.string_length {
+            Some(limit) if len > limit => Err(LimitExceeded("string length", limit)),
+            _ => self.reserve_heap(len),
+        }
+    }
+
+    /// Check Vector Length
+    ///
+    /// Return an error if a vector of len elements would exceed the vector
+    /// length limit, or if the heap has no room for them. A vector's
+    /// elements are counted as heap cells when it's allocated.
+    pub fn check_vector_length(&mut self, len: usize) -> Result<(), Error> {
+        match self.limits.vector_length {
+            Some(limit) if len > limit => Err(LimitExceeded("vector length", limit)),
+            _ => self.reserve_heap(len),
+        }
+    }
+
+    /// Check Allocation
+    ///
+    /// Return an error if vcell, a value returned by a builtin, exceeds a
+    /// limit. This is where every string and big number a builtin produces
+    /// is checked: a string against the string length limit, and the chars
+    /// of a string or the 64 bit digits of a big number are counted as heap
+    /// cells when they're allocated.
+    pub fn check_allocation(&mut self, vcell: &VCell) -> Result<(), Error> {
+        match vcell {
+            VCell::String(s) => self.check_string_length(s.len()),
+            VCell::Number(Number::BigInt(num)) => {
+                self.reserve_heap(num.bits().div_ceil(64) as usize)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Reserve Heap
+    ///
+    /// Return an error if allocating cells more vcells would grow the heap
+    /// past its limit, collecting garbage first if the heap doesn't have
+    /// room for them. Builtins reserve the cells they allocate in proportion
+    /// to their arguments, so that they fail before growing the heap.
+    pub fn reserve_heap(&mut self, cells: usize) -> Result<(), Error> {
+        let Some(limit) = self.limits.heap_cells else {
+            return Ok(());
+        };
+        if cells <= self.heap.free_size() {
+            return Ok(());
+        }
+        self.collect_garbage();
+        if self.heap.used_size().saturating_add(cells) > limit.max(self.heap.capacity()) {
+            return Err(LimitExceeded("heap cell", limit));
+        }
+        Ok(())
+    }
+
+    /// Check Heap Limit
+    ///
+    /// Called once the heap has been exhausted, that is, it has reached its
+    /// limit and had to grow past it to satisfy an allocation that wasn't
+    /// reserved. Collect garbage, and return an error if the heap is still
+    /// over its limit.
+    pub fn check_heap_limit(&mut self) -> Result<(), Error> {
+        self.heap.clear_exhausted();
+        self.collect_garbage();
+        match self.limits.heap_cells {
+            Some(limit) if self.heap.used_size() > limit => Err(LimitExceeded("heap cell", limit)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Reset Stack Limit
+    ///
+    /// Apply the stack depth limit to the current stack, removing the
+    /// headroom granted by exceeding it once the stack has unwound below
+    /// the limit.
+    pub fn reset_stack_limit(&mut self) {
+        self.stack.set_limit(self.limits.stack_depth);
+    }
+}
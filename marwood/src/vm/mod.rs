@@ -2,25 +2,48 @@ use crate::cell::Cell;
 use crate::error::Error;
 use crate::lex;
 use crate::parse;
+use crate::prelude::PRELUDE_IMAGE;
+use crate::source::{Source, SourceMap};
+use crate::vm::debug::Debugger;
 use crate::vm::environment::GlobalEnvironment;
+use crate::vm::execution::ExecutionState;
+use crate::vm::gc::{GcConfig, GcStats};
 use crate::vm::heap::{Heap, HeapRef};
+use crate::vm::interrupt::InterruptHandle;
+use crate::vm::limits::Limits;
 use crate::vm::stack::Stack;
+use crate::vm::thread::Scheduler;
 use crate::vm::trace::StackTrace;
 use crate::vm::vcell::VCell;
 use log::trace;
+use std::collections::HashMap;
 use std::fmt::Debug;
+use std::rc::Rc;
 
 pub mod builtin;
+pub mod charset;
 pub mod compare;
 pub mod compile;
 pub mod continuation;
+pub mod debug;
 pub mod environment;
+pub mod exception;
+pub mod execution;
+pub mod fork;
 pub mod gc;
 pub mod heap;
+pub mod image;
+pub mod inline;
+pub mod interrupt;
+pub mod ir;
 pub mod lambda;
+pub mod limits;
 pub mod opcode;
+pub mod optimize;
 pub mod run;
 pub mod stack;
+pub mod string;
+pub mod thread;
 pub mod trace;
 pub mod transform;
 pub mod vcell;
@@ -28,6 +51,8 @@ pub mod vector;
 
 const HEAP_CHUNK_SIZE: usize = 8192;
 
+const PRELUDE_SOURCE: &str = include_str!("../../prelude.scm");
+
 #[derive(Debug)]
 pub struct Vm {
     /// The heap and global environment
@@ -44,10 +69,44 @@ pub struct Vm {
     pub bp: usize,
 
     /// System Interface (display, write, etc).
-    sys: Box<dyn SystemInterface>,
+    sys: Rc<dyn SystemInterface>,
 
     /// Stacktrace of last error
     last_stacktrace: Option<StackTrace>,
+
+    /// Whether compiled bytecode is run through the optimizer
+    optimize: bool,
+
+    /// Garbage collector tuning
+    gc_config: GcConfig,
+
+    /// Memory limits
+    limits: Limits,
+
+    /// The stack pointer on entry to the running builtin, below which the
+    /// slots it has popped remain roots of garbage collection
+    builtin_sp: usize,
+
+    /// Set by another thread to stop the running program
+    interrupt: InterruptHandle,
+
+    /// Suspended executions started with start()
+    executions: HashMap<usize, ExecutionState>,
+
+    /// The states of evaluations set aside while an execution is resumed
+    saved_states: Vec<ExecutionState>,
+
+    /// The id of the next execution to start
+    next_execution: usize,
+
+    /// Green threads started with thread-start!
+    scheduler: Scheduler,
+
+    /// Breakpoints and the state of a program paused under the debugger
+    debugger: Debugger,
+
+    /// The spans of the expression being compiled
+    source_map: SourceMap,
 }
 
 impl Vm {
@@ -55,6 +114,16 @@ impl Vm {
     ///
     /// Return a new Vm
     pub fn new() -> Vm {
+        let mut vm = Vm::new_without_prelude();
+        vm.load_prelude();
+        vm
+    }
+
+    /// New Without Prelude
+    ///
+    /// Return a new Vm with only builtin procedures loaded, such as for
+    /// compiling the prelude itself.
+    pub fn new_without_prelude() -> Vm {
         let mut vm = Vm {
             heap: Heap::new(HEAP_CHUNK_SIZE),
             ip: (usize::MAX, 0),
@@ -63,23 +132,39 @@ impl Vm {
             ep: usize::MAX,
             acc: VCell::undefined(),
             bp: 0,
-            sys: Box::new(StubInterface {}),
+            sys: Rc::new(StubInterface {}),
             last_stacktrace: None,
+            optimize: true,
+            gc_config: GcConfig::default(),
+            limits: Limits::default(),
+            builtin_sp: 0,
+            interrupt: InterruptHandle::new(),
+            executions: HashMap::new(),
+            saved_states: vec![],
+            next_execution: 0,
+            scheduler: Scheduler::new(),
+            debugger: Debugger::new(),
+            source_map: SourceMap::default(),
         };
         vm.load_builtins();
-        vm.load_prelude();
         vm
     }
 
     /// Load Prelude
     ///
-    /// Read and compile prelude.scm
+    /// Load the precompiled prelude image, or read and compile prelude.scm
+    /// if there's no image to load, as when building the image itself.
     pub fn load_prelude(&mut self) {
-        let prelude_text = include_str!("../../prelude.scm");
-        let prelude_tokens = lex::scan(prelude_text).expect("invalid prelude");
+        if image::image_source_hash(PRELUDE_IMAGE) == Ok(image::source_hash(PRELUDE_SOURCE)) {
+            self.load_bytecode(PRELUDE_IMAGE)
+                .expect("invalid prelude image");
+            return;
+        }
+        trace!("no prelude image, compiling prelude.scm");
+        let prelude_tokens = lex::scan(PRELUDE_SOURCE).expect("invalid prelude");
         let mut it = prelude_tokens.iter().peekable();
         while it.peek().is_some() {
-            let ast = parse::parse(prelude_text, &mut it).expect("invalid prelude");
+            let ast = parse::parse(PRELUDE_SOURCE, &mut it).expect("invalid prelude");
             self.eval(&ast).expect("invalid prelude");
         }
     }
@@ -96,6 +181,22 @@ impl Vm {
         self.run()
     }
 
+    /// Prepare Eval Spanned
+    ///
+    /// Compile the expression contained within cell as prepare_eval does,
+    /// attributing its bytecode to the spans of cell's lists, so that errors
+    /// and stack traces may report where in the source they occurred.
+    ///
+    /// # Arguments
+    /// `cell` - An expression to evaluate
+    /// `spans` - The spans recorded for cell by parse::parse_spanned
+    pub fn prepare_eval_spanned(&mut self, cell: &Cell, spans: SourceMap) -> Result<(), Error> {
+        self.source_map = spans;
+        let result = self.prepare_eval(cell);
+        self.source_map = SourceMap::default();
+        result
+    }
+
     pub fn prepare_eval(&mut self, cell: &Cell) -> Result<(), Error> {
         let lambda = self.compile_runnable(cell)?;
         trace!("entry: \n{}", self.decompile_text(&lambda));
@@ -113,13 +214,62 @@ impl Vm {
     /// # Arguments
     /// `text` - The text to eval
     pub fn eval_text<'a>(&mut self, text: &'a str) -> Result<(Cell, Option<&'a str>), Error> {
-        let (cell, remaining_text) = parse::parse_text(text)?;
-        self.prepare_eval(&cell)?;
+        let tokens = lex::scan(text)?;
+        let mut cur = tokens.iter().peekable();
+        let mut spans = SourceMap::new(Source::new("<input>", text));
+        let cell = parse::parse_spanned(text, &mut cur, &mut spans)?;
+        let remaining_text = cur.peek().map(|it| &text[it.span.0..]);
+        self.prepare_eval_spanned(&cell, spans)?;
         Ok((self.run()?, remaining_text))
     }
 
+    /// Eval Source
+    ///
+    /// Parse and eval each expression of the source text, such as the
+    /// contents of a file, returning the result of the last expression.
+    /// Errors are attributed to the expression they occurred in by name,
+    /// line and column.
+    ///
+    /// # Arguments
+    /// `name` - The name of the source, such as its file name
+    /// `text` - The text to eval
+    pub fn eval_source(&mut self, name: &str, text: &str) -> Result<Cell, Error> {
+        let source = Source::new(name, text);
+        let tokens = lex::scan(text)?;
+        let mut cur = tokens.iter().peekable();
+        let mut result = Cell::Void;
+        while cur.peek().is_some() {
+            let mut spans = SourceMap::new(source.clone());
+            let cell = parse::parse_spanned(text, &mut cur, &mut spans)?;
+            self.prepare_eval_spanned(&cell, spans)?;
+            result = self.run()?;
+        }
+        Ok(result)
+    }
+
+    /// Set GC Config
+    ///
+    /// Tune when garbage is collected, and how much work each increment
+    /// of a collection may do.
+    pub fn set_gc_config(&mut self, config: GcConfig) {
+        self.gc_config = config;
+    }
+
+    pub fn gc_config(&self) -> &GcConfig {
+        &self.gc_config
+    }
+
+    /// GC Stats
+    ///
+    /// Return statistics about the heap and garbage collection, such as the
+    /// number of collections, the pauses taken to perform them and the
+    /// memory they reclaimed.
+    pub fn gc_stats(&self) -> GcStats {
+        self.heap.gc_stats()
+    }
+
     pub fn set_system_interface(&mut self, sys: Box<dyn SystemInterface>) {
-        self.sys = sys;
+        self.sys = Rc::from(sys);
     }
 
     pub fn display(&self, cell: &Cell) {
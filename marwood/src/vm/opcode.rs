@@ -13,6 +13,7 @@ use std::iter::Peekable;
 pub enum OpCode {
     // VM Primitives
     Cons,
+    Guard,
     Jmp,
     Jnt,
     Mov,
@@ -27,9 +28,136 @@ pub enum OpCode {
     CallAcc,
     ClosureAcc,
     Enter,
+    Prompt,
     Ret,
     TCallAcc,
     VarArg,
+
+    // Inline Primitive Procedures
+    Add,
+    Car,
+    Cdr,
+    Eq,
+    Gt,
+    IsNull,
+    IsPair,
+    IsZero,
+    Lt,
+    Not,
+    NumEq,
+    Sub,
+}
+
+impl OpCode {
+    /// From U8
+    ///
+    /// Return the opcode whose discriminant is byte, the inverse of
+    /// `op as u8`. This is used to read serialized bytecode.
+    pub fn from_u8(byte: u8) -> Option<OpCode> {
+        const OPCODES: [OpCode; 30] = [
+            OpCode::Cons,
+            OpCode::Guard,
+            OpCode::Jmp,
+            OpCode::Jnt,
+            OpCode::Mov,
+            OpCode::MovImmediate,
+            OpCode::Push,
+            OpCode::PushAcc,
+            OpCode::PushImmediate,
+            OpCode::Halt,
+            OpCode::VPushAcc,
+            OpCode::CallAcc,
+            OpCode::ClosureAcc,
+            OpCode::Enter,
+            OpCode::Prompt,
+            OpCode::Ret,
+            OpCode::TCallAcc,
+            OpCode::VarArg,
+            OpCode::Add,
+            OpCode::Car,
+            OpCode::Cdr,
+            OpCode::Eq,
+            OpCode::Gt,
+            OpCode::IsNull,
+            OpCode::IsPair,
+            OpCode::IsZero,
+            OpCode::Lt,
+            OpCode::Not,
+            OpCode::NumEq,
+            OpCode::Sub,
+        ];
+        OPCODES.get(byte as usize).cloned()
+    }
+
+    /// Operand Count
+    ///
+    /// Return the number of operand cells that follow this opcode in
+    /// bytecode.
+    pub fn operand_count(&self) -> usize {
+        schema()
+            .get(self)
+            .expect("unknown opcode")
+            .operands
+            .iter()
+            .filter(|it| **it != Operand::Acc)
+            .count()
+    }
+
+    /// Jump Operand
+    ///
+    /// Return the index of the operand that holds a bytecode offset to
+    /// jump to, if this opcode may jump.
+    pub fn jump_operand(&self) -> Option<usize> {
+        match self {
+            OpCode::Jmp | OpCode::Jnt | OpCode::Prompt => Some(0),
+            OpCode::Guard
+            | OpCode::Add
+            | OpCode::Car
+            | OpCode::Cdr
+            | OpCode::Eq
+            | OpCode::Gt
+            | OpCode::IsNull
+            | OpCode::IsPair
+            | OpCode::IsZero
+            | OpCode::Lt
+            | OpCode::Not
+            | OpCode::NumEq
+            | OpCode::Sub => Some(2),
+            _ => None,
+        }
+    }
+
+    /// Is Primitive
+    ///
+    /// Return true if this is an inline primitive procedure opcode.
+    pub fn is_primitive(&self) -> bool {
+        !matches!(
+            self,
+            OpCode::Jmp | OpCode::Jnt | OpCode::Guard | OpCode::Prompt
+        ) && self.jump_operand().is_some()
+    }
+
+    /// Primitive
+    ///
+    /// Return the inline primitive opcode for applying the builtin procedure
+    /// bound to name with argc arguments, if one exists.
+    pub fn primitive(name: &str, argc: usize) -> Option<OpCode> {
+        match (name, argc) {
+            ("+", 2) => Some(OpCode::Add),
+            ("car", 1) => Some(OpCode::Car),
+            ("cdr", 1) => Some(OpCode::Cdr),
+            ("eq?", 2) => Some(OpCode::Eq),
+            (">", 2) => Some(OpCode::Gt),
+            ("null?", 1) => Some(OpCode::IsNull),
+            ("pair?", 1) => Some(OpCode::IsPair),
+            ("zero?", 1) => Some(OpCode::IsZero),
+            ("<", 2) => Some(OpCode::Lt),
+            ("not", 1) => Some(OpCode::Not),
+            ("=", 2) => Some(OpCode::NumEq),
+            ("-", 2) => Some(OpCode::Sub),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -47,9 +175,9 @@ pub enum Operand {
     /// value. It must be a register or reference type.
     ///
     /// * If the operand is a register, the value is stored in the
-    /// register itself. No dereference occurs.
+    ///   register itself. No dereference occurs.
     /// * If the operand is a reference type, the value is stored
-    /// in the location the reference points to.
+    ///   in the location the reference points to.
     StoreReference,
 
     /// Dereference
@@ -58,9 +186,9 @@ pub enum Operand {
     /// or reference type.
     ///
     /// * If the operand is a register, then the value is read from the
-    /// register.
+    ///   register.
     /// * If the operand is a reference type, the value is read from
-    /// the location the reference points to.
+    ///   the location the reference points to.
     LoadReference,
 
     /// Acc
@@ -98,6 +226,7 @@ fn schema() -> &'static HashMap<OpCode, Schema> {
             (OpCode::ClosureAcc, Schema::new("CLOSURE", vec![Operand::Acc])),
             (OpCode::Cons, Schema::new("CONS", vec![])),
             (OpCode::Enter, Schema::new("ENTER", vec![])),
+            (OpCode::Guard, Schema::new("GUARD", primitive_operands())),
             (OpCode::Halt, Schema::new("HALT", vec![])),
             (OpCode::Jmp, Schema::new("JMP", vec![Operand::Immediate])),
             (OpCode::Jnt, Schema::new("JNT", vec![Operand::Immediate])),
@@ -106,15 +235,42 @@ fn schema() -> &'static HashMap<OpCode, Schema> {
             (OpCode::Push, Schema::new("PUSH", vec![Operand::LoadReference])),
             (OpCode::PushImmediate, Schema::new("PUSH", vec![Operand::Immediate])),
             (OpCode::PushAcc, Schema::new("PUSH", vec![Operand::Acc])),
+            (OpCode::Prompt, Schema::new("PROMPT", vec![Operand::Immediate])),
             (OpCode::Ret, Schema::new("RET", vec![])),
             (OpCode::TCallAcc, Schema::new("TCALL", vec![Operand::Acc])),
             (OpCode::VPushAcc, Schema::new("VPUSH", vec![])),
-            (OpCode::VarArg, Schema::new("VARARG", vec![]))
+            (OpCode::VarArg, Schema::new("VARARG", vec![])),
+            (OpCode::Add, Schema::new("ADD", primitive_operands())),
+            (OpCode::Car, Schema::new("CAR", primitive_operands())),
+            (OpCode::Cdr, Schema::new("CDR", primitive_operands())),
+            (OpCode::Eq, Schema::new("EQ", primitive_operands())),
+            (OpCode::Gt, Schema::new("GT", primitive_operands())),
+            (OpCode::IsNull, Schema::new("NULL", primitive_operands())),
+            (OpCode::IsPair, Schema::new("PAIR", primitive_operands())),
+            (OpCode::IsZero, Schema::new("ZERO", primitive_operands())),
+            (OpCode::Lt, Schema::new("LT", primitive_operands())),
+            (OpCode::Not, Schema::new("NOT", primitive_operands())),
+            (OpCode::NumEq, Schema::new("NUMEQ", primitive_operands())),
+            (OpCode::Sub, Schema::new("SUB", primitive_operands()))
         ]);
     }
     &SCHEMA
 }
 
+/// Primitive Operands
+///
+/// Inline primitives take the global environment slot the procedure is
+/// bound to, the builtin the slot must still hold for the primitive to be
+/// applied inline, and the offset to jump to once it has been. GUARD takes
+/// the same operands, but jumps if the slot no longer holds the builtin.
+fn primitive_operands() -> Vec<Operand> {
+    vec![
+        Operand::LoadReference,
+        Operand::Immediate,
+        Operand::Immediate,
+    ]
+}
+
 #[derive(Debug)]
 pub struct DecompiledInstruction {
     op: &'static str,
@@ -137,23 +293,24 @@ impl Display for DecompiledInstruction {
             })
             .collect::<Vec<_>>();
         let values = &self.values;
+        let mut operand_text = format!(
+            "{0: <12} {1: <12}",
+            operands.first().unwrap_or(&"".to_string()),
+            operands.get(1).unwrap_or(&"".to_string()),
+        );
+        for it in operands.iter().skip(2) {
+            let _ = write!(operand_text, " {: <12}", it);
+        }
         if !operands.is_empty() && !values.is_empty() {
             write!(
                 f,
-                "{0: <8} {1: <12} {2: <12} //{3: <10}",
+                "{0: <8} {1} //{2: <10}",
                 op,
-                operands.first().unwrap_or(&"".to_string()),
-                operands.get(1).unwrap_or(&"".to_string()),
+                operand_text,
                 values.join(" ")
             )
         } else if !operands.is_empty() {
-            write!(
-                f,
-                "{0: <8} {1: <12} {2: <12}",
-                op,
-                operands.first().unwrap_or(&"".to_string()),
-                operands.get(1).unwrap_or(&"".to_string()),
-            )
+            write!(f, "{0: <8} {1}", op, operand_text)
         } else {
             write!(f, "{}", op)
         }
@@ -262,4 +419,12 @@ mod tests {
             assert_eq!(vm.acc, VCell::Bool(true));
         }
     }
+
+    #[test]
+    fn from_u8() {
+        for op in schema().keys() {
+            assert_eq!(OpCode::from_u8(op.clone() as u8), Some(op.clone()));
+        }
+        assert_eq!(OpCode::from_u8(schema().len() as u8), None);
+    }
 }
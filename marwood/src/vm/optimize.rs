@@ -0,0 +1,621 @@
+use crate::error::Error;
+use crate::error::Error::InvalidBytecode;
+use crate::source::Span;
+use crate::vm::lambda::Lambda;
+use crate::vm::opcode::OpCode;
+use crate::vm::vcell::VCell;
+use crate::vm::Vm;
+use log::trace;
+use std::collections::{HashMap, HashSet};
+
+/// Instruction
+///
+/// A decoded instruction. While optimizing, jump operands refer to labels
+/// rather than bytecode offsets, so that instructions may be inserted and
+/// removed freely. A label is attached to the instruction it refers to,
+/// and moves to the following instruction if that instruction is removed.
+#[derive(Clone, Debug)]
+struct Instruction {
+    labels: Vec<usize>,
+    op: OpCode,
+    operands: Vec<VCell>,
+    span: Option<Span>,
+}
+
+impl Instruction {
+    fn new(op: OpCode, operands: Vec<VCell>, span: Option<Span>) -> Instruction {
+        Instruction {
+            labels: vec![],
+            op,
+            operands,
+            span,
+        }
+    }
+
+    fn target(&self) -> Option<usize> {
+        self.op
+            .jump_operand()
+            .and_then(|it| self.operands[it].as_ptr().ok())
+    }
+
+    fn set_target(&mut self, label: usize) {
+        if let Some(it) = self.op.jump_operand() {
+            self.operands[it] = VCell::ptr(label);
+        }
+    }
+
+    /// Is Acc Store
+    ///
+    /// Return true if this is a MOV or MOV immediate to %acc.
+    fn is_acc_store(&self) -> bool {
+        matches!(self.op, OpCode::Mov | OpCode::MovImmediate)
+            && self.operands[1] == VCell::Acc
+            && self.operands[0] != VCell::Acc
+    }
+}
+
+impl Vm {
+    /// Set Optimize
+    ///
+    /// Enable or disable the bytecode optimizer for procedures compiled
+    /// by this Vm. The optimizer is enabled by default.
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    /// Optimize Lambda
+    ///
+    /// Run the optimization pipeline over lambda's bytecode if the
+    /// optimizer is enabled. The pipeline repeats each of the following
+    /// until no further changes are made:
+    ///
+    /// * Constant folding of inline primitives applied to literals. The
+    ///   folded value is guarded in the same way as the primitive, so
+    ///   redefining the builtin still takes effect.
+    /// * Removal of constant if tests.
+    /// * Collapsing MOV x %acc, PUSH %acc into PUSH x when %acc is not
+    ///   read afterwards.
+    /// * Removal of MOV immediate stores to %acc that are never read.
+    /// * Jump threading, and replacing jumps to RET with RET.
+    /// * Elimination of unreachable code and jumps to the next instruction.
+    pub fn optimize_lambda(&mut self, lambda: &mut Lambda) -> Result<(), Error> {
+        if !self.optimize {
+            return Ok(());
+        }
+        trace!("before optimization: \n{}", self.decompile_text(lambda));
+        self.run_optimizer(lambda)?;
+        trace!("after optimization: \n{}", self.decompile_text(lambda));
+        Ok(())
+    }
+
+    /// Decompile Optimization
+    ///
+    /// Return the decompiled text of lambda before and after running the
+    /// optimization pipeline over a copy of it, whether or not the optimizer
+    /// is enabled. Given a lambda compiled with the optimizer disabled, this
+    /// shows what the optimizer makes of it.
+    pub fn decompile_optimization(&mut self, lambda: &Lambda) -> Result<(String, String), Error> {
+        let mut optimized = lambda.clone();
+        self.run_optimizer(&mut optimized)?;
+        Ok((self.decompile_text(lambda), self.decompile_text(&optimized)))
+    }
+
+    fn run_optimizer(&mut self, lambda: &mut Lambda) -> Result<(), Error> {
+        let mut code = decode(lambda)?;
+        let mut next_label = lambda.bc.len();
+        loop {
+            prune_labels(&mut code);
+            let mut changed = self.fold_constants(&mut code, &mut next_label)?;
+            changed |= remove_constant_tests(&mut code);
+            changed |= collapse_acc_pushes(&mut code);
+            changed |= remove_dead_acc_stores(&mut code);
+            changed |= thread_jumps(&mut code);
+            changed |= remove_unreachable(&mut code);
+            changed |= remove_jumps_to_next(&mut code);
+            if !changed {
+                break;
+            }
+        }
+        encode(code, lambda)
+    }
+
+    /// Fold Constants
+    ///
+    /// Find applications of inline primitives whose arguments are all
+    /// pushed immediates, in the form:
+    ///
+    /// ```text
+    /// PUSH $arg ... PUSH argc[n] PRIM [slot] $builtin $end MOV [slot] %acc CALL %acc
+    /// ```
+    ///
+    /// and replace them with a guarded constant:
+    ///
+    /// ```text
+    /// GUARD [slot] $builtin $fallback MOV $result %acc JMP $end
+    /// fallback: PUSH $arg ... PUSH argc[n] MOV [slot] %acc CALL %acc
+    /// ```
+    fn fold_constants(
+        &mut self,
+        code: &mut Vec<Instruction>,
+        next_label: &mut usize,
+    ) -> Result<bool, Error> {
+        let mut changed = false;
+        let mut i = 0;
+        while i < code.len() {
+            let Some(n) = self.foldable_application(code, i) else {
+                i += 1;
+                continue;
+            };
+            let args = code[i..i + n]
+                .iter()
+                .map(|it| &it.operands[0])
+                .collect::<Vec<_>>();
+            let prim = &code[i + n + 1];
+            // Errors are left to be reported when the code is run.
+            let Ok(Some(result)) = self.apply_primitive(&prim.op, &args) else {
+                i += 1;
+                continue;
+            };
+            let result = self.heap.maybe_put(result);
+            let (slot, builtin) = (prim.operands[0].clone(), prim.operands[1].clone());
+            let span = prim.span.clone();
+            let end = prim.target().ok_or(InvalidBytecode)?;
+
+            // The primitive is no longer needed in the fallback, as the
+            // guard has already established it would fall through.
+            code.remove(i + n + 1);
+
+            let fallback = *next_label;
+            *next_label += 1;
+            let mut guard = Instruction::new(
+                OpCode::Guard,
+                vec![slot, builtin, VCell::ptr(fallback)],
+                span.clone(),
+            );
+            guard.labels = std::mem::take(&mut code[i].labels);
+            code[i].labels.push(fallback);
+            code.splice(
+                i..i,
+                [
+                    guard,
+                    Instruction::new(OpCode::MovImmediate, vec![result, VCell::Acc], span.clone()),
+                    Instruction::new(OpCode::Jmp, vec![VCell::ptr(end)], span),
+                ],
+            );
+            changed = true;
+            i += n + 6;
+        }
+        Ok(changed)
+    }
+
+    /// Foldable Application
+    ///
+    /// Return the number of arguments of the primitive application starting
+    /// at code[i], if it is in the form fold_constants expects.
+    fn foldable_application(&self, code: &[Instruction], i: usize) -> Option<usize> {
+        let mut n = 0;
+        while let Some(it) = code.get(i + n) {
+            match (&it.op, it.operands.first()) {
+                (OpCode::PushImmediate, Some(VCell::ArgumentCount(argc))) if *argc == n => break,
+                (OpCode::PushImmediate, Some(arg)) if self.is_literal(arg) => n += 1,
+                _ => return None,
+            }
+        }
+        let prim = code.get(i + n + 1)?;
+        let mov = code.get(i + n + 2)?;
+        let call = code.get(i + n + 3)?;
+        let straight_line = code[i + 1..i + n + 4].iter().all(|it| it.labels.is_empty());
+        let end = &code.get(i + n + 4)?.labels;
+        (straight_line
+            && prim.op.is_primitive()
+            && mov.is_acc_store()
+            && mov.operands[0] == prim.operands[0]
+            && matches!(call.op, OpCode::CallAcc | OpCode::TCallAcc)
+            && end.contains(&prim.target()?))
+        .then_some(n)
+    }
+
+    fn is_literal(&self, vcell: &VCell) -> bool {
+        matches!(
+            self.heap.get(vcell),
+            VCell::Bool(_)
+                | VCell::Char(_)
+                | VCell::Nil
+                | VCell::Number(_)
+                | VCell::Pair(_, _)
+                | VCell::String(_)
+                | VCell::Symbol(_)
+                | VCell::Vector(_)
+        )
+    }
+}
+
+/// Decode
+///
+/// Decode lambda's bytecode into instructions, replacing jump offsets with
+/// labels. The label for an instruction is its original bytecode offset.
+fn decode(lambda: &Lambda) -> Result<Vec<Instruction>, Error> {
+    let bc = &lambda.bc;
+    let mut code = vec![];
+    let mut offsets = HashMap::new();
+    let mut it = 0;
+    while it < bc.len() {
+        let op = bc[it].as_opcode()?;
+        let len = op.operand_count();
+        let operands = bc.get(it + 1..it + 1 + len).ok_or(InvalidBytecode)?;
+        offsets.insert(it, code.len());
+        let span = lambda.span_at(it).cloned();
+        code.push(Instruction::new(op, operands.to_vec(), span));
+        it += 1 + len;
+    }
+    for idx in 0..code.len() {
+        if let Some(target) = code[idx].target() {
+            let target_idx = *offsets.get(&target).ok_or(InvalidBytecode)?;
+            code[target_idx].labels.push(target);
+        }
+    }
+    Ok(code)
+}
+
+/// Encode
+///
+/// Encode instructions back into lambda's bytecode, replacing labels with
+/// the offset of the instruction they are attached to.
+fn encode(code: Vec<Instruction>, lambda: &mut Lambda) -> Result<(), Error> {
+    let mut offsets = HashMap::new();
+    let mut offset = 0;
+    for it in &code {
+        for label in &it.labels {
+            offsets.insert(*label, offset);
+        }
+        offset += 1 + it.operands.len();
+    }
+    lambda.bc = Vec::with_capacity(offset);
+    lambda.spans.clear();
+    for mut it in code {
+        if let Some(label) = it.target() {
+            it.set_target(*offsets.get(&label).ok_or(InvalidBytecode)?);
+        }
+        lambda.set_span(it.span);
+        lambda.emit(VCell::OpCode(it.op));
+        lambda.bc.extend(it.operands);
+    }
+    Ok(())
+}
+
+/// Label Index
+///
+/// Return a map from each label to the index of the instruction it is
+/// attached to.
+fn label_index(code: &[Instruction]) -> HashMap<usize, usize> {
+    code.iter()
+        .enumerate()
+        .flat_map(|(idx, it)| it.labels.iter().map(move |label| (*label, idx)))
+        .collect()
+}
+
+/// Prune Labels
+///
+/// Remove labels that are no longer the target of any jump.
+fn prune_labels(code: &mut [Instruction]) {
+    let targets = code
+        .iter()
+        .filter_map(Instruction::target)
+        .collect::<HashSet<_>>();
+    for it in code.iter_mut() {
+        it.labels.retain(|label| targets.contains(label));
+    }
+}
+
+/// Remove
+///
+/// Remove the instruction at idx, moving its labels to the following
+/// instruction.
+fn remove(code: &mut Vec<Instruction>, idx: usize) {
+    let labels = code.remove(idx).labels;
+    if let Some(next) = code.get_mut(idx) {
+        next.labels.extend(labels);
+    }
+}
+
+/// Is Acc Dead After
+///
+/// Return true if the value of %acc after code[idx] is overwritten before
+/// it can be read, considering only the straight line code that follows
+/// and any unconditional jumps within it.
+fn is_acc_dead_after(code: &[Instruction], idx: usize) -> bool {
+    let mut idx = idx + 1;
+    let mut visited = HashSet::new();
+    while let Some(it) = code.get(idx) {
+        match it.op {
+            OpCode::Mov | OpCode::MovImmediate if it.operands[0] == VCell::Acc => return false,
+            OpCode::Mov | OpCode::MovImmediate if it.operands[1] == VCell::Acc => return true,
+            OpCode::Mov | OpCode::MovImmediate | OpCode::PushImmediate => {}
+            OpCode::Push if it.operands[0] == VCell::Acc => return false,
+            OpCode::Push => {}
+            OpCode::Cons => return true,
+            OpCode::Jmp => {
+                let label = it.target().unwrap();
+                if !visited.insert(label) {
+                    return false;
+                }
+                match code.iter().position(|it| it.labels.contains(&label)) {
+                    Some(target) => idx = target,
+                    None => return false,
+                }
+                continue;
+            }
+            // A primitive doesn't read %acc, and only jumps once it has
+            // stored its result in %acc.
+            _ if it.op.is_primitive() => {}
+            _ => return false,
+        }
+        idx += 1;
+    }
+    false
+}
+
+/// Remove Constant Tests
+///
+/// A JNT directly following a MOV immediate to %acc either always or
+/// never jumps.
+fn remove_constant_tests(code: &mut Vec<Instruction>) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+    while i + 1 < code.len() {
+        if code[i].op == OpCode::MovImmediate
+            && code[i].operands[1] == VCell::Acc
+            && code[i + 1].op == OpCode::Jnt
+            && code[i + 1].labels.is_empty()
+        {
+            if code[i].operands[0] == VCell::Bool(false) {
+                code[i + 1].op = OpCode::Jmp;
+            } else {
+                remove(code, i + 1);
+            }
+            changed = true;
+        }
+        i += 1;
+    }
+    changed
+}
+
+/// Collapse Acc Pushes
+///
+/// Collapse MOV x %acc, PUSH %acc into PUSH x.
+fn collapse_acc_pushes(code: &mut Vec<Instruction>) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+    while i + 1 < code.len() {
+        if code[i].is_acc_store()
+            && code[i + 1].op == OpCode::PushAcc
+            && code[i + 1].labels.is_empty()
+            && is_acc_dead_after(code, i + 1)
+        {
+            code[i].op = match code[i].op {
+                OpCode::MovImmediate => OpCode::PushImmediate,
+                _ => OpCode::Push,
+            };
+            code[i].operands.truncate(1);
+            remove(code, i + 1);
+            changed = true;
+        }
+        i += 1;
+    }
+    changed
+}
+
+/// Remove Dead Acc Stores
+///
+/// Remove MOV immediate stores to %acc whose value is never read, such
+/// as the #<void> result of a define that is followed by another
+/// expression, and collapse MOV x %acc, MOV %acc y into MOV x y when the
+/// value isn't otherwise read from %acc.
+fn remove_dead_acc_stores(code: &mut Vec<Instruction>) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+    while i < code.len() {
+        if code[i].op == OpCode::MovImmediate
+            && code[i].is_acc_store()
+            && is_acc_dead_after(code, i)
+        {
+            remove(code, i);
+            changed = true;
+        } else if code[i].is_acc_store()
+            && code.get(i + 1).is_some_and(|next| {
+                next.op == OpCode::Mov && next.operands[0] == VCell::Acc && next.labels.is_empty()
+            })
+            && is_acc_dead_after(code, i + 1)
+        {
+            code[i].operands[1] = code[i + 1].operands[1].clone();
+            remove(code, i + 1);
+            changed = true;
+        } else {
+            i += 1;
+        }
+    }
+    changed
+}
+
+/// Thread Jumps
+///
+/// Retarget jumps to unconditional jumps to the final destination, and
+/// replace unconditional jumps to RET with RET.
+fn thread_jumps(code: &mut [Instruction]) -> bool {
+    let labels = label_index(code);
+    let mut changed = false;
+    for i in 0..code.len() {
+        let Some(label) = code[i].target() else {
+            continue;
+        };
+        let mut target = label;
+        let mut visited = HashSet::from([target]);
+        while let Some(idx) = labels.get(&target) {
+            match code[*idx].target() {
+                Some(next) if code[*idx].op == OpCode::Jmp && visited.insert(next) => target = next,
+                _ => break,
+            }
+        }
+        if target != label {
+            code[i].set_target(target);
+            changed = true;
+        }
+        if code[i].op == OpCode::Jmp
+            && labels
+                .get(&target)
+                .is_some_and(|idx| code[*idx].op == OpCode::Ret)
+        {
+            code[i].op = OpCode::Ret;
+            code[i].operands.clear();
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Remove Unreachable
+///
+/// Remove instructions that cannot be reached from the entry point.
+fn remove_unreachable(code: &mut Vec<Instruction>) -> bool {
+    let labels = label_index(code);
+    let mut reachable = vec![false; code.len()];
+    let mut pending = vec![0];
+    while let Some(idx) = pending.pop() {
+        if idx >= code.len() || reachable[idx] {
+            continue;
+        }
+        reachable[idx] = true;
+        if let Some(target) = code[idx].target() {
+            // A jump to a missing label leaves what's reachable unknown
+            match labels.get(&target) {
+                Some(target) => pending.push(*target),
+                None => return false,
+            }
+        }
+        if !matches!(code[idx].op, OpCode::Jmp | OpCode::Ret | OpCode::Halt) {
+            pending.push(idx + 1);
+        }
+    }
+    if reachable.iter().all(|it| *it) {
+        return false;
+    }
+    let mut reachable = reachable.into_iter();
+    code.retain(|_| reachable.next().unwrap());
+    true
+}
+
+/// Remove Jumps To Next
+///
+/// Remove unconditional jumps to the instruction that follows them.
+fn remove_jumps_to_next(code: &mut Vec<Instruction>) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+    while i + 1 < code.len() {
+        match code[i].target() {
+            Some(target) if code[i].op == OpCode::Jmp && code[i + 1].labels.contains(&target) => {
+                remove(code, i);
+                changed = true;
+            }
+            _ => i += 1,
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lex, parse};
+
+    fn compile(vm: &mut Vm, text: &str) -> Lambda {
+        let mut lambda = vm.compile_runnable(&parse!(text)).unwrap();
+        let main = lambda.bc[3].as_ptr().unwrap();
+        lambda = vm.heap.get_at_index(main).as_lambda().unwrap().clone();
+        lambda
+    }
+
+    fn ops(lambda: &Lambda) -> Vec<OpCode> {
+        decode(lambda)
+            .unwrap()
+            .into_iter()
+            .map(|it| it.op)
+            .collect()
+    }
+
+    #[test]
+    fn constant_if() {
+        let mut vm = Vm::new();
+        let lambda = compile(&mut vm, "(if #t 1 2)");
+        assert_eq!(
+            ops(&lambda),
+            vec![OpCode::Enter, OpCode::MovImmediate, OpCode::Ret]
+        );
+        assert_eq!(lambda.bc[2], VCell::from(1));
+    }
+
+    #[test]
+    fn push_immediate() {
+        let mut vm = Vm::new();
+        let lambda = compile(&mut vm, "(list 1 'a)");
+        assert_eq!(
+            ops(&lambda),
+            vec![
+                OpCode::Enter,
+                OpCode::PushImmediate,
+                OpCode::PushImmediate,
+                OpCode::PushImmediate,
+                OpCode::Mov,
+                OpCode::TCallAcc,
+                OpCode::Ret
+            ]
+        );
+    }
+
+    #[test]
+    fn fold_constants() {
+        let mut vm = Vm::new();
+        let lambda = compile(&mut vm, "(+ 1 2)");
+        assert_eq!(
+            ops(&lambda)[..4],
+            [
+                OpCode::Enter,
+                OpCode::Guard,
+                OpCode::MovImmediate,
+                OpCode::Ret
+            ]
+        );
+        assert_eq!(lambda.bc[6], VCell::from(3));
+    }
+
+    #[test]
+    fn disabled() {
+        let mut vm = Vm::new();
+        vm.set_optimize(false);
+        let lambda = compile(&mut vm, "(if #t 1 2)");
+        assert!(ops(&lambda).contains(&OpCode::Jnt));
+    }
+
+    #[test]
+    fn decompile_before_and_after() {
+        let mut vm = Vm::new();
+        vm.set_optimize(false);
+        let lambda = compile(&mut vm, "(if #t 1 2)");
+        let (before, after) = vm.decompile_optimization(&lambda).unwrap();
+        assert_eq!(before, vm.decompile_text(&lambda));
+        assert!(before.contains("JNT"));
+        assert!(!after.contains("JNT"));
+        assert_eq!(after.lines().count(), 3);
+        assert!(ops(&lambda).contains(&OpCode::Jnt));
+    }
+
+    #[test]
+    fn missing_label() {
+        let mut code = vec![
+            Instruction::new(OpCode::Jmp, vec![VCell::ptr(100)], None),
+            Instruction::new(OpCode::Ret, vec![], None),
+        ];
+        assert!(!thread_jumps(&mut code));
+        assert!(!remove_unreachable(&mut code));
+        assert_eq!(code.len(), 2);
+    }
+}
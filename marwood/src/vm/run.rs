@@ -1,18 +1,24 @@
 use crate::cell::Cell;
 use crate::error::Error;
 use crate::error::Error::{
-    InvalidBytecode, InvalidNumArgs, InvalidProcedure, InvalidSyntax, VariableNotBound,
+    Interrupted, InvalidBytecode, InvalidNumArgs, InvalidProcedure, VariableNotBound,
 };
+use crate::number::Number;
 use crate::vm::environment::{BindingSource, EnvironmentMap, LexicalEnvironment};
+use crate::vm::gc::{Phase, Stopwatch};
 use crate::vm::lambda::Lambda;
 use crate::vm::opcode::OpCode;
+use crate::vm::thread::Switch;
 use crate::vm::trace::StackTrace;
-use crate::vm::vcell::VCell;
 use crate::vm::vcell::VCell::LexicalEnvPtr;
+use crate::vm::vcell::{BuiltInProc, VCell};
 use crate::vm::Vm;
 use log::trace;
 use std::rc::Rc;
 
+/// The number of instructions executed between checks for an interrupt.
+const INTERRUPT_INTERVAL: usize = 1024;
+
 impl Vm {
     /// Run
     ///
@@ -34,25 +40,58 @@ impl Vm {
     pub fn run_count_vcell(&mut self, count: usize) -> Result<Option<&VCell>, Error> {
         self.last_stacktrace = None;
         let mut cycles = 0;
+        let mut next_gc = self.gc_config.interval;
         loop {
             cycles += 1;
-            if cycles % 8192 == 0 {
+            if cycles == next_gc {
                 self.run_gc();
+                next_gc += self.gc_config.interval;
             }
             if cycles == count {
+                self.resume_primordial_thread();
                 self.run_gc();
                 return Ok(None);
             }
-            match self.run_one() {
+            let op = match self.debugger.attached {
+                true => self
+                    .lambda()
+                    .get(self.ip.1)
+                    .and_then(|it| it.as_opcode().ok()),
+                false => None,
+            };
+            let result = if cycles % INTERRUPT_INTERVAL == 1 && self.interrupt.take() {
+                Err(Interrupted)
+            } else {
+                self.run_one()
+            };
+            match result {
                 Ok(true) => break,
-                Ok(false) => continue,
+                Ok(false) => {
+                    if let Some(op) = op {
+                        if self.debug_pause(op) {
+                            return Ok(None);
+                        }
+                    }
+                    if let Some(switch) = self.scheduler.switch.take() {
+                        self.switch_thread(switch);
+                    } else if cycles % self.scheduler.quantum == 0
+                        && !self.scheduler.ready.is_empty()
+                    {
+                        self.switch_thread(Switch::Yield);
+                    }
+                }
                 Err(e) => {
+                    if self.raise_error(&e) {
+                        continue;
+                    }
+                    self.fail_thread(&e);
                     self.last_stacktrace = Some(StackTrace::new(
                         &self.stack,
                         &self.heap,
                         self.ip,
                         self.acc.clone(),
                     ));
+                    self.reset_after_error();
                     return Err(e);
                 }
             }
@@ -67,6 +106,9 @@ impl Vm {
     /// If the bool is set true, it indicates a HALT was encountered and
     /// execution should cease.
     fn run_one(&mut self) -> Result<bool, Error> {
+        if self.heap.is_exhausted() {
+            self.check_heap_limit()?;
+        }
         self.trace_instruction();
         let op_code = self.read_opcode()?;
         match op_code {
@@ -78,6 +120,14 @@ impl Vm {
             OpCode::Jmp => {
                 self.ip.1 = self.read_operand()?.as_ptr()?;
             }
+            OpCode::Guard => {
+                let slot = self.read_operand()?.as_env_slot()?;
+                let builtin = self.read_operand()?;
+                let offset = self.read_operand()?.as_ptr()?;
+                if self.globenv.get_slot(slot) != builtin {
+                    self.ip.1 = offset;
+                }
+            }
             OpCode::Jnt => {
                 let offset = self.read_operand()?.as_ptr()?;
                 if let VCell::Bool(false) = self.heap.get(&self.acc) {
@@ -94,14 +144,14 @@ impl Vm {
             }
             OpCode::Push => {
                 let vcell = self.load_operand()?;
-                self.stack.push(vcell);
+                self.stack.push(vcell)?;
             }
             OpCode::PushImmediate => {
                 let vcell = self.read_operand()?;
-                self.stack.push(vcell);
+                self.stack.push(vcell)?;
             }
             OpCode::PushAcc => {
-                self.stack.push(self.acc.clone());
+                self.stack.push(self.acc.clone())?;
             }
             OpCode::Halt => return Ok(true),
 
@@ -120,10 +170,49 @@ impl Vm {
             OpCode::VPushAcc => {
                 let vector_ptr = self.heap.get(self.stack.pop()?);
                 let vector = vector_ptr.as_vector()?;
+                self.heap.shade(&self.acc);
                 vector.push(self.acc.clone());
                 self.acc = vector_ptr;
             }
 
+            // Inline primitives are guarded versions of common builtins. They're
+            // followed by the regular runtime application of the same builtin, and
+            // jump past it if they were able to apply the primitive.
+            OpCode::Add
+            | OpCode::Car
+            | OpCode::Cdr
+            | OpCode::Eq
+            | OpCode::Gt
+            | OpCode::IsNull
+            | OpCode::IsPair
+            | OpCode::IsZero
+            | OpCode::Lt
+            | OpCode::Not
+            | OpCode::NumEq
+            | OpCode::Sub => {
+                let slot = self.read_operand()?.as_env_slot()?;
+                let builtin = self.read_operand()?;
+                let offset = self.read_operand()?.as_ptr()?;
+                if self.globenv.get_slot(slot) == builtin {
+                    let argc = self.stack.get_offset(0)?.as_argc()?;
+                    let result = match argc {
+                        1 => self.apply_primitive(&op_code, &[self.stack.get_offset(-1)?])?,
+                        2 => self.apply_primitive(
+                            &op_code,
+                            &[self.stack.get_offset(-2)?, self.stack.get_offset(-1)?],
+                        )?,
+                        _ => return Err(InvalidBytecode),
+                    };
+                    if let Some(result) = result {
+                        for _ in 0..=argc {
+                            self.stack.pop()?;
+                        }
+                        self.acc = self.heap.maybe_put(result);
+                        self.ip.1 = offset;
+                    }
+                }
+            }
+
             // Procedure Application
             //
             // The opcodes CLOSURE, CALL, ENTER and RET are related to creation and application
@@ -133,6 +222,11 @@ impl Vm {
             // * CALL applies a procedure, setting up the call frame from the caller's point of view
             // * ENTER is the first instruction of a procedure, and finishes setting up a call frame
             //   from the procedure's point of view
+            // * PROMPT pushes a prompt frame, delimiting the continuations captured by the
+            //   procedures it calls. See continuation.rs.
+            //
+            // A procedure's locals are pushed after ENTER, and are addressed relative to %bp
+            // past the saved %bp. RET discards them along with the rest of the frame.
             //
             //
             OpCode::ClosureAcc => {
@@ -153,30 +247,20 @@ impl Vm {
                     VCell::Closure(lambda, _) => lambda,
                     VCell::Lambda(_) => self.acc.as_ptr()?,
                     VCell::BuiltInProc(proc) => {
-                        let proc = proc.as_ref();
-                        self.acc = match proc.eval(self)? {
-                            VCell::Ptr(ptr) => VCell::Ptr(ptr),
-                            vcell => self.heap.maybe_put(vcell),
-                        };
+                        self.call_builtin(&proc)?;
                         return Ok(false);
                     }
                     VCell::Continuation(cont) => {
-                        let cont = &*cont;
-                        if self.stack.pop()?.as_argc()? == 0 {
-                            return Err(InvalidSyntax("expected value".into()));
-                        }
-                        let result = self.stack.pop()?.clone();
-                        self.restore_continuation(cont);
-                        self.acc = result;
+                        self.apply_continuation(&cont)?;
                         return Ok(false);
                     }
                     other => {
                         return Err(InvalidProcedure(self.heap.get_as_cell(&other)));
                     }
                 };
-                self.stack.push(VCell::EnvironmentPointer(self.ep));
+                self.stack.push(VCell::EnvironmentPointer(self.ep))?;
                 self.stack
-                    .push(VCell::InstructionPointer(self.ip.0, self.ip.1));
+                    .push(VCell::InstructionPointer(self.ip.0, self.ip.1))?;
                 self.ip.0 = lambda;
                 self.ip.1 = 0;
             }
@@ -185,21 +269,11 @@ impl Vm {
                     VCell::Closure(lambda, _) => lambda,
                     VCell::Lambda(_) => self.acc.as_ptr()?,
                     VCell::BuiltInProc(proc) => {
-                        let proc = proc.as_ref();
-                        self.acc = match proc.eval(self)? {
-                            VCell::Ptr(ptr) => VCell::Ptr(ptr),
-                            vcell => self.heap.maybe_put(vcell),
-                        };
+                        self.call_builtin(&proc)?;
                         return Ok(false);
                     }
                     VCell::Continuation(cont) => {
-                        let cont = &*cont;
-                        if self.stack.pop()?.as_argc()? == 0 {
-                            return Err(InvalidSyntax("expected value".into()));
-                        }
-                        let result = self.stack.pop()?.clone();
-                        self.restore_continuation(cont);
-                        self.acc = result;
+                        self.apply_continuation(&cont)?;
                         return Ok(false);
                     }
                     other => {
@@ -229,12 +303,12 @@ impl Vm {
                     *self.stack.get_sp_mut() = self.bp - frame_argc;
                     for it in (0..argc).rev() {
                         let val = self.stack.get(saved_sp - it - 1)?.clone();
-                        self.stack.push(val);
+                        self.stack.push(val)?;
                     }
 
-                    self.stack.push(VCell::ArgumentCount(argc));
-                    self.stack.push(saved_ep);
-                    self.stack.push(saved_ip);
+                    self.stack.push(VCell::ArgumentCount(argc))?;
+                    self.stack.push(saved_ep)?;
+                    self.stack.push(saved_ip)?;
                     self.bp = saved_bp.as_bp()?;
                     self.ip.0 = lambda;
                     self.ip.1 = 0;
@@ -255,7 +329,7 @@ impl Vm {
                     return Err(InvalidNumArgs(lambda.to_string()));
                 }
 
-                self.stack.push(VCell::BasePointer(self.bp));
+                self.stack.push(VCell::BasePointer(self.bp))?;
                 self.bp = self.stack.get_sp() - 4;
 
                 if let Some(closure_env_ptr) = closure_env {
@@ -269,6 +343,12 @@ impl Vm {
                     self.ep = lexical_env_ptr;
                 }
             }
+            OpCode::Prompt => {
+                let abort = self.read_operand()?.as_ptr()?;
+                self.stack
+                    .push(VCell::InstructionPointer(self.ip.0, abort))?;
+                self.stack.push(VCell::Prompt(self.bp))?;
+            }
             OpCode::Ret => {
                 let n = self.stack.get(self.bp + 1)?.as_argc()?;
 
@@ -310,10 +390,10 @@ impl Vm {
 
                     // Push the list on the stack, a new argc, and restore the caller's
                     // frame
-                    self.stack.push(VCell::ptr(varargs));
-                    self.stack.push(VCell::ArgumentCount(req_argc + 1));
-                    self.stack.push(saved_ip);
-                    self.stack.push(saved_ep);
+                    self.stack.push(VCell::ptr(varargs))?;
+                    self.stack.push(VCell::ArgumentCount(req_argc + 1))?;
+                    self.stack.push(saved_ip)?;
+                    self.stack.push(saved_ep)?;
                 }
             }
         }
@@ -321,6 +401,52 @@ impl Vm {
         Ok(false)
     }
 
+    /// Apply Primitive
+    ///
+    /// Apply the inline primitive op to args, returning None if the
+    /// arguments are not handled by the primitive. Args are the values as
+    /// they would be passed to the builtin, i.e. possibly heap references.
+    pub fn apply_primitive(&self, op: &OpCode, args: &[&VCell]) -> Result<Option<VCell>, Error> {
+        let arg = |idx: usize| -> Result<&VCell, Error> {
+            Ok(match args.get(idx).ok_or(InvalidBytecode)? {
+                VCell::Ptr(ptr) => self.heap.get_at_index(*ptr),
+                vcell => vcell,
+            })
+        };
+        Ok(match op {
+            OpCode::Car => match arg(0)? {
+                VCell::Pair(car, _) => Some(VCell::ptr(*car)),
+                _ => None,
+            },
+            OpCode::Cdr => match arg(0)? {
+                VCell::Pair(_, cdr) => Some(VCell::ptr(*cdr)),
+                _ => None,
+            },
+            OpCode::IsNull => Some(arg(0)?.is_nil().into()),
+            OpCode::IsPair => Some(arg(0)?.is_pair().into()),
+            OpCode::Not => Some(matches!(arg(0)?, VCell::Bool(false)).into()),
+            OpCode::IsZero => match arg(0)? {
+                VCell::Number(x) => Some((*x == Number::from(0)).into()),
+                _ => None,
+            },
+            OpCode::Eq => match args {
+                [left, right] => Some(self.eqv(left, right)?.into()),
+                _ => return Err(InvalidBytecode),
+            },
+            op => match (arg(0)?, arg(1)?) {
+                (VCell::Number(x), VCell::Number(y)) => Some(match op {
+                    OpCode::Add => VCell::Number(x + y),
+                    OpCode::Sub => VCell::Number(x - y),
+                    OpCode::Gt => (x > y).into(),
+                    OpCode::Lt => (x < y).into(),
+                    OpCode::NumEq => (x == y).into(),
+                    _ => return Err(InvalidBytecode),
+                }),
+                _ => None,
+            },
+        })
+    }
+
     /// Get Symbol Bound To
     ///
     /// Given either an environment slot, or a symbol reference, return the
@@ -419,15 +545,16 @@ impl Vm {
                 self.acc = vcell;
             }
             VCell::Ptr(ptr) => {
-                *self.heap.get_at_index_mut(ptr) = vcell;
+                self.heap.put_at_index(ptr, vcell);
             }
             VCell::BasePointerOffset(offset) => {
-                *self.stack.get_offset_mut((self.bp as i64) + offset)? = vcell;
+                *self.stack.get_mut((self.bp as i64 + offset) as usize)? = vcell;
             }
             VCell::GlobalEnvSlot(slot) => {
                 self.globenv.put_slot(slot, vcell);
             }
             VCell::LexicalEnvSlot(slot) => {
+                self.heap.shade(&vcell);
                 let lexical_env = self.heap.get_at_index(self.ep).as_lexical_env()?;
                 match lexical_env.get(slot) {
                     VCell::LexicalEnvPtr(env, slot) => {
@@ -474,43 +601,128 @@ impl Vm {
         self.stack.get(offset)
     }
 
-    /// Run GC
+    /// Call Builtin
     ///
-    /// Run GC performs two steps in order:
+    /// Apply a builtin procedure to the arguments on the stack, leaving its
+    /// result in %acc. The arguments remain roots of garbage collection
+    /// while the builtin runs, even once it has popped them, and any string
+    /// or big number it returns is checked against the Vm's limits.
+    fn call_builtin(&mut self, proc: &BuiltInProc) -> Result<(), Error> {
+        self.builtin_sp = self.stack.get_sp();
+        let result = proc.eval(self);
+        self.builtin_sp = 0;
+        let result = result?;
+        self.check_allocation(&result)?;
+        self.acc = match result {
+            VCell::Ptr(ptr) => VCell::Ptr(ptr),
+            vcell => self.heap.maybe_put(vcell),
+        };
+        Ok(())
+    }
+
+    /// Run GC
     ///
-    /// 1. Check if heap utilization is > 75%, aborting gc is not.
+    /// Run GC performs one increment of garbage collection, each of which
+    /// marks or sweeps up to the configured budget of vcells:
     ///
-    /// 2. It performs a mark on all roots:
+    /// 1. If no collection is in progress, check if heap utilization is at
+    ///    or above the configured threshold, aborting gc if not. Otherwise
+    ///    begin a collection by shading the roots:
     ///    * The global environment
-    ///    * Any data referecned by the running program & stack
+    ///    * Any data referenced by the running program & stack
     ///
-    /// 3. A sweep, freeing any vcells not marked as used in step #1.
+    /// 2. Mark the objects reachable from the grey set. References stored
+    ///    into the heap meanwhile are shaded by the write barrier, but the
+    ///    roots are not, so once the grey set is empty the roots are shaded
+    ///    again and marking completes in a single pause.
+    ///
+    /// 3. Sweep, freeing any vcells not marked as used in step #2. If heap
+    ///    utilization is still above the grow threshold, grow the heap.
     pub fn run_gc(&mut self) {
-        if (self.heap.used_size() as f64 / self.heap.capacity() as f64) < 0.75_f64 {
+        self.reset_stack_limit();
+        if self.heap.gc_phase() == Phase::Idle && self.heap_utilization() < self.gc_config.threshold
+        {
             return;
         }
+        self.gc_increment(self.gc_config.budget.unwrap_or(usize::MAX));
+    }
+
+    /// Collect Garbage
+    ///
+    /// Finish any collection in progress, and then perform a full collection
+    /// in a single pause, regardless of heap utilization.
+    pub fn collect_garbage(&mut self) {
+        if self.heap.gc_phase() != Phase::Idle {
+            self.gc_increment(usize::MAX);
+        }
+        self.gc_increment(usize::MAX);
+    }
+
+    /// GC Increment
+    ///
+    /// Perform one increment of garbage collection as described by run_gc,
+    /// beginning a collection if none is in progress, and recording the
+    /// pause. Return true if the increment completed the collection.
+    fn gc_increment(&mut self, budget: usize) -> bool {
+        let stopwatch = Stopwatch::start();
+        if self.heap.gc_phase() == Phase::Idle {
+            self.heap.begin_mark();
+            self.shade_roots();
+        }
 
-        self.globenv
-            .iter_bindings()
-            .for_each(|it| self.heap.mark(*it, false));
-
-        self.globenv
-            .iter_slots()
-            .filter_map(|it| it.as_ptr().ok())
-            .for_each(|it| self.heap.mark(it, false));
-
-        self.stack
-            .iter_to_sp()
-            .for_each(|it| self.heap.mark_vcell(it, false));
-        self.heap.mark_vcell(&self.acc, false);
-        self.heap.mark(self.ip.0, false);
-        self.heap.mark(self.ep, false);
-        self.heap.sweep();
-
-        // If after GC the heap utilization is still high, grow the heap.
-        if (self.heap.used_size() as f64 / self.heap.capacity() as f64) > 0.75_f64 {
+        if self.heap.gc_phase() == Phase::Mark && self.heap.mark_step(budget) {
+            self.shade_roots();
+            self.heap.mark_step(usize::MAX);
+            self.heap.begin_sweep();
+        }
+
+        let completed =
+            matches!(self.heap.gc_phase(), Phase::Sweep(_)) && self.heap.sweep_step(budget);
+        if completed
+            && self.heap_utilization() > self.gc_config.grow_threshold
+            && !self.heap.is_full()
+        {
             self.heap.grow();
         }
+        self.heap.record_pause(stopwatch.elapsed(), completed);
+        completed
+    }
+
+    /// Shade Roots
+    ///
+    /// Shade every root of the heap: global environment bindings and slots,
+    /// the stack and the registers.
+    fn shade_roots(&mut self) {
+        for sym in self.globenv.iter_bindings() {
+            self.heap.shade(&VCell::Ptr(*sym));
+        }
+        for it in self.globenv.iter_slots() {
+            self.heap.shade(it);
+        }
+        for it in self
+            .stack
+            .iter_to(self.stack.get_sp().max(self.builtin_sp) + 1)
+        {
+            self.heap.shade(it);
+        }
+        self.heap.shade(&self.acc);
+        self.heap.shade(&VCell::Ptr(self.ip.0));
+        self.heap.shade(&VCell::Ptr(self.ep));
+        for state in self.executions.values().chain(&self.saved_states) {
+            for it in state.iter_roots() {
+                self.heap.shade(&it);
+            }
+        }
+        for it in self.scheduler.iter_roots() {
+            self.heap.shade(&it);
+        }
+        for it in &self.debugger.breakpoints {
+            self.heap.shade(&VCell::Ptr(*it));
+        }
+    }
+
+    fn heap_utilization(&self) -> f64 {
+        self.heap.used_size() as f64 / self.heap.capacity() as f64
     }
 
     /// Build Closure Environment
@@ -641,4 +853,29 @@ impl Vm {
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_to_base_pointer_offset() {
+        let mut vm = Vm::new();
+        let mut lambda = Lambda::new(vec![]);
+        lambda.emit(OpCode::PushImmediate);
+        lambda.emit(VCell::from(1));
+        lambda.emit(OpCode::PushImmediate);
+        lambda.emit(VCell::from(2));
+        lambda.emit(OpCode::MovImmediate);
+        lambda.emit(VCell::from(3));
+        lambda.emit(VCell::BasePointerOffset(0));
+        lambda.emit(OpCode::Mov);
+        lambda.emit(VCell::BasePointerOffset(0));
+        lambda.emit(VCell::Acc);
+        lambda.emit(OpCode::Halt);
+        let lambda = vm.heap.put(lambda);
+        vm.ip = (lambda.as_ptr().unwrap(), 0);
+        vm.bp = 0;
+
+        // %bp[0] is the first value pushed, not the top of the stack
+        assert_eq!(vm.run(), Ok(Cell::from(3)));
+    }
+}
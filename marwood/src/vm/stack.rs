@@ -1,8 +1,90 @@
 use crate::error::Error;
-use crate::error::Error::InvalidStackIndex;
+use crate::error::Error::{InvalidStackIndex, LimitExceeded};
 use crate::vm::vcell::VCell;
 use log::trace;
 use std::fmt::{Debug, Display};
+use std::rc::Rc;
+
+/// The number of stack slots past the stack depth limit that exception
+/// handlers may use to handle exceeding it.
+const STACK_HEADROOM: usize = 4096;
+
+/// Segment
+///
+/// Segment is an immutable run of stack slots starting at stack index
+/// base. Segments are frozen out of the stack when a continuation is
+/// captured, and are shared by the stack and any continuations captured
+/// from it. The slots below base are held by the parent segment.
+///
+/// Each segment also holds skip pointers to the ancestors 2, 4, 8... segments
+/// up its chain, so that the segment holding a stack index is found in
+/// logarithmic rather than linear time.
+#[derive(Debug)]
+struct Segment {
+    base: usize,
+    slots: Vec<VCell>,
+    parent: Option<Rc<Segment>>,
+    skips: Vec<Rc<Segment>>,
+}
+
+impl Segment {
+    fn new(base: usize, slots: Vec<VCell>, parent: Option<Rc<Segment>>) -> Segment {
+        let mut skips = vec![];
+        let mut jump = parent.clone();
+        while let Some(segment) = jump {
+            jump = segment.jump(skips.len()).cloned();
+            skips.extend(jump.clone());
+        }
+        Segment {
+            base,
+            slots,
+            parent,
+            skips,
+        }
+    }
+
+    /// Jump
+    ///
+    /// Return the ancestor 2^k segments up the chain, if there is one.
+    fn jump(&self, k: usize) -> Option<&Rc<Segment>> {
+        match k {
+            0 => self.parent.as_ref(),
+            k => self.skips.get(k - 1),
+        }
+    }
+
+    /// Find
+    ///
+    /// Return the segment of this segment's chain that holds stack index
+    /// index, taking the longest jumps down the chain that don't pass it.
+    fn find(&self, index: usize) -> Option<&Segment> {
+        if self.base <= index {
+            return Some(self);
+        }
+        let mut segment = self;
+        for k in (0..=self.skips.len()).rev() {
+            if let Some(ancestor) = segment.jump(k).filter(|it| it.base > index) {
+                segment = ancestor;
+            }
+        }
+        segment.parent.as_deref()
+    }
+}
+
+impl Drop for Segment {
+    /// Drop the chain of parent segments iteratively, so that dropping a
+    /// deep chain doesn't overflow the native stack.
+    fn drop(&mut self) {
+        self.skips.clear();
+        let mut next = self.parent.take();
+        while let Some(segment) = next {
+            next = Rc::try_unwrap(segment).ok().and_then(|mut segment| {
+                segment.skips.clear();
+                segment.parent.take()
+            });
+        }
+    }
+}
 
 /// Stack
 ///
@@ -14,22 +96,56 @@ use std::fmt::{Debug, Display};
 /// itself. It does not currently attempt to shrink itself,
 /// because it would need to know whether or not the VM is
 /// still referencing addresses positive to the stack pointer.
-#[derive(Debug, Eq, PartialEq, Clone)]
+///
+/// The slots below split are frozen in a chain of segments shared
+/// with captured continuations, and the slots from split up are live.
+/// Capturing a continuation freezes the live slots up to the stack
+/// pointer instead of copying the stack, and writing to a frozen slot
+/// thaws the slots from it up to split by copying them back into the
+/// live slots. The cost of call/cc is then proportional to the slots
+/// written since the last capture, rather than to the depth of the stack.
+#[derive(Debug, Clone)]
 pub struct Stack {
-    /// Stack contents
-    stack: Vec<VCell>,
+    /// Frozen stack contents below split
+    frozen: Option<Rc<Segment>>,
+
+    /// The index of the first live slot
+    split: usize,
+
+    /// Live stack contents, indexed by stack index. The slots below split
+    /// are unused.
+    live: Vec<VCell>,
 
     /// Stack Pointer. SP points to the top value to be pushed onto the stack,
     /// This value backs the SP register of the VM
     sp: usize,
+
+    /// The stack depth limit, if any
+    limit: Option<usize>,
+
+    /// Whether pushing has exceeded the limit, granting headroom past it
+    /// until the stack unwinds below the limit again
+    overflowed: bool,
 }
 
 impl Stack {
     /// Make a new stack with an initial stack size of 256 slots.
     pub fn new() -> Stack {
+        Stack::from_vec(vec![VCell::undefined(); 256], 0)
+    }
+
+    /// From Vec
+    ///
+    /// Construct a stack from its contents and stack pointer, such as the
+    /// stack of a continuation restored from a snapshot.
+    pub fn from_vec(stack: Vec<VCell>, sp: usize) -> Stack {
         Stack {
-            stack: vec![VCell::undefined(); 256],
-            sp: 0,
+            frozen: None,
+            split: 0,
+            live: stack,
+            sp,
+            limit: None,
+            overflowed: false,
         }
     }
 
@@ -38,22 +154,46 @@ impl Stack {
     /// Clear clears any old stack values so that they're no longer
     /// participating in garbage collection.
     pub fn clear(&mut self) {
-        let size = self.stack.len();
-        self.stack = vec![VCell::undefined(); size];
+        let size = self.len();
+        self.frozen = None;
+        self.split = 0;
+        self.live = vec![VCell::undefined(); size];
     }
 
     /// Iter
     ///
     /// Return an iterator to the stack vector
     pub fn iter(&self) -> impl Iterator<Item = &VCell> {
-        self.stack.iter()
+        self.iter_to(self.len())
     }
 
     /// Iter
     ///
     /// Return an iterator to the stack vector up to the stack pointer
     pub fn iter_to_sp(&self) -> impl Iterator<Item = &VCell> {
-        self.stack[0..self.sp + 1].iter()
+        self.iter_to(self.sp + 1)
+    }
+
+    /// Iter To
+    ///
+    /// Return an iterator to the slots of the stack below end, walking the
+    /// frozen segments once rather than looking up each slot.
+    pub fn iter_to(&self, end: usize) -> impl Iterator<Item = &VCell> {
+        let mut frozen = vec![];
+        let mut upper = self.split.min(end);
+        let mut next = self.frozen.as_deref();
+        while let Some(segment) = next {
+            if segment.base < upper {
+                frozen.push(&segment.slots[..upper - segment.base]);
+                upper = segment.base;
+            }
+            next = segment.parent.as_deref();
+        }
+        let live = self
+            .live
+            .get(self.split..end.min(self.live.len()))
+            .unwrap_or(&[]);
+        frozen.into_iter().rev().flatten().chain(live)
     }
 
     /// Get
@@ -63,7 +203,14 @@ impl Stack {
     /// This supports absolute stack addressing modes, such as those
     /// needed for BP[offset]
     pub fn get(&self, index: usize) -> Result<&VCell, Error> {
-        self.stack.get(index).ok_or(InvalidStackIndex(index))
+        if index >= self.split {
+            return self.live.get(index).ok_or(InvalidStackIndex(index));
+        }
+        self.frozen
+            .as_deref()
+            .and_then(|it| it.find(index))
+            .and_then(|it| it.slots.get(index - it.base))
+            .ok_or(InvalidStackIndex(index))
     }
 
     /// Get Mut
@@ -73,7 +220,10 @@ impl Stack {
     /// This supports absolute stack addressing modes, such as those
     /// needed for BP[offset]
     pub fn get_mut(&mut self, index: usize) -> Result<&mut VCell, Error> {
-        self.stack.get_mut(index).ok_or(InvalidStackIndex(index))
+        if index < self.split {
+            self.thaw(index);
+        }
+        self.live.get_mut(index).ok_or(InvalidStackIndex(index))
     }
 
     /// Get Offset
@@ -88,16 +238,14 @@ impl Stack {
     /// `offset` - The offset from the top of the stack to access, where an offset
     /// of 0 is the top oif the stack.
     pub fn get_offset(&self, offset: i64) -> Result<&VCell, Error> {
-        let index = (self.sp as i64 + offset) as usize;
-        self.stack.get(index).ok_or(InvalidStackIndex(index))
+        self.get((self.sp as i64 + offset) as usize)
     }
 
     /// Get Offset Mut
     ///
     /// Identical to get(), but returns a mut stack value.
     pub fn get_offset_mut(&mut self, offset: i64) -> Result<&mut VCell, Error> {
-        let index = (self.sp as i64 + offset) as usize;
-        self.stack.get_mut(index).ok_or(InvalidStackIndex(index))
+        self.get_mut((self.sp as i64 + offset) as usize)
     }
 
     /// Sp
@@ -119,37 +267,99 @@ impl Stack {
 
     /// Grow
     ///
-    /// Grow the stack by doubling its current size. Any new elements
-    /// have the value of VCell::Undefined
-    fn grow(&mut self) {
-        self.stack.resize(self.stack.len() * 2, VCell::Undefined);
+    /// Grow the live slots by doubling their size until index is a live
+    /// slot. Any new elements have the value of VCell::Undefined
+    fn grow(&mut self, index: usize) {
+        let mut len = self.live.len().max(1);
+        while len <= index {
+            len *= 2;
+        }
+        if len > self.live.len() {
+            self.live.resize(len, VCell::Undefined);
+        }
+    }
+
+    /// Thaw
+    ///
+    /// Copy the frozen slots from index up to split into the live slots,
+    /// lowering split to index and releasing the segments above it.
+    fn thaw(&mut self, index: usize) {
+        self.grow(self.split);
+        let mut upper = self.split;
+        let mut next = self.frozen.as_deref();
+        while let Some(segment) = next {
+            if upper <= index {
+                break;
+            }
+            let lower = segment.base.max(index);
+            if lower < upper {
+                self.live[lower..upper]
+                    .clone_from_slice(&segment.slots[lower - segment.base..upper - segment.base]);
+                upper = lower;
+            }
+            next = segment.parent.as_deref();
+        }
+        self.split = index;
+        self.frozen = Stack::trim(self.frozen.take(), index);
+    }
+
+    /// Trim
+    ///
+    /// Release the segments of the chain frozen that start at or above
+    /// split.
+    fn trim(mut frozen: Option<Rc<Segment>>, split: usize) -> Option<Rc<Segment>> {
+        while let Some(segment) = &frozen {
+            if segment.base < split {
+                break;
+            }
+            frozen = segment.parent.clone();
+        }
+        frozen
     }
 
     /// Len
     ///
     /// Return the current stack size
     pub fn len(&self) -> usize {
-        self.stack.len()
+        self.live.len().max(self.split)
     }
 
     pub fn is_empty(&self) -> bool {
         self.sp == 0
     }
 
+    /// Set Limit
+    ///
+    /// Limit the depth of the stack. Exceeding the limit extends it by a
+    /// headroom for exception handlers, which are run on top of the stack
+    /// that exceeded it, until the stack has unwound below the limit.
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+        if limit.is_none_or(|limit| self.sp <= limit) {
+            self.overflowed = false;
+        }
+    }
+
     /// Push
     ///
-    /// Push a value at the top of the stack, incrementing SP
-    pub fn push<T: Into<VCell> + Debug + Display>(&mut self, vcell: T) {
-        match self.stack.get_mut(self.sp + 1) {
-            Some(slot) => {
-                *slot = vcell.into();
-                self.sp += 1;
-            }
-            None => {
-                self.grow();
-                self.push(vcell)
+    /// Push a value at the top of the stack, incrementing SP, or return
+    /// an error if it would exceed the stack depth limit.
+    pub fn push<T: Into<VCell> + Debug + Display>(&mut self, vcell: T) -> Result<(), Error> {
+        let index = self.sp + 1;
+        if let Some(limit) = self.limit {
+            let headroom = if self.overflowed { STACK_HEADROOM } else { 0 };
+            if index > limit.saturating_add(headroom) {
+                self.overflowed = true;
+                return Err(LimitExceeded("stack depth", limit));
             }
         }
+        if index < self.split {
+            self.thaw(index);
+        }
+        self.grow(index);
+        self.live[index] = vcell.into();
+        self.sp = index;
+        Ok(())
     }
 
     /// Pop
@@ -157,14 +367,12 @@ impl Stack {
     /// Pop the top of the stack, returning a Option<VCell> that is
     /// Some(&VCell), or None if the stack was empty.
     pub fn pop(&mut self) -> Result<&VCell, Error> {
-        return if self.sp > 0 {
+        if self.sp > 0 {
             self.sp -= 1;
-            self.stack
-                .get(self.sp + 1)
-                .ok_or(InvalidStackIndex(self.sp + 1))
+            self.get(self.sp + 1)
         } else {
             Err(InvalidStackIndex(0))
-        };
+        }
     }
 
     /// Print a stack trace of the slots between start and end
@@ -180,27 +388,54 @@ impl Stack {
 
     /// To Continuation
     ///
-    /// Clone a subset of the stack into the return Stack, cloning
-    /// only the portion of the stack necessary to save the current continuation.
-    pub fn to_continuation(&self) -> Stack {
+    /// Freeze the live portion of the stack up to the stack pointer into a
+    /// new segment, and return a Stack sharing the frozen segments. Only the
+    /// slots written since the last continuation was captured are moved,
+    /// the rest of the stack is already frozen.
+    pub fn to_continuation(&mut self) -> Stack {
+        if self.sp >= self.split {
+            self.grow(self.sp);
+            let slots = self.live[self.split..=self.sp]
+                .iter_mut()
+                .map(|it| std::mem::replace(it, VCell::Undefined))
+                .collect();
+            self.frozen = Some(Rc::new(Segment::new(self.split, slots, self.frozen.take())));
+            self.split = self.sp + 1;
+        }
         Stack {
-            stack: self.stack[0..self.sp + 1].to_vec(),
+            frozen: Stack::trim(self.frozen.clone(), self.sp + 1),
+            split: self.sp + 1,
+            live: vec![],
             sp: self.sp,
+            limit: None,
+            overflowed: false,
         }
     }
 
     /// Restore Continuation
     ///
     /// Given a continuation stack returned by to_continuation, restore it.
+    /// The continuation's frozen segments are shared rather than copied,
+    /// and are only copied once the restored stack writes to them.
     pub fn restore_continuation(&mut self, cont: &Stack) {
-        self.stack
-            .split_at_mut(cont.stack.len())
-            .0
-            .clone_from_slice(&cont.stack);
+        self.grow(cont.len().max(cont.sp + 1) - 1);
+        self.frozen = cont.frozen.clone();
+        self.split = cont.split;
+        if let Some(live) = cont.live.get(cont.split..) {
+            self.live[cont.split..cont.split + live.len()].clone_from_slice(live);
+        }
         self.sp = cont.sp;
     }
 }
 
+impl PartialEq for Stack {
+    fn eq(&self, other: &Self) -> bool {
+        self.sp == other.sp && self.iter_to_sp().eq(other.iter_to_sp())
+    }
+}
+
+impl Eq for Stack {}
+
 impl Default for Stack {
     fn default() -> Self {
         Self::new()
@@ -217,7 +452,7 @@ mod tests {
         let mut stack = Stack::new();
         assert_eq!(stack.len(), 256);
         for i in 0..1024 {
-            stack.push(VCell::Number(Number::from(i)));
+            stack.push(VCell::Number(Number::from(i))).unwrap();
         }
         assert_eq!(stack.len(), 2048)
     }
@@ -225,9 +460,9 @@ mod tests {
     #[test]
     fn relative_access() {
         let mut stack = Stack::new();
-        stack.push(VCell::number(0));
-        stack.push(VCell::number(1));
-        stack.push(VCell::number(2));
+        stack.push(VCell::number(0)).unwrap();
+        stack.push(VCell::number(1)).unwrap();
+        stack.push(VCell::number(2)).unwrap();
         assert_eq!(stack.get_offset(2), Ok(&VCell::Undefined));
         assert_eq!(stack.get_offset(1), Ok(&VCell::Undefined));
         assert_eq!(stack.get_offset(0), Ok(&VCell::number(2)));
@@ -239,25 +474,47 @@ mod tests {
     #[test]
     fn push_and_pop() {
         let mut stack = Stack::new();
-        stack.push(VCell::number(1));
-        stack.push(VCell::number(2));
+        stack.push(VCell::number(1)).unwrap();
+        stack.push(VCell::number(2)).unwrap();
         assert_eq!(stack.pop(), Ok(&VCell::number(2)));
         assert_eq!(stack.pop(), Ok(&VCell::number(1)));
         assert_eq!(stack.pop(), Err(InvalidStackIndex(0)));
     }
 
+    #[test]
+    fn push_past_limit() {
+        let mut stack = Stack::new();
+        stack.set_limit(Some(2));
+        stack.push(VCell::number(1)).unwrap();
+        stack.push(VCell::number(2)).unwrap();
+        assert_eq!(
+            stack.push(VCell::number(3)),
+            Err(LimitExceeded("stack depth", 2))
+        );
+        assert_eq!(stack.push(VCell::number(3)), Ok(()));
+
+        stack.pop().unwrap();
+        stack.pop().unwrap();
+        stack.set_limit(Some(2));
+        stack.push(VCell::number(3)).unwrap();
+        assert_eq!(
+            stack.push(VCell::number(4)),
+            Err(LimitExceeded("stack depth", 2))
+        );
+    }
+
     #[test]
     fn continuation() {
         let mut stack = Stack::new();
-        stack.push(VCell::number(1));
-        stack.push(VCell::number(2));
-        stack.push(VCell::number(3));
+        stack.push(VCell::number(1)).unwrap();
+        stack.push(VCell::number(2)).unwrap();
+        stack.push(VCell::number(3)).unwrap();
         assert_eq!(stack.get_sp(), 3);
         let cont = stack.to_continuation();
 
         *stack.get_sp_mut() = 0;
-        stack.push(VCell::number(4));
-        stack.push(VCell::number(5));
+        stack.push(VCell::number(4)).unwrap();
+        stack.push(VCell::number(5)).unwrap();
         assert_eq!(stack.get_sp(), 2);
         assert_eq!(stack.get_offset(0), Ok(&VCell::number(5)));
         assert_eq!(stack.get_offset(-1), Ok(&VCell::number(4)));
@@ -268,4 +525,78 @@ mod tests {
         assert_eq!(stack.get_offset(-1), Ok(&VCell::number(2)));
         assert_eq!(stack.get_offset(-2), Ok(&VCell::number(1)));
     }
+
+    #[test]
+    fn continuations_share_frozen_segments() {
+        let mut stack = Stack::new();
+        stack.push(VCell::number(1)).unwrap();
+        stack.push(VCell::number(2)).unwrap();
+        let first = stack.to_continuation();
+        stack.push(VCell::number(3)).unwrap();
+        let second = stack.to_continuation();
+
+        // Only the slot pushed since the first capture was frozen again
+        let segment = second.frozen.as_ref().unwrap();
+        assert_eq!(segment.base, 3);
+        assert_eq!(segment.slots, vec![VCell::number(3)]);
+        assert!(Rc::ptr_eq(
+            segment.parent.as_ref().unwrap(),
+            first.frozen.as_ref().unwrap()
+        ));
+        assert_eq!(
+            second.iter_to_sp().cloned().collect::<Vec<_>>(),
+            vec![
+                VCell::undefined(),
+                VCell::number(1),
+                VCell::number(2),
+                VCell::number(3)
+            ]
+        );
+    }
+
+    #[test]
+    fn writes_thaw_frozen_slots() {
+        let mut stack = Stack::new();
+        stack.push(VCell::number(1)).unwrap();
+        stack.push(VCell::number(2)).unwrap();
+        stack.push(VCell::number(3)).unwrap();
+        let cont = stack.to_continuation();
+
+        *stack.get_mut(2).unwrap() = VCell::number(20);
+        assert_eq!(stack.split, 2);
+        assert_eq!(stack.get(1), Ok(&VCell::number(1)));
+        assert_eq!(stack.get(2), Ok(&VCell::number(20)));
+        assert_eq!(stack.get(3), Ok(&VCell::number(3)));
+        assert_eq!(cont.get(2), Ok(&VCell::number(2)));
+
+        // Returning below the captured frames and pushing thaws only the
+        // overwritten slots
+        *stack.get_sp_mut() = 0;
+        stack.push(VCell::number(4)).unwrap();
+        assert_eq!(stack.split, 1);
+        assert_eq!(stack.get(1), Ok(&VCell::number(4)));
+        assert_eq!(cont.get(1), Ok(&VCell::number(1)));
+
+        stack.restore_continuation(&cont);
+        assert_eq!(stack, cont);
+        assert_eq!(stack.get(2), Ok(&VCell::number(2)));
+    }
+
+    #[test]
+    fn get_across_segments() {
+        let mut stack = Stack::new();
+        let mut conts = vec![];
+        for i in 1..=1000 {
+            stack.push(VCell::number(i)).unwrap();
+            conts.push(stack.to_continuation());
+        }
+        for i in 1..=1000 {
+            assert_eq!(stack.get(i as usize), Ok(&VCell::number(i)));
+        }
+        assert_eq!(conts[499].get(250), Ok(&VCell::number(250)));
+        assert_eq!(conts[499].get(501), Err(InvalidStackIndex(501)));
+        for i in (1..=1000).rev() {
+            assert_eq!(stack.pop(), Ok(&VCell::number(i)));
+        }
+    }
 }
@@ -0,0 +1,280 @@
+use crate::char::foldcase_chars;
+use crate::error::Error;
+use crate::error::Error::InvalidStringIndex;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+
+/// Scheme String
+///
+/// SchemeString is the mutable string type backing VCell::String. Unlike a
+/// UTF-8 String, it stores one element per character so that string-ref,
+/// string-set! and substring are O(1) in the character index.
+///
+/// Strings containing only ASCII characters are stored as bytes, and are
+/// widened to a vector of chars the first time a non-ASCII character is
+/// stored.
+#[derive(Clone, Debug)]
+pub struct SchemeString {
+    repr: RefCell<Repr>,
+}
+
+#[derive(Clone, Debug)]
+enum Repr {
+    Ascii(Vec<u8>),
+    Wide(Vec<char>),
+}
+
+impl SchemeString {
+    pub fn from_chars(chars: Vec<char>) -> SchemeString {
+        let repr = match chars.iter().all(char::is_ascii) {
+            true => Repr::Ascii(chars.into_iter().map(|c| c as u8).collect()),
+            false => Repr::Wide(chars),
+        };
+        SchemeString {
+            repr: RefCell::new(repr),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &*self.repr.borrow() {
+            Repr::Ascii(bytes) => bytes.len(),
+            Repr::Wide(chars) => chars.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<char> {
+        match &*self.repr.borrow() {
+            Repr::Ascii(bytes) => bytes.get(index).map(|b| *b as char),
+            Repr::Wide(chars) => chars.get(index).copied(),
+        }
+    }
+
+    /// Put
+    ///
+    /// Replace the character at index with c, or return an error if index
+    /// is out of range.
+    pub fn put(&self, index: usize, c: char) -> Result<(), Error> {
+        let len = self.len();
+        if index >= len {
+            return Err(InvalidStringIndex(index, len.saturating_sub(1)));
+        }
+        self.fill(index, index + 1, c);
+        Ok(())
+    }
+
+    /// Fill
+    ///
+    /// Replace each character in the range start..end with c.
+    pub fn fill(&self, start: usize, end: usize, c: char) {
+        let mut repr = self.repr.borrow_mut();
+        if !c.is_ascii() {
+            repr.widen();
+        }
+        match &mut *repr {
+            Repr::Ascii(bytes) => bytes[start..end].fill(c as u8),
+            Repr::Wide(chars) => chars[start..end].fill(c),
+        }
+    }
+
+    /// Copy From
+    ///
+    /// Overwrite the characters starting at index at with the characters
+    /// in from.
+    pub fn copy_from(&self, at: usize, from: &[char]) {
+        let mut repr = self.repr.borrow_mut();
+        if !from.iter().all(char::is_ascii) {
+            repr.widen();
+        }
+        match &mut *repr {
+            Repr::Ascii(bytes) => {
+                for (dst, c) in bytes[at..at + from.len()].iter_mut().zip(from) {
+                    *dst = *c as u8;
+                }
+            }
+            Repr::Wide(chars) => chars[at..at + from.len()].copy_from_slice(from),
+        }
+    }
+
+    /// Chars
+    ///
+    /// Return a copy of the characters in the range start..end.
+    pub fn chars(&self, start: usize, end: usize) -> Vec<char> {
+        match &*self.repr.borrow() {
+            Repr::Ascii(bytes) => bytes[start..end].iter().map(|b| *b as char).collect(),
+            Repr::Wide(chars) => chars[start..end].to_vec(),
+        }
+    }
+
+    pub fn to_chars(&self) -> Vec<char> {
+        self.chars(0, self.len())
+    }
+
+    /// Substring
+    ///
+    /// Return the characters in the range start..end as a new string.
+    pub fn substring(&self, start: usize, end: usize) -> SchemeString {
+        let repr = match &*self.repr.borrow() {
+            Repr::Ascii(bytes) => Repr::Ascii(bytes[start..end].to_vec()),
+            Repr::Wide(chars) => Repr::Wide(chars[start..end].to_vec()),
+        };
+        SchemeString {
+            repr: RefCell::new(repr),
+        }
+    }
+
+    /// Compare Folded
+    ///
+    /// Compare this string to other after applying full Unicode case
+    /// folding to both, as string-ci<? and friends do.
+    pub fn cmp_folded(&self, other: &SchemeString) -> Ordering {
+        match (&*self.repr.borrow(), &*other.repr.borrow()) {
+            (Repr::Ascii(left), Repr::Ascii(right)) => left
+                .iter()
+                .map(u8::to_ascii_lowercase)
+                .cmp(right.iter().map(u8::to_ascii_lowercase)),
+            (left, right) => foldcase_chars(left.iter()).cmp(foldcase_chars(right.iter())),
+        }
+    }
+}
+
+impl Repr {
+    fn iter(&self) -> impl Iterator<Item = char> + '_ {
+        let (ascii, wide) = match self {
+            Repr::Ascii(bytes) => (Some(bytes.iter().map(|b| *b as char)), None),
+            Repr::Wide(chars) => (None, Some(chars.iter().copied())),
+        };
+        ascii
+            .into_iter()
+            .flatten()
+            .chain(wide.into_iter().flatten())
+    }
+
+    fn widen(&mut self) {
+        if let Repr::Ascii(bytes) = self {
+            *self = Repr::Wide(bytes.iter().map(|b| *b as char).collect());
+        }
+    }
+}
+
+impl From<&str> for SchemeString {
+    fn from(s: &str) -> Self {
+        let repr = match s.is_ascii() {
+            true => Repr::Ascii(s.as_bytes().to_vec()),
+            false => Repr::Wide(s.chars().collect()),
+        };
+        SchemeString {
+            repr: RefCell::new(repr),
+        }
+    }
+}
+
+impl From<String> for SchemeString {
+    fn from(s: String) -> Self {
+        match s.is_ascii() {
+            true => SchemeString {
+                repr: RefCell::new(Repr::Ascii(s.into_bytes())),
+            },
+            false => SchemeString::from(s.as_str()),
+        }
+    }
+}
+
+impl From<Vec<char>> for SchemeString {
+    fn from(chars: Vec<char>) -> Self {
+        SchemeString::from_chars(chars)
+    }
+}
+
+impl PartialEq for SchemeString {
+    fn eq(&self, other: &Self) -> bool {
+        match (&*self.repr.borrow(), &*other.repr.borrow()) {
+            (Repr::Ascii(left), Repr::Ascii(right)) => left == right,
+            (Repr::Wide(left), Repr::Wide(right)) => left == right,
+            (Repr::Ascii(ascii), Repr::Wide(wide)) | (Repr::Wide(wide), Repr::Ascii(ascii)) => {
+                ascii.len() == wide.len() && ascii.iter().zip(wide).all(|(a, w)| *a as char == *w)
+            }
+        }
+    }
+}
+
+impl Eq for SchemeString {}
+
+impl PartialOrd for SchemeString {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for SchemeString {
+    /// Compare strings by code point, as comparing their UTF-8 encodings
+    /// would.
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (&*self.repr.borrow(), &*other.repr.borrow()) {
+            (Repr::Ascii(left), Repr::Ascii(right)) => left.cmp(right),
+            (Repr::Wide(left), Repr::Wide(right)) => left.cmp(right),
+            (left, right) => left.iter().cmp(right.iter()),
+        }
+    }
+}
+
+impl Display for SchemeString {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &*self.repr.borrow() {
+            // Ascii strings are always valid UTF-8
+            Repr::Ascii(bytes) => write!(f, "{}", std::str::from_utf8(bytes).unwrap()),
+            Repr::Wide(chars) => chars.iter().try_for_each(|c| write!(f, "{}", c)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ascii_is_widened_on_demand() {
+        let s = SchemeString::from("foo");
+        assert!(matches!(*s.repr.borrow(), Repr::Ascii(_)));
+        s.put(1, '🐶').unwrap();
+        assert!(matches!(*s.repr.borrow(), Repr::Wide(_)));
+        assert_eq!(s.to_string(), "f🐶o");
+        assert_eq!(s.get(1), Some('🐶'));
+        assert_eq!(s.len(), 3);
+    }
+
+    #[test]
+    fn equality_across_representations() {
+        let wide = SchemeString::from("o🐶o");
+        wide.put(1, 'f').unwrap();
+        assert_eq!(wide, SchemeString::from("ofo"));
+        assert_ne!(wide, SchemeString::from("of"));
+    }
+
+    #[test]
+    fn put_out_of_range() {
+        let s = SchemeString::from("foo");
+        assert_eq!(s.put(3, 'x'), Err(InvalidStringIndex(3, 2)));
+        assert_eq!(s.to_string(), "foo");
+    }
+
+    #[test]
+    fn ordering_across_representations() {
+        let wide = SchemeString::from("a🐶");
+        assert!(SchemeString::from("ab") < wide);
+        assert!(wide > SchemeString::from("a"));
+        assert!(SchemeString::from("abc") < SchemeString::from("abd"));
+        assert_eq!(
+            SchemeString::from("STRASSE").cmp_folded(&SchemeString::from("straße")),
+            Ordering::Equal
+        );
+        assert_eq!(
+            SchemeString::from("ABC").cmp_folded(&SchemeString::from("abd")),
+            Ordering::Less
+        );
+    }
+}
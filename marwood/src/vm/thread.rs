@@ -0,0 +1,285 @@
+use crate::cell::Cell;
+use crate::error::Error;
+use crate::error::Error::InvalidSyntax;
+use crate::vm::execution::ExecutionState;
+use crate::vm::stack::Stack;
+use crate::vm::vcell::VCell;
+use crate::vm::Vm;
+use std::collections::VecDeque;
+
+/// The number of instructions a thread runs before it's preempted.
+const DEFAULT_QUANTUM: usize = 1000;
+
+/// Green Thread
+///
+/// A thread that isn't running, along with the thread object it was
+/// started with. The primordial thread, that is the evaluation that was
+/// running when the first thread was started, has no thread object.
+#[derive(Debug, Clone)]
+pub struct GreenThread {
+    pub thread: Option<VCell>,
+    pub state: ExecutionState,
+}
+
+/// Switch
+///
+/// A request made by a builtin to switch threads once it returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Switch {
+    /// Suspend the running thread and run the next ready thread
+    Yield,
+    /// Discard the running thread, which has terminated
+    Exit,
+}
+
+/// Scheduler
+///
+/// The scheduler implements the lightweight threads of SRFI 18. Threads
+/// are scheduled round robin by the run loop, which preempts the running
+/// thread once it has executed a quantum of instructions, or once it
+/// yields.
+///
+/// Each thread has its own stack, registers and exception handlers, which
+/// are swapped with the Vm's when switching threads. The thread objects
+/// themselves are vectors created by the prelude, which implements the
+/// rest of the thread API on top of the %thread builtins.
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    /// Threads ready to run, in the order they'll be run
+    pub ready: VecDeque<GreenThread>,
+
+    /// The thread object of the running thread, or None if the running
+    /// thread is the primordial thread
+    pub current: Option<VCell>,
+
+    /// A switch requested by the running thread
+    pub switch: Option<Switch>,
+
+    /// The number of instructions a thread runs before it's preempted
+    pub quantum: usize,
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Scheduler {
+            ready: VecDeque::new(),
+            current: None,
+            switch: None,
+            quantum: DEFAULT_QUANTUM,
+        }
+    }
+
+    /// Iter Roots
+    ///
+    /// Return an iterator to the thread objects and suspended state of every
+    /// thread, which are roots of garbage collection.
+    pub fn iter_roots(&self) -> impl Iterator<Item = VCell> + '_ {
+        self.ready
+            .iter()
+            .flat_map(|it| it.state.iter_roots().chain(it.thread.clone()))
+            .chain(self.current.clone())
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Scheduler::new()
+    }
+}
+
+impl Vm {
+    /// Set Thread Quantum
+    ///
+    /// Set the number of instructions a thread may run before it's
+    /// preempted by the next ready thread.
+    pub fn set_thread_quantum(&mut self, quantum: usize) {
+        self.scheduler.quantum = quantum.max(1);
+    }
+
+    /// Spawn Thread
+    ///
+    /// Make the thread object thread ready to run. A new thread starts by
+    /// applying %thread-main to its thread object, which runs the thread's
+    /// thunk and exits the thread once it returns.
+    pub fn spawn_thread(&mut self, thread: VCell) -> Result<(), Error> {
+        let main = self
+            .global("%thread-main")
+            .ok_or_else(|| InvalidSyntax("threads require the prelude".into()))?;
+        let lambda = match self.heap.get(&main) {
+            VCell::Closure(lambda, _) => lambda,
+            _ => main.as_ptr()?,
+        };
+
+        // %thread-main never returns, the frame's saved registers are only
+        // placeholders.
+        let mut stack = Stack::new();
+        stack.push(thread.clone())?;
+        stack.push(VCell::ArgumentCount(1))?;
+        stack.push(VCell::EnvironmentPointer(self.ep))?;
+        stack.push(VCell::InstructionPointer(lambda, 0))?;
+
+        self.scheduler.ready.push_back(GreenThread {
+            thread: Some(thread),
+            state: ExecutionState {
+                stack,
+                acc: main,
+                ip: (lambda, 0),
+                ..ExecutionState::idle()
+            },
+        });
+        Ok(())
+    }
+
+    /// Switch Thread
+    ///
+    /// Suspend the running thread, or discard it if it has exited, and run
+    /// the next ready thread if there is one.
+    pub fn switch_thread(&mut self, switch: Switch) {
+        let Some(mut next) = self.scheduler.ready.pop_front() else {
+            return;
+        };
+        self.swap_execution_state(&mut next.state);
+        let thread = std::mem::replace(&mut self.scheduler.current, next.thread);
+        if switch == Switch::Yield {
+            self.scheduler.ready.push_back(GreenThread {
+                thread,
+                state: next.state,
+            });
+        }
+    }
+
+    /// Resume Primordial Thread
+    ///
+    /// Switch back to the primordial thread if another thread is running,
+    /// such as before returning from the run loop with a suspended program.
+    pub fn resume_primordial_thread(&mut self) {
+        if self.scheduler.current.is_none() {
+            return;
+        }
+        if let Some(idx) = self
+            .scheduler
+            .ready
+            .iter()
+            .position(|it| it.thread.is_none())
+        {
+            let primordial = self.scheduler.ready.remove(idx).unwrap();
+            self.scheduler.ready.push_front(primordial);
+            self.switch_thread(Switch::Yield);
+        }
+    }
+
+    /// Fail Thread
+    ///
+    /// Called when the running thread failed with an error that couldn't be
+    /// handled. The thread is terminated with the error's message as the
+    /// reason, and the primordial thread is discarded along with it so that
+    /// the Vm may evaluate again.
+    pub fn fail_thread(&mut self, error: &Error) {
+        if let Some(thread) = self.scheduler.current.take() {
+            if let VCell::Vector(vector) = self.heap.get(&thread) {
+                let reason = self.heap.put_cell(&Cell::String(error.to_string()));
+                let failed = self.heap.put_cell(&Cell::new_symbol("failed"));
+                self.heap.shade(&reason);
+                self.heap.shade(&failed);
+                vector.put(5, reason);
+                vector.put(4, failed);
+            }
+            self.scheduler.ready.retain(|it| it.thread.is_some());
+        }
+        self.scheduler.switch = None;
+    }
+
+    /// Remove Thread
+    ///
+    /// Remove the thread object thread from the ready threads, returning
+    /// true if it was ready.
+    pub fn remove_thread(&mut self, thread: &VCell) -> bool {
+        let len = self.scheduler.ready.len();
+        self.scheduler
+            .ready
+            .retain(|it| it.thread.as_ref() != Some(thread));
+        len != self.scheduler.ready.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::gc::GcConfig;
+    use crate::{cell, lex, parse};
+
+    #[test]
+    fn threads_are_preempted() {
+        let mut vm = Vm::new();
+        vm.set_thread_quantum(10);
+        vm.eval(&parse!("(define b-started #f)")).unwrap();
+
+        // A never yields, so b may only start before a finishes if a is
+        // preempted
+        assert_eq!(
+            vm.eval(&parse!(
+                r#"
+                (let ((a (thread-start!
+                           (make-thread
+                             (lambda ()
+                               (let loop ((i 0)) (if (< i 1000) (loop (+ i 1))))
+                               b-started))))
+                      (b (thread-start! (make-thread (lambda () (set! b-started #t) 'b)))))
+                  (list (thread-join! a) (thread-join! b)))
+                "#
+            )),
+            Ok(parse!("(#t b)"))
+        );
+        assert!(vm.scheduler.ready.is_empty());
+    }
+
+    #[test]
+    fn threads_survive_gc() {
+        let mut vm = Vm::new();
+        vm.set_thread_quantum(7);
+        vm.set_gc_config(GcConfig {
+            interval: 1,
+            threshold: 0.0,
+            grow_threshold: 0.75,
+            budget: Some(16),
+        });
+        assert_eq!(
+            vm.eval(&parse!(
+                r#"
+                (let ((threads
+                       (map (lambda (n)
+                              (thread-start!
+                                (make-thread
+                                  (lambda ()
+                                    (let loop ((i 0) (acc '()))
+                                      (if (= i n)
+                                          (apply + (map car acc))
+                                          (loop (+ i 1) (cons (list i) acc))))))))
+                            '(100 200 300))))
+                  (map thread-join! threads))
+                "#
+            )),
+            Ok(parse!("(4950 19900 44850)"))
+        );
+    }
+
+    #[test]
+    fn suspended_program_resumes_primordial_thread() {
+        let mut vm = Vm::new();
+        vm.set_thread_quantum(5);
+        vm.prepare_eval(&parse!(
+            r#"
+            (let ((t (thread-start! (make-thread (lambda () (let loop ((i 0)) (if (< i 1000) (loop (+ i 1)) i)))))))
+              (thread-join! t))
+            "#
+        ))
+        .unwrap();
+        let result = loop {
+            if let Some(result) = vm.run_count(23).unwrap() {
+                break result;
+            }
+            assert!(vm.scheduler.current.is_none());
+        };
+        assert_eq!(result, cell![1000]);
+    }
+}
@@ -1,4 +1,5 @@
 use crate::cell::Cell;
+use crate::source::Span;
 use crate::vm::heap::Heap;
 use crate::vm::opcode::OpCode;
 use crate::vm::stack::Stack;
@@ -8,11 +9,13 @@ use crate::vm::vcell::VCell;
 ///
 /// Stack Frame represents one stack frame in a backtrace. It includes a
 /// description of the applied procedure, and the arguments pushed to the
-/// procedure in order.
+/// procedure in order, along with the span of the expression the frame
+/// was evaluating if it was compiled from source.
 #[derive(Debug)]
 pub struct StackFrame {
     pub name: Option<String>,
     pub desc: Option<Cell>,
+    pub span: Option<Span>,
 }
 
 /// Stack Trace
@@ -36,11 +39,12 @@ impl StackTrace {
         let ip = heap.get_at_index(ip.0).as_lambda().unwrap();
 
         // Reverse %ip to last instruction
-        ip_idx -= 1;
+        ip_idx = ip_idx.saturating_sub(1);
         while ip_idx > 0 && !matches!(ip.get(ip_idx).unwrap(), VCell::OpCode(_)) {
             ip_idx -= 1;
         }
         let op_code = ip.get(ip_idx).unwrap().as_opcode().unwrap();
+        let span = ip.span_at(ip_idx).cloned();
 
         // If the just executed instruction was procedure application for a builtin, then
         // the builtin is the top frame.
@@ -50,6 +54,7 @@ impl StackTrace {
                     frames.push(StackFrame {
                         name: Some(proc.desc().to_owned()),
                         desc: None,
+                        span: span.clone(),
                     })
                 }
             }
@@ -60,19 +65,119 @@ impl StackTrace {
         frames.push(StackFrame {
             name: None,
             desc: ip.desc_args.clone(),
+            span,
         });
 
-        // Iterate the stack backwards
+        // Iterate the stack backwards. A frame's saved %ip is the return
+        // address of the call it's evaluating.
         for sp in (0..stack.get_sp()).rev() {
-            if let Ok(VCell::InstructionPointer(ip, _)) = stack.get(sp) {
+            if let Ok(VCell::InstructionPointer(ip, ip_idx)) = stack.get(sp) {
                 let ip = heap.get_at_index(*ip).as_lambda().unwrap();
                 frames.push(StackFrame {
                     name: None,
                     desc: ip.desc_args.clone(),
+                    span: ip.span_at(ip_idx.saturating_sub(1)).cloned(),
                 });
             }
         }
 
         StackTrace { frames }
     }
+
+    /// Span
+    ///
+    /// Return the span of the innermost frame that has one, which is where
+    /// the error that produced this trace occurred.
+    pub fn span(&self) -> Option<&Span> {
+        self.frames.iter().find_map(|it| it.span.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use crate::vm::Vm;
+    use crate::{lex, parse};
+
+    const SOURCE: &str = r#"(define (f x)
+  (let ((y (+ x 1)))
+    (when (> y 0)
+      (car y))))
+
+(define (g n) (+ 1 (f n)))
+(g 5)
+"#;
+
+    fn locations(trace: &StackTrace) -> Vec<Option<String>> {
+        trace
+            .frames
+            .iter()
+            .map(|it| it.span.as_ref().map(|it| it.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn frames_report_spans() {
+        let mut vm = Vm::new();
+        assert_eq!(
+            vm.eval_source("test.scm", SOURCE),
+            Err(Error::ExpectedPairButFound(parse!("6")))
+        );
+        let trace = vm.last_stacktrace().unwrap();
+        assert_eq!(
+            locations(trace),
+            vec![
+                Some("test.scm:4:7".into()),
+                Some("test.scm:4:7".into()),
+                Some("test.scm:6:20".into()),
+                None
+            ]
+        );
+        assert_eq!(
+            trace.span().unwrap().excerpt(),
+            "4 |       (car y))))\n  |       ^^^^^^^"
+        );
+        assert_eq!(
+            vm.eval_source("test.scm", "(define x 1) (+ x 1)"),
+            Ok(parse!("2"))
+        );
+    }
+
+    #[test]
+    fn spans_survive_optimization() {
+        let mut vm = Vm::new();
+        vm.set_optimize(false);
+        assert!(vm.eval_source("test.scm", SOURCE).is_err());
+        let unoptimized = locations(vm.last_stacktrace().unwrap());
+
+        let mut vm = Vm::new();
+        assert!(vm.eval_source("test.scm", SOURCE).is_err());
+        assert_eq!(locations(vm.last_stacktrace().unwrap()), unoptimized);
+
+        // Folding (+ 1 2) into a constant doesn't move the spans of the
+        // instructions around it
+        assert!(vm
+            .eval_source(
+                "test.scm",
+                "(define (h v)\n  (vector-ref v (+ 1 2)))\n(h #(1))"
+            )
+            .is_err());
+        assert_eq!(
+            vm.last_stacktrace().unwrap().span().unwrap().to_string(),
+            "test.scm:2:3"
+        );
+    }
+
+    #[test]
+    fn expressions_without_source_have_no_span() {
+        let mut vm = Vm::new();
+        assert!(vm.eval(&parse!("(car 1)")).is_err());
+        assert!(vm.last_stacktrace().unwrap().span().is_none());
+        assert!(vm.eval_text("(car 1)").is_err());
+        assert_eq!(
+            vm.last_stacktrace().unwrap().span().unwrap().to_string(),
+            "<input>:1:1"
+        );
+    }
 }
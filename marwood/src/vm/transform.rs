@@ -5,17 +5,17 @@ use crate::error::Error::InvalidSyntax;
 
 macro_rules! car {
     ($cell:expr) => {{
-        $cell
-            .car()
-            .ok_or(Error::ExpectedPairButFound($cell.clone()))?
+        let cell = $cell;
+        cell.car()
+            .ok_or_else(|| Error::ExpectedPairButFound(cell.clone()))?
     }};
 }
 
 macro_rules! cdr {
     ($cell:expr) => {{
-        $cell
-            .cdr()
-            .ok_or(Error::ExpectedPairButFound($cell.clone()))?
+        let cell = $cell;
+        cell.cdr()
+            .ok_or_else(|| Error::ExpectedPairButFound(cell.clone()))?
     }};
 }
 
@@ -135,12 +135,11 @@ impl Pattern {
 
     fn find_expanded_variables(expr: &Cell, pattern: &mut Pattern) {
         match expr {
-            Cell::Symbol(_) => {
+            Cell::Symbol(_)
                 if pattern.is_variable_candidate(expr)
-                    && !pattern.expanded_variables.iter().any(|it| it == expr)
-                {
-                    pattern.expanded_variables.push(expr.clone());
-                }
+                    && !pattern.expanded_variables.iter().any(|it| it == expr) =>
+            {
+                pattern.expanded_variables.push(expr.clone());
             }
             Cell::Pair(_, _) => {
                 for it in expr {
@@ -157,6 +156,7 @@ impl Pattern {
 /// Transform is a runtime representation of a set of syntax-rules.
 #[derive(Debug, Eq, PartialEq)]
 pub struct Transform {
+    source: Cell,
     keyword: Cell,
     ellipsis: Cell,
     syntax_rules: Vec<(Pattern, Cell)>,
@@ -172,6 +172,7 @@ impl Transform {
     /// # Arguments
     /// `expr` - the full (define-syntax ...) expression
     pub fn try_new(expr: &Cell) -> Result<Transform, Error> {
+        let source = expr.clone();
         let expr = expr.collect_vec();
         let (keyword, mut syntax_rules) = match expr.as_slice() {
             [_, keyword, syntax_rules] => (*keyword, *syntax_rules),
@@ -222,6 +223,7 @@ impl Transform {
         }
 
         Ok(Transform {
+            source,
             keyword: keyword.clone(),
             ellipsis,
             syntax_rules: syntax_rules_vec,
@@ -240,6 +242,14 @@ impl Transform {
         &self.keyword
     }
 
+    /// Source
+    ///
+    /// Return the (define-syntax ...) expression this transform was built
+    /// from.
+    pub fn source(&self) -> &Cell {
+        &self.source
+    }
+
     /// Check Template Syntax
     ///
     /// * Any symbol preceding an ellipsis must be a pattern variable
@@ -330,6 +340,9 @@ impl Transform {
         if (pattern.is_pair() || pattern.is_nil()) && !(expr.is_pair() || expr.is_nil()) {
             return false;
         }
+        if pattern.is_improper_list() && !pattern.iter().any(|it| it == &self.ellipsis) {
+            return self.improper_pattern_match(pattern, expr, env);
+        }
         if expr.is_pair() && pattern.is_pair() && (expr.is_list() != pattern.is_list()) {
             return false;
         }
@@ -394,27 +407,65 @@ impl Transform {
 
             in_ellipsis = pattern_iter.peek() == Some(&&self.ellipsis);
 
-            match pattern {
-                Cell::Symbol(_) => {
-                    if self.is_literal(pattern) {
-                        if pattern != expr {
-                            return false;
-                        }
-                    } else if pattern != &cell!["_"] {
-                        env.add_binding(pattern, expr);
-                    }
-                }
-                Cell::Pair(_, _) => {
-                    if !self.pattern_match(pattern, expr, env) {
+            if !self.element_match(pattern, expr, env) {
+                return false;
+            }
+        }
+    }
+
+    /// Improper Pattern Match
+    ///
+    /// Match an improper pattern without an ellipsis, (P1 ... Pn . Px),
+    /// which matches any list or improper list of n or more elements. P1
+    /// through Pn match the first n elements, and Px matches the remaining
+    /// list or improper tail.
+    ///
+    /// # Arguments
+    /// `pattern` - The improper pattern to attempt to apply
+    /// `expr` - The expression to match
+    /// `bindings` - The set of matched variable bindings
+    fn improper_pattern_match<'a>(
+        &self,
+        mut pattern: &'a Cell,
+        mut expr: &'a Cell,
+        env: &mut PatternEnvironment<'a>,
+    ) -> bool {
+        while let Cell::Pair(car, cdr) = pattern {
+            match expr {
+                Cell::Pair(expr_car, expr_cdr) => {
+                    if !self.element_match(car, expr_car, env) {
                         return false;
                     }
+                    pattern = cdr;
+                    expr = expr_cdr;
                 }
-                pattern => {
-                    if pattern != expr {
-                        return false;
-                    }
+                _ => return false,
+            }
+        }
+        self.element_match(pattern, expr, env)
+    }
+
+    /// Element Match
+    ///
+    /// Match a single element of a pattern against expr, binding it if the
+    /// element is a pattern variable.
+    fn element_match<'a>(
+        &self,
+        pattern: &'a Cell,
+        expr: &'a Cell,
+        env: &mut PatternEnvironment<'a>,
+    ) -> bool {
+        match pattern {
+            Cell::Symbol(_) => {
+                if self.is_literal(pattern) {
+                    return pattern == expr;
+                } else if pattern != &cell!["_"] {
+                    env.add_binding(pattern, expr);
                 }
+                true
             }
+            Cell::Pair(_, _) => self.pattern_match(pattern, expr, env),
+            pattern => pattern == expr,
         }
     }
 
@@ -435,7 +486,7 @@ impl Transform {
     ) -> Option<Cell> {
         match template {
             Cell::Symbol(_) => {
-                return if pattern.is_variable(template) {
+                if pattern.is_variable(template) {
                     env.get_binding(template).cloned()
                 } else {
                     Some(template.clone())
@@ -564,34 +615,34 @@ mod tests {
 
     #[test]
     fn bad_patterns() {
-        assert!(Pattern::try_new(&parse!("#t"), &cell!["..."], &vec![]).is_err());
-        assert!(Pattern::try_new(&parse!("(_ ... a)"), &cell!["..."], &vec![]).is_err());
-        assert!(Pattern::try_new(&parse!("(_ a . ...)"), &cell!["..."], &vec![]).is_err());
-        assert!(Pattern::try_new(&parse!("(_ a a)"), &cell!["..."], &vec![]).is_err());
+        assert!(Pattern::try_new(&parse!("#t"), &cell!["..."], &[]).is_err());
+        assert!(Pattern::try_new(&parse!("(_ ... a)"), &cell!["..."], &[]).is_err());
+        assert!(Pattern::try_new(&parse!("(_ a . ...)"), &cell!["..."], &[]).is_err());
+        assert!(Pattern::try_new(&parse!("(_ a a)"), &cell!["..."], &[]).is_err());
     }
 
     #[test]
     fn pattern_variable() {
         assert_eq!(
-            Pattern::try_new(&parse!("(_ a b c)"), &cell!["..."], &vec![])
+            Pattern::try_new(&parse!("(_ a b c)"), &cell!["..."], &[])
                 .unwrap()
                 .variables,
             vec![cell!["a"], cell!["b"], cell!["c"]]
         );
         assert_eq!(
-            Pattern::try_new(&parse!("(_ a b . c)"), &cell!["..."], &vec![])
+            Pattern::try_new(&parse!("(_ a b . c)"), &cell!["..."], &[])
                 .unwrap()
                 .variables,
             vec![cell!["a"], cell!["b"], cell!["c"]]
         );
         assert_eq!(
-            Pattern::try_new(&parse!("(_ a* ...)"), &cell!["..."], &vec![])
+            Pattern::try_new(&parse!("(_ a* ...)"), &cell!["..."], &[])
                 .unwrap()
                 .variables,
             vec![cell!["a*"]]
         );
         assert_eq!(
-            Pattern::try_new(&parse!("(_ (a* b*) ...)"), &cell!["..."], &vec![])
+            Pattern::try_new(&parse!("(_ (a* b*) ...)"), &cell!["..."], &[])
                 .unwrap()
                 .variables,
             vec![cell!["a*"], cell!["b*"]]
@@ -601,7 +652,7 @@ mod tests {
     #[test]
     fn expanded_pattern_variables() {
         let pattern =
-            Pattern::try_new(&parse!("(_ a (b (c ...)) ...)"), &cell!["..."], &vec![]).unwrap();
+            Pattern::try_new(&parse!("(_ a (b (c ...)) ...)"), &cell!["..."], &[]).unwrap();
         assert_eq!(pattern.variables, vec![cell!["a"], cell!["b"], cell!["c"]]);
         assert_eq!(pattern.expanded_variables, vec![cell!["b"], cell!["c"]]);
     }
@@ -834,6 +885,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn improper_pattern_matches_lists() {
+        let transform = Transform::try_new(&parse!(
+            r#"
+        (define-syntax define-thunk
+              (syntax-rules ()
+                [(_ (name . formals) body ...) (define name (lambda formals body ...))]
+        ))
+        "#
+        ))
+        .unwrap();
+        assert_eq!(
+            transform.transform(&parse!("(define-thunk (f a b) 1 2)")),
+            Ok(parse!("(define f (lambda (a b) 1 2))"))
+        );
+        assert_eq!(
+            transform.transform(&parse!("(define-thunk (f) 1)")),
+            Ok(parse!("(define f (lambda () 1))"))
+        );
+        assert_eq!(
+            transform.transform(&parse!("(define-thunk (f a . rest) 1)")),
+            Ok(parse!("(define f (lambda (a . rest) 1))"))
+        );
+        assert!(transform.transform(&parse!("(define-thunk f 1)")).is_err());
+    }
+
     #[test]
     fn single_variable_expansion() {
         let transform = Transform::try_new(&parse!(
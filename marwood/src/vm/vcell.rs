@@ -2,20 +2,20 @@ use crate::char::write_escaped_char;
 use crate::error::Error;
 use crate::error::Error::ExpectedType;
 use crate::number::Number;
+use crate::vm::charset::CharSet;
 use crate::vm::continuation::Continuation;
 use crate::vm::environment::LexicalEnvironment;
 use crate::vm::heap::HeapRef;
 use crate::vm::lambda::Lambda;
 use crate::vm::opcode::OpCode;
+use crate::vm::string::SchemeString;
 use crate::vm::transform::Transform;
 use crate::vm::vector::Vector;
 use crate::vm::Vm;
 use std::borrow::Cow;
 use std::borrow::Cow::{Borrowed, Owned};
-use std::cell::RefCell;
 use std::fmt;
 use std::fmt::{Debug, Formatter};
-use std::ops::Deref;
 use std::rc::Rc;
 
 /// VCell
@@ -37,10 +37,11 @@ pub enum VCell {
     Number(Number),
     Pair(HeapRef, HeapRef),
     Symbol(Rc<String>),
-    String(Rc<RefCell<String>>),
+    String(Rc<SchemeString>),
     Vector(Rc<Vector>),
 
     // other scheme values
+    CharSet(Rc<CharSet>),
     Undefined,
     Void,
 
@@ -63,13 +64,16 @@ pub enum VCell {
     GlobalEnvSlot(usize),
     InstructionPointer(HeapRef, usize),
     OpCode(OpCode),
+    Prompt(usize),
     Ptr(HeapRef),
 }
 
+type BuiltInFn = dyn Fn(&mut Vm) -> Result<VCell, Error>;
+
 #[derive(Clone)]
 pub struct BuiltInProc {
     desc: &'static str,
-    proc: Rc<dyn Fn(&mut Vm) -> Result<VCell, Error>>,
+    proc: Rc<BuiltInFn>,
 }
 
 impl BuiltInProc {
@@ -104,6 +108,7 @@ pub const ARGUMENT_COUNT_TYPE_TEXT: &str = "#<argument-count>";
 pub const BASE_POINTER_TYPE_TEXT: &str = "#<base-pointer>";
 pub const BASE_POINTER_OFFSET_TYPE_TEXT: &str = "#<base-pointer-offset>";
 pub const BOOL_TYPE_TEXT: &str = "#<bool>";
+pub const CHAR_SET_TYPE_TEXT: &str = "#<char-set>";
 pub const CHAR_TYPE_TEXT: &str = "#<char>";
 pub const CLOSURE_TYPE_TEXT: &str = "#<closure>";
 pub const CONTINUATION_TYPE_TEXT: &str = "#<continuation>";
@@ -120,6 +125,7 @@ pub const LAMBDA_TYPE_TEXT: &str = "#<lambda>";
 pub const NIL_TYPE_TEXT: &str = "#<nil>";
 pub const OPCODE_TYPE_TEXT: &str = "#<opcode>";
 pub const PAIR_TYPE_TEXT: &str = "#<pair>";
+pub const PROMPT_TYPE_TEXT: &str = "#<prompt>";
 pub const PTR_TYPE_TEXT: &str = "#<ptr>";
 pub const STRING_TYPE_TEXT: &str = "#<string>";
 pub const SYMBOL_TYPE_TEXT: &str = "#<symbol>";
@@ -141,6 +147,7 @@ impl VCell {
             VCell::BasePointerOffset(_) => BASE_POINTER_OFFSET_TYPE_TEXT,
             VCell::Bool(_) => BOOL_TYPE_TEXT,
             VCell::Char(_) => CHAR_TYPE_TEXT,
+            VCell::CharSet(_) => CHAR_SET_TYPE_TEXT,
             VCell::Continuation(_) => CONTINUATION_TYPE_TEXT,
             VCell::Closure(_, _) => CLOSURE_TYPE_TEXT,
             VCell::EnvironmentPointer(_) => ENVIRONMENT_POINTER_TYPE_TEXT,
@@ -154,6 +161,7 @@ impl VCell {
             VCell::Number(_) => NUMBER_TYPE_TEXT,
             VCell::OpCode(_) => OPCODE_TYPE_TEXT,
             VCell::Pair(_, _) => PAIR_TYPE_TEXT,
+            VCell::Prompt(_) => PROMPT_TYPE_TEXT,
             VCell::Ptr(_) => PTR_TYPE_TEXT,
             VCell::String(_) => STRING_TYPE_TEXT,
             VCell::Symbol(_) => SYMBOL_TYPE_TEXT,
@@ -199,8 +207,8 @@ impl VCell {
         VCell::GlobalEnvSlot(slot.into())
     }
 
-    pub fn string<T: Into<String>>(s: T) -> VCell {
-        VCell::String(Rc::new(RefCell::new(s.into())))
+    pub fn string<T: Into<SchemeString>>(s: T) -> VCell {
+        VCell::String(Rc::new(s.into()))
     }
 
     pub fn symbol<T: Into<String>>(sym: T) -> VCell {
@@ -327,7 +335,7 @@ impl VCell {
         }
     }
 
-    pub fn as_string(&self) -> Result<&RefCell<String>, Error> {
+    pub fn as_string(&self) -> Result<&SchemeString, Error> {
         match self {
             VCell::String(s) => Ok(s),
             _ => Err(ExpectedType(SYMBOL_TYPE_TEXT, self.type_text())),
@@ -459,6 +467,12 @@ impl From<char> for VCell {
     }
 }
 
+impl From<CharSet> for VCell {
+    fn from(cs: CharSet) -> Self {
+        VCell::CharSet(Rc::new(cs))
+    }
+}
+
 impl From<i64> for VCell {
     fn from(num: i64) -> Self {
         VCell::Number(Number::from(num))
@@ -500,6 +514,7 @@ impl fmt::Display for VCell {
             VCell::Bool(false) => write!(f, "#f"),
             VCell::Char(c) => write_escaped_char(*c, f),
             VCell::Closure(_, _) => write!(f, "#<closure>"),
+            VCell::CharSet(_) => write!(f, "#<char-set>"),
             VCell::Continuation(_) => write!(f, "#<continuation>"),
             VCell::EnvironmentPointer(ep) => write!(f, "%ep[${:02x}]", ep),
             VCell::GlobalEnvSlot(slot) => write!(f, "genv[${:02x}]", slot),
@@ -515,8 +530,9 @@ impl fmt::Display for VCell {
             VCell::Number(number) => write!(f, "{:?}", number),
             VCell::OpCode(val) => write!(f, "{:?}", val),
             VCell::Pair(car, cdr) => write!(f, "(${:02x} . ${:02x})", car, cdr),
+            VCell::Prompt(bp) => write!(f, "%prompt[${:02x}]", bp),
             VCell::Ptr(ptr) => write!(f, "${:02x}", ptr),
-            VCell::String(s) => write!(f, "\"{}\"", (**s).borrow().deref()),
+            VCell::String(s) => write!(f, "\"{}\"", s),
             VCell::Symbol(s) => write!(f, "{}", *s),
             VCell::BuiltInProc(proc) => write!(f, "#<builtin:{}>", proc.desc()),
             VCell::Undefined => write!(f, "undefined"),
@@ -1,6 +1,7 @@
 #[macro_use]
 mod common;
 use marwood::cell::Cell;
+use marwood::error::Error::EscapeContinuationExpired;
 use marwood::lex;
 use marwood::parse;
 use marwood::vm::Vm;
@@ -68,3 +69,94 @@ fn factorial_cps() {
             "(factorial 10)" 
             => "3628800"];
 }
+
+#[test]
+fn sort_comparator_escape() {
+    evals![
+        "(call/cc (lambda (k) (list-sort (lambda (x y) (k 'escaped)) '(3 2 1))))" => "escaped",
+        "(define v (vector 3 2 1))" => "#<void>",
+        "(call/cc (lambda (k) (vector-sort! v (lambda (x y) (k 'escaped)))))" => "escaped",
+        "v" => "#(3 2 1)",
+        "(list-sort < '(3 2 1))" => "(1 2 3)"
+    ];
+    evals![
+        "(define resume #f)" => "#<void>",
+        "(define result
+            (list-sort (lambda (x y)
+                         (call/cc (lambda (k) (set! resume k)))
+                         (< x y))
+                       '(3 1 2)))" => "#<void>",
+        "result" => "(1 2 3)",
+        "(resume #t)" => "#<void>",
+        "result" => "(1 2 3)"
+    ];
+}
+
+#[test]
+fn escape_continuations() {
+    evals![
+        "(call/ec procedure?)" => "#t",
+        "(call/ec (lambda (k) 10))" => "10",
+        "(+ 1 (call-with-escape-continuation (lambda (k) (+ 10 (k 5)))))" => "6",
+        "(define (find-first pred lst)
+           (call/ec
+             (lambda (return)
+               (for-each (lambda (x) (if (pred x) (return x))) lst)
+               #f)))" => "#<void>",
+        "(find-first even? '(1 3 4 5 6))" => "4",
+        "(find-first even? '(1 3 5))" => "#f",
+        "(call/ec (lambda (outer) (+ 1 (call/ec (lambda (inner) (outer 'outer))))))" => "outer",
+        "(call/ec (lambda (k) (with-exception-handler (lambda (e) (k 'handled)) (lambda () (raise 'oops)))))" => "handled",
+        "%handlers" => "()",
+        "(define saved #f)" => "#<void>",
+        "(call/ec (lambda (k) (set! saved k) 1))" => "1",
+        "(guard (e (#t (error-object-message e))) (saved 2))"
+            => "\"escape continuation invoked after its extent has ended\""
+    ];
+    fails!["(let ((k (call/ec (lambda (k) k)))) (k 1))" => EscapeContinuationExpired];
+}
+
+#[test]
+fn coroutine_generators() {
+    evals![
+        "(define g (make-coroutine-generator (lambda (yield) (for-each yield '(1 2 3 4)))))" => "#<void>",
+        "(call/cc (lambda (k) (generator-for-each (lambda (x) (if (= x 2) (k x))) g)))" => "2",
+        "(g)" => "3",
+        "(call/ec (lambda (k) (generator-for-each k g)))" => "4",
+        "(eof-object? (g))" => "#t",
+        "(define (interleave a b)
+           (make-coroutine-generator
+             (lambda (yield)
+               (let loop ()
+                 (let ((x (a)) (y (b)))
+                   (unless (and (eof-object? x) (eof-object? y))
+                     (unless (eof-object? x) (yield x))
+                     (unless (eof-object? y) (yield y))
+                     (loop)))))))" => "#<void>",
+        "(generator->list
+           (interleave (make-coroutine-generator (lambda (yield) (yield 'a) (yield 'b)))
+                       (make-iota-generator 3)))" => "(a 0 b 1 2)",
+        "(reset (generator->list (gmap (lambda (x) (shift k (cons x (k x)))) (generator 1 2))))"
+            => "(1 2 1 2)"
+    ];
+}
+
+#[test]
+fn reentered_continuations_share_assigned_variables() {
+    evals![
+        "(define k #f)" => "#<void>",
+        "(define (f) (let ((n 0)) (call/cc (lambda (c) (set! k c))) (set! n (+ n 1)) n))" => "#<void>",
+        "(define r (f))" => "#<void>",
+        "r" => "1",
+        "(k #f)" => "#<void>",
+        "r" => "2"
+    ];
+
+    evals![
+        "(define k #f)" => "#<void>",
+        "(define (g) (let loop ((i 0)) (call/cc (lambda (c) (set! k c))) (set! i (+ i 1)) i))" => "#<void>",
+        "(define r (g))" => "#<void>",
+        "(k #f)" => "#<void>",
+        "r" => "2"
+    ];
+}
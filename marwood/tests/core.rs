@@ -4,7 +4,8 @@ mod common;
 use marwood::cell;
 use marwood::cell::Cell;
 use marwood::error::Error::{
-    InvalidProcedure, InvalidSyntax, InvalidUsePrimitive, UnquotedNil, VariableNotBound,
+    ExpectedPairButFound, InvalidArgs, InvalidProcedure, InvalidSyntax, InvalidUsePrimitive,
+    UnquotedNil, VariableNotBound,
 };
 use marwood::lex;
 use marwood::parse;
@@ -51,7 +52,8 @@ fn quasiquote() {
         "`(10 20 ,(+ 10 10 10))" => "(10 20 30)",
         "`#(10 20 ,(+ 10 10 10))" => "#(10 20 30)",
         "``(x ,x ,,x)" => "(quasiquote (x (unquote x) (unquote 42)))",
-        "``#(x ,x ,,x)" => "(quasiquote #(x (unquote x) (unquote 42)))"
+        "``#(x ,x ,,x)" => "(quasiquote #(x (unquote x) (unquote 42)))",
+        "(((lambda (x) (lambda () `(x ,x))) 10))" => "(x 10)"
     ];
 }
 
@@ -227,6 +229,89 @@ fn tail_recursive() {
     ];
 }
 
+#[test]
+fn inline_primitives() {
+    evals![
+        "(define (second l) (car (cdr l)))" => "#<void>",
+        "(second '(1 2 3))" => "2",
+        "(define (count l) (if (null? l) 0 (+ 1 (count (cdr l)))))" => "#<void>",
+        "(count '(1 2 3))" => "3",
+        "(eq? 'a 'a)" => "#t",
+        "(not (pair? '()))" => "#t",
+        "(list (zero? 0) (= 1 1) (< 1 2) (> 1 2) (- 5 3))" => "(#t #t #t #f 2)",
+        "(let ((car cdr)) (car '(1 2)))" => "(2)",
+        "((lambda (+) (+ 1 2)) -)" => "-1"
+    ];
+    evals![
+        "(define (first l) (car l))" => "#<void>",
+        "(define (car l) 'shadowed)" => "#<void>",
+        "(first '(1 2))" => "shadowed",
+        "(define (loop n) (if (= n 0) 'done (loop (- n 1))))" => "#<void>",
+        "(define (- x y) (+ x (* -1 y)))" => "#<void>",
+        "(loop 10000)" => "done"
+    ];
+}
+
+#[test]
+fn inline_primitive_errors() {
+    fails![
+        "(car 1)" => ExpectedPairButFound(cell![1]),
+        "(+ 1 'a)" => InvalidArgs("+".into(), "number".into(), "a".into())
+    ];
+    evals!["(zero? 'a)" => "#f"];
+}
+
+#[test]
+fn optimized_constants() {
+    evals![
+        "(define (three) (+ 1 2))" => "#<void>",
+        "(define (pick) (if (< 1 2) 'yes 'no))" => "#<void>",
+        "(define (head) (car '(a b)))" => "#<void>",
+        "(list (three) (pick) (head))" => "(3 yes a)",
+        "(define (+ x y) (* x y))" => "#<void>",
+        "(define (< x y) #f)" => "#<void>",
+        "(list (three) (pick) (head))" => "(2 no a)"
+    ];
+    fails!["((lambda () (car 1)))" => ExpectedPairButFound(cell![1])];
+}
+
+#[test]
+fn inlined_procedures() {
+    evals![
+        "(define (f x) (let ((y (+ x 1)) (z (* x 2))) (list y z)))" => "#<void>",
+        "(f 3)" => "(4 6)",
+        "(define (sum n) (let loop ((i 0) (acc 0)) (if (= i n) acc (loop (+ i 1) (+ acc i)))))" => "#<void>",
+        "(sum 100)" => "4950",
+        "(define (gcd* a b) (define (step a b) (if (= b 0) a (step b (remainder a b)))) (step a b))" => "#<void>",
+        "(gcd* 48 18)" => "6",
+        "(define (parity n)
+           (define (even? n) (if (= n 0) 'even (odd? (- n 1))))
+           (define (odd? n) (if (= n 0) 'odd (even? (- n 1))))
+           (even? n))" => "#<void>",
+        "(parity 7)" => "odd",
+        "(define (thunks n)
+           (let loop ((i 0) (acc '()))
+             (if (= i n) (map (lambda (f) (f)) acc)
+                 (let ((j (* i i))) (loop (+ i 1) (cons (lambda () j) acc))))))" => "#<void>",
+        "(thunks 4)" => "(9 4 1 0)"
+    ];
+    evals![
+        "(define k #f)" => "#<void>",
+        "(define seen '())" => "#<void>",
+        "(define (count-to n)
+           (let loop ((i 0))
+             (when (< i n)
+               (let ((j i))
+                 (call/cc (lambda (c) (if (= j 1) (set! k c))))
+                 (set! seen (cons j seen))
+                 (loop (+ i 1))))))" => "#<void>",
+        "(count-to 3)" => "#<void>",
+        "seen" => "(2 1 0)",
+        "(if (< (length seen) 6) (k #f))" => "#<void>",
+        "seen" => "(2 1 2 1 0)"
+    ];
+}
+
 #[test]
 fn disallow_aliasing_syntactic_symbol() {
     fails!["(define if 42)" => InvalidUsePrimitive("if".into())];
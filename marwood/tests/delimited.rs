@@ -0,0 +1,95 @@
+#[macro_use]
+mod common;
+use marwood::cell::Cell;
+use marwood::error::Error::NoContinuationPrompt;
+use marwood::lex;
+use marwood::parse;
+use marwood::vm::Vm;
+
+#[test]
+fn reset_and_shift() {
+    evals![
+        "(reset 1)" => "1",
+        "(reset (+ 1 (shift k 5)))" => "5",
+        "(+ 1 (reset (+ 10 (shift k (k (k 100))))))" => "121",
+        "(reset (list 1 (shift k (append (k 'a) (k 'b)))))" => "(1 a 1 b)",
+        "(reset (+ 1 (reset (* 2 (shift k (k 10))))))" => "21",
+        "(reset (+ 1 (shift k1 (+ 10 (shift k2 100)))))" => "100",
+        "(define saved #f)" => "#<void>",
+        "(+ 1000 (reset (* 2 (shift k (set! saved k) 1))))" => "1001",
+        "(saved 5)" => "10",
+        "(saved (saved 5))" => "20",
+        "(+ 1 (saved 5))" => "11"
+    ];
+    fails!["(shift k 1)" => NoContinuationPrompt(parse!("(default)"))];
+}
+
+#[test]
+fn prompts() {
+    evals![
+        "(call-with-continuation-prompt (lambda () 10))" => "10",
+        "(call-with-continuation-prompt
+           (lambda () (+ 1 (abort-current-continuation (default-continuation-prompt-tag) (lambda () 42)))))" => "42",
+        "(define tag (make-continuation-prompt-tag 'tag))" => "#<void>",
+        "(call-with-continuation-prompt
+           (lambda () (+ 1 (abort-current-continuation tag 1 2)))
+           tag
+           (lambda (a b) (list 'aborted a b)))" => "(aborted 1 2)",
+        "(call-with-continuation-prompt
+           (lambda ()
+             (call-with-continuation-prompt
+               (lambda () (abort-current-continuation tag 'outer))
+               (default-continuation-prompt-tag)
+               (lambda (v) (list 'inner v))))
+           tag
+           (lambda (v) (list 'outer v)))" => "(outer outer)",
+        "(call-with-continuation-prompt
+           (lambda ()
+             (+ 1 (call-with-composable-continuation (lambda (k) (k (k 1))) tag)))
+           tag)" => "4"
+    ];
+}
+
+#[test]
+fn generators() {
+    evals![
+        "(define (make-generator lst)
+           (define next
+             (lambda (resume)
+               (reset
+                 (for-each (lambda (x) (shift k (begin (set! next k) x))) lst)
+                 'done)))
+           (lambda () (next #t)))" => "#<void>",
+        "(define g (make-generator '(1 2 3)))" => "#<void>",
+        "(let* ((a (g)) (b (g)) (c (g)) (d (g))) (list a b c d))" => "(1 2 3 done)"
+    ];
+}
+
+#[test]
+fn exception_handlers() {
+    evals![
+        "(reset (with-exception-handler
+                  (lambda (e) 'handled)
+                  (lambda () (shift k 'aborted))))" => "aborted",
+        "%handlers" => "()",
+        "(guard (e (#t (list 'caught e)))
+           (reset (+ 1 (shift k (raise 'oops)))))" => "(caught oops)",
+        "(reset (guard (e (#t (list 'caught e)))
+                  (+ 1 (shift k (k 1)))))" => "2"
+    ];
+}
+
+#[test]
+fn prompt_frames() {
+    evals![
+        "(define (add-shift a b) (+ a (shift k (k (k b)))))" => "#<void>",
+        "(reset (add-shift 1 2))" => "4",
+        "(define tag (make-continuation-prompt-tag 'tag))" => "#<void>",
+        "(call-with-continuation-prompt
+           (lambda ()
+             (set! %call-with-prompt #f)
+             (+ 1 (abort-current-continuation tag 41)))
+           tag
+           (lambda (v) (+ v 1)))" => "42"
+    ];
+}
@@ -0,0 +1,68 @@
+#[macro_use]
+mod common;
+use marwood::cell;
+use marwood::cell::Cell;
+use marwood::error::Error::{ErrorSignal, ExpectedPairButFound, UncaughtException};
+use marwood::lex;
+use marwood::parse;
+use marwood::vm::Vm;
+
+#[test]
+fn guard() {
+    evals![
+        "(guard (e (#t (list 'caught e))) (raise 'oops))" => "(caught oops)",
+        "(guard (e ((string? e) 'string) ((symbol? e) 'symbol)) (raise 'oops))" => "symbol",
+        "(guard (e ((assq 'a e) => cdr) ((assq 'b e))) (raise (list (cons 'a 42))))" => "42",
+        "(guard (e ((assq 'a e) => cdr) ((assq 'b e))) (raise (list (cons 'b 23))))" => "(b . 23)",
+        "(guard (e (else 'else)) (raise 1))" => "else",
+        "(guard (e (#f 'unreachable)) (+ 1 2))" => "3",
+        "(guard (e (else 'outer)) (guard (e ((string? e) 'inner)) (raise 'x)))" => "outer",
+        "(guard (e (else 'outer)) (guard (e ((symbol? e) 'inner)) (raise 'x)))" => "inner",
+        "(guard (e (#t (guard (e2 (#t (list e e2))) (raise 'second)))) (raise 'first))" => "(first second)"
+    ];
+}
+
+#[test]
+fn with_exception_handler() {
+    evals![
+        "(with-exception-handler (lambda (e) 10) (lambda () (+ 1 (raise-continuable 'c))))" => "11",
+        "(with-exception-handler (lambda (e) 0) (lambda () (+ 1 2)))" => "3",
+        "(guard (e ((string? e) e))
+           (with-exception-handler
+             (lambda (e) (raise \"from handler\"))
+             (lambda () (raise 'inner))))" => "\"from handler\"",
+        "(guard (e ((error-object? e) (error-object-irritants e)))
+           (with-exception-handler
+             (lambda (e) 'ignored)
+             (lambda () (raise 'non-continuable))))" => "(non-continuable)",
+        "%handlers" => "()"
+    ];
+}
+
+#[test]
+fn error_objects() {
+    evals![
+        "(guard (e ((error-object? e) (list (error-object-message e) (error-object-irritants e)))) (error \"boom\" 1 2))"
+            => "(\"boom\" (1 2))",
+        "(guard (e ((error-object? e) (error-object-message e))) (car 1))" => "\"expected pair, but found 1\"",
+        "(guard (e ((error-object? e) (error-object-message e))) (undefined-variable))" => "\"undefined-variable is not bound\"",
+        "(guard (e ((error-object? e) 'error) (else 'other)) (raise 'oops))" => "other",
+        "(error-object? (vector 1 2 3))" => "#f"
+    ];
+}
+
+#[test]
+fn uncaught_exceptions() {
+    fails![
+        "(raise 'oops)" => UncaughtException(cell!["oops"]),
+        "(raise-continuable 1)" => UncaughtException(cell![1]),
+        "(guard (e ((string? e) e)) (raise 'oops))" => UncaughtException(cell!["oops"]),
+        "(guard (e ((string? e) e)) (error \"boom\" 1))" => ErrorSignal(vec![Cell::String("boom".into()), cell![1]]),
+        "(guard (e ((string? e) e)) (car 1))" => ErrorSignal(vec![Cell::String("expected pair, but found 1".into())]),
+        "(with-exception-handler (lambda (e) 0) (lambda () (raise 'x)))" => ErrorSignal(vec![
+            Cell::String("exception handler returned from non-continuable raise".into()),
+            cell!["x"]
+        ]),
+        "(car 1)" => ExpectedPairButFound(cell![1])
+    ];
+}
@@ -0,0 +1,121 @@
+#[macro_use]
+mod common;
+
+use marwood::cell;
+use marwood::cell::Cell;
+use marwood::lex;
+use marwood::parse;
+use marwood::vm::gc::GcConfig;
+use marwood::vm::Vm;
+
+/// Evaluate each expression in a Vm that is always collecting garbage, a
+/// few vcells at a time, so that every mutation races the collector.
+macro_rules! collects {
+    ($($lhs:expr => $rhs:expr),+) => {{
+        for (interval, budget) in [(1, Some(1)), (1, Some(7)), (4, Some(64)), (512, None)] {
+            let mut vm = Vm::new();
+            vm.set_gc_config(GcConfig {
+                interval,
+                threshold: 0.0,
+                grow_threshold: 0.75,
+                budget,
+            });
+            $(
+                assert_eq!(vm.eval(&parse!($lhs)), Ok(match $rhs {
+                    "#<void>" => Cell::Void,
+                    _ => parse!($rhs)
+                }));
+            )+
+        }
+    }};
+}
+
+#[test]
+fn incremental_collection() {
+    collects![
+        "(define (build n) (let loop ((i 0) (acc '())) (if (= i n) acc (loop (+ i 1) (cons (list i) acc)))))" => "#<void>",
+        "(define keep (build 1000))" => "#<void>",
+        "(length keep)" => "1000",
+        "(apply + (map car keep))" => "499500"
+    ];
+}
+
+#[test]
+fn write_barriers() {
+    collects![
+        "(define p (cons 1 2))" => "#<void>",
+        "(define v (make-vector 10 #f))" => "#<void>",
+        "(define saved #f)" => "#<void>",
+        "(define (save! x) (set! saved x))" => "#<void>",
+        "(define (churn n) (if (> n 0) (begin (list n n n) (churn (- n 1)))))" => "#<void>",
+        "(define (fill! i)
+           (if (< i 600)
+             (begin
+               (set-car! p (list i))
+               (set-cdr! p (vector i))
+               (vector-set! v (remainder i 10) (list i i))
+               (save! (cons i (list i)))
+               (churn 5)
+               (fill! (+ i 1)))))" => "#<void>",
+        "(fill! 0)" => "#<void>",
+        "p" => "((599) . #(599))",
+        "v" => "#((590 590) (591 591) (592 592) (593 593) (594 594) (595 595) (596 596) (597 597) (598 598) (599 599))",
+        "saved" => "(599 599)",
+        "(let ((counter (let ((xs '())) (lambda (x) (set! xs (cons x xs)) xs)))) (let loop ((i 0)) (if (< i 500) (begin (counter (list i)) (churn 3) (loop (+ i 1))) (length (counter 'done)))))" => "501"
+    ];
+}
+
+#[test]
+fn symbols_survive_sweep() {
+    collects![
+        "(define (syms n) (if (> n 0) (begin (string->symbol (number->string n)) (syms (- n 1)))))" => "#<void>",
+        "(syms 500)" => "#<void>",
+        "(define s (string->symbol \"250\"))" => "#<void>",
+        "(syms 500)" => "#<void>",
+        "(eq? s (string->symbol \"250\"))" => "#t"
+    ];
+}
+
+#[test]
+fn gc_stats() {
+    let mut vm = Vm::new();
+    let before = vm.gc_stats();
+    assert_eq!(before.used, vm.heap.used_size());
+    assert_eq!(before.heap_size, vm.heap.capacity());
+    assert!(before.peak_used >= before.used);
+
+    vm.eval(&parse!(
+        "(define (build n) (if (> n 0) (cons n (build (- n 1))) '()))"
+    ))
+    .unwrap();
+    vm.eval(&parse!("(length (build 100000))")).unwrap();
+    vm.eval(&parse!("(gc)")).unwrap();
+    let after = vm.gc_stats();
+    assert!(after.collections > before.collections);
+    assert!(after.increments >= after.collections);
+    assert!(after.cells_reclaimed >= 100000);
+    assert_eq!(
+        after.bytes_reclaimed,
+        after.cells_reclaimed * std::mem::size_of::<marwood::vm::vcell::VCell>()
+    );
+    assert!(after.heap_growths > before.heap_growths);
+    assert!(after.peak_used >= 100000);
+    assert!(after.used < after.peak_used);
+    assert!(after.max_pause >= after.last_pause);
+    assert!(after.total_pause >= after.max_pause);
+
+    assert_eq!(
+        vm.eval(&parse!("(map car (gc-stats))")),
+        Ok(parse!(
+            "(collections increments total-pause max-pause last-pause cells-reclaimed \
+              bytes-reclaimed heap-growths heap-size used peak-used)"
+        ))
+    );
+    assert_eq!(
+        vm.eval(&parse!(
+            "(let ((before (cdr (assq 'collections (gc-stats))))) (gc) (- (cdr (assq 'collections (gc-stats))) before))"
+        )),
+        Ok(cell![1])
+    );
+    assert!(vm.eval(&parse!("(gc 1)")).is_err());
+}
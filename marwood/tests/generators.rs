@@ -0,0 +1,136 @@
+#[macro_use]
+mod common;
+use marwood::cell::Cell;
+use marwood::lex;
+use marwood::parse;
+use marwood::vm::Vm;
+
+#[test]
+fn eof_object() {
+    evals![
+        "(eof-object? (eof-object))" => "#t",
+        "(eof-object? '())" => "#f",
+        "(eq? (eof-object) (eof-object))" => "#t"
+    ];
+}
+
+#[test]
+fn constructors() {
+    evals![
+        "(generator->list (generator))" => "()",
+        "(generator->list (generator 1 2 3))" => "(1 2 3)",
+        "(generator->list (circular-generator 1 2 3) 5)" => "(1 2 3 1 2)",
+        "(generator->list (make-iota-generator 3))" => "(0 1 2)",
+        "(generator->list (make-iota-generator 3 8))" => "(8 9 10)",
+        "(generator->list (make-iota-generator 3 8 2))" => "(8 10 12)",
+        "(generator->list (make-range-generator 3) 4)" => "(3 4 5 6)",
+        "(generator->list (make-range-generator 3 8))" => "(3 4 5 6 7)",
+        "(generator->list (make-range-generator 3 8 2))" => "(3 5 7)",
+        "(generator->list (list->generator '(1 2 3)))" => "(1 2 3)",
+        "(generator->list (vector->generator #(1 2 3 4 5)))" => "(1 2 3 4 5)",
+        "(generator->list (vector->generator #(1 2 3 4 5) 2))" => "(3 4 5)",
+        "(generator->list (vector->generator #(1 2 3 4 5) 2 4))" => "(3 4)",
+        "(generator->list (reverse-vector->generator #(1 2 3 4 5)))" => "(5 4 3 2 1)",
+        "(generator->list (reverse-vector->generator #(1 2 3 4 5) 2 4))" => "(4 3)",
+        "(generator->list (string->generator \"abc\"))" => "(#\\a #\\b #\\c)",
+        "(generator->list (string->generator \"abcde\" 1 3))" => "(#\\b #\\c)",
+        "(generator->list (make-for-each-generator for-each '(1 2 3)))" => "(1 2 3)",
+        "(generator->list (make-for-each-generator vector-for-each #(1 2 3)))" => "(1 2 3)",
+        "(generator->list (make-unfold-generator (lambda (s) (> s 5)) (lambda (s) (* s 2)) (lambda (s) (+ s 1)) 0))"
+            => "(0 2 4 6 8 10)"
+    ];
+}
+
+#[test]
+fn coroutine_generators() {
+    evals![
+        "(define g
+           (make-coroutine-generator
+             (lambda (yield) (let loop ((i 0)) (when (< i 3) (yield i) (loop (+ i 1)))))))" => "#<void>",
+        "(list (g) (g) (g) (eof-object? (g)) (eof-object? (g)))" => "(0 1 2 #t #t)",
+        "(define (tree-walk tree)
+           (make-coroutine-generator
+             (lambda (yield)
+               (let walk ((tree tree))
+                 (cond ((null? tree) #f)
+                       ((pair? tree) (walk (car tree)) (walk (cdr tree)))
+                       (else (yield tree)))))))" => "#<void>",
+        "(generator->list (tree-walk '((1 2) (3 (4 5)) 6)))" => "(1 2 3 4 5 6)",
+        "(generator->list (gmap + (tree-walk '(1 (2 3))) (tree-walk '((10) 20 30))))" => "(11 22 33)"
+    ];
+}
+
+#[test]
+fn operations() {
+    evals![
+        "(generator->list (gcons* 'a 'b (generator 1 2)))" => "(a b 1 2)",
+        "(generator->list (gappend (generator 1 2) (generator) (generator 3)))" => "(1 2 3)",
+        "(generator->list (gflatten (generator '(1 2) '() '(3))))" => "(1 2 3)",
+        "(generator->list (ggroup (generator 1 2 3 4 5) 2))" => "((1 2) (3 4) (5))",
+        "(generator->list (ggroup (generator 1 2 3 4 5) 2 0))" => "((1 2) (3 4) (5 0))",
+        "(generator->list (gmerge < (generator 1 4 7) (generator 2 5) (generator 3 6 9)))"
+            => "(1 2 3 4 5 6 7 9)",
+        "(generator->list (gmap (lambda (x) (* x x)) (make-iota-generator 4)))" => "(0 1 4 9)",
+        "(generator->list (gmap + (generator 1 2 3) (generator 10 20)))" => "(11 22)",
+        "(generator->list (gfilter odd? (make-iota-generator 10)))" => "(1 3 5 7 9)",
+        "(generator->list (gremove odd? (make-iota-generator 10)))" => "(0 2 4 6 8)",
+        "(generator->list (gtake (make-iota-generator 10) 3))" => "(0 1 2)",
+        "(generator->list (gtake (generator 1 2) 4 'x))" => "(1 2 x x)",
+        "(generator->list (gdrop (make-iota-generator 5) 3))" => "(3 4)",
+        "(generator->list (gtake-while (lambda (x) (< x 3)) (make-iota-generator 10)))" => "(0 1 2)",
+        "(generator->list (gdrop-while (lambda (x) (< x 3)) (make-iota-generator 5)))" => "(3 4)",
+        "(generator->list (gdelete 2 (generator 1 2 3 2 1)))" => "(1 3 1)",
+        "(generator->list (gdelete-neighbor-dups (generator 1 1 2 2 2 3 1)))" => "(1 2 3 1)",
+        "(generator->list (gindex (generator 'a 'b 'c 'd 'e) (generator 0 2 4)))" => "(a c e)",
+        "(generator->list (gselect (generator 'a 'b 'c 'd) (generator #t #f #f #t)))" => "(a d)"
+    ];
+}
+
+#[test]
+fn consumers() {
+    evals![
+        "(generator->list (make-iota-generator 10) 3)" => "(0 1 2)",
+        "(generator->reverse-list (generator 1 2 3))" => "(3 2 1)",
+        "(generator->vector (generator 1 2 3))" => "#(1 2 3)",
+        "(define v (vector 'a 'b 'c 'd))" => "#<void>",
+        "(generator->vector! v 1 (generator 1 2))" => "2",
+        "v" => "#(a 1 2 d)",
+        "(generator->string (generator #\\a #\\b))" => "\"ab\"",
+        "(generator-fold + 0 (generator 1 2 3))" => "6",
+        "(generator-fold cons '() (generator 1 2 3))" => "(3 2 1)",
+        "(define sum 0)" => "#<void>",
+        "(generator-for-each (lambda (x y) (set! sum (+ sum (* x y)))) (generator 1 2) (generator 3 4))" => "#<void>",
+        "sum" => "11",
+        "(generator-map->list (lambda (x) (* 2 x)) (generator 1 2 3))" => "(2 4 6)",
+        "(generator-find even? (generator 1 3 4 5))" => "4",
+        "(generator-find even? (generator 1 3))" => "#f",
+        "(generator-count odd? (make-iota-generator 10))" => "5",
+        "(generator-any even? (generator 1 2 3))" => "#t",
+        "(generator-any even? (generator 1 3))" => "#f",
+        "(generator-every odd? (generator 1 3))" => "#t",
+        "(generator-every odd? (generator 1 2 3))" => "#f",
+        "(generator-unfold (generator 1 2 3)
+           (lambda (stop? mapper successor seed)
+             (let loop ((seed seed) (acc '()))
+               (if (stop? seed) (reverse acc) (loop (successor seed) (cons (mapper seed) acc))))))"
+            => "(1 2 3)"
+    ];
+}
+
+#[test]
+fn accumulators() {
+    evals![
+        "(define (accumulate acc . items) (for-each acc items) (acc (eof-object)))" => "#<void>",
+        "(accumulate (make-accumulator * 1 (lambda (n) (- n))) 2 3)" => "-6",
+        "(accumulate (count-accumulator) 'a 'b 'c)" => "3",
+        "(accumulate (list-accumulator) 1 2 3)" => "(1 2 3)",
+        "(accumulate (reverse-list-accumulator) 1 2 3)" => "(3 2 1)",
+        "(accumulate (vector-accumulator) 1 2 3)" => "#(1 2 3)",
+        "(accumulate (reverse-vector-accumulator) 1 2 3)" => "#(3 2 1)",
+        "(accumulate (vector-accumulator! (vector 0 0 0 0) 1) 1 2)" => "#(0 1 2 0)",
+        "(accumulate (string-accumulator) #\\a #\\b)" => "\"ab\"",
+        "(accumulate (sum-accumulator) 1 2 3)" => "6",
+        "(accumulate (product-accumulator) 2 3 4)" => "24",
+        "(accumulate (sum-accumulator))" => "0"
+    ];
+}
@@ -0,0 +1,175 @@
+use marwood::cell::Cell;
+use marwood::error::Error::LimitExceeded;
+use marwood::lex;
+use marwood::parse;
+use marwood::vm::limits::Limits;
+use marwood::vm::Vm;
+
+fn limited_vm() -> Vm {
+    let mut vm = Vm::new();
+    vm.set_limits(Limits {
+        heap_cells: Some(200_000),
+        stack_depth: Some(10_000),
+        string_length: Some(1000),
+        vector_length: Some(1000),
+    });
+    vm
+}
+
+#[test]
+fn heap_limit() {
+    let mut vm = limited_vm();
+    assert_eq!(
+        vm.eval(&parse!("(let loop ((l '())) (loop (cons 1 l)))")),
+        Err(LimitExceeded("heap cell", 200_000))
+    );
+    assert_eq!(vm.eval(&parse!("(+ 1 2)")), Ok(Cell::from(3)));
+    assert_eq!(
+        vm.eval(&parse!(
+            "(length (let loop ((l '()) (n 0)) (if (= n 1000) l (loop (cons n l) (+ n 1)))))"
+        )),
+        Ok(Cell::from(1000))
+    );
+    assert_eq!(
+        vm.eval(&parse!(
+            "(guard (e ((error-object? e) (error-object-message e)))
+               (let loop ((l '())) (loop (cons 1 l))))"
+        )),
+        Ok(parse!(r#""heap cell limit of 200000 exceeded""#))
+    );
+    assert_eq!(
+        vm.eval(&parse!("(let loop ((l '())) (loop (cons 1 l)))")),
+        Err(LimitExceeded("heap cell", 200_000))
+    );
+    vm.eval(&parse!("(gc)")).unwrap();
+    assert!(vm.heap.used_size() <= 200_000);
+    assert!(vm.heap.capacity() < 400_000);
+}
+
+#[test]
+fn stack_limit() {
+    let mut vm = limited_vm();
+    vm.eval(&parse!("(define (f n) (+ 1 (f n)))")).unwrap();
+    assert_eq!(
+        vm.eval(&parse!("(f 1)")),
+        Err(LimitExceeded("stack depth", 10_000))
+    );
+    assert_eq!(vm.eval(&parse!("(+ 1 2)")), Ok(Cell::from(3)));
+    assert_eq!(
+        vm.eval(&parse!(
+            "(guard (e ((error-object? e) (error-object-message e))) (f 1))"
+        )),
+        Ok(parse!(r#""stack depth limit of 10000 exceeded""#))
+    );
+    assert_eq!(
+        vm.eval(&parse!("(guard (e (#t 'caught)) (f 1))")),
+        Ok(parse!("caught"))
+    );
+    assert_eq!(
+        vm.eval(&parse!("(f 1)")),
+        Err(LimitExceeded("stack depth", 10_000))
+    );
+    assert_eq!(
+        vm.eval(&parse!(
+            "(let loop ((n 0)) (if (= n 100) n (+ 0 (loop (+ n 1)))))"
+        )),
+        Ok(Cell::from(100))
+    );
+}
+
+#[test]
+fn string_and_vector_limits() {
+    let mut vm = limited_vm();
+    for (expr, error) in [
+        ("(make-vector 1001)", LimitExceeded("vector length", 1000)),
+        ("(make-string 1001)", LimitExceeded("string length", 1000)),
+        (
+            "(vector-append (make-vector 600) (make-vector 600))",
+            LimitExceeded("vector length", 1000),
+        ),
+        (
+            "(string-append (make-string 600) (make-string 600))",
+            LimitExceeded("string length", 1000),
+        ),
+        (
+            "(list->string (cons #\\a (string->list (make-string 1000))))",
+            LimitExceeded("string length", 1000),
+        ),
+    ] {
+        assert_eq!(vm.eval(&parse!(expr)), Err(error));
+    }
+    assert_eq!(
+        vm.eval(&parse!("(vector-length (make-vector 1000))")),
+        Ok(Cell::from(1000))
+    );
+    assert_eq!(
+        vm.eval(&parse!(
+            "(guard (e ((error-object? e) (error-object-message e))) (make-string 5000))"
+        )),
+        Ok(parse!(r#""string length limit of 1000 exceeded""#))
+    );
+}
+
+#[test]
+fn limits_checked_before_growing() {
+    let mut vm = Vm::new();
+    vm.set_limits(Limits {
+        heap_cells: Some(200_000),
+        stack_depth: Some(10_000),
+        ..Limits::default()
+    });
+    for expr in [
+        "(make-vector 1000000)",
+        "(string-append (make-string 150000) (make-string 150000))",
+        "(vector->list (make-vector 150000))",
+    ] {
+        assert_eq!(
+            vm.eval(&parse!(expr)),
+            Err(LimitExceeded("heap cell", 200_000))
+        );
+        assert!(vm.heap.capacity() < 400_000);
+    }
+    assert_eq!(
+        vm.eval(&parse!("(apply + (vector->list (make-vector 50000 1)))")),
+        Err(LimitExceeded("stack depth", 10_000))
+    );
+    assert_eq!(
+        vm.eval(&parse!("(apply + (vector->list (make-vector 5000 1)))")),
+        Ok(Cell::from(5000))
+    );
+}
+
+#[test]
+fn builtin_strings_are_limited() {
+    let mut vm = limited_vm();
+    for expr in [
+        "(number->string (expt 10 2000))",
+        "(string-upcase (make-string 600 #\\ß))",
+    ] {
+        assert_eq!(
+            vm.eval(&parse!(expr)),
+            Err(LimitExceeded("string length", 1000))
+        );
+    }
+    assert_eq!(
+        vm.eval(&parse!("(string-length (number->string (expt 10 999)))")),
+        Ok(Cell::from(1000))
+    );
+}
+
+#[test]
+fn big_numbers_are_counted_as_heap_cells() {
+    let mut vm = Vm::new();
+    vm.set_limits(Limits {
+        heap_cells: Some(50_000),
+        ..Limits::default()
+    });
+    assert_eq!(
+        vm.eval(&parse!("(let loop ((n 3)) (loop (* n n)))")),
+        Err(LimitExceeded("heap cell", 50_000))
+    );
+    assert_eq!(
+        vm.eval(&parse!("(> (expt 2 100000) 0)")),
+        Ok(Cell::from(true))
+    );
+}
@@ -103,3 +103,24 @@ fn assoc() {
            "(assv '(1 2) '((0 foo) ((1 2) bar) (2 baz)))" => "#f"
     ];
 }
+
+#[test]
+fn list_sort() {
+    evals!["(list-sort < '())" => "()",
+           "(list-sort < '(1))" => "(1)",
+           "(list-sort < '(3 1 2))" => "(1 2 3)",
+           "(list-sort > '(3 1 4 1 5 9 2 6))" => "(9 6 5 4 3 2 1 1)",
+           "(list-sort string<? '(\"pear\" \"apple\" \"fig\"))" => "(\"apple\" \"fig\" \"pear\")",
+           "(list-sort (lambda (x y) (< (car x) (car y))) '((2 a) (1 b) (2 c) (1 d)))"
+            => "((1 b) (1 d) (2 a) (2 c))"
+    ];
+    evals!["(define calls 0)" => "#<void>",
+           "(define (counting< x y) (set! calls (+ calls 1)) (< x y))" => "#<void>",
+           "(list-sort counting< '(5 4 3 2 1))" => "(1 2 3 4 5)",
+           "(> calls 0)" => "#t"
+    ];
+    evals!["(list-merge < '(1 3 5) '(2 4 6))" => "(1 2 3 4 5 6)",
+           "(list-sorted? < '(1 2 2 3))" => "#t",
+           "(list-sorted? < '(1 3 2))" => "#f"
+    ];
+}
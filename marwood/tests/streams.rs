@@ -0,0 +1,119 @@
+#[macro_use]
+mod common;
+use marwood::cell::Cell;
+use marwood::lex;
+use marwood::parse;
+use marwood::vm::limits::Limits;
+use marwood::vm::Vm;
+
+#[test]
+fn primitives() {
+    evals![
+        "(stream? stream-null)" => "#t",
+        "(stream? '())" => "#f",
+        "(stream-null? stream-null)" => "#t",
+        "(stream-pair? stream-null)" => "#f",
+        "(define s (stream-cons 1 (stream-cons 2 stream-null)))" => "#<void>",
+        "(list (stream? s) (stream-pair? s) (stream-null? s))" => "(#t #t #f)",
+        "(stream-car s)" => "1",
+        "(stream-car (stream-cdr s))" => "2",
+        "(stream-null? (stream-cdr (stream-cdr s)))" => "#t",
+        "(stream-pair? (stream-cons (car '()) stream-null))" => "#t"
+    ];
+    fails!["(stream-car stream-null)" => marwood::error::Error::ErrorSignal(vec![
+        Cell::String("stream-car: expected stream pair".into()),
+        parse!("#((stream) ((#t)))")
+    ])];
+}
+
+#[test]
+fn laziness() {
+    evals![
+        "(define count 0)" => "#<void>",
+        "(define s (stream-cons (begin (set! count (+ count 1)) 'a) (stream-cons (car '()) stream-null)))" => "#<void>",
+        "count" => "0",
+        "(stream-car s)" => "a",
+        "(stream-car s)" => "a",
+        "count" => "1",
+        "(define-stream (integers-from n) (stream-cons n (integers-from (+ n 1))))" => "#<void>",
+        "(stream->list 5 (integers-from 0))" => "(0 1 2 3 4)",
+        "(define nat (stream-lambda (n) (stream-cons n (nat (+ n 1)))))" => "#<void>",
+        "(stream-ref (nat 0) 100)" => "100"
+    ];
+}
+
+#[test]
+fn library() {
+    evals![
+        "(stream->list (stream 1 2 3))" => "(1 2 3)",
+        "(stream->list (list->stream '(1 2 3)))" => "(1 2 3)",
+        "(stream->list (stream-append (stream 1 2) stream-null (stream 3)))" => "(1 2 3)",
+        "(stream->list (stream-concat (stream (stream 1 2) (stream) (stream 3))))" => "(1 2 3)",
+        "(stream->list 5 (stream-constant 1 2))" => "(1 2 1 2 1)",
+        "(stream->list (stream-drop 2 (stream 1 2 3 4)))" => "(3 4)",
+        "(stream->list (stream-drop-while odd? (stream 1 3 4 5)))" => "(4 5)",
+        "(stream->list 5 (stream-filter odd? (stream-from 0)))" => "(1 3 5 7 9)",
+        "(stream-fold + 0 (stream 1 2 3))" => "6",
+        "(define acc '())" => "#<void>",
+        "(stream-for-each (lambda (x y) (set! acc (cons (+ x y) acc))) (stream 1 2 3) (stream 10 20))" => "#<void>",
+        "acc" => "(22 11)",
+        "(stream->list 3 (stream-from 5 -2))" => "(5 3 1)",
+        "(stream->list 4 (stream-iterate (lambda (x) (* x 2)) 1))" => "(1 2 4 8)",
+        "(stream-length (stream 1 2 3))" => "3",
+        "(stream->list 3 (stream-map * (stream-from 1) (stream-from 1)))" => "(1 4 9)",
+        "(stream->list (stream-range 0 5))" => "(0 1 2 3 4)",
+        "(stream->list (stream-range 5 0))" => "(5 4 3 2 1)",
+        "(stream->list (stream-range 0 10 3))" => "(0 3 6 9)",
+        "(stream-ref (stream 'a 'b 'c) 2)" => "c",
+        "(stream->list (stream-reverse (stream 1 2 3)))" => "(3 2 1)",
+        "(stream->list (stream-scan + 0 (stream 1 2 3)))" => "(0 1 3 6)",
+        "(stream->list (stream-take 3 (stream-from 0)))" => "(0 1 2)",
+        "(stream->list (stream-take-while (lambda (x) (< x 3)) (stream-from 0)))" => "(0 1 2)",
+        "(stream->list (stream-unfold (lambda (x) (* x x)) (lambda (x) (< x 5)) (lambda (x) (+ x 1)) 0))"
+            => "(0 1 4 9 16)",
+        "(stream->list (stream-zip (stream 1 2) (stream 'a 'b 'c)))" => "((1 a) (2 b))",
+        "(stream->list
+           (stream-let loop ((n 3))
+             (if (zero? n) stream-null (stream-cons n (loop (- n 1))))))" => "(3 2 1)"
+    ];
+}
+
+#[test]
+fn sieve() {
+    evals![
+        "(define-stream (sieve strm)
+           (let ((p (stream-car strm)))
+             (stream-cons p (sieve (stream-filter (lambda (n) (not (zero? (modulo n p)))) (stream-cdr strm))))))" => "#<void>",
+        "(stream->list 10 (sieve (stream-from 2)))" => "(2 3 5 7 11 13 17 19 23 29)"
+    ];
+}
+
+#[test]
+fn iterative_forcing_runs_in_constant_space() {
+    let mut vm = Vm::new();
+    vm.set_limits(Limits {
+        heap_cells: Some(20_000),
+        stack_depth: Some(1_000),
+        string_length: None,
+        vector_length: None,
+    });
+
+    // R7RS's iterative forcing test
+    vm.eval(&parse!(
+        "(define (loop n) (delay-force (if (= n 0) (delay 'done) (loop (- n 1)))))"
+    ))
+    .unwrap();
+    assert_eq!(vm.eval(&parse!("(force (loop 10000))")), Ok(parse!("done")));
+
+    // Filtering far into a stream must not hold on to the filtered elements
+    assert_eq!(
+        vm.eval(&parse!(
+            "(stream-car (stream-filter (lambda (n) (= n 10000)) (stream-from 0)))"
+        )),
+        Ok(Cell::from(10000))
+    );
+    assert_eq!(
+        vm.eval(&parse!("(stream-ref (stream-from 0) 10000)")),
+        Ok(Cell::from(10000))
+    );
+}
@@ -5,7 +5,7 @@ use marwood::lex;
 use marwood::parse;
 use marwood::vm::Vm;
 
-use marwood::error::Error::{InvalidStringIndex, InvalidSyntax};
+use marwood::error::Error::{InvalidStringIndex, InvalidSyntax, VariableNotBound};
 
 #[test]
 fn eval_string_char_literals() {
@@ -221,6 +221,172 @@ fn string_procedures() {
     ];
 }
 
+#[test]
+fn string_set_widens() {
+    evals![
+        "(define s (make-string 3 #\\a))" => "#<void>",
+        "(string-set! s 1 #\\🐶)" => "#<void>",
+        "s" => "\"a🐶a\"",
+        "(string-ref s 2)" => "#\\a",
+        "(string-set! s 1 #\\b)" => "#<void>",
+        "(equal? s \"aba\")" => "#t",
+        "(string=? s \"aba\")" => "#t",
+        "(string-copy s 1)" => "\"ba\""
+    ];
+}
+
+#[test]
+fn string_copy_mut() {
+    evals![
+        "(define s (make-string 5 #\\-))" => "#<void>",
+        "(string-copy! s 1 \"o🐶o\")" => "#<void>",
+        "s" => "\"-o🐶o-\"",
+        "(string-copy! s 0 \"abc\" 1 3)" => "#<void>",
+        "s" => "\"bc🐶o-\"",
+        "(string-copy! s 1 s 0 3)" => "#<void>",
+        "s" => "\"bbc🐶-\""
+    ];
+    fails![
+        "(string-copy! (make-string 2) 1 \"abc\")" =>
+            InvalidSyntax("string-copy!: to string is too small".into()),
+        "(string-copy! (make-string 2) 0 \"abc\" 0 4)" => InvalidStringIndex(4, 3)
+    ];
+}
+
+#[test]
+fn string_map_and_for_each() {
+    evals![
+        "(string-map char-upcase \"o🐶o\")" => "\"O🐶O\"",
+        "(string-map (lambda (a b) (if (char<? a b) a b)) \"adcz\" \"bbb\")" => "\"abb\""
+    ];
+    evals![
+        "(define n 0)" => "#<void>",
+        "(string-for-each (lambda (a b) (set! n (+ n 1))) \"abcd\" \"ab\")" => "#<void>",
+        "n" => "2"
+    ];
+}
+
+#[test]
+fn string_searching() {
+    evals![
+        "(string-index \"o🐶o\" #\\o)" => "0",
+        "(string-index \"o🐶o\" #\\o 1)" => "2",
+        "(string-index \"ab1c\" char-numeric?)" => "2",
+        "(string-index \"abc\" #\\z)" => "#f",
+        "(string-search-forward \"🐶o\" \"o🐶o🐶o\" 0)" => "1",
+        "(string-search-forward \"🐶o\" \"o🐶o🐶o\" 2)" => "3",
+        "(string-search-forward \"x\" \"o🐶o\" 0)" => "#f",
+        "(string-contains \"o🐶o\" \"o\")" => "0",
+        "(string-contains \"o🐶o\" \"\")" => "0",
+        "(string-contains \"o🐶o\" \"🐶o\")" => "1",
+        "(string-prefix? \"o🐶\" \"o🐶o\")" => "#t",
+        "(string-prefix? \"🐶\" \"o🐶o\")" => "#f",
+        "(string-suffix? \"🐶o\" \"o🐶o\")" => "#t",
+        "(string-suffix? \"o🐶\" \"o🐶o\")" => "#f"
+    ];
+    fails!["(string-search-forward \"o\" \"o🐶o\" 4)" => InvalidStringIndex(4, 3)];
+}
+
+#[test]
+fn string_join_and_split() {
+    evals![
+        "(string-join '(\"a\" \"b\" \"c\"))" => "\"a b c\"",
+        "(string-join '(\"a\" \"b\" \"c\") \", \")" => "\"a, b, c\"",
+        "(string-join '())" => "\"\"",
+        "(string-split \"a,b,,c\" #\\,)" => "(\"a\" \"b\" \"\" \"c\")",
+        "(string-split \"a b\tc\" char-whitespace?)" => "(\"a\" \"b\" \"c\")",
+        "(string-split \"\" #\\,)" => "(\"\")"
+    ];
+    fails!["(string-join '(\"a\" 1))" =>
+        InvalidSyntax("bad argument to string-join: 1 is not a string".into())];
+}
+
+#[test]
+fn string_trim_pad_reverse() {
+    evals![
+        "(string-trim \"  o🐶o \")" => "\"o🐶o\"",
+        "(string-trim-left \"  o🐶o \")" => "\"o🐶o \"",
+        "(string-trim-right \"  o🐶o \")" => "\"  o🐶o\"",
+        "(string-trim \"xxaxx\" #\\x)" => "\"a\"",
+        "(string-trim-left \"12ab\" char-numeric?)" => "\"ab\"",
+        "(string-pad \"🐶o\" 4)" => "\"  🐶o\"",
+        "(string-pad \"o🐶o\" 2)" => "\"🐶o\"",
+        "(string-pad \"7\" 3 #\\0)" => "\"007\"",
+        "(string-pad-right \"🐶o\" 4)" => "\"🐶o  \"",
+        "(string-pad-right \"o🐶o\" 2)" => "\"o🐶\"",
+        "(string-reverse \"o🐶a\")" => "\"a🐶o\"",
+        "(string-reverse \"\")" => "\"\""
+    ];
+    fails![
+        "(char-criterion #\\a)" => VariableNotBound("char-criterion".into()),
+        "(drop-chars-while char-whitespace? '())" => VariableNotBound("drop-chars-while".into())
+    ];
+}
+
+#[test]
+fn char_sets() {
+    evals![
+        "(char-set? (char-set #\\a))" => "#t",
+        "(char-set? #\\a)" => "#f",
+        "(char-set-contains? char-set:letter #\\λ)" => "#t",
+        "(char-set-contains? char-set:letter #\\1)" => "#f",
+        "(char-set-contains? char-set:whitespace #\\tab)" => "#t",
+        "(char-set-contains? char-set:digit #\\7)" => "#t",
+        "(char-set-contains? char-set:upper-case #\\Σ)" => "#t",
+        "(char-set-contains? char-set:lower-case #\\Σ)" => "#f",
+        "(char-set-contains? char-set:empty #\\a)" => "#f",
+        "(char-set-contains? char-set:full #\\🐶)" => "#t",
+        "(char-set->list (char-set #\\c #\\a #\\b #\\a))" => "(#\\a #\\b #\\c)",
+        "(char-set->list (string->char-set \"o🐶o\"))" => "(#\\o #\\🐶)",
+        "(char-set->list (list->char-set '(#\\x #\\y)))" => "(#\\x #\\y)",
+        "(char-set->list (char-set-union (char-set #\\a) (char-set #\\c) (char-set #\\b)))" =>
+            "(#\\a #\\b #\\c)",
+        "(char-set->list (char-set-intersection (string->char-set \"abc\") (string->char-set \"bcd\")))" =>
+            "(#\\b #\\c)",
+        "(char-set->list (char-set-difference (string->char-set \"abcd\") (char-set #\\b) (char-set #\\d)))" =>
+            "(#\\a #\\c)",
+        "(char-set-contains? (char-set-complement char-set:letter) #\\1)" => "#t",
+        "(char-set-size (string->char-set \"hello\"))" => "4",
+        "(char-set-size (char-set-union char-set:letter (char-set-complement char-set:letter)))" => "1112064"
+    ];
+    prints!["char-set:letter" => "#<char-set>"];
+    evals![
+        "(string-index \"ab1c\" char-set:digit)" => "2",
+        "(string-split \"a b\\tc\" char-set:whitespace)" => "(\"a\" \"b\" \"c\")",
+        "(string-trim \"12ab34\" char-set:digit)" => "\"ab\""
+    ];
+    fails!["(char-set-contains? \"abc\" #\\a)" =>
+        InvalidSyntax("bad argument to char-set-contains?: \"abc\" is not a char-set".into())];
+}
+
+#[test]
+fn unicode_case_mapping() {
+    evals![
+        "(char-upcase #\\ß)" => "#\\ß",
+        "(char-foldcase #\\ß)" => "#\\ß",
+        "(char-foldcase #\\ς)" => "#\\σ",
+        "(char-ci=? #\\Σ #\\ς #\\σ)" => "#t",
+        "(char-ci<? #\\a #\\Λ)" => "#t",
+        "(string-upcase \"straße\")" => "\"STRASSE\"",
+        "(string-downcase \"ΧΑΟΣ\")" => "\"χαος\"",
+        "(string-foldcase \"Straße\")" => "\"strasse\"",
+        "(string-ci=? \"Straße\" \"STRASSE\")" => "#t",
+        "(string-ci<? \"apple\" \"BANANA\")" => "#t"
+    ];
+}
+
+#[test]
+fn unicode_normalization() {
+    evals![
+        "(string-length (string-normalize-nfc (string #\\e #\\x301)))" => "1",
+        "(string-normalize-nfc (string #\\e #\\x301))" => "\"é\"",
+        "(string-length (string-normalize-nfd \"é\"))" => "2",
+        "(string=? (string-normalize-nfd \"é\") (string #\\e #\\x301))" => "#t",
+        "(string-normalize-nfkc \"ﬁ\")" => "\"fi\"",
+        "(string-length (string-normalize-nfkd \"ǆ̌\"))" => "4"
+    ];
+}
+
 #[test]
 fn symbol_procedures() {
     evals!["(string->symbol \"12foo\")" => "\\x31;2foo",
@@ -0,0 +1,185 @@
+#[macro_use]
+mod common;
+use marwood::cell::Cell;
+use marwood::error::Error::ErrorSignal;
+use marwood::lex;
+use marwood::parse;
+use marwood::vm::Vm;
+
+#[test]
+fn threads() {
+    evals![
+        "(define t (make-thread (lambda () (+ 1 2)) 'adder))" => "#<void>",
+        "(thread? t)" => "#t",
+        "(thread? (vector 1 2 3))" => "#f",
+        "(thread-name t)" => "adder",
+        "(thread-specific-set! t 'specific)" => "#<void>",
+        "(thread-specific t)" => "specific",
+        "(eq? (thread-start! t) t)" => "#t",
+        "(thread-join! t)" => "3",
+        "(thread-join! t)" => "3",
+        "(thread-name (current-thread))" => "primordial",
+        "(let ((t (thread-start! (make-thread current-thread)))) (eq? (thread-join! t) t))" => "#t",
+        "(let ((t (make-thread (lambda () 'started))))
+           (list (thread-join! (thread-start! t))
+                 (guard (e (#t 'already-started)) (thread-start! t))))" => "(started already-started)"
+    ];
+}
+
+#[test]
+fn many_threads() {
+    evals![
+        "(define (count-to n) (let loop ((i 0)) (if (< i n) (loop (+ i 1)) n)))" => "#<void>",
+        "(define threads
+           (map (lambda (n) (thread-start! (make-thread (lambda () (count-to (* n 1000))))))
+                '(1 2 3 4 5 6 7 8 9 10)))" => "#<void>",
+        "(map thread-join! threads)" => "(1000 2000 3000 4000 5000 6000 7000 8000 9000 10000)"
+    ];
+}
+
+#[test]
+fn thread_yield() {
+    evals![
+        "(define log '())" => "#<void>",
+        "(define (worker name)
+           (lambda ()
+             (let loop ((i 0))
+               (if (< i 3)
+                   (begin (set! log (cons (list name i) log)) (thread-yield!) (loop (+ i 1)))))))" => "#<void>",
+        "(let ((a (thread-start! (make-thread (worker 'a))))
+               (b (thread-start! (make-thread (worker 'b)))))
+           (thread-join! a)
+           (thread-join! b)
+           (reverse log))" => "((a 0) (b 0) (a 1) (b 1) (a 2) (b 2))",
+        "(thread-yield!)" => "#<void>"
+    ];
+}
+
+#[test]
+fn uncaught_exceptions() {
+    evals![
+        "(define t (thread-start! (make-thread (lambda () (raise 'oops)))))" => "#<void>",
+        "(guard (e ((uncaught-exception? e) (uncaught-exception-reason e))) (thread-join! t))" => "oops",
+        "(guard (e ((uncaught-exception? e)
+                    (error-object-message (uncaught-exception-reason e))))
+           (thread-join! (thread-start! (make-thread (lambda () (car '()))))))"
+           => "\"expected pair, but found ()\"",
+        "(guard (e ((uncaught-exception? e) (map thread? (error-object-irritants (uncaught-exception-reason e)))))
+           (thread-join! (thread-start! (make-thread (lambda () (error \"failed\" (current-thread)))))))"
+           => "(#t)",
+        "(+ 1 2)" => "3"
+    ];
+    fails!["(thread-join! (current-thread))" =>
+        ErrorSignal(vec![
+            Cell::String("thread may not join itself".into()),
+            parse!("#((thread) #f primordial #f runnable #f)")
+        ])
+    ];
+}
+
+#[test]
+fn terminate() {
+    evals![
+        "(define t (thread-start! (make-thread (lambda () (let loop () (thread-yield!) (loop))))))" => "#<void>",
+        "(thread-yield!)" => "#<void>",
+        "(thread-terminate! t)" => "#<void>",
+        "(guard (e ((terminated-thread-exception? e) 'terminated)) (thread-join! t))" => "terminated",
+        "(define t (thread-start! (make-thread (lambda () (thread-terminate! (current-thread)) 'unreachable))))" => "#<void>",
+        "(guard (e ((terminated-thread-exception? e) 'terminated)) (thread-join! t))" => "terminated",
+        "(guard (e (#t 'error)) (thread-terminate! (current-thread)))" => "error",
+        "(define t (make-thread (lambda () 'never-started)))" => "#<void>",
+        "(thread-join! t 0 'timeout)" => "timeout",
+        "(guard (e ((join-timeout-exception? e) 'timeout)) (thread-join! t 0))" => "timeout",
+        "(guard (e (#t (error-object-message e))) (thread-join! t))"
+            => "\"deadlock: thread-join! on a thread that can never run\""
+    ];
+}
+
+#[test]
+fn mutexes() {
+    evals![
+        "(define m (make-mutex 'm))" => "#<void>",
+        "(list (mutex? m) (mutex? (make-thread car)) (mutex-name m) (mutex-state m))" => "(#t #f m not-abandoned)",
+        "(mutex-lock! m)" => "#t",
+        "(eq? (mutex-state m) (current-thread))" => "#t",
+        "(mutex-unlock! m)" => "#t",
+        "(mutex-lock! m #f #f)" => "#t",
+        "(mutex-state m)" => "not-owned",
+        "(mutex-lock! m 0)" => "#f",
+        "(mutex-unlock! m)" => "#t",
+        "(mutex-lock! m 0)" => "#t",
+        "(mutex-unlock! m)" => "#t",
+        "(define counter 0)" => "#<void>",
+        "(define (increment n)
+           (lambda ()
+             (let loop ((i 0))
+               (if (< i n)
+                   (begin
+                     (mutex-lock! m)
+                     (let ((value counter))
+                       (let spin ((j 0)) (if (< j 10) (spin (+ j 1))))
+                       (set! counter (+ value 1)))
+                     (mutex-unlock! m)
+                     (loop (+ i 1)))))))" => "#<void>",
+        "(for-each thread-join!
+           (map (lambda (n) (thread-start! (make-thread (increment 200)))) '(1 2 3 4 5)))" => "#<void>",
+        "counter" => "1000",
+        "(thread-join! (thread-start! (make-thread (lambda () (mutex-lock! m)))))" => "#t",
+        "(mutex-state m)" => "abandoned",
+        "(guard (e ((abandoned-mutex-exception? e) 'abandoned)) (mutex-lock! m))" => "abandoned",
+        "(eq? (mutex-state m) (current-thread))" => "#t",
+        "(guard (e (#t (error-object-message e))) (mutex-lock! m))"
+            => "\"deadlock: mutex-lock! on a mutex that is never unlocked\""
+    ];
+}
+
+#[test]
+fn condition_variables() {
+    evals![
+        "(define m (make-mutex))" => "#<void>",
+        "(define cv (make-condition-variable 'cv))" => "#<void>",
+        "(list (condition-variable? cv) (condition-variable? m) (condition-variable-name cv))" => "(#t #f cv)",
+        "(define queue '())" => "#<void>",
+        "(define (consumer)
+           (mutex-lock! m)
+           (if (null? queue)
+               (begin (mutex-unlock! m cv) (consumer))
+               (let ((item (car queue)))
+                 (set! queue (cdr queue))
+                 (mutex-unlock! m)
+                 item)))" => "#<void>",
+        "(define consumers
+           (map (lambda (n) (thread-start! (make-thread consumer))) '(1 2 3)))" => "#<void>",
+        "(thread-yield!)" => "#<void>",
+        "(define (produce item)
+           (mutex-lock! m)
+           (set! queue (append queue (list item)))
+           (condition-variable-signal! cv)
+           (mutex-unlock! m))" => "#<void>",
+        "(produce 'a)" => "#t",
+        "(produce 'b)" => "#t",
+        "(produce 'c)" => "#t",
+        "(list-sort string<? (map (lambda (t) (symbol->string (thread-join! t))) consumers))"
+            => "(\"a\" \"b\" \"c\")",
+        "(define woken 0)" => "#<void>",
+        "(define waiters
+           (map (lambda (n)
+                  (thread-start!
+                    (make-thread
+                      (lambda ()
+                        (mutex-lock! m)
+                        (mutex-unlock! m cv)
+                        (mutex-lock! m)
+                        (set! woken (+ woken 1))
+                        (mutex-unlock! m)))))
+                '(1 2 3)))" => "#<void>",
+        "(thread-yield!)" => "#<void>",
+        "(condition-variable-broadcast! cv)" => "#<void>",
+        "(for-each thread-join! waiters)" => "#<void>",
+        "woken" => "3",
+        "(begin (mutex-lock! m) (mutex-unlock! m cv 0))" => "#f",
+        "(vector-ref cv 3)" => "()",
+        "(guard (e (#t (error-object-message e))) (mutex-lock! m) (mutex-unlock! m cv))"
+            => "\"deadlock: condition variable is never signaled\""
+    ];
+}
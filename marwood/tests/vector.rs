@@ -53,6 +53,12 @@ fn vector_set() {
            "(vector-fill! v 42)" => "#<void>",
            "v" => "#(42 42 42)"
     ];
+    evals!["(define v (vector 1 2 3 4))" => "#<void>",
+           "(vector-fill! v 0 1)" => "#<void>",
+           "v" => "#(1 0 0 0)",
+           "(vector-fill! v 9 1 3)" => "#<void>",
+           "v" => "#(1 9 9 0)"
+    ];
     fails!["(vector-set! '(1 2 3) 0 0))" =>
             InvalidSyntax("(1 2 3) is not a vector".into())];
     fails!["(vector-fill! '(1 2 3) 0))" =>
@@ -64,7 +70,14 @@ fn vector_set() {
 #[test]
 fn list_vector_conversions() {
     evals!["(vector->list #(1 2 3))" => "(1 2 3)",
-           "(vector->list #())" => "()"
+           "(vector->list #())" => "()",
+           "(vector->list #(1 2 3) 1)" => "(2 3)",
+           "(vector->list #(1 2 3) 1 2)" => "(2)",
+           "(vector->list #(1 2 3) 3)" => "()"
+    ];
+    fails!["(vector->list #(1 2 3) 1 4)" => InvalidVectorIndex(4, 3),
+           "(vector->list #(1 2 3) 2 1)" =>
+            InvalidSyntax("vector->list requires start <= end".into())
     ];
     evals!["(list->vector '(1 2 3))" => "#(1 2 3)",
            "(list->vector '())" => "#()"
@@ -100,3 +113,47 @@ fn vector_copy_mut() {
         "a" => "#(4 5 6)"
     ];
 }
+
+#[test]
+fn vector_sort() {
+    evals![
+        "(vector-sort < #())" => "#()",
+        "(vector-sort < #(3 1 2))" => "#(1 2 3)",
+        "(vector-sort < #(5 4 3 2 1) 1 4)" => "#(2 3 4)",
+        "(vector-sort (lambda (x y) (< (car x) (car y))) #((2 a) (1 b) (2 c) (1 d)))"
+            => "#((1 b) (1 d) (2 a) (2 c))",
+        "(vector-sorted? < #(1 2 3))" => "#t",
+        "(vector-sorted? < #(3 2 1))" => "#f"
+    ];
+    evals![
+        "(define v (vector 5 4 3 2 1))" => "#<void>",
+        "(vector-sort! v <)" => "#<void>",
+        "v" => "#(1 2 3 4 5)",
+        "(define w (vector 5 4 3 2 1))" => "#<void>",
+        "(vector-sort! w < 1 4)" => "#<void>",
+        "w" => "#(5 2 3 4 1)"
+    ];
+}
+
+#[test]
+fn vector_append() {
+    evals![
+        "(vector-append)" => "#()",
+        "(vector-append #(1 2))" => "#(1 2)",
+        "(vector-append #(1 2) #() #(3) #(4 5))" => "#(1 2 3 4 5)"
+    ];
+}
+
+#[test]
+fn vector_map_and_for_each() {
+    evals![
+        "(vector-map (lambda (x) (* x x)) #(1 2 3))" => "#(1 4 9)",
+        "(vector-map + #(1 2 3) #(10 20))" => "#(11 22)",
+        "(vector-map + #() #(1 2))" => "#()"
+    ];
+    evals![
+        "(define acc '())" => "#<void>",
+        "(vector-for-each (lambda (x y) (set! acc (cons (- x y) acc))) #(10 20 30) #(1 2))" => "#<void>",
+        "acc" => "(18 9)"
+    ];
+}